@@ -0,0 +1 @@
+//! Sandbox integration tests live in `tests/`.
@@ -0,0 +1,248 @@
+//! End-to-end flows against a sandbox node. Build the contracts first with `make build-local`,
+//! then run with `make test-integration`.
+use near_workspaces::network::Sandbox;
+use near_workspaces::types::{Gas, NearToken};
+use near_workspaces::{Account, Contract, Worker};
+use serde_json::{json, Value};
+
+const AMM_WASM: &str = "../res/nftamm_local.wasm";
+const NFT_WASM: &str = "../res/test_nft_local.wasm";
+const ONE_NEAR: u128 = 10u128.pow(24);
+
+struct Env {
+    // keeps the sandbox node alive for the duration of the test
+    _worker: Worker<Sandbox>,
+    amm: Contract,
+    nft: Contract,
+    owner: Account,
+    trader: Account,
+}
+
+async fn setup() -> anyhow::Result<Env> {
+    let worker = near_workspaces::sandbox().await?;
+    let amm = worker.dev_deploy(&std::fs::read(AMM_WASM)?).await?;
+    let nft = worker.dev_deploy(&std::fs::read(NFT_WASM)?).await?;
+    let owner = worker.dev_create_account().await?;
+    let trader = worker.dev_create_account().await?;
+
+    amm.call("new")
+        .args_json(json!({ "protocol_fee_multiplier": (10u128.pow(16)).to_string() }))
+        .transact()
+        .await?
+        .into_result()?;
+    nft.call("new")
+        .args_json(json!({ "owner_id": nft.id() }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    for account in [&owner, &trader] {
+        account
+            .call(amm.id(), "storage_deposit")
+            .args_json(json!({}))
+            .deposit(NearToken::from_near(1))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+    for i in 0..4 {
+        owner
+            .call(nft.id(), "nft_mint")
+            .args_json(json!({ "token_id": format!("{}", i), "receiver_id": owner.id() }))
+            .deposit(NearToken::from_millinear(100))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+    Ok(Env { _worker: worker, amm, nft, owner, trader })
+}
+
+async fn deposit_nft(env: &Env, account: &Account, token_id: &str) -> anyhow::Result<()> {
+    account
+        .call(env.nft.id(), "nft_transfer_call")
+        .args_json(json!({ "receiver_id": env.amm.id(), "token_id": token_id, "msg": "" }))
+        .deposit(NearToken::from_yoctonear(1))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(())
+}
+
+async fn nft_owner(env: &Env, token_id: &str) -> anyhow::Result<String> {
+    let token: Value = env
+        .nft
+        .view("nft_token")
+        .args_json(json!({ "token_id": token_id }))
+        .await?
+        .json()?;
+    Ok(token["owner_id"].as_str().unwrap().to_string())
+}
+
+// trade pool holding tokens 0 and 1, spot price 1 NEAR, delta 0.1 NEAR and the 1% fee tier
+async fn create_trade_pool(env: &Env) -> anyhow::Result<u64> {
+    deposit_nft(env, &env.owner, "0").await?;
+    deposit_nft(env, &env.owner, "1").await?;
+    let outcome = env
+        .owner
+        .call(env.amm.id(), "create_pair")
+        .args_json(json!({
+            "pool_type": 2,
+            "bonding_curve": 0,
+            "asset_id": env.nft.id(),
+            "spot_price": ONE_NEAR.to_string(),
+            "delta": (ONE_NEAR / 10).to_string(),
+            "fee": (10u128.pow(16)).to_string(),
+            "asset_recipient": null,
+            "initial_token_ids": ["0", "1"],
+            "locked_til": 0,
+        }))
+        .deposit(NearToken::from_near(2))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    println!("create_pair gas burnt: {}", outcome.total_gas_burnt);
+    Ok(outcome.into_result()?.json()?)
+}
+
+#[tokio::test]
+#[ignore = "requires a sandbox node and built wasm, run with `make test-integration`"]
+async fn test_deposit_and_create_pair() -> anyhow::Result<()> {
+    let env = setup().await?;
+    let pool_id = create_trade_pool(&env).await?;
+
+    let info: Value = env
+        .amm
+        .view("get_pool_info")
+        .args_json(json!({ "pool_id": pool_id }))
+        .await?
+        .json()?;
+    assert_eq!(info["num_nfts"], 2);
+    assert_eq!(info["near_balance"], (2 * ONE_NEAR).to_string());
+
+    let lp: Value = env
+        .amm
+        .view("lp_balance_of")
+        .args_json(json!({ "pool_id": pool_id, "account_id": env.owner.id() }))
+        .await?
+        .json()?;
+    // the minimum liquidity is locked on the first mint
+    assert_eq!(lp, (2 * ONE_NEAR - 1_000).to_string());
+    assert_eq!(info["locked_lp"], "1000");
+
+    let deposits: Value = env
+        .amm
+        .view("get_deposits")
+        .args_json(json!({ "account_id": env.owner.id() }))
+        .await?
+        .json()?;
+    assert_ne!(deposits["storage_usage"], 0);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "requires a sandbox node and built wasm, run with `make test-integration`"]
+async fn test_swap_both_directions() -> anyhow::Result<()> {
+    let env = setup().await?;
+    let pool_id = create_trade_pool(&env).await?;
+
+    let outcome = env
+        .trader
+        .call(env.amm.id(), "swap")
+        .args_json(json!({ "actions": [{
+            "pool_id": pool_id,
+            "swap_type": 1,
+            "min_output_near": null,
+            "input_token_ids": [],
+            "output_token_ids": ["0"],
+            "num_out_nfts": 1,
+        }]}))
+        .deposit(NearToken::from_near(2))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    println!("buy swap gas burnt: {}", outcome.total_gas_burnt);
+    outcome.into_result()?;
+    assert_eq!(nft_owner(&env, "0").await?, env.trader.id().to_string());
+
+    deposit_nft(&env, &env.trader, "0").await?;
+    let outcome = env
+        .trader
+        .call(env.amm.id(), "swap")
+        .args_json(json!({ "actions": [{
+            "pool_id": pool_id,
+            "swap_type": 0,
+            "min_output_near": "0",
+            "input_token_ids": ["0"],
+            "output_token_ids": [],
+            "num_out_nfts": null,
+        }]}))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    println!("sell swap gas burnt: {}", outcome.total_gas_burnt);
+    outcome.into_result()?;
+
+    let held: Value = env
+        .amm
+        .view("get_all_held_ids")
+        .args_json(json!({ "pool_id": pool_id }))
+        .await?
+        .json()?;
+    assert_eq!(held.as_array().unwrap().len(), 2);
+    assert_eq!(nft_owner(&env, "0").await?, env.amm.id().to_string());
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "requires a sandbox node and built wasm, run with `make test-integration`"]
+async fn test_add_and_remove_liquidity() -> anyhow::Result<()> {
+    let env = setup().await?;
+    let pool_id = create_trade_pool(&env).await?;
+
+    deposit_nft(&env, &env.owner, "2").await?;
+    env.owner
+        .call(env.amm.id(), "add_liquidity")
+        .args_json(json!({ "pool_id": pool_id, "token_ids": ["2"] }))
+        .deposit(NearToken::from_near(1))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?
+        .into_result()?;
+    let info: Value = env
+        .amm
+        .view("get_pool_info")
+        .args_json(json!({ "pool_id": pool_id }))
+        .await?
+        .json()?;
+    assert_eq!(info["num_nfts"], 3);
+
+    let lp: Value = env
+        .amm
+        .view("lp_balance_of")
+        .args_json(json!({ "pool_id": pool_id, "account_id": env.owner.id() }))
+        .await?
+        .json()?;
+    let balance_before = env.owner.view_account().await?.balance;
+    env.owner
+        .call(env.amm.id(), "remove_liquidity")
+        .args_json(json!({ "pool_id": pool_id, "lp": lp }))
+        .deposit(NearToken::from_yoctonear(1))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let supply: Value = env
+        .amm
+        .view("lp_total_supply")
+        .args_json(json!({ "pool_id": pool_id }))
+        .await?
+        .json()?;
+    assert_eq!(supply, "1000");
+    assert!(env.owner.view_account().await?.balance > balance_before);
+    for token_id in ["0", "1", "2"] {
+        assert_eq!(nft_owner(&env, token_id).await?, env.owner.id().to_string());
+    }
+    Ok(())
+}
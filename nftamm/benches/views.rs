@@ -0,0 +1,56 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::{testing_env, AccountId, VMConfig};
+use nftamm::curves::curve::BondingCurve;
+use nftamm::pair::{Pair, PoolType};
+use nftamm::Contract;
+
+const NUM_POOLS: u64 = 20;
+const NFTS_PER_POOL: u64 = 50;
+
+fn setup() -> Contract {
+    let owner = AccountId::new_unchecked("owner.near".to_string());
+    let nft = AccountId::new_unchecked("nft.near".to_string());
+    // views are called many times within the same mocked context, so don't meter gas
+    testing_env!(
+        VMContextBuilder::new()
+            .predecessor_account_id(owner.clone())
+            .build(),
+        VMConfig::free()
+    );
+    let mut contract = Contract::new(None, None, None);
+    for pool_id in 0..NUM_POOLS {
+        let mut pair = Pair::new(
+            BondingCurve::LinearCurve,
+            PoolType::NFT,
+            nft.clone(),
+            10u128.pow(24),
+            10u128.pow(22),
+            0,
+            owner.clone(),
+            Some(owner.clone()),
+            0,
+            0,
+            pool_id,
+        );
+        let token_ids = (0..NFTS_PER_POOL)
+            .map(|i| format!("{}-{}", pool_id, i))
+            .collect::<Vec<_>>();
+        pair.deposit_and_mint_lp(owner.clone(), owner.clone(), &token_ids, &0, &[]);
+        contract.pools.push(pair);
+    }
+    contract
+}
+
+fn bench_get_pools(c: &mut Criterion) {
+    let contract = setup();
+    c.bench_function("get_pools", |b| {
+        b.iter(|| black_box(contract.get_pools(None, None, None, None)))
+    });
+    c.bench_function("get_pools_with_token_ids", |b| {
+        b.iter(|| black_box(contract.get_pools(None, None, Some(true), None)))
+    });
+}
+
+criterion_group!(benches, bench_get_pools);
+criterion_main!(benches);
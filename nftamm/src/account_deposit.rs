@@ -4,13 +4,38 @@ use near_sdk::{env, require, AccountId, Balance, PanicOnDefault, StorageUsage};
 
 use near_contract_standards::non_fungible_token::TokenId;
 use crate::*;
+use crate::token_locations::{insert_token_location, remove_token_location};
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct AccountDeposit {
     pub assets: UnorderedMap<AssetId, UnorderedMap<TokenId, bool>>,
-    pub near_balance: Balance,
+    // near covering the storage usage, only moved by `settle_storage_reserve`
+    pub storage_reserve: Balance,
+    // near the account can trade and withdraw
+    pub available_balance: Balance,
     pub storage_usage: StorageUsage
 }
 
+impl AccountDeposit {
+    pub(crate) fn total_balance(&self) -> Balance {
+        self.storage_reserve + self.available_balance
+    }
+
+    // moves near between the available balance and the storage reserve until the reserve covers
+    // the storage usage exactly, returns false if the available balance cannot cover it
+    pub(crate) fn settle_storage_reserve(&mut self) -> bool {
+        let storage_cost = self.storage_usage as Balance * env::storage_byte_cost();
+        if self.storage_reserve > storage_cost {
+            self.available_balance += self.storage_reserve - storage_cost;
+            self.storage_reserve = storage_cost;
+        } else {
+            let amount = (storage_cost - self.storage_reserve).min(self.available_balance);
+            self.available_balance -= amount;
+            self.storage_reserve += amount;
+        }
+        self.storage_reserve == storage_cost
+    }
+}
+
 impl Contract {
     pub(crate) fn internal_get_account_or_revert(&self, account_id: &AccountId) -> AccountDeposit {
         log!("internal_get_account_or_revert {:?}", account_id);
@@ -21,13 +46,14 @@ impl Contract {
     }
     pub(crate) fn internal_deposit_nft(&mut self, account_id: &AccountId, asset_id: &AssetId, token_id: &TokenId) {
         let mut account_deposit = self.internal_get_account_or_revert(account_id);
+        insert_token_location(asset_id, token_id, TokenLocation::AccountDeposit(account_id.clone()));
         match account_deposit.assets.get(asset_id) {
             Some(mut token_ids) => {
                 token_ids.insert(token_id, &true);
                 account_deposit.assets.insert(asset_id, &token_ids);
             },
             None => {
-                let mut token_ids = UnorderedMap::new(StorageKey::AssetDeposit { account_id: account_id.clone(), asset_id: asset_id.clone() });
+                let mut token_ids = UnorderedMap::new(StorageKey::asset_deposit(account_id, asset_id));
                 token_ids.insert(token_id, &true);
                 account_deposit.assets.insert(asset_id, &token_ids);
             }
@@ -38,6 +64,7 @@ impl Contract {
     pub(crate) fn internal_deposit_nft_with_storage_check(&mut self, account_id: &AccountId, asset_id: &AssetId, token_id: &TokenId) {
         let prev_storage = env::storage_usage();
         self.internal_deposit_nft(account_id, asset_id, token_id);
+        self.internal_record_activity(account_id, ActivityKind::Deposit, None, Some(asset_id), 1, 0);
         self.assert_storage(account_id, prev_storage, None);   
     }
 
@@ -49,9 +76,68 @@ impl Contract {
         };
 
         for token_id in token_ids {
-            existing_token_ids.remove(token_id);
+            require!(
+                existing_token_ids.remove(token_id).is_some(),
+                format!("token id {} not deposited", token_id)
+            );
+            remove_token_location(asset_id, token_id, &TokenLocation::AccountDeposit(account_id.clone()));
         }
-        account_deposit.assets.insert(asset_id, &existing_token_ids);
+        // empty maps would keep their entry in the account assets forever
+        if existing_token_ids.is_empty() {
+            account_deposit.assets.remove(asset_id);
+        } else {
+            account_deposit.assets.insert(asset_id, &existing_token_ids);
+        }
+        self.account_deposits.insert(account_id, &account_deposit);
+    }
+
+    // gives the storage released since `prev_storage` back to the account
+    pub(crate) fn internal_release_storage(&mut self, account_id: &AccountId, prev_storage: StorageUsage) {
+        let released = prev_storage.saturating_sub(env::storage_usage());
+        if released == 0 {
+            return;
+        }
+        let mut account_deposit = self.internal_get_account_or_revert(account_id);
+        account_deposit.storage_usage = account_deposit.storage_usage.saturating_sub(released);
+        account_deposit.settle_storage_reserve();
+        self.account_deposits.insert(account_id, &account_deposit);
+    }
+
+    // removes the empty asset maps of the account, returning how many were removed
+    pub(crate) fn internal_gc_deposits(&mut self, account_id: &AccountId) -> u64 {
+        let mut account_deposit = self.internal_get_account_or_revert(account_id);
+        let empty_assets: Vec<AssetId> = account_deposit
+            .assets
+            .iter()
+            .filter(|(_, token_ids)| token_ids.is_empty())
+            .map(|(asset_id, _)| asset_id)
+            .collect();
+        for asset_id in &empty_assets {
+            account_deposit.assets.remove(asset_id);
+        }
+        self.account_deposits.insert(account_id, &account_deposit);
+        empty_assets.len() as u64
+    }
+
+    // near of the account deposit that is not reserved for storage
+    pub(crate) fn internal_available_near(&self, account_id: &AccountId) -> Balance {
+        match self.account_deposits.get(account_id) {
+            Some(account_deposit) => account_deposit.available_balance,
+            None => 0
+        }
+    }
+
+    pub(crate) fn internal_withdraw_near(&mut self, account_id: &AccountId, amount: Balance) {
+        require!(self.internal_available_near(account_id) >= amount, "not enough deposited near");
+        let mut account_deposit = self.internal_get_account_or_revert(account_id);
+        account_deposit.available_balance -= amount;
+        self.account_deposits.insert(account_id, &account_deposit);
+    }
+
+    // credits near the account can trade and withdraw
+    pub(crate) fn internal_credit_near(&mut self, account_id: &AccountId, amount: Balance) {
+        let mut account_deposit = self.internal_get_account_or_revert(account_id);
+        account_deposit.available_balance += amount;
         self.account_deposits.insert(account_id, &account_deposit);
     }
 
@@ -62,16 +148,11 @@ impl Contract {
         attached_deposit: Option<Balance>,
     ) {
         let attached_deposit = attached_deposit.unwrap_or(0);
-        log!("reading account");
         let mut account_deposit = self.internal_get_account_or_revert(account_id);
-        log!("done get account");
         account_deposit.storage_usage += self.compute_storage_usage(prev_storage);
-        log!("done compute storage");
-        account_deposit.near_balance += attached_deposit;
+        account_deposit.available_balance += attached_deposit;
+        require!(account_deposit.settle_storage_reserve(), "storage usage exceeds near balance");
         self.account_deposits.insert(account_id, &account_deposit);
-        log!("done insert");
-        let storage_cost = (account_deposit.storage_usage as u128) * env::storage_byte_cost();
-        require!(account_deposit.near_balance >= storage_cost, "storage usage exceeds near balance");
     }
 
     pub(crate) fn compute_storage_usage(&self, prev: StorageUsage) -> StorageUsage {
@@ -0,0 +1,87 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// activities kept per account, older ones are dropped
+const MAX_ACCOUNT_ACTIVITIES: usize = 20;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ActivityKind {
+    Buy,
+    Sell,
+    AddLiquidity,
+    RemoveLiquidity,
+    Deposit,
+    Withdraw,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountActivity {
+    pub kind: ActivityKind,
+    // none for deposits and withdrawals of the account deposit
+    pub pool_id: Option<u64>,
+    pub asset_id: Option<AssetId>,
+    pub num_tokens: u64,
+    pub near_amount: U128,
+    // in seconds
+    pub timestamp: u64,
+}
+
+impl Contract {
+    // only registered accounts keep a history, the bounded buffer being a small part of their storage
+    pub(crate) fn internal_record_activity(
+        &mut self,
+        account_id: &AccountId,
+        kind: ActivityKind,
+        pool_id: Option<u64>,
+        asset_id: Option<&AssetId>,
+        num_tokens: u64,
+        near_amount: Balance,
+    ) {
+        if self.account_deposits.get(account_id).is_none() {
+            return;
+        }
+        let mut activities = self.account_activities.get(account_id).unwrap_or_default();
+        if activities.len() >= MAX_ACCOUNT_ACTIVITIES {
+            activities.remove(0);
+        }
+        activities.push(AccountActivity {
+            kind,
+            pool_id,
+            asset_id: asset_id.cloned(),
+            num_tokens,
+            near_amount: near_amount.into(),
+            timestamp: env::block_timestamp_ms() / 1000,
+        });
+        self.account_activities.insert(account_id, &activities);
+    }
+
+    pub(crate) fn internal_record_activity_in_pool(
+        &mut self,
+        account_id: &AccountId,
+        kind: ActivityKind,
+        pool_id: u64,
+        num_tokens: u64,
+        near_amount: Balance,
+    ) {
+        let asset_id = self.get_nft_asset_id(pool_id);
+        self.internal_record_activity(account_id, kind, Some(pool_id), Some(&asset_id), num_tokens, near_amount);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Last swaps, liquidity changes, deposits and withdrawals of the account, most recent first.
+    pub fn get_account_activity(&self, account_id: AccountId, from_index: Option<u64>, limit: Option<u64>) -> Vec<AccountActivity> {
+        self.account_activities
+            .get(&account_id)
+            .unwrap_or_default()
+            .into_iter()
+            .rev()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(MAX_ACCOUNT_ACTIVITIES as u64) as usize)
+            .collect()
+    }
+}
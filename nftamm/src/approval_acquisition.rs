@@ -0,0 +1,198 @@
+use crate::*;
+use crate::nft_core::ext_nft_core;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{ext_contract, near_bindgen, PromiseResult};
+
+/// Msg of an `nft_approve` offering the token to the pools at `ask` or more.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ApprovalAsk {
+    pub ask: U128,
+}
+
+#[ext_contract(ext_approval_resolver)]
+pub trait ApprovalAcquisitionResolver {
+    fn acquire_via_approval_resolve(&mut self, pool_id: u64, seller_id: AccountId, token_id: TokenId, ask: U128, operation_id: u64) -> bool;
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Lets keepers buy approved nfts into the pool for at most `cap` each, none disabling it.
+    pub fn set_approval_buy_cap(&mut self, pool_id: u64, cap: Option<U128>) {
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        pool.assert_owner();
+        pool.approval_buy_cap = cap.map(|cap| cap.0);
+    }
+
+    pub fn get_approval_ask(&self, asset_id: AssetId, token_id: TokenId) -> Option<U128> {
+        self.approval_asks.get(&(asset_id, token_id))
+    }
+
+    /// Buys for the pool, with the pool near, a token its seller approved the contract for with
+    /// an ask of at most `max_price`, the seller getting their ask in their deposit and the nft
+    /// joining the pool as an acquired one. The ask must be within the approval buy cap set by the
+    /// pool owner and below what the pool pays a seller for one nft. The curve is left unchanged.
+    /// Callable by anyone.
+    pub fn acquire_via_approval(&mut self, pool_id: u64, token_id: TokenId, seller_id: AccountId, approval_id: u64, max_price: U128) -> Promise {
+        self.assert_not_flagged(pool_id, std::slice::from_ref(&token_id));
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pool_id);
+        let asset_id = self.get_nft_asset_id(pool_id);
+        let key = (asset_id.clone(), token_id.clone());
+        require!(self.deposit_approvals.get(&key) == Some((seller_id.clone(), approval_id)), "token not approved by the seller");
+        let ask = self.approval_asks.get(&key).unwrap_or_else(|| env::panic_str("token not offered to pools"));
+        require!(ask.0 <= max_price.0, "seller ask above max price");
+
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let cap = pool.approval_buy_cap.unwrap_or_else(|| env::panic_str("approval buys disabled"));
+        require!(ask.0 <= cap, "seller ask above the approval buy cap");
+        pool.assert_sells_enabled();
+        pool.assert_nft_pool();
+        require!(pool.pool_type != PoolType::NFT, "nft pools do not buy nfts");
+        require!(pool.asset_recipient.is_none(), "pool sends bought nfts to its asset recipient");
+        require!(!pool.token_ids_in_pools.contains(&token_id), "token already in pool");
+        require!(pool.max_nfts.map_or(true, |max| pool.num_items() < max), "pool nft cap reached");
+        let quote = pool
+            .quote_sell(1, protocol_fee_multiplier)
+            .unwrap_or_else(|error_code| env::panic_str(&format!("failed to get sell info: {:?}", error_code)));
+        require!(ask.0 < quote.near_amount, "seller ask not below pool bid");
+        require!(ask.0 <= pool.near_balance, "not enough near in pool");
+        // reserved until resolved, given back to the pool if the nft is not delivered
+        pool.near_balance -= ask.0;
+
+        let prev_storage = env::storage_usage();
+        self.deposit_approvals.remove(&key);
+        self.approval_asks.remove(&key);
+        self.internal_release_storage(&seller_id, prev_storage);
+        self.internal_update_pool_activity(pool_id);
+
+        let operation_id = self.internal_start_operation(OperationKind::ApprovalBuy, &seller_id, ask.0);
+        ext_nft_core::ext(asset_id)
+            .with_static_gas(GAS_FOR_NFT_TRANSFER_CALL)
+            .with_attached_deposit(1)
+            .nft_transfer(env::current_account_id(), token_id.clone(), Some(approval_id), None)
+            .then(
+                ext_approval_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .acquire_via_approval_resolve(pool_id, seller_id, token_id, ask, operation_id),
+            )
+    }
+
+    /// Adds the nft to the pool and pays the seller deposit if the transfer succeeded, otherwise
+    /// returns the reserved near to the pool.
+    #[private]
+    pub fn acquire_via_approval_resolve(&mut self, pool_id: u64, seller_id: AccountId, token_id: TokenId, ask: U128, operation_id: u64) -> bool {
+        self.internal_finish_operation(operation_id, OperationKind::ApprovalBuy, &seller_id, ask.0);
+        let transferred = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        let pool = &mut self.pools[pool_id as usize];
+        if transferred {
+            pool.add_acquired_token(&token_id);
+            log!("pool {} bought {} from {} for {}", pool_id, token_id, seller_id, ask.0);
+        } else {
+            pool.near_balance += ask.0;
+        }
+        self.internal_update_pool_activity(pool_id);
+        if transferred {
+            // the seller may have unregistered since approving
+            if self.account_deposits.get(&seller_id).is_some() {
+                self.internal_credit_near(&seller_id, ask.0);
+            } else {
+                Promise::new(seller_id).transfer(ask.0);
+            }
+        }
+        transferred
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApprovalReceiver;
+
+    const ASK: Balance = 4 * NEAR / 10;
+
+    // token pool with 10 near buying approved nfts up to half a near, nft 7 of the seller
+    // offered to it at ASK with approval id 3
+    fn setup_approval() -> (Contract, u64, AccountId) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        let seller = account("seller.near");
+        register(&mut contract, &owner, NEAR);
+        register(&mut contract, &seller, NEAR);
+        // creating a pool withdraws from the deposit of the collection, even without nfts
+        deposit_nfts(&mut contract, &owner, &["1"]);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Token, &[], 10 * NEAR);
+        set_caller(&owner, 0);
+        contract.set_approval_buy_cap(pool_id, Some(U128(NEAR / 2)));
+        set_caller(&nft(), 0);
+        contract.nft_on_approve("7".to_string(), seller.clone(), 3, format!("{{\"ask\":\"{}\"}}", ASK));
+        (contract, pool_id, seller)
+    }
+
+    fn acquire(contract: &mut Contract, pool_id: u64, seller: &AccountId) {
+        set_caller(&account("keeper.near"), 0);
+        contract.acquire_via_approval(pool_id, "7".to_string(), seller.clone(), 3, U128(NEAR / 2));
+        assert_eq!(contract.pools[pool_id as usize].near_balance, 10 * NEAR - ASK);
+        assert!(contract.get_approval_ask(nft(), "7".to_string()).is_none());
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == nft()));
+    }
+
+    #[test]
+    fn test_acquire_via_approval() {
+        let (mut contract, pool_id, seller) = setup_approval();
+        let seller_near = contract.internal_available_near(&seller);
+        acquire(&mut contract, pool_id, &seller);
+
+        set_promise_result(PromiseResult::Successful(vec![]));
+        assert!(contract.acquire_via_approval_resolve(pool_id, seller.clone(), "7".to_string(), U128(ASK), 0));
+        let pool = &contract.pools[pool_id as usize];
+        assert!(pool.token_ids_in_pools.contains(&"7".to_string()));
+        assert_eq!(pool.num_acquired_tokens, 1);
+        assert_eq!(pool.near_balance, 10 * NEAR - ASK);
+        assert!(contract.internal_available_near(&seller) >= seller_near + ASK);
+        assert!(contract.pending_operations.get(&0).is_none());
+    }
+
+    #[test]
+    fn test_acquire_via_approval_failed_transfer() {
+        let (mut contract, pool_id, seller) = setup_approval();
+        let seller_near = contract.internal_available_near(&seller);
+        acquire(&mut contract, pool_id, &seller);
+
+        set_promise_result(PromiseResult::Failed);
+        assert!(!contract.acquire_via_approval_resolve(pool_id, seller.clone(), "7".to_string(), U128(ASK), 0));
+        let pool = &contract.pools[pool_id as usize];
+        assert!(!pool.token_ids_in_pools.contains(&"7".to_string()));
+        assert_eq!(pool.num_acquired_tokens, 0);
+        assert_eq!(pool.near_balance, 10 * NEAR);
+        assert!(contract.internal_available_near(&seller) < seller_near + ASK);
+        assert!(contract.pending_operations.get(&0).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "callback does not match pending operation")]
+    fn test_acquire_via_approval_resolve_of_other_amount() {
+        let (mut contract, pool_id, seller) = setup_approval();
+        acquire(&mut contract, pool_id, &seller);
+        set_promise_result(PromiseResult::Successful(vec![]));
+        contract.acquire_via_approval_resolve(pool_id, seller, "7".to_string(), U128(NEAR), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "seller ask above the approval buy cap")]
+    fn test_acquire_via_approval_above_cap() {
+        let (mut contract, pool_id, seller) = setup_approval();
+        set_caller(&account("owner.near"), 0);
+        contract.set_approval_buy_cap(pool_id, Some(U128(ASK - 1)));
+        acquire(&mut contract, pool_id, &seller);
+    }
+
+    #[test]
+    #[should_panic(expected = "token not approved by the seller")]
+    fn test_acquire_via_approval_of_other_approval_id() {
+        let (mut contract, pool_id, seller) = setup_approval();
+        set_caller(&account("keeper.near"), 0);
+        contract.acquire_via_approval(pool_id, "7".to_string(), seller, 4, U128(NEAR / 2));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::*;
+use crate::nft_core::ext_nft_core;
+use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApprovalReceiver;
+use crate::approval_acquisition::ApprovalAsk;
+use near_sdk::{ext_contract, near_bindgen, serde_json, PromiseOrValue, PromiseResult};
+
+// most tokens pulled by one pull_deposits call
+const MAX_PULLED_TOKENS: usize = 50;
+
+#[ext_contract(ext_pull_resolver)]
+pub trait PullDepositResolver {
+    fn pull_deposit_resolve(&mut self, account_id: AccountId, asset_id: AssetId, token_id: TokenId, operation_id: u64);
+}
+
+impl Contract {
+    // deposits tokens the collection reported transferred in a single nft_on_transfer, the
+    // storage being checked once for the batch
+    pub(crate) fn internal_batch_deposit_nfts(&mut self, account_id: &AccountId, asset_id: &AssetId, token_ids: &[TokenId]) {
+        let prev_storage = env::storage_usage();
+        for token_id in token_ids {
+            self.assert_token_not_held(asset_id, token_id);
+            self.internal_deposit_nft(account_id, asset_id, token_id);
+        }
+        self.internal_record_activity(account_id, ActivityKind::Deposit, None, Some(asset_id), token_ids.len() as u64, 0);
+        self.assert_storage(account_id, prev_storage, None);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Lets nft_on_transfer of the collection deposit the extra token ids given in its msg.
+    /// Only for collections whose contract transfers all the ids of a batch before calling
+    /// nft_on_transfer once, as the msg is otherwise set by the sender.
+    pub fn set_batch_transfer_collection(&mut self, asset_id: AssetId, enabled: bool) {
+        self.assert_governance();
+        if enabled {
+            self.batch_transfer_collections.insert(&asset_id);
+        } else {
+            self.batch_transfer_collections.remove(&asset_id);
+        }
+    }
+
+    pub fn is_batch_transfer_collection(&self, asset_id: AssetId) -> bool {
+        self.batch_transfer_collections.contains(&asset_id)
+    }
+
+    /// Pulls tokens the caller approved the contract for into its deposit, one nft_transfer
+    /// each, instead of one nft_transfer_call per token from the caller. Tokens failing to
+    /// transfer are not deposited.
+    pub fn pull_deposits(&mut self, asset_id: AssetId, token_ids: Vec<TokenId>) {
+        require!(!token_ids.is_empty(), "no tokens to pull");
+        require!(token_ids.len() <= MAX_PULLED_TOKENS, format!("cannot pull more than {} tokens", MAX_PULLED_TOKENS));
+        let account_id = env::predecessor_account_id();
+        self.internal_get_account_or_revert(&account_id);
+        for token_id in token_ids {
+            let prev_storage = env::storage_usage();
+            let (owner_id, approval_id) = self
+                .deposit_approvals
+                .remove(&(asset_id.clone(), token_id.clone()))
+                .unwrap_or_else(|| env::panic_str(&format!("token id {} not approved", token_id)));
+            require!(owner_id == account_id, format!("token id {} approved by another owner", token_id));
+            self.assert_token_not_held(&asset_id, &token_id);
+            self.approval_asks.remove(&(asset_id.clone(), token_id.clone()));
+            self.internal_release_storage(&account_id, prev_storage);
+            let operation_id = self.internal_start_operation(OperationKind::NftPull, &account_id, 1);
+            ext_nft_core::ext(asset_id.clone())
+                .with_static_gas(GAS_FOR_NFT_TRANSFER_CALL)
+                .with_attached_deposit(1)
+                .nft_transfer(env::current_account_id(), token_id.clone(), Some(approval_id), None)
+                .then(
+                    ext_pull_resolver::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                        .pull_deposit_resolve(account_id.clone(), asset_id.clone(), token_id, operation_id),
+                );
+        }
+    }
+
+    #[private]
+    pub fn pull_deposit_resolve(&mut self, account_id: AccountId, asset_id: AssetId, token_id: TokenId, operation_id: u64) {
+        self.internal_finish_operation(operation_id, OperationKind::NftPull, &account_id, 1);
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            // not required to be covered, the nft being already transferred
+            let prev_storage = env::storage_usage();
+            self.internal_deposit_nft(&account_id, &asset_id, &token_id);
+            self.internal_record_activity(&account_id, ActivityKind::Deposit, None, Some(&asset_id), 1, 0);
+            let mut account_deposit = self.internal_get_account_or_revert(&account_id);
+            account_deposit.storage_usage += self.compute_storage_usage(prev_storage);
+            account_deposit.settle_storage_reserve();
+            self.account_deposits.insert(&account_id, &account_deposit);
+        }
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenApprovalReceiver for Contract {
+    /// Records the approval so that the owner can pull the token with `pull_deposits`, and the ask
+    /// of a msg `ApprovalAsk` offering the token to `acquire_via_approval`. Only for collections
+    /// traded by pools, the owner being registered and paying the storage of the records.
+    fn nft_on_approve(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: u64,
+        msg: String,
+    ) -> PromiseOrValue<String> {
+        let asset_id = env::predecessor_account_id();
+        require!(self.collections.get(&asset_id).is_some(), "collection not traded by pools");
+        let prev_storage = env::storage_usage();
+        let key = (asset_id, token_id);
+        match serde_json::from_str::<ApprovalAsk>(&msg) {
+            Ok(approval_ask) => self.approval_asks.insert(&key, &approval_ask.ask),
+            Err(_) => self.approval_asks.remove(&key),
+        };
+        self.deposit_approvals.insert(&key, &(owner_id.clone(), approval_id));
+        self.assert_storage(&owner_id, prev_storage, None);
+        PromiseOrValue::Value("approved".to_string())
+    }
+}
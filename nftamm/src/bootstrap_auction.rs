@@ -0,0 +1,321 @@
+use crate::*;
+use crate::circuit_breaker::CircuitBreakerState;
+use crate::curves::U256;
+use crate::curves::units::Yocto;
+use crate::events::emit_event;
+use crate::pair::validate_pool_params;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// most nfts auctioned before a pool starts trading on its curve
+const MAX_BOOTSTRAP_NFTS: u64 = 20;
+const MIN_BOOTSTRAP_DURATION_SEC: u64 = 3600;
+const MAX_BOOTSTRAP_DURATION_SEC: u64 = 7 * 24 * 3600;
+
+pub(crate) const ERR_POOL_BOOTSTRAPPING: &str = "E_POOL_BOOTSTRAPPING: the pool trades after its bootstrap auction";
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BootstrapBid {
+    pub bidder_id: AccountId,
+    pub amount: U128,
+}
+
+/// Ascending auction of the first `num_nfts` nfts of a new pool. The bids left when it ends win
+/// one nft each at the lowest of them, which becomes the spot price of the pool.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BootstrapAuction {
+    pub num_nfts: u64,
+    pub reserve_price: U128,
+    // in seconds
+    pub ends_at: u64,
+    // at most num_nfts, a full book only taking bids above its lowest one
+    pub bids: Vec<BootstrapBid>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BootstrapSettleEvent<'a> {
+    pub pool_id: u64,
+    pub clearing_price: U128,
+    pub winners: &'a [(AccountId, TokenId)],
+}
+
+// storage of a bid in the pool, paid by the bidder until the bid is refunded or settled
+fn bid_storage(bidder_id: &AccountId) -> StorageUsage {
+    // borsh length prefix, account id and amount
+    4 + bidder_id.as_str().len() as StorageUsage + 16
+}
+
+impl Pair {
+    pub(crate) fn assert_not_bootstrapping(&self) {
+        require!(self.bootstrap.is_none(), ERR_POOL_BOOTSTRAPPING);
+    }
+}
+
+impl Contract {
+    fn internal_charge_bid_storage(&mut self, bidder_id: &AccountId) {
+        let mut account_deposit = self.internal_get_account_or_revert(bidder_id);
+        account_deposit.storage_usage += bid_storage(bidder_id);
+        require!(account_deposit.settle_storage_reserve(), "storage usage exceeds near balance");
+        self.account_deposits.insert(bidder_id, &account_deposit);
+    }
+
+    // refunds `amount` of a bid to the bidder deposit, releasing the storage of the bid
+    fn internal_refund_bid(&mut self, bidder_id: &AccountId, amount: Balance) {
+        let mut account_deposit = self.internal_get_account_or_revert(bidder_id);
+        account_deposit.storage_usage = account_deposit.storage_usage.saturating_sub(bid_storage(bidder_id));
+        account_deposit.available_balance += amount;
+        account_deposit.settle_storage_reserve();
+        self.account_deposits.insert(bidder_id, &account_deposit);
+    }
+
+    // near of the bids of running bootstrap auctions
+    pub(crate) fn internal_bootstrap_bids_near(&self) -> Balance {
+        self.pools
+            .iter()
+            .filter_map(|pool| pool.bootstrap.as_ref())
+            .flat_map(|auction| auction.bids.iter())
+            .map(|bid| bid.amount.0)
+            .sum()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Auctions `num_nfts` nfts of a pool that never traded for `duration_sec`, the pool only
+    /// trading once `settle_bootstrap_auction` priced it.
+    pub fn start_bootstrap_auction(&mut self, pool_id: u64, num_nfts: u64, reserve_price: U128, duration_sec: u64) {
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        pool.assert_owner();
+        pool.assert_nft_pool();
+        pool.assert_not_bootstrapping();
+        pool.assert_buys_enabled();
+        require!(pool.pool_type != PoolType::Token, "token pools hold no nfts");
+        require!(pool.virtual_reserves.is_none(), "virtual reserves price the pool");
+        require!(pool.trade_seq == 0, "pool already traded");
+        require!(num_nfts > 0 && num_nfts <= MAX_BOOTSTRAP_NFTS, format!("must auction between 1 and {} nfts", MAX_BOOTSTRAP_NFTS));
+        require!(num_nfts <= pool.num_items(), "not enough nfts in pool");
+        require!(reserve_price.0 > 0, "reserve price must be positive");
+        require!(
+            duration_sec >= MIN_BOOTSTRAP_DURATION_SEC && duration_sec <= MAX_BOOTSTRAP_DURATION_SEC,
+            format!("auction must last between {} and {} seconds", MIN_BOOTSTRAP_DURATION_SEC, MAX_BOOTSTRAP_DURATION_SEC)
+        );
+        pool.bootstrap = Some(BootstrapAuction {
+            num_nfts,
+            reserve_price,
+            ends_at: env::block_timestamp_ms() / 1000 + duration_sec,
+            bids: vec![],
+        });
+        self.assert_storage(&account_id, prev_storage, None);
+    }
+
+    /// Bids the attached near for one nft of the auction. Once the book is full, the bid must
+    /// beat the lowest one, refunded to its bidder deposit.
+    #[payable]
+    pub fn place_bootstrap_bid(&mut self, pool_id: u64) {
+        let bidder_id = env::predecessor_account_id();
+        let amount = env::attached_deposit();
+        self.internal_charge_bid_storage(&bidder_id);
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let auction = pool.bootstrap.as_mut().unwrap_or_else(|| env::panic_str("no bootstrap auction"));
+        require!(env::block_timestamp_ms() / 1000 < auction.ends_at, "auction ended");
+        require!(amount >= auction.reserve_price.0, "bid below reserve price");
+        let mut outbid = None;
+        if auction.bids.len() as u64 == auction.num_nfts {
+            let (lowest, _) = auction
+                .bids
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, bid)| bid.amount.0)
+                .unwrap();
+            require!(amount > auction.bids[lowest].amount.0, "bid not above the lowest bid");
+            outbid = Some(auction.bids.swap_remove(lowest));
+        }
+        auction.bids.push(BootstrapBid { bidder_id, amount: amount.into() });
+        if let Some(outbid) = outbid {
+            self.internal_refund_bid(&outbid.bidder_id, outbid.amount.0);
+        }
+    }
+
+    /// Ends the auction once over, callable by anyone. Each bid, highest first, gets one nft in
+    /// its bidder deposit at the lowest bid while the pool has nfts to sell at that price, the
+    /// rest is refunded to the bidder deposits, and the pool starts trading at that price.
+    pub fn settle_bootstrap_auction(&mut self, pool_id: u64) {
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pool_id);
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let auction = pool.bootstrap.take().unwrap_or_else(|| env::panic_str("no bootstrap auction"));
+        require!(env::block_timestamp_ms() / 1000 >= auction.ends_at, "auction not ended");
+        let mut bids = auction.bids;
+        bids.sort_by(|a, b| b.amount.0.cmp(&a.amount.0));
+        let clearing_price = match bids.last() {
+            Some(bid) => bid.amount.0,
+            None => return,
+        };
+
+        // the owner may have withdrawn nfts or disabled buys during the auction
+        let token_ids = if pool.sides.buys_enabled {
+            let protocol_fee = (U256::from(clearing_price) * U256::from(protocol_fee_multiplier) / WAD).as_u128();
+            pool.preview_any_token_ids(bids.len() as u64, clearing_price - protocol_fee)
+        } else {
+            vec![]
+        };
+        if !token_ids.is_empty() && validate_pool_params(&pool.curve, pool.pool_type, Yocto(clearing_price), pool.delta, pool.fee).is_empty() {
+            pool.spot_price = clearing_price;
+            pool.breaker_state = CircuitBreakerState::new(clearing_price);
+        }
+        let asset_id = pool.nft_token.clone();
+        let asset_recipient = pool.asset_recipient.clone();
+
+        let mut winners = vec![];
+        for (bid, token_id) in bids.iter().zip(token_ids.iter()) {
+            let pool = &mut self.pools[pool_id as usize];
+            let protocol_fee = pool.buy_nfts_at_price(&vec![token_id.clone()], clearing_price, protocol_fee_multiplier);
+            pool.record_trade(true, clearing_price, protocol_fee);
+            self.internal_credit_trade_protocol_fee(pool_id, protocol_fee);
+            if let Some(asset_recipient) = &asset_recipient {
+                let mut asset_recipient_deposit = self.internal_get_account_or_revert(asset_recipient);
+                asset_recipient_deposit.available_balance += clearing_price - protocol_fee;
+                self.account_deposits.insert(asset_recipient, &asset_recipient_deposit);
+            }
+            // not required to be covered, the nft being paid for
+            let prev_storage = env::storage_usage();
+            self.internal_deposit_nft(&bid.bidder_id, &asset_id, token_id);
+            let mut account_deposit = self.internal_get_account_or_revert(&bid.bidder_id);
+            account_deposit.storage_usage += self.compute_storage_usage(prev_storage);
+            account_deposit.settle_storage_reserve();
+            self.account_deposits.insert(&bid.bidder_id, &account_deposit);
+            self.internal_record_activity_in_pool(&bid.bidder_id, ActivityKind::Buy, pool_id, 1, clearing_price);
+            self.internal_refund_bid(&bid.bidder_id, bid.amount.0 - clearing_price);
+            winners.push((bid.bidder_id.clone(), token_id.clone()));
+        }
+        for bid in bids.iter().skip(winners.len()) {
+            self.internal_refund_bid(&bid.bidder_id, bid.amount.0);
+        }
+        if !winners.is_empty() {
+            self.internal_record_pool_trade(pool_id);
+        }
+        emit_event("bootstrap_settle", BootstrapSettleEvent {
+            pool_id,
+            clearing_price: clearing_price.into(),
+            winners: &winners,
+        });
+    }
+
+    pub fn get_bootstrap_auction(&self, pool_id: u64) -> Option<BootstrapAuction> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.bootstrap.clone()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::swap::Action;
+    use crate::testing::*;
+
+    const DURATION_SEC: u64 = 3600;
+
+    // nft pool of nfts 1 to 3 auctioning 2 of them from half a near, and three registered bidders
+    fn setup_auction() -> (Contract, u64) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        for account_id in [&owner, &account("a.near"), &account("b.near"), &account("c.near")] {
+            register(&mut contract, account_id, NEAR);
+        }
+        let pool_id = create_pool(&mut contract, &owner, PoolType::NFT, &["1", "2", "3"], 0);
+        set_caller(&owner, 0);
+        contract.start_bootstrap_auction(pool_id, 2, U128(NEAR / 2), DURATION_SEC);
+        (contract, pool_id)
+    }
+
+    fn bid(contract: &mut Contract, pool_id: u64, bidder: &str, amount: Balance) {
+        set_caller(&account(bidder), amount);
+        contract.place_bootstrap_bid(pool_id);
+    }
+
+    fn num_deposited_nfts(contract: &Contract, account_id: &AccountId) -> u64 {
+        let account_deposit = contract.account_deposits.get(account_id).unwrap();
+        account_deposit.assets.get(&nft()).map_or(0, |token_ids| token_ids.len())
+    }
+
+    #[test]
+    fn test_bootstrap_auction_settled() {
+        let (mut contract, pool_id) = setup_auction();
+        let near_a = contract.internal_available_near(&account("a.near"));
+        let near_b = contract.internal_available_near(&account("b.near"));
+        bid(&mut contract, pool_id, "a.near", NEAR);
+        bid(&mut contract, pool_id, "b.near", 2 * NEAR);
+        bid(&mut contract, pool_id, "c.near", 3 * NEAR / 2);
+        // the lowest bid of the full book is refunded
+        assert_eq!(contract.internal_available_near(&account("a.near")), near_a + NEAR);
+        assert_eq!(contract.get_bootstrap_auction(pool_id).unwrap().bids.len(), 2);
+
+        advance_time_sec(DURATION_SEC);
+        set_caller(&account("keeper.near"), 0);
+        contract.settle_bootstrap_auction(pool_id);
+        let pool = &contract.pools[pool_id as usize];
+        assert!(pool.bootstrap.is_none());
+        assert_eq!(pool.spot_price, 3 * NEAR / 2);
+        assert_eq!(pool.token_ids_in_pools.len(), 1);
+        assert_eq!(num_deposited_nfts(&contract, &account("b.near")), 1);
+        assert_eq!(num_deposited_nfts(&contract, &account("c.near")), 1);
+        // the highest bidder pays the clearing price and the storage of its nft
+        let refunded_b = contract.internal_available_near(&account("b.near"));
+        assert!(refunded_b > near_b && refunded_b < near_b + NEAR / 2);
+    }
+
+    #[test]
+    fn test_bootstrap_auction_without_bids() {
+        let (mut contract, pool_id) = setup_auction();
+        advance_time_sec(DURATION_SEC);
+        contract.settle_bootstrap_auction(pool_id);
+        let pool = &contract.pools[pool_id as usize];
+        assert!(pool.bootstrap.is_none());
+        assert_eq!((pool.spot_price, pool.token_ids_in_pools.len()), (SPOT_PRICE, 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "bid below reserve price")]
+    fn test_bootstrap_bid_below_reserve() {
+        let (mut contract, pool_id) = setup_auction();
+        bid(&mut contract, pool_id, "a.near", NEAR / 2 - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "bid not above the lowest bid")]
+    fn test_bootstrap_bid_not_above_full_book() {
+        let (mut contract, pool_id) = setup_auction();
+        bid(&mut contract, pool_id, "a.near", NEAR);
+        bid(&mut contract, pool_id, "b.near", NEAR);
+        bid(&mut contract, pool_id, "c.near", NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "auction ended")]
+    fn test_bootstrap_bid_after_end() {
+        let (mut contract, pool_id) = setup_auction();
+        advance_time_sec(DURATION_SEC);
+        bid(&mut contract, pool_id, "a.near", NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "auction not ended")]
+    fn test_settle_running_bootstrap_auction() {
+        let (mut contract, pool_id) = setup_auction();
+        bid(&mut contract, pool_id, "a.near", NEAR);
+        advance_time_sec(DURATION_SEC - 1);
+        contract.settle_bootstrap_auction(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "E_POOL_BOOTSTRAPPING")]
+    fn test_buy_during_bootstrap_auction() {
+        let (mut contract, pool_id) = setup_auction();
+        set_caller(&account("a.near"), 2 * NEAR);
+        contract.swap(vec![Action::buy_tokens(pool_id, token_ids(&["1"]))], Some(true));
+    }
+}
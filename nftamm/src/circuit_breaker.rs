@@ -0,0 +1,151 @@
+use crate::*;
+use crate::curves::U256;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CircuitBreakerConfig {
+    // base 1e18, move of the spot price within a window tripping the breaker
+    pub max_price_move: U128,
+    pub window_sec: u64,
+    // trading pause once tripped
+    pub cooldown_sec: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PoolCircuitBreaker {
+    // the contract wide breaker, if any
+    Default,
+    Custom(CircuitBreakerConfig),
+    Disabled,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CircuitBreakerState {
+    pub window_start: u64,
+    // spot price when the window started, moves are measured from it
+    pub reference_price: U128,
+    // spot price after the last trade
+    pub last_price: U128,
+    pub paused_until: u64,
+}
+
+impl CircuitBreakerState {
+    pub(crate) fn new(spot_price: u128) -> Self {
+        CircuitBreakerState {
+            window_start: env::block_timestamp_ms() / 1000,
+            reference_price: spot_price.into(),
+            last_price: spot_price.into(),
+            paused_until: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolCircuitBreakerInfo {
+    pub setting: PoolCircuitBreaker,
+    // breaker applied to the pool, none if disabled
+    pub config: Option<CircuitBreakerConfig>,
+    pub state: CircuitBreakerState,
+}
+
+fn assert_valid_config(config: &CircuitBreakerConfig) {
+    require!(config.max_price_move.0 > 0, "max price move must be positive");
+    require!(config.window_sec > 0 && config.cooldown_sec > 0, "window and cooldown must be positive");
+}
+
+impl Pair {
+    pub(crate) fn assert_circuit_breaker_closed(&self) {
+        require!(self.is_circuit_breaker_closed(), "pool paused by circuit breaker");
+    }
+
+    pub(crate) fn is_circuit_breaker_closed(&self) -> bool {
+        self.breaker_state.paused_until <= env::block_timestamp_ms() / 1000
+    }
+}
+
+impl Contract {
+    pub(crate) fn pool_circuit_breaker(&self, pool_id: u64) -> Option<CircuitBreakerConfig> {
+        match &self.pools[pool_id as usize].circuit_breaker {
+            PoolCircuitBreaker::Default => self.circuit_breaker.clone(),
+            PoolCircuitBreaker::Custom(config) => Some(config.clone()),
+            PoolCircuitBreaker::Disabled => None,
+        }
+    }
+
+    // called after every trade, pauses the pool if its spot price moved too much within the window
+    pub(crate) fn internal_update_circuit_breaker(&mut self, pool_id: u64) {
+        let config = self.pool_circuit_breaker(pool_id);
+        let pool = &mut self.pools[pool_id as usize];
+        let now = env::block_timestamp_ms() / 1000;
+        let state = &mut pool.breaker_state;
+        if let Some(config) = config {
+            if now >= state.window_start + config.window_sec || state.reference_price.0 == 0 {
+                state.window_start = now;
+                state.reference_price = state.last_price;
+            }
+            let reference_price = U256::from(state.reference_price.0);
+            let price_move = U256::from(pool.spot_price.abs_diff(state.reference_price.0)) * U256::from(WAD);
+            if reference_price > U256::zero() && price_move > reference_price * U256::from(config.max_price_move.0) {
+                state.paused_until = now + config.cooldown_sec;
+                // the next window starts after the cooldown, at the tripping price
+                state.window_start = state.paused_until;
+                state.reference_price = pool.spot_price.into();
+                log!("pool {} paused by circuit breaker until {}", pool_id, state.paused_until);
+            }
+        }
+        state.last_price = pool.spot_price.into();
+    }
+
+    fn assert_owner_or_governance(&self, pool_id: u64) {
+        let account_id = env::predecessor_account_id();
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        require!(account_id == pool.owner || self.is_governance(&account_id), "only pool owner or governance");
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the circuit breaker of pools using the default one, none disables it.
+    pub fn set_circuit_breaker(&mut self, config: Option<CircuitBreakerConfig>) {
+        self.assert_governance();
+        if let Some(config) = &config {
+            assert_valid_config(config);
+        }
+        self.circuit_breaker = config;
+    }
+
+    /// Tunes or disables the circuit breaker of a pool, restarting its window. Only governance
+    /// lifts a pause in progress.
+    pub fn set_pool_circuit_breaker(&mut self, pool_id: u64, setting: PoolCircuitBreaker) {
+        self.assert_owner_or_governance(pool_id);
+        if let PoolCircuitBreaker::Custom(config) = &setting {
+            assert_valid_config(config);
+        }
+        let is_governance = self.is_governance(&env::predecessor_account_id());
+        let pool = &mut self.pools[pool_id as usize];
+        let paused_until = pool.breaker_state.paused_until;
+        pool.circuit_breaker = setting;
+        pool.breaker_state = CircuitBreakerState::new(pool.spot_price);
+        if !is_governance {
+            pool.breaker_state.paused_until = paused_until;
+        }
+    }
+
+    pub fn get_circuit_breaker(&self) -> Option<CircuitBreakerConfig> {
+        self.circuit_breaker.clone()
+    }
+
+    pub fn get_pool_circuit_breaker(&self, pool_id: u64) -> PoolCircuitBreakerInfo {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        PoolCircuitBreakerInfo {
+            setting: pool.circuit_breaker.clone(),
+            config: self.pool_circuit_breaker(pool_id),
+            state: pool.breaker_state.clone(),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct CollectionStats {
+    pub num_pools: u64,
+    pub total_items: u64,
+    pub total_near: Balance,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionInfo {
+    pub nft_token: AssetId,
+    pub num_pools: u64,
+    pub total_items: u64,
+    pub total_near: U128,
+    // cheapest buy of one nft and best sale of one nft, royalty included
+    pub best_buy_price: Option<U128>,
+    pub best_sell_price: Option<U128>,
+}
+
+impl Contract {
+    // applies the change of the pool balances since it was last indexed to its collection stats
+    pub(crate) fn internal_update_collection_stats(&mut self, pool_id: u64) {
+        let pool = &self.pools[pool_id as usize];
+        if pool.mt_token_id.is_some() {
+            return;
+        }
+        let nft_token = pool.nft_token.clone();
+        let (num_items, near_balance) = (pool.num_items(), pool.near_balance);
+        let mut stats = self.collections.get(&nft_token).unwrap_or_default();
+        match pool.indexed_liquidity {
+            Some((prev_items, prev_near)) => {
+                stats.total_items = stats.total_items - prev_items + num_items;
+                stats.total_near = stats.total_near - prev_near + near_balance;
+            }
+            None => {
+                stats.num_pools += 1;
+                stats.total_items += num_items;
+                stats.total_near += near_balance;
+            }
+        }
+        self.collections.insert(&nft_token, &stats);
+        self.pools[pool_id as usize].indexed_liquidity = Some((num_items, near_balance));
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_num_collections(&self) -> u64 {
+        self.collections.len()
+    }
+
+    /// Collections with at least one pool, with the aggregates of their pools.
+    pub fn get_collections(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<CollectionInfo> {
+        self.collections
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(100) as usize)
+            .map(|(nft_token, stats)| CollectionInfo {
+                best_buy_price: self.best_buy_hop(&nft_token, 1).map(|(hop, _)| hop.near_amount),
+                best_sell_price: self.best_sell_hop(&nft_token, 1).map(|(hop, _)| hop.near_amount),
+                nft_token,
+                num_pools: stats.num_pools,
+                total_items: stats.total_items,
+                total_near: stats.total_near.into(),
+            })
+            .collect()
+    }
+}
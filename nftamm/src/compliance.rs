@@ -0,0 +1,231 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{ext_contract, near_bindgen, serde_json, PromiseResult};
+
+const GAS_FOR_COMPLIANCE_VIEW: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_COMPLIANCE_RESOLVE: Gas = Gas(10_000_000_000_000);
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ComplianceConfig {
+    // contract answering is_allowed(account_id) -> bool
+    pub contract_id: AccountId,
+    // trades of more near than this need an approval of the compliance contract
+    pub threshold: U128,
+    // how long an approval lets the account trade above the threshold
+    pub approval_ttl_sec: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ComplianceApproval {
+    // compliance config version when approved, changing the config invalidates approvals
+    pub config_nonce: u64,
+    pub expires_at: u64,
+}
+
+#[ext_contract(ext_compliance)]
+pub trait ComplianceChecker {
+    fn is_allowed(&self, account_id: AccountId) -> bool;
+}
+
+#[ext_contract(ext_compliance_resolver)]
+pub trait ComplianceResolver {
+    fn compliance_approval_resolve(&mut self, account_id: AccountId, config_nonce: u64, operation_id: u64) -> bool;
+}
+
+impl Contract {
+    pub(crate) fn assert_compliance(&self, account_id: &AccountId, near_amount: Balance) {
+        let config = match &self.compliance {
+            Some(config) => config,
+            None => return,
+        };
+        if near_amount <= config.threshold.0 {
+            return;
+        }
+        require!(
+            self.is_compliance_approved(account_id.clone()),
+            "trade above compliance threshold not approved"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets or removes the contract approving accounts to trade above a near threshold. Existing
+    /// approvals are invalidated.
+    pub fn set_compliance(&mut self, config: Option<ComplianceConfig>) {
+        self.assert_governance();
+        self.compliance = config;
+        self.compliance_nonce += 1;
+    }
+
+    /// Asks the compliance contract whether the caller is allowed and, if so, lets them trade
+    /// above the threshold for the approval ttl.
+    pub fn request_compliance_approval(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        self.internal_get_account_or_revert(&account_id);
+        let config = self.compliance.clone().unwrap_or_else(|| env::panic_str("compliance not enabled"));
+        let operation_id = self.internal_start_operation(OperationKind::ComplianceApproval, &account_id, 0);
+        ext_compliance::ext(config.contract_id)
+            .with_static_gas(GAS_FOR_COMPLIANCE_VIEW)
+            .is_allowed(account_id.clone())
+            .then(
+                ext_compliance_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_COMPLIANCE_RESOLVE)
+                    .compliance_approval_resolve(account_id, self.compliance_nonce, operation_id),
+            )
+    }
+
+    #[private]
+    pub fn compliance_approval_resolve(&mut self, account_id: AccountId, config_nonce: u64, operation_id: u64) -> bool {
+        self.internal_finish_operation(operation_id, OperationKind::ComplianceApproval, &account_id, 0);
+        let allowed = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<bool>(&value).unwrap_or(false),
+            _ => false,
+        };
+        let config = match &self.compliance {
+            Some(config) if self.compliance_nonce == config_nonce => config.clone(),
+            // the config changed while the compliance contract was queried
+            _ => return false,
+        };
+        if !allowed {
+            self.compliance_approvals.remove(&account_id);
+            return false;
+        }
+
+        let prev_storage = env::storage_usage();
+        self.compliance_approvals.insert(
+            &account_id,
+            &ComplianceApproval {
+                config_nonce,
+                expires_at: env::block_timestamp_ms() / 1000 + config.approval_ttl_sec,
+            },
+        );
+        self.assert_storage(&account_id, prev_storage, None);
+        true
+    }
+
+    pub fn get_compliance(&self) -> Option<ComplianceConfig> {
+        self.compliance.clone()
+    }
+
+    pub fn is_compliance_approved(&self, account_id: AccountId) -> bool {
+        if self.compliance.is_none() {
+            return true;
+        }
+        match self.compliance_approvals.get(&account_id) {
+            Some(approval) => {
+                approval.config_nonce == self.compliance_nonce
+                    && approval.expires_at > env::block_timestamp_ms() / 1000
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::swap::Action;
+    use crate::testing::*;
+
+    const APPROVAL_TTL_SEC: u64 = 3600;
+
+    // nft pool of nfts 1 and 2, whose buys exceed the compliance threshold of 1 near, and a
+    // registered trader
+    fn setup_compliance() -> (Contract, u64, AccountId) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        let trader = account("trader.near");
+        register(&mut contract, &owner, NEAR);
+        register(&mut contract, &trader, NEAR);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::NFT, &["1", "2"], 0);
+        set_caller(&governance(), 0);
+        contract.set_compliance(Some(ComplianceConfig {
+            contract_id: account("kyc.near"),
+            threshold: U128(NEAR),
+            approval_ttl_sec: APPROVAL_TTL_SEC,
+        }));
+        (contract, pool_id, trader)
+    }
+
+    // approval request of the trader answered with `allowed`
+    fn request_approval(contract: &mut Contract, trader: &AccountId, allowed: &[u8]) -> bool {
+        set_caller(trader, 0);
+        let operation_id = contract.next_operation_id;
+        let config_nonce = contract.compliance_nonce;
+        contract.request_compliance_approval();
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == account("kyc.near")));
+        set_promise_result(PromiseResult::Successful(allowed.to_vec()));
+        contract.compliance_approval_resolve(trader.clone(), config_nonce, operation_id)
+    }
+
+    fn buy(contract: &mut Contract, pool_id: u64, trader: &AccountId) {
+        set_caller(trader, 2 * NEAR);
+        contract.swap(vec![Action::buy_tokens(pool_id, token_ids(&["1"]))], Some(true));
+    }
+
+    #[test]
+    fn test_compliance_approval() {
+        let (mut contract, pool_id, trader) = setup_compliance();
+        assert!(request_approval(&mut contract, &trader, b"true"));
+        assert!(contract.is_compliance_approved(trader.clone()));
+        buy(&mut contract, pool_id, &trader);
+
+        advance_time_sec(APPROVAL_TTL_SEC);
+        assert!(!contract.is_compliance_approved(trader));
+    }
+
+    #[test]
+    #[should_panic(expected = "trade above compliance threshold not approved")]
+    fn test_trade_above_threshold_without_approval() {
+        let (mut contract, pool_id, trader) = setup_compliance();
+        buy(&mut contract, pool_id, &trader);
+    }
+
+    #[test]
+    fn test_trade_below_threshold_without_approval() {
+        let (mut contract, pool_id, trader) = setup_compliance();
+        set_caller(&governance(), 0);
+        contract.set_compliance(Some(ComplianceConfig {
+            contract_id: account("kyc.near"),
+            threshold: U128(10 * NEAR),
+            approval_ttl_sec: APPROVAL_TTL_SEC,
+        }));
+        buy(&mut contract, pool_id, &trader);
+    }
+
+    #[test]
+    fn test_compliance_denied() {
+        let (mut contract, _, trader) = setup_compliance();
+        assert!(request_approval(&mut contract, &trader, b"true"));
+        // a later denial revokes the approval
+        assert!(!request_approval(&mut contract, &trader, b"false"));
+        assert!(!contract.is_compliance_approved(trader));
+    }
+
+    #[test]
+    fn test_compliance_config_changed_during_request() {
+        let (mut contract, _, trader) = setup_compliance();
+        set_caller(&trader, 0);
+        let operation_id = contract.next_operation_id;
+        let config_nonce = contract.compliance_nonce;
+        contract.request_compliance_approval();
+        set_caller(&governance(), 0);
+        contract.set_compliance(contract.get_compliance());
+
+        set_promise_result(PromiseResult::Successful(b"true".to_vec()));
+        assert!(!contract.compliance_approval_resolve(trader.clone(), config_nonce, operation_id));
+        assert!(!contract.is_compliance_approved(trader));
+    }
+
+    #[test]
+    fn test_approval_invalidated_by_config_change() {
+        let (mut contract, _, trader) = setup_compliance();
+        assert!(request_approval(&mut contract, &trader, b"true"));
+        set_caller(&governance(), 0);
+        contract.set_compliance(contract.get_compliance());
+        assert!(!contract.is_compliance_approved(trader));
+    }
+}
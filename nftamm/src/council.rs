@@ -0,0 +1,268 @@
+use crate::*;
+use crate::events::emit_event;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use near_sdk::near_bindgen;
+
+// time council members have to confirm a proposal
+const COUNCIL_PROPOSAL_EXPIRY_SEC: u64 = 7 * 24 * 3600;
+const MAX_COUNCIL_MEMBERS: usize = 20;
+const MAX_COUNCIL_PROPOSALS: u64 = 50;
+const GAS_FOR_COUNCIL_CALL: Gas = Gas(100_000_000_000_000);
+
+/// Accounts governing the contract in place of `governance_id`, `threshold` of them confirming
+/// each governance call.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Council {
+    pub members: Vec<AccountId>,
+    pub threshold: u32,
+}
+
+/// Governance call made by the contract to itself once confirmed by the council threshold.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CouncilProposal {
+    pub proposal_id: u64,
+    pub proposer_id: AccountId,
+    pub method_name: String,
+    // json args of the call
+    pub args: String,
+    pub confirmations: Vec<AccountId>,
+    // in seconds
+    pub expires_at: u64,
+}
+
+impl Contract {
+    // governance is the contract calling itself on behalf of the council while one is set
+    pub(crate) fn is_governance(&self, account_id: &AccountId) -> bool {
+        match &self.council {
+            Some(_) => account_id == &env::current_account_id(),
+            None => account_id == &self.governance_id,
+        }
+    }
+
+    fn assert_council_member(&self) -> AccountId {
+        let account_id = env::predecessor_account_id();
+        let council = self.council.as_ref().unwrap_or_else(|| env::panic_str("no council set"));
+        require!(council.members.contains(&account_id), "only council members");
+        account_id
+    }
+
+    fn internal_remove_expired_council_proposals(&mut self) {
+        let now = env::block_timestamp_ms() / 1000;
+        let expired: Vec<u64> = self
+            .council_proposals
+            .values()
+            .filter(|proposal| proposal.expires_at <= now)
+            .map(|proposal| proposal.proposal_id)
+            .collect();
+        for proposal_id in expired {
+            self.council_proposals.remove(&proposal_id);
+        }
+    }
+
+    // runs the proposal if confirmed by the threshold, returning whether it ran
+    fn internal_try_execute_council_proposal(&mut self, proposal: &CouncilProposal) -> bool {
+        let threshold = self.council.as_ref().map_or(u32::MAX, |council| council.threshold);
+        if (proposal.confirmations.len() as u32) < threshold {
+            return false;
+        }
+        self.council_proposals.remove(&proposal.proposal_id);
+        Promise::new(env::current_account_id()).function_call(
+            proposal.method_name.clone(),
+            proposal.args.clone().into_bytes(),
+            0,
+            GAS_FOR_COUNCIL_CALL,
+        );
+        emit_event("council_execute", proposal);
+        true
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Hands governance to a council, or back to `governance_id` if none. While a council is
+    /// set, this is itself a council call.
+    pub fn set_council(&mut self, council: Option<Council>) {
+        self.assert_governance();
+        if let Some(council) = &council {
+            require!(!council.members.is_empty(), "council has no members");
+            require!(council.members.len() <= MAX_COUNCIL_MEMBERS, format!("council cannot exceed {} members", MAX_COUNCIL_MEMBERS));
+            let mut members = council.members.clone();
+            members.sort();
+            members.dedup();
+            require!(members.len() == council.members.len(), "duplicate council member");
+            require!(
+                council.threshold > 0 && council.threshold as usize <= council.members.len(),
+                "threshold must be between 1 and the number of members"
+            );
+        }
+        // confirmations were given by the previous members
+        self.council_proposals.clear();
+        self.council = council;
+        emit_event("set_council", &self.council);
+    }
+
+    pub fn get_council(&self) -> Option<Council> {
+        self.council.clone()
+    }
+
+    /// Proposes a call to a governance method of the contract with the given json args, the
+    /// proposer confirming it. Returns the proposal id.
+    pub fn propose_council_action(&mut self, method_name: String, args: String) -> u64 {
+        let account_id = self.assert_council_member();
+        require!(interface::is_call_method(&method_name), format!("unknown method {}", method_name));
+        require!(serde_json::from_str::<serde_json::Value>(&args).is_ok(), "args are not valid json");
+        self.internal_remove_expired_council_proposals();
+        require!(self.council_proposals.len() < MAX_COUNCIL_PROPOSALS, "too many open council proposals");
+
+        let proposal_id = self.next_council_proposal_id;
+        self.next_council_proposal_id += 1;
+        let proposal = CouncilProposal {
+            proposal_id,
+            proposer_id: account_id.clone(),
+            method_name,
+            args,
+            confirmations: vec![account_id],
+            expires_at: env::block_timestamp_ms() / 1000 + COUNCIL_PROPOSAL_EXPIRY_SEC,
+        };
+        emit_event("council_propose", &proposal);
+        if !self.internal_try_execute_council_proposal(&proposal) {
+            self.council_proposals.insert(&proposal_id, &proposal);
+        }
+        proposal_id
+    }
+
+    /// Confirms the proposal, calling the method once the threshold is reached.
+    pub fn confirm_council_action(&mut self, proposal_id: u64) {
+        let account_id = self.assert_council_member();
+        let mut proposal = self
+            .council_proposals
+            .get(&proposal_id)
+            .unwrap_or_else(|| env::panic_str("unknown council proposal"));
+        require!(proposal.expires_at > env::block_timestamp_ms() / 1000, "council proposal expired");
+        require!(!proposal.confirmations.contains(&account_id), "already confirmed");
+        proposal.confirmations.push(account_id.clone());
+        emit_event("council_confirm", &proposal);
+        if !self.internal_try_execute_council_proposal(&proposal) {
+            self.council_proposals.insert(&proposal_id, &proposal);
+        }
+    }
+
+    /// Only the proposer withdraws a proposal before it is executed.
+    pub fn cancel_council_action(&mut self, proposal_id: u64) {
+        let account_id = env::predecessor_account_id();
+        let proposal = self
+            .council_proposals
+            .get(&proposal_id)
+            .unwrap_or_else(|| env::panic_str("unknown council proposal"));
+        require!(proposal.proposer_id == account_id, "only the proposer");
+        self.council_proposals.remove(&proposal_id);
+        emit_event("council_cancel", &proposal);
+    }
+
+    pub fn get_council_proposal(&self, proposal_id: u64) -> Option<CouncilProposal> {
+        self.council_proposals.get(&proposal_id)
+    }
+
+    pub fn get_council_proposals(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<CouncilProposal> {
+        self.council_proposals
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(100) as usize)
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use near_sdk::test_utils::get_created_receipts;
+
+    fn members() -> Vec<AccountId> {
+        vec![account("a.near"), account("b.near"), account("c.near")]
+    }
+
+    // council of a, b and c with a threshold of 2
+    fn setup_council() -> Contract {
+        let mut contract = setup_contract();
+        contract.set_council(Some(Council { members: members(), threshold: 2 }));
+        contract
+    }
+
+    #[test]
+    fn test_council_proposal_executed_at_threshold() {
+        let mut contract = setup_council();
+        set_caller(&account("a.near"), 0);
+        let proposal_id = contract.propose_council_action("set_fee_tiers".to_string(), r#"{"fee_tiers": []}"#.to_string());
+        assert!(get_created_receipts().is_empty());
+        assert_eq!(contract.get_council_proposal(proposal_id).unwrap().confirmations, vec![account("a.near")]);
+
+        set_caller(&account("b.near"), 0);
+        contract.confirm_council_action(proposal_id);
+        assert!(contract.get_council_proposal(proposal_id).is_none());
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, contract_id());
+    }
+
+    #[test]
+    fn test_council_call_is_governance() {
+        let mut contract = setup_council();
+        set_caller(&contract_id(), 0);
+        contract.set_fee_tiers(vec![]);
+        assert!(contract.fee_tiers.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "only governance")]
+    fn test_governance_id_replaced_by_council() {
+        let mut contract = setup_council();
+        set_caller(&governance(), 0);
+        contract.set_fee_tiers(vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "only council members")]
+    fn test_council_proposal_by_non_member() {
+        let mut contract = setup_council();
+        set_caller(&governance(), 0);
+        contract.propose_council_action("set_fee_tiers".to_string(), r#"{"fee_tiers": []}"#.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "already confirmed")]
+    fn test_council_confirmation_counted_once() {
+        let mut contract = setup_council();
+        set_caller(&account("a.near"), 0);
+        let proposal_id = contract.propose_council_action("set_fee_tiers".to_string(), r#"{"fee_tiers": []}"#.to_string());
+        contract.confirm_council_action(proposal_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "council proposal expired")]
+    fn test_council_confirmation_after_expiry() {
+        let mut contract = setup_council();
+        set_caller(&account("a.near"), 0);
+        let proposal_id = contract.propose_council_action("set_fee_tiers".to_string(), r#"{"fee_tiers": []}"#.to_string());
+        advance_time_sec(COUNCIL_PROPOSAL_EXPIRY_SEC);
+        set_caller(&account("b.near"), 0);
+        contract.confirm_council_action(proposal_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "threshold must be between 1 and the number of members")]
+    fn test_council_threshold_above_members() {
+        let mut contract = setup_contract();
+        contract.set_council(Some(Council { members: members(), threshold: 4 }));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate council member")]
+    fn test_council_duplicate_member() {
+        let mut contract = setup_contract();
+        contract.set_council(Some(Council { members: vec![account("a.near"), account("a.near")], threshold: 1 }));
+    }
+}
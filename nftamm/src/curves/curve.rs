@@ -1,5 +1,6 @@
 use near_sdk::{borsh::{self, BorshDeserialize, BorshSerialize}, PanicOnDefault, near_bindgen, serde::{Serialize, Deserialize}, env};
 use super::{linear, exponential, BuyInfo, SellInfo};
+use super::units::{Wad, Yocto};
 
 #[near_bindgen]
 #[repr(u8)]
@@ -48,29 +49,31 @@ impl Curve {
 
     pub(crate) fn get_buy_info(
         &self,
-        spot_price: u128,
+        spot_price: Yocto,
         delta: u128,
         num_items: u64,
-        fee_multiplier: u128,
-        protocol_fee_multiplier: u128,
+        fee_multiplier: Wad,
+        protocol_fee_multiplier: Wad,
     ) -> BuyInfo {
+        let (spot_price, fee_multiplier, protocol_fee_multiplier) = (spot_price.0, fee_multiplier.0, protocol_fee_multiplier.0);
         match self.curve_type {
             BondingCurve::LinearCurve => linear::get_buy_info(spot_price, delta, num_items, fee_multiplier, protocol_fee_multiplier),
             BondingCurve::ExponentialCurve => exponential::get_buy_info(spot_price, delta, num_items, fee_multiplier, protocol_fee_multiplier)
-        }
+        }.checked()
     }
 
     pub(crate) fn get_sell_info(
         &self, 
-        spot_price: u128,
+        spot_price: Yocto,
         delta: u128,
         num_items: u64,
-        fee_multiplier: u128,
-        protocol_fee_multiplier: u128,
+        fee_multiplier: Wad,
+        protocol_fee_multiplier: Wad,
     ) -> SellInfo {
+        let (spot_price, fee_multiplier, protocol_fee_multiplier) = (spot_price.0, fee_multiplier.0, protocol_fee_multiplier.0);
         match self.curve_type {
             BondingCurve::LinearCurve => linear::get_sell_info(spot_price, delta, num_items, fee_multiplier, protocol_fee_multiplier),
             BondingCurve::ExponentialCurve => exponential::get_sell_info(spot_price, delta, num_items, fee_multiplier, protocol_fee_multiplier)
-        }
+        }.checked()
     }
 }
\ No newline at end of file
@@ -2,11 +2,13 @@ use near_sdk::{borsh::{self, BorshDeserialize, BorshSerialize}, near_bindgen, se
 
 #[near_bindgen]
 #[repr(u8)]
-#[derive(BorshDeserialize, BorshSerialize, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(BorshDeserialize, BorshSerialize, PartialEq, Serialize, Deserialize, Clone, Debug)]
 #[serde(crate = "near_sdk::serde")]
 #[warn(non_camel_case_types)]
 pub enum CurveErrorCode {
     Ok = 0,
     InvalidNumItem = 1,
-    SpotPriceOverflow = 2
+    SpotPriceOverflow = 2,
+    // the real balances of the pool cannot cover the trade
+    InsufficientLiquidity = 3,
 }
\ No newline at end of file
@@ -1,19 +1,21 @@
-use crate::curves::{errorcodes::CurveErrorCode, WAD, BuyInfo, SellInfo, U256};
+use crate::curves::{errorcodes::CurveErrorCode, WAD, BuyInfo, SellInfo, U256, checked_as_u128, div_up, mul_wad_up};
 pub const MIN_PRICE: u128 = 10u128.pow(24);
 
+// x^n in base_unit fixed point by squaring, rounding half up like the solmate fpow of sudoswap
 fn fpow(x: U256, n: u64, base_unit: U256) -> U256 {
-    let mut z = U256::zero();
     if x == U256::zero() {
-        if n == 0 {
-            z = base_unit;
-        } else {
-            //default z = 0   
-        }
-    } else {
-        z = base_unit;
-        for _i in 0..n {
-            z = z * x / base_unit;
+        return if n == 0 { base_unit } else { U256::zero() };
+    }
+    let half = base_unit / 2;
+    let mut z = if n % 2 == 1 { x } else { base_unit };
+    let mut x = x;
+    let mut n = n / 2;
+    while n > 0 {
+        x = (x * x + half) / base_unit;
+        if n % 2 == 1 {
+            z = (z * x + half) / base_unit;
         }
+        n /= 2;
     }
     z
 }
@@ -49,25 +51,18 @@ pub(crate) fn get_buy_info(
 
     let new_spot_rice = U256::from(spot_price) * delta_pow_n / WAD;
 
-    if new_spot_rice > U256::from(u128::MAX) {
-        return BuyInfo {
-            error_code: CurveErrorCode::SpotPriceOverflow,
-            new_spot_price: 0,
-            new_delta: 0,
-            input_value: U256::from(0),
-            protocol_fee: U256::from(0),
-        };
-    }
-
-    let new_spot_rice = new_spot_rice.as_u128();
+    let new_spot_rice = match checked_as_u128(new_spot_rice) {
+        Ok(new_spot_rice) => new_spot_rice,
+        Err(error_code) => return BuyInfo::overflow(error_code),
+    };
 
-    let buy_spot_price = U256::from(spot_price) * U256::from(delta) / WAD;
+    let buy_spot_price = mul_wad_up(U256::from(spot_price), U256::from(delta));
 
-    let mut input_value = buy_spot_price * ((delta_pow_n - WAD) * U256::from(WAD) / (delta - WAD)) / WAD;
+    let mut input_value = mul_wad_up(buy_spot_price, div_up((delta_pow_n - WAD) * U256::from(WAD), U256::from(delta - WAD)));
 
     let protocol_fee = (U256::from(input_value) * U256::from(protocol_fee_multiplier)) / WAD;
 
-    input_value += (U256::from(input_value) * U256::from(fee_multiplier)) / WAD;
+    input_value += mul_wad_up(input_value, U256::from(fee_multiplier));
     input_value += protocol_fee;
 
     let new_delta = delta;
@@ -77,7 +72,7 @@ pub(crate) fn get_buy_info(
         new_spot_price: new_spot_rice,
         new_delta: new_delta,
         input_value: input_value,
-        protocol_fee: input_value,
+        protocol_fee: protocol_fee,
     }
 }
 
@@ -112,7 +107,7 @@ pub(crate) fn get_sell_info(
 
     let protocol_fee = output_value * U256::from(protocol_fee_multiplier) / WAD;
 
-    output_value -= output_value * U256::from(fee_multiplier) / WAD;
+    output_value -= mul_wad_up(output_value, U256::from(fee_multiplier));
     output_value -= protocol_fee;
     return SellInfo {
         error_code: CurveErrorCode::Ok,
@@ -125,11 +120,37 @@ pub(crate) fn get_sell_info(
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
-    use super::fpow;
-    use crate::curves::{U256};
+    use super::{fpow, get_buy_info, get_sell_info, MIN_PRICE};
+    use crate::curves::{errorcodes::CurveErrorCode, U256, WAD};
+
+    // sudoswap ExponentialCurve test vectors, prices scaled from ether to near
+    const NEAR: u128 = 10u128.pow(24);
 
     #[test]
     fn test_fpow() {
         assert_eq!(fpow(4.into(), 8, 2.into()), U256::from(512u64));
+        assert_eq!(fpow(U256::from(2 * WAD), 5, U256::from(WAD)), U256::from(32 * WAD));
+        assert_eq!(fpow(U256::from(WAD / 2), 0, U256::from(WAD)), U256::from(WAD));
+    }
+
+    #[test]
+    fn test_get_buy_info_example() {
+        let buy_info = get_buy_info(3 * NEAR, 2 * WAD, 5, 5 * 10u128.pow(15), 3 * 10u128.pow(15));
+        assert_eq!(buy_info.error_code, CurveErrorCode::Ok);
+        assert_eq!(buy_info.new_spot_price, 96 * NEAR);
+        assert_eq!(buy_info.new_delta, 2 * WAD);
+        assert_eq!(buy_info.input_value, U256::from(187_488 * NEAR / 1000));
+        assert_eq!(buy_info.protocol_fee, U256::from(558 * NEAR / 1000));
+    }
+
+    #[test]
+    fn test_get_sell_info_example() {
+        let sell_info = get_sell_info(3 * NEAR, 2 * WAD, 5, 5 * 10u128.pow(15), 3 * 10u128.pow(15));
+        assert_eq!(sell_info.error_code, CurveErrorCode::Ok);
+        // 0.09375 near in the reference, clamped to the near minimum price
+        assert_eq!(sell_info.new_spot_price, MIN_PRICE);
+        assert_eq!(sell_info.new_delta, 2 * WAD);
+        assert_eq!(sell_info.output_value, U256::from(5_766 * NEAR / 1000));
+        assert_eq!(sell_info.protocol_fee, U256::from(174_375 * NEAR / 10_000_000));
     }
 }
@@ -1,4 +1,4 @@
-use crate::curves::{errorcodes::CurveErrorCode, WAD, BuyInfo, SellInfo, U256};
+use crate::curves::{errorcodes::CurveErrorCode, WAD, BuyInfo, SellInfo, U256, checked_as_u128, mul_wad_up};
 
 pub(crate) fn validate_delta(_delta: u128) -> bool {
     //all valids for linear curve
@@ -27,23 +27,17 @@ pub(crate) fn get_buy_info(
         };
     }
 
-    let new_spot_rice = spot_price + delta * (num_items as u128);
-    if new_spot_rice > u128::MAX {
-        return BuyInfo {
-            error_code: CurveErrorCode::SpotPriceOverflow,
-            new_spot_price: 0,
-            new_delta: 0,
-            input_value: U256::from(0),
-            protocol_fee: U256::from(0),
-        };
-    }
+    let new_spot_rice = match checked_as_u128(U256::from(spot_price) + U256::from(delta) * U256::from(num_items)) {
+        Ok(new_spot_rice) => new_spot_rice,
+        Err(error_code) => return BuyInfo::overflow(error_code),
+    };
 
-    let buy_spot_price = spot_price + delta;
-    let mut input_value = U256::from(num_items) * U256::from(buy_spot_price)
+    let buy_spot_price = U256::from(spot_price) + U256::from(delta);
+    let mut input_value = U256::from(num_items) * buy_spot_price
         + U256::from(num_items) * U256::from(num_items - 1) * U256::from(delta) / 2;
     let protocol_fee = (U256::from(input_value) * U256::from(protocol_fee_multiplier)) / WAD;
 
-    input_value += (U256::from(input_value) * U256::from(fee_multiplier)) / WAD;
+    input_value += mul_wad_up(input_value, U256::from(fee_multiplier));
     input_value += protocol_fee;
 
     let new_delta = delta;
@@ -88,7 +82,7 @@ pub(crate) fn get_sell_info(
     let mut output_value = U256::from(spot_price) * num_items - U256::from(num_items) * (num_items - 1) * U256::from(delta) / 2;
     let protocol_fee = output_value * U256::from(protocol_fee_multiplier) / WAD;
 
-    output_value -= output_value * U256::from(fee_multiplier) / WAD;
+    output_value -= mul_wad_up(output_value, U256::from(fee_multiplier));
     output_value -= protocol_fee;
     return SellInfo {
         error_code: CurveErrorCode::Ok,
@@ -98,3 +92,15 @@ pub(crate) fn get_sell_info(
         protocol_fee: protocol_fee,
     };
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::get_buy_info;
+    use crate::curves::errorcodes::CurveErrorCode;
+
+    #[test]
+    fn test_buy_info_spot_price_overflow() {
+        let buy_info = get_buy_info(u128::MAX - 1, 1, 2, 0, 0);
+        assert_eq!(buy_info.error_code, CurveErrorCode::SpotPriceOverflow);
+    }
+}
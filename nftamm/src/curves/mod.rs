@@ -20,7 +20,80 @@ pub struct SellInfo {
     pub output_value: U256,
     pub protocol_fee: U256,
 }
+
+/// Converts to u128, failing with `SpotPriceOverflow` instead of panicking when the value does not fit.
+pub fn checked_as_u128(value: U256) -> Result<u128, CurveErrorCode> {
+    if value > U256::from(u128::MAX) {
+        return Err(CurveErrorCode::SpotPriceOverflow);
+    }
+    Ok(value.as_u128())
+}
+
+/// `a / b` rounded up. Amounts paid to a pool round up and amounts paid by a pool round down,
+/// so rounding never takes value out of a pool.
+pub(crate) fn div_up(a: U256, b: U256) -> U256 {
+    if a.is_zero() {
+        return a;
+    }
+    (a - 1) / b + 1
+}
+
+/// `a * b / WAD` rounded up, see `div_up`.
+pub(crate) fn mul_wad_up(a: U256, b: U256) -> U256 {
+    div_up(a * b, U256::from(WAD))
+}
+
+/// Same as `checked_as_u128` but panics with the given message on overflow.
+pub(crate) fn expect_u128(value: U256, msg: &str) -> u128 {
+    checked_as_u128(value).unwrap_or_else(|_| near_sdk::env::panic_str(msg))
+}
+
+impl BuyInfo {
+    pub(crate) fn overflow(error_code: CurveErrorCode) -> BuyInfo {
+        BuyInfo {
+            error_code,
+            new_spot_price: 0,
+            new_delta: 0,
+            input_value: U256::from(0),
+            protocol_fee: U256::from(0),
+        }
+    }
+
+    // a successful quote must be payable in u128
+    pub(crate) fn checked(self) -> BuyInfo {
+        if self.error_code == CurveErrorCode::Ok {
+            if let Err(code) = checked_as_u128(self.input_value).and(checked_as_u128(self.protocol_fee)) {
+                return BuyInfo::overflow(code);
+            }
+        }
+        self
+    }
+}
+
+impl SellInfo {
+    pub(crate) fn overflow(error_code: CurveErrorCode) -> SellInfo {
+        SellInfo {
+            error_code,
+            new_spot_price: 0,
+            new_delta: 0,
+            output_value: U256::from(0),
+            protocol_fee: U256::from(0),
+        }
+    }
+
+    // a successful quote must be payable in u128
+    pub(crate) fn checked(self) -> SellInfo {
+        if self.error_code == CurveErrorCode::Ok {
+            if let Err(code) = checked_as_u128(self.output_value).and(checked_as_u128(self.protocol_fee)) {
+                return SellInfo::overflow(code);
+            }
+        }
+        self
+    }
+}
 mod linear;
 pub mod errorcodes;
 mod exponential;
-pub mod curve;
\ No newline at end of file
+pub(crate) mod virtual_reserves;
+pub mod curve;
+pub mod units;
\ No newline at end of file
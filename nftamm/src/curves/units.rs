@@ -0,0 +1,87 @@
+// Units of the amounts handled by the curves and pools. Both borsh serialize as the bare u128, so
+// stored pools keep their layout, and json serialize as a `U128` string.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use super::{mul_wad_up, U256, WAD};
+
+/// Multiplier scaled by 1e18, such as a trade fee or a protocol fee multiplier.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", from = "U128", into = "U128")]
+pub struct Wad(pub u128);
+
+/// Amount of yoctoNEAR, such as a spot price or a pool balance.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", from = "U128", into = "U128")]
+pub struct Yocto(pub u128);
+
+impl Wad {
+    pub const ZERO: Wad = Wad(0);
+    pub const ONE: Wad = Wad(WAD);
+
+    pub fn as_u256(self) -> U256 {
+        U256::from(self.0)
+    }
+
+    /// `amount` scaled by the multiplier, rounded down.
+    pub fn of(self, amount: Yocto) -> U256 {
+        amount.as_u256() * self.as_u256() / U256::from(WAD)
+    }
+
+    /// `amount` scaled by the multiplier, rounded up.
+    pub fn of_up(self, amount: Yocto) -> U256 {
+        mul_wad_up(amount.as_u256(), self.as_u256())
+    }
+}
+
+impl Yocto {
+    pub fn as_u256(self) -> U256 {
+        U256::from(self.0)
+    }
+}
+
+impl From<U128> for Wad {
+    fn from(value: U128) -> Self {
+        Wad(value.0)
+    }
+}
+
+impl From<Wad> for U128 {
+    fn from(value: Wad) -> Self {
+        U128(value.0)
+    }
+}
+
+impl From<U128> for Yocto {
+    fn from(value: U128) -> Self {
+        Yocto(value.0)
+    }
+}
+
+impl From<Yocto> for U128 {
+    fn from(value: Yocto) -> Self {
+        U128(value.0)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units_keep_u128_borsh_layout() {
+        let value = 123_456_789u128 * WAD;
+        assert_eq!(Wad(value).try_to_vec().unwrap(), value.try_to_vec().unwrap());
+        assert_eq!(Yocto(value).try_to_vec().unwrap(), value.try_to_vec().unwrap());
+        assert_eq!(Wad::try_from_slice(&value.try_to_vec().unwrap()).unwrap(), Wad(value));
+    }
+
+    #[test]
+    fn test_wad_of() {
+        let fee = Wad(WAD / 3);
+        assert_eq!(fee.of(Yocto(10)), U256::from(3));
+        assert_eq!(fee.of_up(Yocto(10)), U256::from(4));
+        assert_eq!(Wad::ONE.of(Yocto(10)), U256::from(10));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::curves::{errorcodes::CurveErrorCode, WAD, BuyInfo, SellInfo, U256, checked_as_u128, mul_wad_up};
+
+// Constant product pricing over near and nft reserves, real balances plus the virtual ones of
+// the pool. Buys are rounded up and sells down, in favor of the pool.
+
+pub(crate) fn get_buy_info(
+    near_reserve: u128,
+    nft_reserve: u64,
+    delta: u128,
+    num_items: u64,
+    fee_multiplier: u128,
+    protocol_fee_multiplier: u128,
+) -> BuyInfo {
+    if num_items == 0 || num_items >= nft_reserve {
+        return BuyInfo {
+            error_code: CurveErrorCode::InvalidNumItem,
+            new_spot_price: 0,
+            new_delta: 0,
+            input_value: U256::from(0),
+            protocol_fee: U256::from(0),
+        };
+    }
+
+    let nfts_left = U256::from(nft_reserve - num_items);
+    let mut input_value = (U256::from(num_items) * U256::from(near_reserve) + nfts_left - 1) / nfts_left;
+    let protocol_fee = input_value * U256::from(protocol_fee_multiplier) / WAD;
+    input_value += mul_wad_up(input_value, U256::from(fee_multiplier));
+
+    let new_spot_price = match checked_as_u128((U256::from(near_reserve) + input_value) / nfts_left) {
+        Ok(new_spot_price) => new_spot_price,
+        Err(error_code) => return BuyInfo::overflow(error_code),
+    };
+    input_value += protocol_fee;
+
+    BuyInfo {
+        error_code: CurveErrorCode::Ok,
+        new_spot_price,
+        new_delta: delta,
+        input_value,
+        protocol_fee,
+    }
+}
+
+pub(crate) fn get_sell_info(
+    near_reserve: u128,
+    nft_reserve: u64,
+    delta: u128,
+    num_items: u64,
+    fee_multiplier: u128,
+    protocol_fee_multiplier: u128,
+) -> SellInfo {
+    if num_items == 0 {
+        return SellInfo {
+            error_code: CurveErrorCode::InvalidNumItem,
+            new_spot_price: 0,
+            new_delta: 0,
+            output_value: U256::from(0),
+            protocol_fee: U256::from(0),
+        };
+    }
+
+    let nfts_after = U256::from(nft_reserve) + U256::from(num_items);
+    let mut output_value = U256::from(num_items) * U256::from(near_reserve) / nfts_after;
+    let protocol_fee = output_value * U256::from(protocol_fee_multiplier) / WAD;
+    output_value -= mul_wad_up(output_value, U256::from(fee_multiplier));
+    output_value -= protocol_fee;
+
+    let new_spot_price = match checked_as_u128((U256::from(near_reserve) - output_value - protocol_fee) / nfts_after) {
+        Ok(new_spot_price) => new_spot_price,
+        Err(error_code) => return SellInfo::overflow(error_code),
+    };
+
+    SellInfo {
+        error_code: CurveErrorCode::Ok,
+        new_spot_price,
+        new_delta: delta,
+        output_value,
+        protocol_fee,
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::{get_buy_info, get_sell_info};
+    use crate::curves::{errorcodes::CurveErrorCode, U256};
+
+    const NEAR: u128 = 10u128.pow(24);
+
+    #[test]
+    fn test_buy_and_sell_keep_reserve_product() {
+        // 100 near against 10 nfts, 10 near spot price
+        let buy_info = get_buy_info(100 * NEAR, 10, 0, 1, 0, 0);
+        assert_eq!(buy_info.error_code, CurveErrorCode::Ok);
+        assert_eq!(buy_info.input_value, U256::from(100 * NEAR / 9 + 1));
+        let sell_info = get_sell_info(100 * NEAR, 10, 0, 1, 0, 0);
+        assert_eq!(sell_info.error_code, CurveErrorCode::Ok);
+        assert_eq!(sell_info.output_value, U256::from(100 * NEAR / 11));
+        assert_eq!(get_buy_info(100 * NEAR, 10, 0, 10, 0, 0).error_code, CurveErrorCode::InvalidNumItem);
+    }
+}
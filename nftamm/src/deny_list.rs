@@ -0,0 +1,97 @@
+use crate::*;
+use near_sdk::near_bindgen;
+
+// most token ids flagged or unflagged by one call
+const MAX_FLAGGED_TOKENS_PER_CALL: usize = 100;
+// most nfts a pool can hold when it starts denying flagged nfts, each of them being checked
+const MAX_DENY_FLAGGED_SCAN: u64 = 500;
+
+impl Contract {
+    pub(crate) fn is_token_flagged(&self, asset_id: &AssetId, token_id: &TokenId) -> bool {
+        self.flagged_tokens.contains(&(asset_id.clone(), token_id.clone()))
+    }
+
+    // panics if the pool denies flagged nfts and one of `token_ids` is
+    pub(crate) fn assert_not_flagged(&self, pool_id: u64, token_ids: &[TokenId]) {
+        let pool = &self.pools[pool_id as usize];
+        if !pool.deny_flagged {
+            return;
+        }
+        for token_id in token_ids {
+            require!(!self.is_token_flagged(&pool.nft_token, token_id), format!("token id {} flagged", token_id));
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Adds the tokens to or removes them from the registry of stolen or otherwise flagged nfts.
+    /// Pools denying flagged nfts holding them stop selling them to buyers of any nfts. The caller
+    /// pays the storage of the flags.
+    pub fn set_flagged_tokens(&mut self, asset_id: AssetId, token_ids: Vec<TokenId>, flagged: bool) {
+        self.assert_governance();
+        require!(token_ids.len() <= MAX_FLAGGED_TOKENS_PER_CALL, "too many token ids");
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        for token_id in &token_ids {
+            let key = (asset_id.clone(), token_id.clone());
+            if flagged {
+                self.flagged_tokens.insert(&key);
+            } else {
+                self.flagged_tokens.remove(&key);
+            }
+        }
+        for pool in self.pools.iter_mut().filter(|p| p.nft_token == asset_id && p.deny_flagged) {
+            for token_id in &token_ids {
+                pool.set_token_flagged(token_id, flagged);
+            }
+        }
+        if flagged {
+            self.assert_storage(&account_id, prev_storage, None);
+        } else {
+            self.internal_release_storage(&account_id, prev_storage);
+        }
+    }
+
+    pub fn is_flagged_token(&self, asset_id: AssetId, token_id: TokenId) -> bool {
+        self.is_token_flagged(&asset_id, &token_id)
+    }
+
+    /// Opts the pool into rejecting flagged nfts on deposits and sales, and keeping the flagged
+    /// nfts it holds out of buys of any nfts. The owner pays the storage of the flagged list, and
+    /// the pool cannot hold more than MAX_DENY_FLAGGED_SCAN nfts when enabling it.
+    pub fn set_pool_deny_flagged(&mut self, pool_id: u64, enabled: bool) {
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.assert_owner();
+        pool.assert_nft_pool();
+        let flagged_token_ids: Vec<TokenId> = if enabled {
+            require!(pool.token_ids_in_pools.len() <= MAX_DENY_FLAGGED_SCAN, "too many nfts in pool to check");
+            pool.token_ids_in_pools
+                .keys()
+                .filter(|token_id| self.is_token_flagged(&pool.nft_token, token_id))
+                .collect()
+        } else {
+            vec![]
+        };
+        let pool = &mut self.pools[pool_id as usize];
+        pool.deny_flagged = enabled;
+        pool.flagged_token_ids.clear();
+        pool.flagged_token_ids.extend(flagged_token_ids);
+        if enabled {
+            self.assert_storage(&account_id, prev_storage, None);
+        } else {
+            self.internal_release_storage(&account_id, prev_storage);
+        }
+    }
+
+    pub fn get_pool_flagged_tokens(&self, pool_id: u64, from_index: Option<u64>, limit: Option<u64>) -> Vec<TokenId> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.flagged_token_ids
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(100) as usize)
+            .collect()
+    }
+}
@@ -0,0 +1,117 @@
+use near_contract_standards::non_fungible_token::TokenId;
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::log;
+
+use crate::pair::Pair;
+
+pub(crate) const EVENT_STANDARD: &str = "nearft";
+const EVENT_VERSION: &str = "1.0.0";
+
+// NEP-297 event log
+pub(crate) fn emit_event<T: Serialize>(event: &str, data: T) {
+    log!(
+        "EVENT_JSON:{}",
+        json!({
+            "standard": EVENT_STANDARD,
+            "version": EVENT_VERSION,
+            "event": event,
+            "data": [data],
+        })
+    );
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CurveState {
+    pub spot_price: U128,
+    pub delta: U128,
+    pub near_balance: U128,
+    pub num_nfts: u64,
+}
+
+impl CurveState {
+    pub fn of(pair: &Pair) -> CurveState {
+        CurveState {
+            spot_price: pair.spot_price.into(),
+            delta: pair.delta.into(),
+            near_balance: pair.near_balance.into(),
+            num_nfts: pair.num_items(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapEvent<'a> {
+    pub pool_id: u64,
+    pub trade_seq: u64,
+    pub account_id: &'a near_sdk::AccountId,
+    pub swap_type: u8,
+    pub token_ids: &'a [TokenId],
+    pub near_amount: U128,
+    pub protocol_fee: U128,
+    pub pre_state: CurveState,
+    pub post_state: CurveState,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AddLiquidityEvent<'a> {
+    pub pool_id: u64,
+    pub account_id: &'a near_sdk::AccountId,
+    pub token_ids: &'a [TokenId],
+    pub near_amount: U128,
+    pub lp_amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DonationEvent<'a> {
+    pub pool_id: u64,
+    pub account_id: &'a near_sdk::AccountId,
+    pub near_amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawNearEvent<'a> {
+    pub pool_id: u64,
+    pub account_id: &'a near_sdk::AccountId,
+    pub near_amount: U128,
+    // credited to the account deposit instead of transferred
+    pub to_deposit: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LpTransferEntry<'a> {
+    pub pool_id: u64,
+    pub receiver_id: &'a near_sdk::AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LpBatchTransferEvent<'a> {
+    pub sender_id: &'a near_sdk::AccountId,
+    pub transfers: Vec<LpTransferEntry<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ForceReleaseEvent {
+    pub pool_id: u64,
+    pub released_time: u64,
+    // none once cancelled
+    pub force_release_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnerTradeFeeEvent<'a> {
+    pub pool_id: u64,
+    pub account_id: &'a near_sdk::AccountId,
+    pub protocol_fee_multiplier: U128,
+}
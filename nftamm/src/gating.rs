@@ -0,0 +1,134 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{ext_contract, near_bindgen, serde_json, PromiseResult};
+
+const GAS_FOR_GATE_VIEW: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_GATE_RESOLVE: Gas = Gas(10_000_000_000_000);
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum GateKind {
+    // balance read with nft_supply_for_owner (NEP-181)
+    NonFungibleToken,
+    // balance read with ft_balance_of (NEP-141)
+    FungibleToken,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolGate {
+    pub kind: GateKind,
+    pub contract_id: AccountId,
+    pub min_amount: U128,
+    // how long a successful balance check lets the account trade
+    pub attestation_ttl_sec: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct GateAttestation {
+    // gate version of the pool when attested, changing the gate invalidates attestations
+    pub gate_nonce: u64,
+    pub expires_at: u64,
+}
+
+#[ext_contract(ext_gate_token)]
+pub trait GateToken {
+    fn nft_supply_for_owner(&self, account_id: AccountId) -> U128;
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+}
+
+#[ext_contract(ext_gate_resolver)]
+pub trait GateResolver {
+    fn gate_attestation_resolve(&mut self, pool_id: u64, account_id: AccountId, gate_nonce: u64, operation_id: u64) -> bool;
+}
+
+impl Contract {
+    pub(crate) fn assert_pool_access(&self, pool_id: u64, account_id: &AccountId) {
+        require!(
+            self.has_pool_access(pool_id, account_id.clone()),
+            format!("access to pool {} not attested", pool_id)
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets or removes the holding requirement to trade against the pool. Existing attestations
+    /// are invalidated.
+    pub fn set_pool_gate(&mut self, pool_id: u64, gate: Option<PoolGate>) {
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        pool.gate = gate;
+        pool.gate_nonce += 1;
+    }
+
+    /// Checks that the caller holds enough of the pool gate token and, if so, lets them trade
+    /// against the pool for the gate attestation ttl.
+    pub fn attest_pool_access(&mut self, pool_id: u64) -> Promise {
+        let account_id = env::predecessor_account_id();
+        self.internal_get_account_or_revert(&account_id);
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let gate = pool.gate.clone().unwrap_or_else(|| env::panic_str("pool is not gated"));
+        let gate_nonce = pool.gate_nonce;
+        let operation_id = self.internal_start_operation(OperationKind::GateAttestation, &account_id, 0);
+        let balance_view = ext_gate_token::ext(gate.contract_id).with_static_gas(GAS_FOR_GATE_VIEW);
+        let balance_view = match gate.kind {
+            GateKind::NonFungibleToken => balance_view.nft_supply_for_owner(account_id.clone()),
+            GateKind::FungibleToken => balance_view.ft_balance_of(account_id.clone()),
+        };
+        balance_view.then(
+            ext_gate_resolver::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_GATE_RESOLVE)
+                .gate_attestation_resolve(pool_id, account_id, gate_nonce, operation_id),
+        )
+    }
+
+    #[private]
+    pub fn gate_attestation_resolve(&mut self, pool_id: u64, account_id: AccountId, gate_nonce: u64, operation_id: u64) -> bool {
+        self.internal_finish_operation(operation_id, OperationKind::GateAttestation, &account_id, 0);
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value).map(|b| b.0).unwrap_or(0),
+            _ => 0,
+        };
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let gate = match &pool.gate {
+            Some(gate) if pool.gate_nonce == gate_nonce => gate.clone(),
+            // the gate changed while the balance was read
+            _ => return false,
+        };
+        if balance < gate.min_amount.0 {
+            return false;
+        }
+
+        let prev_storage = env::storage_usage();
+        self.gate_attestations.insert(
+            &(pool_id, account_id.clone()),
+            &GateAttestation {
+                gate_nonce,
+                expires_at: env::block_timestamp_ms() / 1000 + gate.attestation_ttl_sec,
+            },
+        );
+        self.assert_storage(&account_id, prev_storage, None);
+        true
+    }
+
+    pub fn get_pool_gate(&self, pool_id: u64) -> Option<PoolGate> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.gate.clone()
+    }
+
+    /// The pool owner always has access to its pool.
+    pub fn has_pool_access(&self, pool_id: u64, account_id: AccountId) -> bool {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        if pool.gate.is_none() || pool.owner == account_id {
+            return true;
+        }
+        match self.gate_attestations.get(&(pool_id, account_id)) {
+            Some(attestation) => {
+                attestation.gate_nonce == pool.gate_nonce
+                    && attestation.expires_at > env::block_timestamp_ms() / 1000
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::convert::TryFrom;
+
+use crate::*;
+use crate::events::emit_event;
+use crate::signed_quote::verify_signature;
+use crate::swap::{Action, SwapResult};
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, PublicKey};
+
+// Swap signed by `signer_id` over the borsh serialization of the intent and submitted by any
+// relayer paying the gas, the trade being funded by the signer deposit.
+#[derive(BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignedIntent {
+    pub signer_id: AccountId,
+    // this contract, so the intent cannot be replayed on another deployment
+    pub contract_id: AccountId,
+    pub actions: Vec<Action>,
+    pub buy_to_escrow: Option<bool>,
+    // in seconds
+    pub expires_at: u64,
+    // above the last nonce executed for the signer
+    pub nonce: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignedIntentEvent<'a> {
+    pub signer_id: &'a AccountId,
+    pub relayer_id: &'a AccountId,
+    pub nonce: u64,
+}
+
+// key of an implicit account, whose id is the hex of its ed25519 public key
+fn implicit_account_key(account_id: &AccountId) -> Option<PublicKey> {
+    let hex = account_id.as_str();
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut bytes = vec![0u8];
+    for i in (0..hex.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&hex[i..i + 2], 16).ok()?);
+    }
+    PublicKey::try_from(bytes).ok()
+}
+
+impl Contract {
+    // keys signing the intents of the account: the one it registered and, for implicit
+    // accounts, the one of its id
+    fn intent_signers(&self, account_id: &AccountId) -> Vec<PublicKey> {
+        self.intent_keys.get(account_id).into_iter().chain(implicit_account_key(account_id)).collect()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the key signing the intents of the caller, none removes it. Implicit accounts can
+    /// sign with the key of their id without setting one.
+    pub fn set_intent_key(&mut self, public_key: Option<PublicKey>) {
+        let account_id = env::predecessor_account_id();
+        self.internal_get_account_or_revert(&account_id);
+        let prev_storage = env::storage_usage();
+        match public_key {
+            Some(public_key) => self.intent_keys.insert(&account_id, &public_key),
+            None => self.intent_keys.remove(&account_id),
+        };
+        if env::storage_usage() > prev_storage {
+            self.assert_storage(&account_id, prev_storage, None);
+        } else {
+            self.internal_release_storage(&account_id, prev_storage);
+        }
+    }
+
+    pub fn get_intent_key(&self, account_id: AccountId) -> Option<PublicKey> {
+        self.intent_keys.get(&account_id)
+    }
+
+    // 0 before the first intent
+    pub fn get_intent_nonce(&self, account_id: AccountId) -> u64 {
+        self.intent_nonces.get(&account_id).unwrap_or(0)
+    }
+
+    /// Executes the swap of a signed intent for its signer, buys paid from and near left
+    /// credited to the signer deposit. The caller only pays the gas.
+    pub fn execute_signed_intent(&mut self, intent: SignedIntent, signature: Base64VecU8) -> SwapResult {
+        require!(intent.contract_id == env::current_account_id(), "intent for another contract");
+        require!(intent.expires_at > env::block_timestamp_ms() / 1000, "intent expired");
+        require!(intent.nonce > self.get_intent_nonce(intent.signer_id.clone()), "intent nonce already used");
+
+        let message = intent.try_to_vec().unwrap();
+        let signed = self
+            .intent_signers(&intent.signer_id)
+            .iter()
+            .any(|public_key| verify_signature(public_key, &message, &signature.0));
+        require!(signed, "invalid intent signature");
+
+        let prev_storage = env::storage_usage();
+        self.intent_nonces.insert(&intent.signer_id, &intent.nonce);
+        self.assert_storage(&intent.signer_id, prev_storage, None);
+
+        let relayer_id = env::predecessor_account_id();
+        emit_event("signed_intent", SignedIntentEvent {
+            signer_id: &intent.signer_id,
+            relayer_id: &relayer_id,
+            nonce: intent.nonce,
+        });
+        self.internal_swap(&intent.signer_id, &intent.actions, intent.buy_to_escrow.unwrap_or(false), 0, true)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use near_sdk::serde_json::{self, json};
+
+    // nft pool of nfts 1 and 2 and a signer with near in its deposit and the test key set
+    fn setup_intent() -> (Contract, u64, AccountId) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        let signer = account("signer.near");
+        register(&mut contract, &owner, NEAR);
+        register(&mut contract, &signer, 5 * NEAR);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::NFT, &["1", "2"], 0);
+        set_caller(&signer, 0);
+        contract.set_intent_key(Some(signer_public_key()));
+        (contract, pool_id, signer)
+    }
+
+    // buy of `token_id` into the signer escrow paid from its deposit
+    fn intent(pool_id: u64, token_id: &str, nonce: u64) -> SignedIntent {
+        let action: Action = serde_json::from_value(json!({
+            "pool_id": pool_id,
+            "swap_type": 1,
+            "input_token_ids": [],
+            "output_token_ids": [token_id],
+            "num_out_nfts": 1,
+            "max_deposit_near": U128(2 * NEAR),
+        }))
+        .unwrap();
+        SignedIntent {
+            signer_id: account("signer.near"),
+            contract_id: contract_id(),
+            actions: vec![action],
+            buy_to_escrow: Some(true),
+            expires_at: env::block_timestamp_ms() / 1000 + 60,
+            nonce,
+        }
+    }
+
+    #[test]
+    fn test_execute_signed_intent() {
+        let (mut contract, pool_id, signer) = setup_intent();
+        let available_near = contract.internal_available_near(&signer);
+
+        set_caller(&account("relayer.near"), 0);
+        let signature = sign(&intent(pool_id, "1", 1));
+        let result = contract.execute_signed_intent(intent(pool_id, "1", 1), signature);
+        assert_eq!(result.actions[0].token_ids, token_ids(&["1"]));
+        assert_eq!(contract.get_intent_nonce(signer.clone()), 1);
+        let bought = contract.account_deposits.get(&signer).unwrap().assets.get(&nft()).unwrap();
+        assert!(bought.get(&"1".to_string()).is_some());
+        assert!(contract.internal_available_near(&signer) < available_near);
+        assert!(contract.account_deposits.get(&account("relayer.near")).is_none());
+
+        // a later nonce executes, skipped nonces being allowed
+        let signature = sign(&intent(pool_id, "2", 5));
+        contract.execute_signed_intent(intent(pool_id, "2", 5), signature);
+        assert_eq!(contract.get_intent_nonce(signer), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "intent nonce already used")]
+    fn test_replayed_signed_intent() {
+        let (mut contract, pool_id, _) = setup_intent();
+        set_caller(&account("relayer.near"), 0);
+        let signature = sign(&intent(pool_id, "1", 1));
+        contract.execute_signed_intent(intent(pool_id, "1", 1), signature.clone());
+        contract.execute_signed_intent(intent(pool_id, "1", 1), signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "intent for another contract")]
+    fn test_signed_intent_for_another_contract() {
+        let (mut contract, pool_id, _) = setup_intent();
+        let mut intent = intent(pool_id, "1", 1);
+        intent.contract_id = account("other-amm.near");
+        let signature = sign(&intent);
+        set_caller(&account("relayer.near"), 0);
+        contract.execute_signed_intent(intent, signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "intent expired")]
+    fn test_expired_signed_intent() {
+        let (mut contract, pool_id, _) = setup_intent();
+        let signature = sign(&intent(pool_id, "1", 1));
+        let intent = intent(pool_id, "1", 1);
+        advance_time_sec(60);
+        set_caller(&account("relayer.near"), 0);
+        contract.execute_signed_intent(intent, signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid intent signature")]
+    fn test_tampered_signed_intent() {
+        let (mut contract, pool_id, _) = setup_intent();
+        let signature = sign(&intent(pool_id, "1", 1));
+        set_caller(&account("relayer.near"), 0);
+        contract.execute_signed_intent(intent(pool_id, "2", 1), signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid intent signature")]
+    fn test_signed_intent_of_removed_key() {
+        let (mut contract, pool_id, signer) = setup_intent();
+        set_caller(&signer, 0);
+        contract.set_intent_key(None);
+        let signature = sign(&intent(pool_id, "1", 1));
+        set_caller(&account("relayer.near"), 0);
+        contract.execute_signed_intent(intent(pool_id, "1", 1), signature);
+    }
+
+    #[test]
+    fn test_implicit_account_key() {
+        let account_id: AccountId = "ab".repeat(32).parse().unwrap();
+        let public_key = implicit_account_key(&account_id).unwrap();
+        assert_eq!(public_key.as_bytes()[0], 0);
+        assert_eq!(&public_key.as_bytes()[1..], &[0xab; 32]);
+        assert!(implicit_account_key(&"alice.near".parse().unwrap()).is_none());
+    }
+}
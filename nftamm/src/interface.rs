@@ -0,0 +1,354 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+const SOURCE_LINK: &str = "https://github.com/DegaLabs/nearft-hackathon-contracts";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum MethodKind {
+    View,
+    Call,
+}
+
+// (standard, version) implemented by the contract
+const STANDARDS: [(&str, &str); 5] = [
+    ("nep330", "1.1.0"),
+    ("nep171", "1.0.0"),
+    ("nep145", "1.0.0"),
+    ("nep245", "1.0.0"),
+    ("nep297", "1.0.0"),
+];
+
+// public methods, resolvers and the initializer excepted. Keep in sync with the near_bindgen impls.
+const METHODS: &[(&str, MethodKind, bool)] = &[
+    ("acquire_via_approval", MethodKind::Call, false),
+    ("add_liquidity", MethodKind::Call, true),
+    ("add_liquidity_batch", MethodKind::Call, true),
+    ("apply_pool_param_change", MethodKind::Call, false),
+    ("apply_pool_sides", MethodKind::Call, false),
+    ("approve_official_pool", MethodKind::Call, true),
+    ("attest_pool_access", MethodKind::Call, false),
+    ("buy_out_pool", MethodKind::Call, true),
+    ("cancel_council_action", MethodKind::Call, false),
+    ("cancel_force_release", MethodKind::Call, false),
+    ("cancel_pool_param_change", MethodKind::Call, false),
+    ("cancel_pool_param_change_proposal", MethodKind::Call, false),
+    ("cancel_treasury_operation", MethodKind::Call, false),
+    ("change_pool_params", MethodKind::Call, false),
+    ("claim_failed_transfer", MethodKind::Call, false),
+    ("claim_nfts", MethodKind::Call, true),
+    ("claim_proceeds", MethodKind::Call, true),
+    ("claim_protocol_fees", MethodKind::Call, true),
+    ("claim_referral_fees", MethodKind::Call, true),
+    ("collect_dust", MethodKind::Call, false),
+    ("confirm_council_action", MethodKind::Call, false),
+    ("contract_source_metadata", MethodKind::View, false),
+    ("create_mt_pair", MethodKind::Call, true),
+    ("create_pair", MethodKind::Call, true),
+    ("create_pair_with_args", MethodKind::Call, true),
+    ("deposit_nfts_to_pool", MethodKind::Call, false),
+    ("donate_to_pool", MethodKind::Call, true),
+    ("early_unlock_pool", MethodKind::Call, true),
+    ("estimate_create_pair_cost", MethodKind::View, false),
+    ("estimate_swap_gas", MethodKind::View, false),
+    ("execute_pool_param_change", MethodKind::Call, false),
+    ("execute_signed_intent", MethodKind::Call, false),
+    ("execute_signed_quote", MethodKind::Call, true),
+    ("execute_treasury_operation", MethodKind::Call, false),
+    ("extend_pool_lock", MethodKind::Call, false),
+    ("force_release", MethodKind::Call, false),
+    ("fulfill_listing", MethodKind::Call, false),
+    ("gc_my_deposits", MethodKind::Call, false),
+    ("get_abi", MethodKind::View, false),
+    ("get_account_activity", MethodKind::View, false),
+    ("get_account_count", MethodKind::View, false),
+    ("get_active_pools", MethodKind::View, false),
+    ("get_all_held_ids", MethodKind::View, false),
+    ("get_approval_ask", MethodKind::View, false),
+    ("get_bootstrap_auction", MethodKind::View, false),
+    ("get_buy_info", MethodKind::View, false),
+    ("get_buy_nft_quote", MethodKind::View, false),
+    ("get_buy_nft_quote_v2", MethodKind::View, false),
+    ("get_circuit_breaker", MethodKind::View, false),
+    ("get_claimable_protocol_fees", MethodKind::View, false),
+    ("get_collection_official_account", MethodKind::View, false),
+    ("get_collection_protocol_fee", MethodKind::View, false),
+    ("get_collections", MethodKind::View, false),
+    ("get_compliance", MethodKind::View, false),
+    ("get_council", MethodKind::View, false),
+    ("get_council_proposal", MethodKind::View, false),
+    ("get_council_proposals", MethodKind::View, false),
+    ("get_deposits", MethodKind::View, false),
+    ("get_deposits_page", MethodKind::View, false),
+    ("get_failed_transfer_count", MethodKind::View, false),
+    ("get_failed_transfers", MethodKind::View, false),
+    ("get_fee_tier_pools", MethodKind::View, false),
+    ("get_fee_tiers", MethodKind::View, false),
+    ("get_force_release_at", MethodKind::View, false),
+    ("get_intent_key", MethodKind::View, false),
+    ("get_intent_nonce", MethodKind::View, false),
+    ("get_lp_holders", MethodKind::View, false),
+    ("get_lp_index", MethodKind::View, false),
+    ("get_lp_pnl", MethodKind::View, false),
+    ("get_marketplace_adapters", MethodKind::View, false),
+    ("get_max_buyable", MethodKind::View, false),
+    ("get_max_protocol_fee_multiplier", MethodKind::View, false),
+    ("get_max_swap_actions", MethodKind::View, false),
+    ("get_metadata", MethodKind::View, false),
+    ("get_mt_deposit", MethodKind::View, false),
+    ("get_nft_asset_id", MethodKind::View, false),
+    ("get_num_active_pools", MethodKind::View, false),
+    ("get_num_collections", MethodKind::View, false),
+    ("get_num_pending_operations", MethodKind::View, false),
+    ("get_param_change_delay", MethodKind::View, false),
+    ("get_pending_operation", MethodKind::View, false),
+    ("get_pending_operations", MethodKind::View, false),
+    ("get_pending_param_change", MethodKind::View, false),
+    ("get_pending_param_changes", MethodKind::View, false),
+    ("get_pending_pool_sides", MethodKind::View, false),
+    ("get_pool_circuit_breaker", MethodKind::View, false),
+    ("get_pool_count", MethodKind::View, false),
+    ("get_pool_dust", MethodKind::View, false),
+    ("get_pool_fee_share", MethodKind::View, false),
+    ("get_pool_flagged_tokens", MethodKind::View, false),
+    ("get_pool_gate", MethodKind::View, false),
+    ("get_pool_health", MethodKind::View, false),
+    ("get_pool_history", MethodKind::View, false),
+    ("get_pool_history_days", MethodKind::View, false),
+    ("get_pool_info", MethodKind::View, false),
+    ("get_pool_oracle", MethodKind::View, false),
+    ("get_pool_param_proposal", MethodKind::View, false),
+    ("get_pool_proceeds", MethodKind::View, false),
+    ("get_pool_protocol_fee_multiplier", MethodKind::View, false),
+    ("get_pool_quote_signer", MethodKind::View, false),
+    ("get_pool_token_ids", MethodKind::View, false),
+    ("get_pool_tokens_with_metadata", MethodKind::View, false),
+    ("get_pool_trade_hook", MethodKind::View, false),
+    ("get_pool_tvl", MethodKind::View, false),
+    ("get_pools", MethodKind::View, false),
+    ("get_pools_infos", MethodKind::View, false),
+    ("get_price_ladder", MethodKind::View, false),
+    ("get_protocol_fee_multiplier", MethodKind::View, false),
+    ("get_protocol_owned_liquidity", MethodKind::View, false),
+    ("get_recently_traded_pools", MethodKind::View, false),
+    ("get_referral", MethodKind::View, false),
+    ("get_referral_fee_share", MethodKind::View, false),
+    ("get_referrals", MethodKind::View, false),
+    ("get_registered_accounts", MethodKind::View, false),
+    ("get_reservation", MethodKind::View, false),
+    ("get_reservation_bond", MethodKind::View, false),
+    ("get_reservations", MethodKind::View, false),
+    ("get_route_quote", MethodKind::View, false),
+    ("get_royalty_override", MethodKind::View, false),
+    ("get_sell_info", MethodKind::View, false),
+    ("get_sell_nft_quote", MethodKind::View, false),
+    ("get_sell_nft_quote_v2", MethodKind::View, false),
+    ("get_token_location", MethodKind::View, false),
+    ("get_token_metadata", MethodKind::View, false),
+    ("get_token_min_price", MethodKind::View, false),
+    ("get_token_priority", MethodKind::View, false),
+    ("get_transferable_lp", MethodKind::View, false),
+    ("get_treasury_delay", MethodKind::View, false),
+    ("get_treasury_operations", MethodKind::View, false),
+    ("get_tvl", MethodKind::View, false),
+    ("get_untracked_near", MethodKind::View, false),
+    ("get_verified_collection", MethodKind::View, false),
+    ("get_verified_collections", MethodKind::View, false),
+    ("get_virtual_reserves", MethodKind::View, false),
+    ("has_pool_access", MethodKind::View, false),
+    ("has_token", MethodKind::View, false),
+    ("in_any_pool", MethodKind::View, false),
+    ("index_active_pools", MethodKind::Call, false),
+    ("index_deposit_tokens", MethodKind::Call, false),
+    ("index_pool_tokens", MethodKind::Call, false),
+    ("is_batch_transfer_collection", MethodKind::View, false),
+    ("is_collection_verified", MethodKind::View, false),
+    ("is_compliance_approved", MethodKind::View, false),
+    ("is_flagged_token", MethodKind::View, false),
+    ("is_oracle_approved", MethodKind::View, false),
+    ("is_quote_used", MethodKind::View, false),
+    ("is_reservation_facilitator", MethodKind::View, false),
+    ("lp_balance_of", MethodKind::View, false),
+    ("lp_is_registered", MethodKind::View, false),
+    ("lp_merge", MethodKind::Call, true),
+    ("lp_metadata", MethodKind::View, false),
+    ("lp_register", MethodKind::Call, true),
+    ("lp_register_batch", MethodKind::Call, true),
+    ("lp_split", MethodKind::Call, true),
+    ("lp_total_supply", MethodKind::View, false),
+    ("lp_transfer", MethodKind::Call, true),
+    ("lp_transfer_call", MethodKind::Call, true),
+    ("migrate_liquidity", MethodKind::Call, true),
+    ("migrate_storage_keys", MethodKind::Call, false),
+    ("mt_on_transfer", MethodKind::Call, false),
+    ("nft_on_approve", MethodKind::Call, false),
+    ("nft_on_transfer", MethodKind::Call, false),
+    ("nft_supply_for_pool", MethodKind::View, false),
+    ("nft_tokens_for_pool", MethodKind::View, false),
+    ("place_bootstrap_bid", MethodKind::Call, true),
+    ("preview_remove_liquidity", MethodKind::View, false),
+    ("propose_council_action", MethodKind::Call, false),
+    ("propose_pool_param_change", MethodKind::Call, false),
+    ("prune_pending_operations", MethodKind::Call, false),
+    ("pull_deposits", MethodKind::Call, false),
+    ("queue_force_release", MethodKind::Call, false),
+    ("queue_treasury_operation", MethodKind::Call, false),
+    ("reconcile_token_location", MethodKind::View, false),
+    ("refresh_token_metadata", MethodKind::Call, false),
+    ("register_referral_code", MethodKind::Call, false),
+    ("release_reservation", MethodKind::Call, false),
+    ("remove_liquidity", MethodKind::Call, true),
+    ("remove_liquidity_with_tokens", MethodKind::Call, true),
+    ("request_compliance_approval", MethodKind::Call, false),
+    ("request_official_pool", MethodKind::Call, false),
+    ("reserve_tokens", MethodKind::Call, true),
+    ("retry_failed_transfers", MethodKind::Call, false),
+    ("revoke_official_pool", MethodKind::Call, true),
+    ("sell_tokens", MethodKind::Call, false),
+    ("set_approval_buy_cap", MethodKind::Call, false),
+    ("set_batch_transfer_collection", MethodKind::Call, false),
+    ("set_circuit_breaker", MethodKind::Call, false),
+    ("set_collection_official_account", MethodKind::Call, false),
+    ("set_collection_protocol_fee", MethodKind::Call, false),
+    ("set_compliance", MethodKind::Call, false),
+    ("set_council", MethodKind::Call, false),
+    ("set_early_unlock_penalty", MethodKind::Call, false),
+    ("set_fee_tiers", MethodKind::Call, false),
+    ("set_flagged_tokens", MethodKind::Call, false),
+    ("set_governance_quotes", MethodKind::Call, false),
+    ("set_intent_key", MethodKind::Call, false),
+    ("set_marketplace_adapter", MethodKind::Call, false),
+    ("set_marketplace_fulfillment", MethodKind::Call, false),
+    ("set_max_swap_actions", MethodKind::Call, false),
+    ("set_only_verified_collections", MethodKind::Call, false),
+    ("set_oracle_approved", MethodKind::Call, false),
+    ("set_owner_trade_protocol_fee", MethodKind::Call, false),
+    ("set_param_change_delay", MethodKind::Call, false),
+    ("set_pool_caps", MethodKind::Call, false),
+    ("set_pool_circuit_breaker", MethodKind::Call, false),
+    ("set_pool_deny_flagged", MethodKind::Call, false),
+    ("set_pool_fee_share", MethodKind::Call, false),
+    ("set_pool_gate", MethodKind::Call, false),
+    ("set_pool_history_days", MethodKind::Call, false),
+    ("set_pool_oracle", MethodKind::Call, false),
+    ("set_pool_quote_signer", MethodKind::Call, false),
+    ("set_pool_sides", MethodKind::Call, false),
+    ("set_pool_trade_hook", MethodKind::Call, false),
+    ("set_pool_type_protocol_fee", MethodKind::Call, false),
+    ("set_protocol_fee_multiplier", MethodKind::Call, false),
+    ("set_protocol_fee_receiver", MethodKind::Call, false),
+    ("set_quote_signer", MethodKind::Call, false),
+    ("set_referral_fee_share", MethodKind::Call, false),
+    ("set_referral_payout", MethodKind::Call, false),
+    ("set_reservation_bond", MethodKind::Call, false),
+    ("set_reservation_facilitator", MethodKind::Call, false),
+    ("set_royalty_override", MethodKind::Call, false),
+    ("set_token_min_price", MethodKind::Call, false),
+    ("set_token_priority", MethodKind::Call, false),
+    ("set_treasury_delay", MethodKind::Call, false),
+    ("set_virtual_reserves", MethodKind::Call, false),
+    ("settle_bootstrap_auction", MethodKind::Call, false),
+    ("settle_reservation", MethodKind::Call, true),
+    ("skim", MethodKind::Call, false),
+    ("start_bootstrap_auction", MethodKind::Call, false),
+    ("storage_available", MethodKind::View, false),
+    ("storage_balance_bounds", MethodKind::View, false),
+    ("storage_balance_bounds_for_account", MethodKind::View, false),
+    ("storage_balance_of", MethodKind::View, false),
+    ("storage_cost_per_nft", MethodKind::View, false),
+    ("storage_deposit", MethodKind::Call, true),
+    ("storage_unregister", MethodKind::Call, false),
+    ("storage_withdraw", MethodKind::Call, true),
+    ("swap", MethodKind::Call, true),
+    ("swap_mt_for_near", MethodKind::Call, true),
+    ("swap_near_for_mt", MethodKind::Call, true),
+    ("sync_pool", MethodKind::Call, false),
+    ("unverify_collection", MethodKind::Call, false),
+    ("update_oracle_price", MethodKind::Call, false),
+    ("validate_pool_params", MethodKind::View, false),
+    ("verify_collection", MethodKind::Call, false),
+    ("vote_pool_param_change", MethodKind::Call, false),
+    ("withdraw_mt", MethodKind::Call, true),
+    ("withdraw_mt_from_deposit", MethodKind::Call, true),
+    ("withdraw_near", MethodKind::Call, true),
+    ("withdraw_nfts", MethodKind::Call, true),
+    ("withdraw_nfts_from_deposit", MethodKind::Call, true),
+];
+
+/// NEP-330 source metadata.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractSourceMetadata {
+    pub version: Option<String>,
+    pub link: Option<String>,
+    pub standards: Vec<Standard>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Standard {
+    pub standard: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MethodAbi {
+    pub name: String,
+    pub kind: MethodKind,
+    pub payable: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractAbi {
+    pub name: String,
+    pub version: String,
+    pub standards: Vec<Standard>,
+    // lp tokens follow the multi fungible token interface of the lp_ methods
+    pub lp_token_spec: String,
+    pub event_standard: String,
+    pub methods: Vec<MethodAbi>,
+}
+
+// whether the method can be called without attached deposit, as council calls are
+pub(crate) fn is_call_method(method_name: &str) -> bool {
+    METHODS
+        .iter()
+        .any(|(name, kind, payable)| *name == method_name && *kind == MethodKind::Call && !payable)
+}
+
+fn standards() -> Vec<Standard> {
+    STANDARDS
+        .iter()
+        .map(|(standard, version)| Standard { standard: standard.to_string(), version: version.to_string() })
+        .collect()
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn contract_source_metadata(&self) -> ContractSourceMetadata {
+        ContractSourceMetadata {
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            link: Some(SOURCE_LINK.to_string()),
+            standards: standards(),
+        }
+    }
+
+    /// Methods, standards and version of the deployed contract, for tooling and explorers.
+    pub fn get_abi(&self) -> ContractAbi {
+        ContractAbi {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            standards: standards(),
+            lp_token_spec: self.lp_metadata(0).spec,
+            event_standard: events::EVENT_STANDARD.to_string(),
+            methods: METHODS
+                .iter()
+                .map(|(name, kind, payable)| MethodAbi { name: name.to_string(), kind: *kind, payable: *payable })
+                .collect(),
+        }
+    }
+}
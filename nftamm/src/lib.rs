@@ -19,21 +19,54 @@ use account_deposit::AccountDeposit;
 use curves::curve::BondingCurve;
 use near_contract_standards::non_fungible_token::TokenId;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet};
 use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     env, log, near_bindgen, require, AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault,
-    Promise, StorageUsage, assert_one_yocto,
+    Promise, PublicKey, StorageUsage, assert_one_yocto, CryptoHash,
 };
 use pair::{Pair, PoolType};
+use verification::VerifiedCollection;
+use gating::GateAttestation;
+use royalty::RoyaltyOverride;
+use metadata_cache::CachedTokenMetadata;
+use compliance::{ComplianceApproval, ComplianceConfig};
+use pending_operations::{OperationKind, PendingOperation};
+use circuit_breaker::CircuitBreakerConfig;
+use collections::CollectionStats;
+use treasury::TreasuryOperation;
+use activity::{AccountActivity, ActivityKind};
+use marketplace::MarketplaceAdapter;
+use reservation::Reservation;
+use pool_governance::PoolParamProposal;
+use council::{Council, CouncilProposal};
+use pool_history::DailyCheckpoint;
+use protocol_fees::PoolFeeShare;
+use referrals::Referral;
+use transfer_retry::FailedTransfer;
+use token_locations::TokenLocation;
+use lp_pnl::LpCostBasis;
 
 use crate::curves::WAD;
-use crate::pair::MAX_FEE;
+use crate::events::{emit_event, AddLiquidityEvent, DonationEvent, ForceReleaseEvent, OwnerTradeFeeEvent, WithdrawNearEvent};
+use crate::pair::{MAX_FEE, MAX_PROTOCOL_FEE};
 const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(5_000_000_000_000);
 const GAS_FOR_NFT_TRANSFER_CALL: Gas = Gas(25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER.0);
+// upper bound of the gas used to add liquidity to one pool of a batch
+const GAS_PER_ADD_LIQUIDITY: Gas = Gas(10_000_000_000_000);
+// number of pools kept in recently_traded_pools
+const RECENTLY_TRADED_POOLS_KEPT: usize = 50;
+// trade fees of 0.5%, 1% and 3%
+const DEFAULT_FEE_TIERS: [Balance; 3] = [5 * 10u128.pow(15), 10u128.pow(16), 3 * 10u128.pow(16)];
+// latest CreatePairArgs layout
+const CREATE_PAIR_ARGS_VERSION: u8 = 1;
+// longest lock a pool can be created with, 5 years
+const MAX_CREATE_LOCK_SEC: u64 = 5 * 365 * 24 * 3600;
 
 mod account_deposit;
 pub mod curves;
+mod events;
 mod nft_core;
 pub mod pair;
 mod receiver;
@@ -42,6 +75,50 @@ mod swap;
 mod utils;
 pub mod view;
 mod multi_lp;
+pub mod verification;
+mod migration;
+mod multi_token;
+pub mod gating;
+pub mod signed_quote;
+pub mod royalty;
+pub mod param_change;
+pub mod metadata_cache;
+pub mod compliance;
+mod pricing;
+pub mod protocol_fees;
+pub mod oracle;
+pub mod pending_operations;
+pub mod circuit_breaker;
+pub mod virtual_reserves;
+pub mod collections;
+pub mod pool_tokens;
+pub mod treasury;
+pub mod activity;
+pub mod marketplace;
+pub mod batch_deposit;
+pub mod trade_hook;
+pub mod reservation;
+pub mod deny_list;
+pub mod pool_governance;
+pub mod council;
+pub mod maintenance;
+pub mod pool_history;
+pub mod lp_restrictions;
+pub mod official_pools;
+pub mod intents;
+pub mod pool_sides;
+pub mod referrals;
+pub mod bootstrap_auction;
+pub mod transfer_retry;
+pub mod token_locations;
+pub mod pagination;
+pub mod approval_acquisition;
+pub mod lp_pnl;
+pub mod interface;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod testing;
+#[cfg(feature = "sim")]
+pub mod sim;
 
 pub type AssetId = AccountId;
 
@@ -58,25 +135,246 @@ pub struct Contract {
     pub storage_per_nft_deposit: StorageUsage,
     pub storage_per_pair_creation: StorageUsage,
     pub created_pool_ids: UnorderedMap<AccountId, Vec<u64>>,
+    // share of the pool near balance (base 1e18) charged for unlocking before released_time,
+    // early unlock is disabled if none
+    pub early_unlock_penalty: Option<Balance>,
+    pub verified_collections: UnorderedMap<AssetId, VerifiedCollection>,
+    pub only_verified_collections: bool,
+    // NEP-245 amounts deposited by (account, mt contract, token id)
+    pub mt_deposits: LookupMap<(AccountId, AssetId, TokenId), Balance>,
+    // protocol fees overriding protocol_fee_multiplier, indexed by pool type
+    pub pool_type_protocol_fees: [Option<Balance>; 3],
+    // protocol fees overriding both of the above for pools of a collection
+    pub collection_protocol_fees: UnorderedMap<AssetId, Balance>,
+    pub gate_attestations: LookupMap<(u64, AccountId), GateAttestation>,
+    // governance key whose signed quotes are redeemable against any pool
+    pub quote_signer: Option<PublicKey>,
+    // hashes of redeemed signed quotes
+    pub used_quotes: LookupSet<CryptoHash>,
+    pub royalty_overrides: UnorderedMap<AssetId, RoyaltyOverride>,
+    // title and media of deposited tokens, kept after they leave the contract
+    pub token_metadata: LookupMap<(AssetId, TokenId), CachedTokenMetadata>,
+    pub compliance: Option<ComplianceConfig>,
+    pub compliance_nonce: u64,
+    pub compliance_approvals: LookupMap<AccountId, ComplianceApproval>,
+    // pools holding near or nfts
+    pub active_pools: UnorderedSet<u64>,
+    // (pool id, last trade timestamp in seconds), most recent first
+    pub recently_traded_pools: Vec<(u64, u64)>,
+    // fees allowed for trade pools, any fee below MAX_FEE is allowed if empty
+    pub fee_tiers: Vec<Balance>,
+    // trade pools indexed by (collection, fee tier)
+    pub fee_tier_pools: LookupMap<(AssetId, Balance), Vec<u64>>,
+    // protocol fees accrued in fungible tokens, protocol_fee_credit being the near ones
+    pub ft_protocol_fee_credits: UnorderedMap<AccountId, Balance>,
+    // accounts pool owners can peg their spot price to
+    pub approved_oracles: UnorderedSet<AccountId>,
+    // operations whose promise is not resolved yet, by operation id
+    pub pending_operations: UnorderedMap<u64, PendingOperation>,
+    pub next_operation_id: u64,
+    // circuit breaker of pools not overriding it
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    // aggregates of the nft pools of every collection
+    pub collections: UnorderedMap<AssetId, CollectionStats>,
+    // timelocked protocol owned liquidity operations, by operation id
+    pub treasury_operations: UnorderedMap<u64, TreasuryOperation>,
+    pub next_treasury_operation_id: u64,
+    // in seconds
+    pub treasury_delay: u64,
+    // recent interactions of registered accounts, oldest first
+    pub account_activities: LookupMap<AccountId, Vec<AccountActivity>>,
+    // marketplaces whose listings pools can buy, by marketplace contract
+    pub marketplace_adapters: UnorderedMap<AccountId, MarketplaceAdapter>,
+    // protocol fee of owners trading against their own non-trade pools, the usual one if none
+    pub owner_trade_protocol_fee: Option<Balance>,
+    // collections whose nft_on_transfer msg can list the other tokens of a batch transfer
+    pub batch_transfer_collections: UnorderedSet<AssetId>,
+    // approvals given to the contract by registered owners, pulled with `pull_deposits`
+    pub deposit_approvals: LookupMap<(AssetId, TokenId), (AccountId, u64)>,
+    // accounts allowed to reserve pool nfts while their checkout completes
+    pub reservation_facilitators: UnorderedSet<AccountId>,
+    pub reservation_bond_per_nft: Balance,
+    pub reservations: UnorderedMap<u64, Reservation>,
+    pub next_reservation_id: u64,
+    // (collection, token id) of stolen or otherwise flagged nfts
+    pub flagged_tokens: LookupSet<(AssetId, TokenId)>,
+    // parameter change voted on by the lps of a trade pool, by pool id
+    pub pool_param_proposals: LookupMap<u64, PoolParamProposal>,
+    // governs in place of governance_id if set
+    pub council: Option<Council>,
+    // governance calls awaiting council confirmations, by proposal id
+    pub council_proposals: UnorderedMap<u64, CouncilProposal>,
+    pub next_council_proposal_id: u64,
+    // daily trade checkpoints of pools, oldest first, by pool id
+    pub pool_checkpoints: LookupMap<u64, Vec<DailyCheckpoint>>,
+    pub pool_history_days: u64,
+    // partners sharing the protocol fee of pool trades, by pool id
+    pub pool_fee_shares: LookupMap<u64, PoolFeeShare>,
+    pub max_swap_actions: u64,
+    // accounts approving official pools of a collection instead of its contract account
+    pub collection_official_accounts: LookupMap<AssetId, AccountId>,
+    // keys signing the swap intents of accounts and the last intent nonce executed for them
+    pub intent_keys: LookupMap<AccountId, PublicKey>,
+    pub intent_nonces: LookupMap<AccountId, u64>,
+    // frontends by referral code, and their share of the protocol fee of the trades they refer
+    pub referrals: UnorderedMap<String, Referral>,
+    pub referral_fee_share: u128,
+    // outbound nft transfers that failed, by transfer id, until retried or claimed
+    pub failed_transfers: UnorderedMap<u64, FailedTransfer>,
+    pub next_failed_transfer_id: u64,
+    // pool or account deposit holding each (collection, token id), written on every move by the
+    // pools and the account deposits themselves
+    pub token_locations: LookupMap<(AssetId, TokenId), TokenLocation>,
+    // ask of the approved tokens offered to pools, alongside their `deposit_approvals` record
+    pub approval_asks: LookupMap<(AssetId, TokenId), U128>,
+    // deposited and withdrawn value of each (pool id, lp), written by the pools themselves
+    pub lp_cost_bases: LookupMap<(u64, AccountId), LpCostBasis>,
+    // ids of the nft pools of each collection, multi token pools excluded
+    pub asset_pool_ids: LookupMap<AssetId, Vec<u64>>,
 }
 
 #[derive(BorshStorageKey, BorshSerialize)]
 enum StorageKey {
     AccountDeposits,
+    // legacy per account keys embedding full account ids, still referenced by maps created
+    // before `migrate_storage_keys` ran on them
+    #[allow(dead_code)]
     AccountDepositAsset {
         account_id: AccountId,
     },
+    #[allow(dead_code)]
     AssetDeposit {
         account_id: AccountId,
         asset_id: AssetId,
     },
     CreatedPoolIds,
+    // pool token ids before `PoolTokens`, moved out by `migrate`
+    #[allow(dead_code)]
     TokenIdsInPools {
         pool_id: u64,
     },
     PoolShare {
         pool_id: u64
+    },
+    VerifiedCollections,
+    AccountDepositAssetHash {
+        account_hash: CryptoHash,
+    },
+    AssetDepositHash {
+        deposit_hash: CryptoHash,
+    },
+    MtDeposits,
+    CollectionProtocolFees,
+    GateAttestations,
+    UsedQuotes,
+    RoyaltyOverrides,
+    TokenMetadata,
+    ComplianceApprovals,
+    ActivePools,
+    FeeTierPools,
+    FtProtocolFeeCredits,
+    ApprovedOracles,
+    PendingOperations,
+    Collections,
+    PoolTokenIds {
+        pool_id: u64,
+    },
+    PoolTokenIndex {
+        pool_id: u64,
+    },
+    PoolTokenDeposits {
+        pool_id: u64,
+    },
+    TreasuryOperations,
+    AccountActivities,
+    MarketplaceAdapters,
+    BatchTransferCollections,
+    DepositApprovals,
+    ReservationFacilitators,
+    Reservations,
+    FlaggedTokens,
+    PoolParamProposals,
+    CouncilProposals,
+    TokenPools,
+    PoolCheckpoints,
+    PoolFeeShares,
+    CollectionOfficialAccounts,
+    IntentKeys,
+    IntentNonces,
+    Referrals,
+    FailedTransfers,
+    // unused, the token locations extending the `TokenPools` index, kept for the later keys
+    #[allow(dead_code)]
+    TokenLocations,
+    ApprovalAsks,
+    LpCostBases,
+    PoolFlaggedTokens {
+        pool_id: u64,
+    },
+    AssetPoolIds,
+}
+
+impl StorageKey {
+    pub(crate) fn account_deposit_assets(account_id: &AccountId) -> StorageKey {
+        StorageKey::AccountDepositAssetHash {
+            account_hash: env::sha256_array(account_id.as_bytes()),
+        }
     }
+
+    pub(crate) fn asset_deposit(account_id: &AccountId, asset_id: &AssetId) -> StorageKey {
+        // ':' cannot appear in account ids, so the pair is unambiguous
+        StorageKey::AssetDepositHash {
+            deposit_hash: env::sha256_array(format!("{}:{}", account_id, asset_id).as_bytes()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PairParams {
+    pub pool_type: u8,
+    pub bonding_curve: u8,
+    pub spot_price: U128,
+    pub delta: U128,
+    pub fee: U128,
+    pub asset_recipient: Option<AccountId>,
+    pub locked_til: u64,
+    // trade pools only, the buy ones if not given
+    pub sell_delta: Option<U128>,
+    pub sell_fee: Option<U128>,
+}
+
+/// Arguments of `create_pair_with_args`, unset optional fields taking their defaults.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CreatePairArgs {
+    // CREATE_PAIR_ARGS_VERSION if not given, newer versions are rejected
+    pub version: Option<u8>,
+    pub pool_type: u8,
+    pub bonding_curve: u8,
+    pub asset_id: AssetId,
+    pub spot_price: U128,
+    pub delta: U128,
+    // lowest fee tier for trade pools, zero for the others
+    pub fee: Option<U128>,
+    pub asset_recipient: Option<AccountId>,
+    pub initial_token_ids: Option<Vec<TokenId>>,
+    // in seconds, not locked if not given
+    pub locked_til: Option<u64>,
+    // trade pools only, the buy ones if not given
+    pub sell_delta: Option<U128>,
+    pub sell_fee: Option<U128>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiquidityDeposit {
+    pub pool_id: u64,
+    // taken from the caller deposit
+    pub token_ids: Vec<TokenId>,
+    // share of the attached near added to the pool
+    pub near_amount: U128,
+    pub min_prices: Option<Vec<U128>>,
 }
 
 #[near_bindgen]
@@ -91,20 +389,88 @@ impl Contract {
     ) -> Self {
         require!(!env::state_exists(), "Already initialized");
         let caller = env::predecessor_account_id();
-        let mut this = Self {
+        if let Some(protocol_fee_multiplier) = protocol_fee_multiplier {
+            require!(protocol_fee_multiplier.0 <= MAX_PROTOCOL_FEE, "protocol fee exceed max");
+        }
+        let mut this = Self::with_defaults(
+            governance_id.unwrap_or(caller.clone()),
+            protocol_fee_receiver_id.unwrap_or(caller.clone()),
+            protocol_fee_multiplier.unwrap_or(U128(10u128.pow(17))).0,
+        );
+        this.measure_storage_usage();
+        this
+    }
+
+    // contract without pools or accounts, every other setting at its default
+    fn with_defaults(governance_id: AccountId, protocol_fee_receiver_id: AccountId, protocol_fee_multiplier: Balance) -> Self {
+        Self {
             pools: vec![],
-            protocol_fee_multiplier: protocol_fee_multiplier.unwrap_or(U128(10u128.pow(17))).0,
-            governance_id: governance_id.unwrap_or(caller.clone()),
-            protocol_fee_receiver_id: protocol_fee_receiver_id.unwrap_or(caller.clone()),
+            protocol_fee_multiplier,
+            governance_id,
+            protocol_fee_receiver_id,
             account_deposits: UnorderedMap::new(StorageKey::AccountDeposits),
             storage_per_account_creation: 0,
             storage_per_nft_deposit: 0,
             storage_per_pair_creation: 0,
             created_pool_ids: UnorderedMap::new(StorageKey::CreatedPoolIds),
             protocol_fee_credit: 0,
-        };
-        this.measure_storage_usage();
-        this
+            early_unlock_penalty: None,
+            verified_collections: UnorderedMap::new(StorageKey::VerifiedCollections),
+            only_verified_collections: false,
+            mt_deposits: LookupMap::new(StorageKey::MtDeposits),
+            pool_type_protocol_fees: [None; 3],
+            collection_protocol_fees: UnorderedMap::new(StorageKey::CollectionProtocolFees),
+            gate_attestations: LookupMap::new(StorageKey::GateAttestations),
+            quote_signer: None,
+            used_quotes: LookupSet::new(StorageKey::UsedQuotes),
+            royalty_overrides: UnorderedMap::new(StorageKey::RoyaltyOverrides),
+            token_metadata: LookupMap::new(StorageKey::TokenMetadata),
+            compliance: None,
+            compliance_nonce: 0,
+            compliance_approvals: LookupMap::new(StorageKey::ComplianceApprovals),
+            active_pools: UnorderedSet::new(StorageKey::ActivePools),
+            recently_traded_pools: vec![],
+            fee_tiers: DEFAULT_FEE_TIERS.to_vec(),
+            fee_tier_pools: LookupMap::new(StorageKey::FeeTierPools),
+            ft_protocol_fee_credits: UnorderedMap::new(StorageKey::FtProtocolFeeCredits),
+            approved_oracles: UnorderedSet::new(StorageKey::ApprovedOracles),
+            pending_operations: UnorderedMap::new(StorageKey::PendingOperations),
+            next_operation_id: 0,
+            circuit_breaker: None,
+            collections: UnorderedMap::new(StorageKey::Collections),
+            treasury_operations: UnorderedMap::new(StorageKey::TreasuryOperations),
+            next_treasury_operation_id: 0,
+            treasury_delay: treasury::DEFAULT_TREASURY_DELAY_SEC,
+            account_activities: LookupMap::new(StorageKey::AccountActivities),
+            marketplace_adapters: UnorderedMap::new(StorageKey::MarketplaceAdapters),
+            owner_trade_protocol_fee: None,
+            batch_transfer_collections: UnorderedSet::new(StorageKey::BatchTransferCollections),
+            deposit_approvals: LookupMap::new(StorageKey::DepositApprovals),
+            reservation_facilitators: UnorderedSet::new(StorageKey::ReservationFacilitators),
+            reservation_bond_per_nft: 0,
+            reservations: UnorderedMap::new(StorageKey::Reservations),
+            next_reservation_id: 0,
+            flagged_tokens: LookupSet::new(StorageKey::FlaggedTokens),
+            pool_param_proposals: LookupMap::new(StorageKey::PoolParamProposals),
+            council: None,
+            council_proposals: UnorderedMap::new(StorageKey::CouncilProposals),
+            next_council_proposal_id: 0,
+            pool_checkpoints: LookupMap::new(StorageKey::PoolCheckpoints),
+            pool_history_days: pool_history::DEFAULT_POOL_HISTORY_DAYS,
+            pool_fee_shares: LookupMap::new(StorageKey::PoolFeeShares),
+            max_swap_actions: swap::DEFAULT_MAX_SWAP_ACTIONS,
+            collection_official_accounts: LookupMap::new(StorageKey::CollectionOfficialAccounts),
+            intent_keys: LookupMap::new(StorageKey::IntentKeys),
+            intent_nonces: LookupMap::new(StorageKey::IntentNonces),
+            referrals: UnorderedMap::new(StorageKey::Referrals),
+            referral_fee_share: 0,
+            failed_transfers: UnorderedMap::new(StorageKey::FailedTransfers),
+            next_failed_transfer_id: 0,
+            token_locations: LookupMap::new(StorageKey::TokenPools),
+            approval_asks: LookupMap::new(StorageKey::ApprovalAsks),
+            lp_cost_bases: LookupMap::new(StorageKey::LpCostBases),
+            asset_pool_ids: LookupMap::new(StorageKey::AssetPoolIds),
+        }
     }
 
     fn measure_storage_usage(&mut self) {
@@ -112,11 +478,10 @@ impl Contract {
         let account_id = AccountId::new_unchecked("a".repeat(64));
         let asset_id = AccountId::new_unchecked("a".repeat(64));
         let account_deposit = AccountDeposit {
-            near_balance: 0u128,
+            storage_reserve: 0,
+            available_balance: 0,
             storage_usage: 0,
-            assets: UnorderedMap::new(StorageKey::AccountDepositAsset {
-                account_id: account_id.clone(),
-            }),
+            assets: UnorderedMap::new(StorageKey::account_deposit_assets(&account_id)),
         };
 
         self.account_deposits.insert(&account_id, &account_deposit);
@@ -126,10 +491,7 @@ impl Contract {
         let mut account_deposit = self.account_deposits.get(&account_id).unwrap();
         account_deposit.assets.insert(
             &asset_id,
-            &UnorderedMap::new(StorageKey::AssetDeposit {
-                account_id: account_id.clone(),
-                asset_id: asset_id.clone(),
-            }),
+            &UnorderedMap::new(StorageKey::asset_deposit(&account_id, &asset_id)),
         );
         let mut deposit_map = account_deposit.assets.get(&asset_id).unwrap();
         let token_id = "a".repeat(64);
@@ -167,13 +529,66 @@ impl Contract {
     }
 
     pub fn set_protocol_fee_receiver(&mut self, account_id: AccountId) {
-        require!(
-            env::predecessor_account_id() == self.governance_id.clone(),
-            "only governance"
-        );
+        self.assert_governance();
         self.protocol_fee_receiver_id = account_id;
     }
 
+    pub fn set_protocol_fee_multiplier(&mut self, protocol_fee_multiplier: U128) {
+        self.assert_governance();
+        require!(protocol_fee_multiplier.0 <= MAX_PROTOCOL_FEE, "protocol fee exceed max");
+        self.protocol_fee_multiplier = protocol_fee_multiplier.0;
+    }
+
+    pub fn set_early_unlock_penalty(&mut self, penalty: Option<U128>) {
+        self.assert_governance();
+        if let Some(penalty) = penalty {
+            require!(penalty.0 <= WAD, "penalty exceeds 100%");
+        }
+        self.early_unlock_penalty = penalty.map(|p| p.0);
+    }
+
+    pub fn set_pool_type_protocol_fee(&mut self, pool_type: u8, protocol_fee: Option<U128>) {
+        self.assert_governance();
+        let pool_type = PoolType::from(pool_type);
+        if let Some(protocol_fee) = protocol_fee {
+            require!(protocol_fee.0 <= MAX_PROTOCOL_FEE, "protocol fee exceed max");
+        }
+        self.pool_type_protocol_fees[pool_type as usize] = protocol_fee.map(|f| f.0);
+    }
+
+    pub fn set_collection_protocol_fee(&mut self, asset_id: AssetId, protocol_fee: Option<U128>) {
+        self.assert_governance();
+        match protocol_fee {
+            Some(protocol_fee) => {
+                require!(protocol_fee.0 <= MAX_PROTOCOL_FEE, "protocol fee exceed max");
+                self.collection_protocol_fees.insert(&asset_id, &protocol_fee.0);
+            }
+            None => {
+                self.collection_protocol_fees.remove(&asset_id);
+            }
+        }
+    }
+
+    /// Sets the protocol fee owners pay when trading against their own token or nft pools, zero
+    /// exempting them. Removed if none.
+    pub fn set_owner_trade_protocol_fee(&mut self, protocol_fee: Option<U128>) {
+        self.assert_governance();
+        if let Some(protocol_fee) = protocol_fee {
+            require!(protocol_fee.0 <= MAX_PROTOCOL_FEE, "protocol fee exceed max");
+        }
+        self.owner_trade_protocol_fee = protocol_fee.map(|f| f.0);
+    }
+
+    /// Sets the fees trade pools can be created with, an empty list allows any fee.
+    pub fn set_fee_tiers(&mut self, fee_tiers: Vec<U128>) {
+        self.assert_governance();
+        for fee in &fee_tiers {
+            require!(fee.0 < MAX_FEE, "trade fee exceed max");
+        }
+        self.fee_tiers = fee_tiers.iter().map(|f| f.0).collect();
+    }
+
+    /// Trade pools must use one of the fee tiers, unless created by governance.
     #[payable]
     pub fn create_pair(
         &mut self,
@@ -187,104 +602,259 @@ impl Contract {
         initial_token_ids: Vec<TokenId>,
         locked_til: u64,
     ) -> u64 {
-        log!(
-            "trade fee {:?}, max fee {:?}, wad {:?}",
-            fee,
-            U128(MAX_FEE),
-            U128(WAD)
-        );
+        let params = PairParams { pool_type, bonding_curve, spot_price, delta, fee, asset_recipient, locked_til, sell_delta: None, sell_fee: None };
+        self.internal_create_pair(&env::predecessor_account_id(), asset_id, params, &initial_token_ids, env::attached_deposit())
+    }
+
+    /// Same as `create_pair` with named arguments, checked up front with the failing argument
+    /// reported.
+    #[payable]
+    pub fn create_pair_with_args(&mut self, args: CreatePairArgs) -> u64 {
+        let (asset_id, params, initial_token_ids) = self.validate_create_pair_args(args);
+        self.internal_create_pair(&env::predecessor_account_id(), asset_id, params, &initial_token_ids, env::attached_deposit())
+    }
+
+    /// `min_prices`, if given, sets for each token the price under which it cannot be bought.
+    #[payable]
+    pub fn add_liquidity(&mut self, pool_id: u64, token_ids: Vec<TokenId>, min_prices: Option<Vec<U128>>) {
         let prev_storage = env::storage_usage();
         let account_id = env::predecessor_account_id();
-        let pool_id = self.pools.len();
-        let new_pair = Pair::new(
-            bonding_curve.into(),
-            pool_type.into(),
-            asset_id.clone(),
-            spot_price.0,
-            delta.0,
-            fee.0,
-            account_id.clone(),
-            asset_recipient.clone(),
-            0u128,
-            locked_til,
-            pool_id as u64,
+        let min_prices = min_prices.unwrap_or_default().iter().map(|p| p.0).collect::<Vec<Balance>>();
+        self.internal_add_liquidity(&account_id, pool_id, &token_ids, env::attached_deposit(), &min_prices);
+        self.assert_storage(&account_id, prev_storage, Some(0));
+    }
+
+    /// Adds liquidity to several pools at once. The attached near must cover the near amounts
+    /// of all deposits, any remainder is credited to the caller deposit.
+    #[payable]
+    pub fn add_liquidity_batch(&mut self, deposits: Vec<LiquidityDeposit>) {
+        require!(!deposits.is_empty(), "no deposits");
+        require!(
+            env::prepaid_gas() >= Gas(GAS_PER_ADD_LIQUIDITY.0 * deposits.len() as u64),
+            "not enough gas for batch"
         );
-        log!("Pool created");
-        self.pools.push(new_pair);
-        match self.created_pool_ids.get(&account_id) {
-            Some(mut pool_ids) => {
-                pool_ids.push(pool_id as u64);
-                self.created_pool_ids.insert(&account_id, &pool_ids);
-            }
-            None => {
-                log!("creating new vector");
-                let mut pool_ids = Vec::<u64>::new();
-                pool_ids.push(pool_id as u64);
-                self.created_pool_ids.insert(&account_id, &pool_ids);
-            }
-        }
-        log!("done added pool");
-
-        if asset_recipient.clone().is_some() {
-            let acc = asset_recipient.unwrap();
-            if self
-                .account_deposits
-                .get(&acc)
-                .is_none()
-            {
-                self.account_deposits.insert(
-                    &acc,
-                    &AccountDeposit {
-                        assets: UnorderedMap::new(StorageKey::AccountDepositAsset {
-                            account_id: acc.clone(),
-                        }),
-                        near_balance: 0,
-                        storage_usage: 0,
-                    },
-                );
-            }
+        let total_near = deposits
+            .iter()
+            .try_fold(0u128, |total, deposit| total.checked_add(deposit.near_amount.0))
+            .unwrap_or_else(|| env::panic_str("near amount overflow"));
+        require!(env::attached_deposit() >= total_near, "attached near does not cover deposits");
+
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        for deposit in &deposits {
+            let min_prices = deposit.min_prices.clone().unwrap_or_default().iter().map(|p| p.0).collect::<Vec<Balance>>();
+            self.internal_add_liquidity(&account_id, deposit.pool_id, &deposit.token_ids, deposit.near_amount.0, &min_prices);
         }
+        self.assert_storage(&account_id, prev_storage, Some(env::attached_deposit() - total_near));
+    }
 
-        self.internal_withdraw_nft(&account_id, &asset_id, &initial_token_ids);
-        let pool = &mut self.pools[pool_id];
-        pool.internal_register_account_lp(&account_id);
-        log!("depositing near");
-        pool.deposit_and_mint_lp(account_id.clone(), account_id.clone(), &initial_token_ids, &env::attached_deposit());
+    /// With `prefer_near`, small burns are paid in near only if the pool keeps enough near
+    /// for its nfts, falling back to a withdrawal of near and nfts otherwise.
+    #[payable]
+    pub fn remove_liquidity(&mut self, pool_id: u64, lp: U128, prefer_near: Option<bool>) {
+        assert_one_yocto();
+        self.internal_remove_liquidity(pool_id, lp.0, prefer_near.unwrap_or(false), &[]);
+    }
+
+    /// Same as `remove_liquidity` without `prefer_near`, the redeemed nfts being
+    /// `preferred_token_ids` first. They must be in the pool and no more than the nfts `lp` is
+    /// entitled to, the rest of the nfts being picked by the pool.
+    #[payable]
+    pub fn remove_liquidity_with_tokens(&mut self, pool_id: u64, lp: U128, preferred_token_ids: Vec<TokenId>) {
+        assert_one_yocto();
+        self.internal_remove_liquidity(pool_id, lp.0, false, &preferred_token_ids);
+    }
+
+    /// Burns `lp` in `from_pool` and deposits the redeemed nfts and near into `to_pool` of the same
+    /// collection without them leaving the contract. Min prices of the redeemed tokens are not kept.
+    #[payable]
+    pub fn migrate_liquidity(&mut self, from_pool: u64, to_pool: u64, lp_amount: U128, min_lp_out: U128) -> U128 {
+        assert_one_yocto();
+        require!(from_pool != to_pool, "cannot migrate to the same pool");
+        let nft_token = self.get_nft_asset_id(from_pool);
+        require!(self.get_nft_asset_id(to_pool) == nft_token, "pools trade different collections");
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(from_pool);
+        let pool = &mut self.pools[from_pool as usize];
+        let (protocol_fee, near_amount, token_ids) = pool.burn_lp(&account_id, lp_amount.0, protocol_fee_multiplier, false, &[]);
+        self.protocol_fee_credit += protocol_fee;
+        self.internal_update_pool_activity(from_pool);
+        self.internal_record_activity_in_pool(&account_id, ActivityKind::RemoveLiquidity, from_pool, token_ids.len() as u64, near_amount);
+
+        let lp_out = self.internal_mint_liquidity(&account_id, to_pool, &token_ids, near_amount, &[]);
+        require!(lp_out >= min_lp_out.0, "migrated lp less than min lp out");
         self.assert_storage(&account_id, prev_storage, Some(0));
-        log!("done assert storage");
-        pool_id as u64
+        lp_out.into()
+    }
+
+    /// Sets the price under which a token the caller deposited to the pool cannot be bought,
+    /// zero removes it.
+    pub fn set_token_min_price(&mut self, pool_id: u64, token_id: TokenId, min_price: U128) {
+        let account_id = env::predecessor_account_id();
+        let pool = &mut self.pools[pool_id as usize];
+        pool.set_min_price(&account_id, &token_id, min_price.0);
     }
 
+    /// Withdraws up to `near_amount` of the pool near balance to the owner, or to the owner
+    /// deposit with `to_deposit` for later use in swaps and pools.
     #[payable]
-    pub fn add_liquidity(&mut self, pool_id: u64, token_ids: Vec<TokenId>) {
+    pub fn withdraw_near(&mut self, pool_id: u64, near_amount: U128, to_deposit: Option<bool>) {
         let prev_storage = env::storage_usage();
         let account_id = env::predecessor_account_id();
+        let to_deposit = to_deposit.unwrap_or(false);
         let pool = &mut self.pools[pool_id as usize];
-        pool.deposit_and_mint_lp(account_id.clone(), account_id.clone(), &token_ids, &env::attached_deposit());
-        self.assert_storage(&account_id, prev_storage, Some(0));
+        let withdrawn = pool.withdraw_near(&near_amount.0);
+        self.internal_update_pool_activity(pool_id);
+        self.internal_record_activity_in_pool(&account_id, ActivityKind::Withdraw, pool_id, 0, withdrawn);
+        let credited = if to_deposit { withdrawn } else { 0 };
+        self.assert_storage(&account_id, prev_storage, Some(env::attached_deposit() + credited));
+        emit_event("withdraw_near", WithdrawNearEvent {
+            pool_id,
+            account_id: &account_id,
+            near_amount: withdrawn.into(),
+            to_deposit,
+        });
+
+        if !to_deposit && withdrawn > 0 {
+            Promise::new(account_id.clone()).transfer(withdrawn);
+        }
+    }
+
+    /// Adds the attached near to the pool near balance without minting lp, raising the value of
+    /// the existing lp of trade pools. Near sent to the contract by plain transfers is not credited
+    /// to any pool.
+    #[payable]
+    pub fn donate_to_pool(&mut self, pool_id: u64) {
+        let near_amount = env::attached_deposit();
+        require!(near_amount > 0, "no near attached");
+        let account_id = env::predecessor_account_id();
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        pool.near_balance += near_amount;
+        pool.assert_near_cap();
+        emit_event("donate", DonationEvent {
+            pool_id,
+            account_id: &account_id,
+            near_amount: near_amount.into(),
+        });
+        self.internal_update_pool_activity(pool_id);
     }
 
+    /// Sends the near earned from sales of a non-trade pool that keeps its own proceeds to its owner.
     #[payable]
-    pub fn remove_liquidity(&mut self, pool_id: u64, lp: U128) {
+    pub fn claim_proceeds(&mut self, pool_id: u64) -> U128 {
         assert_one_yocto();
         let account_id = env::predecessor_account_id();
-        let nft_token = self.get_nft_asset_id(pool_id);
         let pool = &mut self.pools[pool_id as usize];
-        let (protocol_fee, withdrawnable_near, token_ids) = pool.burn_lp(&account_id, lp.0, self.protocol_fee_multiplier);
-        self.protocol_fee_credit += protocol_fee;
-        Promise::new(account_id.clone()).transfer(withdrawnable_near);
-        self.transfer_nfts(&account_id, &nft_token, &token_ids);
+        let proceeds = pool.claim_proceeds();
+        if proceeds > 0 {
+            Promise::new(account_id).transfer(proceeds);
+        }
+        proceeds.into()
     }
 
-    #[payable]
-    pub fn withdraw_near(&mut self, pool_id: u64, near_amount: U128) {
+    /// Bounds the nfts (or multi token amount) the pool can hold and the near that can be
+    /// deposited to it, unset bounds are removed. Current holdings above new caps are kept.
+    pub fn set_pool_caps(&mut self, pool_id: u64, max_nfts: Option<u64>, max_near: Option<U128>) {
+        let pool = &mut self.pools[pool_id as usize];
+        pool.set_caps(max_nfts, max_near.map(|n| n.0));
+    }
+
+    /// Sets the token ids, in order, that buys of any nfts from the pool take before others.
+    /// Buys of specific token ids are not affected.
+    pub fn set_token_priority(&mut self, pool_id: u64, token_ids: Vec<TokenId>) {
         let prev_storage = env::storage_usage();
         let account_id = env::predecessor_account_id();
         let pool = &mut self.pools[pool_id as usize];
-        pool.withdraw_near(&near_amount.0);
-        self.assert_storage(&account_id, prev_storage, Some(env::attached_deposit()));
+        pool.set_token_priority(token_ids);
+        self.assert_storage(&account_id, prev_storage, None);
+    }
+
+    /// Pushes back the release time of the pool liquidity. The lock can never be shortened.
+    pub fn extend_pool_lock(&mut self, pool_id: u64, released_time: u64) {
+        let pool = &mut self.pools[pool_id as usize];
+        pool.extend_lock(released_time);
+    }
+
+    /// Releases the liquidity of the pool before its lock expires, for `early_unlock_penalty` of
+    /// the owner liquidity. Trade pool owners forfeit that share of their lp to the other lps,
+    /// other pools pay it from their near balance to the protocol. Returns the penalty in near.
+    #[payable]
+    pub fn early_unlock_pool(&mut self, pool_id: u64) -> U128 {
+        assert_one_yocto();
+        let penalty_multiplier = self
+            .early_unlock_penalty
+            .unwrap_or_else(|| env::panic_str("early unlock disabled"));
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let penalty = pool.early_unlock(penalty_multiplier);
+        if pool.pool_type != PoolType::Trade {
+            self.protocol_fee_credit += penalty;
+        }
+        self.internal_update_pool_activity(pool_id);
+        penalty.into()
+    }
+
+    /// Queues a governance release of the pool lock, applicable by `force_release` after
+    /// FORCE_RELEASE_DELAY_SEC, for locks set far in the future by mistake.
+    pub fn queue_force_release(&mut self, pool_id: u64) -> u64 {
+        self.assert_governance();
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let force_release_at = pool.queue_force_release();
+        emit_event("force_release_queued", ForceReleaseEvent {
+            pool_id,
+            released_time: pool.released_time,
+            force_release_at: Some(force_release_at),
+        });
+        force_release_at
+    }
+
+    pub fn cancel_force_release(&mut self, pool_id: u64) {
+        self.assert_governance();
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        require!(pool.force_release_at.take().is_some(), "no force release queued");
+        emit_event("force_release_cancelled", ForceReleaseEvent {
+            pool_id,
+            released_time: pool.released_time,
+            force_release_at: None,
+        });
+    }
+
+    /// Releases the pool liquidity once the queued forced release is effective.
+    pub fn force_release(&mut self, pool_id: u64) {
+        self.assert_governance();
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let force_release_at = pool.force_release_at;
+        pool.force_release();
+        emit_event("force_release", ForceReleaseEvent {
+            pool_id,
+            released_time: pool.released_time,
+            force_release_at,
+        });
+    }
+
+    /// Moves nfts of the owner deposit into its non-trade pool, minting no lp. The owner pays
+    /// the storage of the pool entries with the storage released by its deposit.
+    pub fn deposit_nfts_to_pool(&mut self, pool_id: u64, token_ids: Vec<TokenId>) {
+        require!(!token_ids.is_empty(), "no tokens to deposit");
+        let account_id = env::predecessor_account_id();
+        let asset_id = self.get_nft_asset_id(pool_id);
+        self.assert_not_flagged(pool_id, &token_ids);
+        let prev_storage = env::storage_usage();
+        self.internal_withdraw_nft(&account_id, &asset_id, &token_ids);
+        self.internal_release_storage(&account_id, prev_storage);
 
-        Promise::new(account_id.clone()).transfer(near_amount.0);
+        let prev_storage = env::storage_usage();
+        self.pools[pool_id as usize].deposit_nfts(&token_ids);
+        emit_event("add_liquidity", AddLiquidityEvent {
+            pool_id,
+            account_id: &account_id,
+            token_ids: &token_ids,
+            near_amount: 0.into(),
+            lp_amount: 0.into(),
+        });
+        self.internal_update_pool_activity(pool_id);
+        self.internal_record_activity_in_pool(&account_id, ActivityKind::Deposit, pool_id, token_ids.len() as u64, 0);
+        self.assert_storage(&account_id, prev_storage, None);
     }
 
     #[payable]
@@ -295,6 +865,8 @@ impl Contract {
         pool.withdraw_nfts(&token_ids);
 
         let asset_id = pool.nft_token.clone();
+        self.internal_update_pool_activity(pool_id);
+        self.internal_record_activity_in_pool(&account_id, ActivityKind::Withdraw, pool_id, token_ids.len() as u64, 0);
         self.transfer_nfts(&account_id, &asset_id, &token_ids);
         {
             self.assert_storage(&account_id, prev_storage, Some(env::attached_deposit()));
@@ -307,28 +879,301 @@ impl Contract {
             "require attachment"
         );
         let account_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
         self.internal_withdraw_nft(&account_id, &asset_id, &token_ids);
+        self.internal_release_storage(&account_id, prev_storage);
+        self.internal_record_activity(&account_id, ActivityKind::Withdraw, None, Some(&asset_id), token_ids.len() as u64, 0);
 
         self.transfer_nfts(&account_id, &asset_id, &token_ids);
     }
+
+    /// Removes the caller deposit entries of collections it no longer holds nfts of, crediting
+    /// the released storage back to its deposit. Returns the number of entries removed.
+    pub fn gc_my_deposits(&mut self) -> u64 {
+        let account_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        let removed = self.internal_gc_deposits(&account_id);
+        self.internal_release_storage(&account_id, prev_storage);
+        removed
+    }
+
+    /// Transfers NFTs held in the caller's internal deposit (e.g. bought with `buy_to_escrow`)
+    /// to `receiver_id`, defaulting to the caller.
+    #[payable]
+    pub fn claim_nfts(
+        &mut self,
+        asset_id: AssetId,
+        token_ids: Vec<TokenId>,
+        receiver_id: Option<AccountId>,
+    ) {
+        require!(
+            env::attached_deposit() >= token_ids.len() as u128,
+            "require attachment"
+        );
+        let account_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        self.internal_withdraw_nft(&account_id, &asset_id, &token_ids);
+        self.internal_release_storage(&account_id, prev_storage);
+        self.internal_record_activity(&account_id, ActivityKind::Withdraw, None, Some(&asset_id), token_ids.len() as u64, 0);
+
+        let receiver_id = receiver_id.unwrap_or_else(|| account_id.clone());
+        self.transfer_nfts_with_refund(&receiver_id, &account_id, &asset_id, &token_ids);
+    }
 }
 
 impl Contract {
+    pub(crate) fn assert_governance(&self) {
+        require!(
+            self.is_governance(&env::predecessor_account_id()),
+            "only governance"
+        );
+    }
+
+    // applies the defaults of the args, panicking on the first invalid pool parameter, the fee
+    // tier and the lock being checked on creation
+    fn validate_create_pair_args(&self, args: CreatePairArgs) -> (AssetId, PairParams, Vec<TokenId>) {
+        let version = args.version.unwrap_or(CREATE_PAIR_ARGS_VERSION);
+        require!(version >= 1 && version <= CREATE_PAIR_ARGS_VERSION, format!("unsupported args version {}", version));
+        let is_trade = args.pool_type == PoolType::Trade as u8;
+        let fee = args.fee.unwrap_or_else(|| match self.fee_tiers.iter().min() {
+            Some(fee) if is_trade => U128(*fee),
+            _ => U128(0),
+        });
+        let validation =
+            self.validate_pool_params(args.bonding_curve, args.pool_type, args.spot_price, args.delta, fee, args.asset_recipient.clone());
+        if let Some(error) = validation.errors.first() {
+            env::panic_str(&format!("invalid {}: {}", error.param, error.reason));
+        }
+        let locked_til = args.locked_til.unwrap_or(0);
+        let params = PairParams {
+            pool_type: args.pool_type,
+            bonding_curve: args.bonding_curve,
+            spot_price: args.spot_price,
+            delta: args.delta,
+            fee,
+            asset_recipient: args.asset_recipient,
+            locked_til,
+            sell_delta: args.sell_delta,
+            sell_fee: args.sell_fee,
+        };
+        (args.asset_id, params, args.initial_token_ids.unwrap_or_default())
+    }
+
+    // checks of every pool creation: the fee tiers of trade pools not created by governance and
+    // the lock length
+    fn assert_create_pair_params(&self, account_id: &AccountId, params: &PairParams) {
+        if PoolType::from(params.pool_type) == PoolType::Trade && !self.fee_tiers.is_empty() && !self.is_governance(account_id) {
+            require!(self.fee_tiers.contains(&params.fee.0), "invalid fee: not an allowed fee tier");
+            require!(
+                params.sell_fee.map_or(true, |f| self.fee_tiers.contains(&f.0)),
+                "invalid sell_fee: not an allowed fee tier"
+            );
+        }
+        require!(
+            params.locked_til <= env::block_timestamp_ms() / 1000 + MAX_CREATE_LOCK_SEC,
+            "invalid locked_til: lock too long"
+        );
+    }
+
+    fn internal_add_liquidity(&mut self, account_id: &AccountId, pool_id: u64, token_ids: &[TokenId], near_amount: Balance, min_prices: &[Balance]) {
+        if !token_ids.is_empty() {
+            let nft_token = self.get_nft_asset_id(pool_id);
+            self.internal_withdraw_nft(account_id, &nft_token, token_ids);
+        }
+        self.internal_mint_liquidity(account_id, pool_id, token_ids, near_amount, min_prices);
+    }
+
+    fn internal_mint_liquidity(&mut self, account_id: &AccountId, pool_id: u64, token_ids: &[TokenId], near_amount: Balance, min_prices: &[Balance]) -> Balance {
+        self.assert_not_flagged(pool_id, token_ids);
+        let pool = &mut self.pools[pool_id as usize];
+        let lp_amount = pool.deposit_and_mint_lp(account_id.clone(), account_id.clone(), token_ids, &near_amount, min_prices);
+        emit_event("add_liquidity", AddLiquidityEvent {
+            pool_id,
+            account_id,
+            token_ids,
+            near_amount: near_amount.into(),
+            lp_amount: lp_amount.into(),
+        });
+        self.internal_update_pool_activity(pool_id);
+        self.internal_record_activity_in_pool(account_id, ActivityKind::AddLiquidity, pool_id, token_ids.len() as u64, near_amount);
+        lp_amount
+    }
+
+    // protocol fee applied to trades and lp burns of the pool: the collection override, then
+    // the pool type one, then the global multiplier, never above MAX_PROTOCOL_FEE
+    pub(crate) fn pool_protocol_fee_multiplier(&self, pool_id: u64) -> Balance {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        self.collection_protocol_fees
+            .get(&pool.nft_token)
+            .or(self.pool_type_protocol_fees[pool.pool_type as usize])
+            .unwrap_or(self.protocol_fee_multiplier)
+            .min(MAX_PROTOCOL_FEE)
+    }
+
+    // protocol fee of a trade of `account_id` against the pool, owners trading against their
+    // non-trade pools paying owner_trade_protocol_fee instead when lower
+    pub(crate) fn trade_protocol_fee_multiplier(&self, pool_id: u64, account_id: &AccountId) -> Balance {
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pool_id);
+        let pool = &self.pools[pool_id as usize];
+        match self.owner_trade_protocol_fee {
+            Some(owner_fee) if pool.pool_type != PoolType::Trade && &pool.owner == account_id && owner_fee < protocol_fee_multiplier => {
+                emit_event("owner_trade_fee_exemption", OwnerTradeFeeEvent {
+                    pool_id,
+                    account_id,
+                    protocol_fee_multiplier: owner_fee.into(),
+                });
+                owner_fee
+            }
+            _ => protocol_fee_multiplier,
+        }
+    }
+
+    pub(crate) fn internal_update_pool_activity(&mut self, pool_id: u64) {
+        self.pools[pool_id as usize].update_lp_index();
+        let pool = &self.pools[pool_id as usize];
+        if pool.near_balance > 0 || pool.num_items() > 0 {
+            self.active_pools.insert(&pool_id);
+        } else {
+            self.active_pools.remove(&pool_id);
+        }
+        self.internal_update_collection_stats(pool_id);
+    }
+
+    pub(crate) fn internal_record_pool_trade(&mut self, pool_id: u64) {
+        self.recently_traded_pools.retain(|(id, _)| *id != pool_id);
+        self.recently_traded_pools.insert(0, (pool_id, env::block_timestamp_ms() / 1000));
+        self.recently_traded_pools.truncate(RECENTLY_TRADED_POOLS_KEPT);
+        self.internal_update_pool_activity(pool_id);
+        self.internal_update_circuit_breaker(pool_id);
+        self.internal_record_pool_checkpoint(pool_id);
+    }
+
+    pub(crate) fn internal_add_asset_pool(&mut self, asset_id: &AssetId, pool_id: u64) {
+        let mut pool_ids = self.asset_pool_ids.get(asset_id).unwrap_or_default();
+        pool_ids.push(pool_id);
+        self.asset_pool_ids.insert(asset_id, &pool_ids);
+    }
+
+    pub(crate) fn internal_add_created_pool(&mut self, account_id: &AccountId, pool_id: u64) {
+        match self.created_pool_ids.get(account_id) {
+            Some(mut pool_ids) => {
+                pool_ids.push(pool_id);
+                self.created_pool_ids.insert(account_id, &pool_ids);
+            }
+            None => {
+                let mut pool_ids = Vec::<u64>::new();
+                pool_ids.push(pool_id);
+                self.created_pool_ids.insert(account_id, &pool_ids);
+            }
+        }
+    }
+
+    // registers the asset recipient if needed, paying its storage out of `near_amount`. Returns
+    // what is left of `near_amount`.
+    pub(crate) fn internal_pay_asset_recipient_registration(&mut self, asset_recipient: &Option<AccountId>, near_amount: Balance) -> Balance {
+        let registration_cost = match asset_recipient {
+            Some(account_id) => self.internal_register_asset_recipient(account_id),
+            None => 0,
+        };
+        require!(
+            near_amount >= registration_cost,
+            format!("attach at least {} to register the asset recipient", registration_cost)
+        );
+        near_amount - registration_cost
+    }
+
+    // creates a pool owned by `account_id` with `initial_token_ids` from its deposit and
+    // `near_amount`, less the asset recipient registration
+    pub(crate) fn internal_create_pair(
+        &mut self,
+        account_id: &AccountId,
+        asset_id: AssetId,
+        params: PairParams,
+        initial_token_ids: &[TokenId],
+        near_amount: Balance,
+    ) -> u64 {
+        self.assert_create_pair_params(account_id, &params);
+        let PairParams { pool_type, bonding_curve, spot_price, delta, fee, asset_recipient, locked_til, sell_delta, sell_fee } = params;
+        self.assert_collection_allowed(&asset_id);
+        let is_fee_tier = self.fee_tiers.contains(&fee.0);
+        let near_deposit = self.internal_pay_asset_recipient_registration(&asset_recipient, near_amount);
+        let prev_storage = env::storage_usage();
+        let pool_id = self.pools.len();
+        let mut new_pair = Pair::new(
+            bonding_curve.into(),
+            pool_type.into(),
+            asset_id.clone(),
+            spot_price.0,
+            delta.0,
+            fee.0,
+            account_id.clone(),
+            asset_recipient.clone(),
+            0u128,
+            locked_til,
+            pool_id as u64,
+        );
+        new_pair.set_sell_side(sell_delta.map(|d| d.0), sell_fee.map(|f| f.0));
+        log!("Pool created");
+        self.pools.push(new_pair);
+        self.internal_add_created_pool(account_id, pool_id as u64);
+        if PoolType::from(pool_type) == PoolType::Trade && is_fee_tier {
+            let mut pool_ids = self.fee_tier_pools.get(&(asset_id.clone(), fee.0)).unwrap_or_default();
+            pool_ids.push(pool_id as u64);
+            self.fee_tier_pools.insert(&(asset_id.clone(), fee.0), &pool_ids);
+        }
+        self.internal_add_asset_pool(&asset_id, pool_id as u64);
+
+        self.internal_withdraw_nft(account_id, &asset_id, initial_token_ids);
+        let pool = &mut self.pools[pool_id];
+        pool.internal_register_account_lp(account_id);
+        pool.deposit_and_mint_lp(account_id.clone(), account_id.clone(), initial_token_ids, &near_deposit, &[]);
+        self.internal_update_pool_activity(pool_id as u64);
+        self.assert_storage(account_id, prev_storage, Some(0));
+        pool_id as u64
+    }
+
+    fn internal_register_asset_recipient(&mut self, account_id: &AccountId) -> Balance {
+        if self.account_deposits.get(account_id).is_some() {
+            return 0;
+        }
+        let prev_storage = env::storage_usage();
+        self.account_deposits.insert(
+            account_id,
+            &AccountDeposit {
+                assets: UnorderedMap::new(StorageKey::account_deposit_assets(account_id)),
+                storage_reserve: 0,
+                available_balance: 0,
+                storage_usage: 0,
+            },
+        );
+        let mut account_deposit = self.account_deposits.get(account_id).unwrap();
+        account_deposit.storage_usage = self.compute_storage_usage(prev_storage);
+        let registration_cost = account_deposit.storage_usage as u128 * env::storage_byte_cost();
+        account_deposit.storage_reserve = registration_cost;
+        self.account_deposits.insert(account_id, &account_deposit);
+        registration_cost
+    }
+
     fn internal_swap_near_for_nfts(
         &mut self,
+        account_id: &AccountId,
         pool_id: u64,
         nft_ids: Option<Vec<TokenId>>,
         num_nfts: u64,
+        max_near_input: Balance,
+        nonce: Option<u64>,
     ) -> (Balance, Balance, Vec<TokenId>) {
+        let protocol_fee_multiplier = self.trade_protocol_fee_multiplier(pool_id, account_id);
         let pool = &mut self.pools[pool_id as usize];
         let protocol_fee: u128;
         let input_amount: u128;
         let token_ids: Vec<TokenId>;
         if nft_ids.is_none() {
             (protocol_fee, input_amount, token_ids) = pool.swap_near_for_any_nfts(
-                env::attached_deposit(),
+                max_near_input,
                 num_nfts,
-                self.protocol_fee_multiplier,
+                protocol_fee_multiplier,
+                nonce,
             );
         } else {
             require!(
@@ -336,9 +1181,9 @@ impl Contract {
                 "invalid nft size"
             );
             (protocol_fee, input_amount) = pool.swap_near_for_specific_nfts(
-                env::attached_deposit(),
+                max_near_input,
                 &nft_ids.clone().unwrap(),
-                self.protocol_fee_multiplier,
+                protocol_fee_multiplier,
             );
             token_ids = nft_ids.unwrap();
         }
@@ -346,15 +1191,32 @@ impl Contract {
         (protocol_fee, input_amount, token_ids)
     }
 
+    fn internal_remove_liquidity(&mut self, pool_id: u64, lp: Balance, prefer_near: bool, preferred_token_ids: &[TokenId]) {
+        let account_id = env::predecessor_account_id();
+        let nft_token = self.get_nft_asset_id(pool_id);
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pool_id);
+        let pool = &mut self.pools[pool_id as usize];
+        let (protocol_fee, withdrawnable_near, token_ids) =
+            pool.burn_lp(&account_id, lp, protocol_fee_multiplier, prefer_near, preferred_token_ids);
+        self.protocol_fee_credit += protocol_fee;
+        self.internal_update_pool_activity(pool_id);
+        self.internal_record_activity_in_pool(&account_id, ActivityKind::RemoveLiquidity, pool_id, token_ids.len() as u64, withdrawnable_near);
+        Promise::new(account_id.clone()).transfer(withdrawnable_near);
+        self.transfer_nfts(&account_id, &nft_token, &token_ids);
+    }
+
     fn internal_swap_nfts_for_near(
         &mut self,
+        account_id: &AccountId,
         pool_id: u64,
         nft_ids: &Vec<TokenId>,
         min_near_out: &Balance,
     ) -> (Balance, Balance) {
+        self.assert_not_flagged(pool_id, nft_ids);
+        let protocol_fee_multiplier = self.trade_protocol_fee_multiplier(pool_id, account_id);
         let pool = &mut self.pools[pool_id as usize];
         let (protocol_fee, output_amount) =
-            pool.swap_nfts_for_near(&nft_ids, min_near_out.clone(), self.protocol_fee_multiplier);
+            pool.swap_nfts_for_near(&nft_ids, min_near_out.clone(), protocol_fee_multiplier);
         (protocol_fee, output_amount)
     }
 }
@@ -365,6 +1227,7 @@ mod tests {
     use near_sdk::{testing_env, Balance};
 
     use super::*;
+    use crate::testing;
 
     fn governance_id() -> AccountId {
         AccountId::new_unchecked("governance.near".to_string())
@@ -417,6 +1280,241 @@ mod tests {
         assert_ne!(contract.storage_per_account_creation, 0);
     }
 
+    #[test]
+    #[should_panic(expected = "protocol fee exceed max")]
+    fn test_new_protocol_fee_above_max() {
+        let context = get_context(user1());
+        testing_env!(context.build());
+        Contract::new(
+            governance_id().into(),
+            protocol_fee_receiver_id().into(),
+            Some((MAX_PROTOCOL_FEE + 1).into()),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid locked_til: lock too long")]
+    fn test_create_pair_lock_too_long() {
+        let mut contract = testing::setup_contract();
+        let owner = user1();
+        testing::register(&mut contract, &owner, 10u128.pow(24));
+        testing::deposit_nfts(&mut contract, &owner, &["1"]);
+        testing_env!(get_context(owner).block_timestamp(env::block_timestamp()).build());
+        let locked_til = env::block_timestamp_ms() / 1000 + MAX_CREATE_LOCK_SEC + 1;
+        contract.create_pair(
+            PoolType::NFT as u8,
+            BondingCurve::LinearCurve as u8,
+            testing::nft(),
+            U128(10u128.pow(24)),
+            U128(10u128.pow(23)),
+            U128(0),
+            None,
+            vec!["1".to_string()],
+            locked_til,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid fee: not an allowed fee tier")]
+    fn test_create_pair_fee_not_in_tiers() {
+        let mut contract = testing::setup_contract();
+        let owner = user1();
+        testing::register(&mut contract, &owner, 10u128.pow(24));
+        testing::deposit_nfts(&mut contract, &owner, &["1"]);
+        testing_env!(get_context(owner).attached_deposit(10u128.pow(25)).build());
+        contract.create_pair(
+            PoolType::Trade as u8,
+            BondingCurve::LinearCurve as u8,
+            testing::nft(),
+            U128(10u128.pow(24)),
+            U128(10u128.pow(23)),
+            U128(2 * 10u128.pow(16)),
+            None,
+            vec!["1".to_string()],
+            0,
+        );
+    }
+
+    // pool of `pool_type` locked for 1000 seconds, early unlocks costing 10%
+    fn locked_pool(contract: &mut Contract, owner: &AccountId, pool_type: PoolType) -> u64 {
+        testing::register(contract, owner, 10u128.pow(24));
+        let pool_id = testing::create_pool(contract, owner, pool_type, &["1", "2"], 10 * testing::NEAR);
+        testing::set_caller(owner, 0);
+        contract.extend_pool_lock(pool_id, env::block_timestamp_ms() / 1000 + 1000);
+        testing::set_caller(&testing::governance(), 0);
+        contract.set_early_unlock_penalty(Some(U128(WAD / 10)));
+        pool_id
+    }
+
+    #[test]
+    fn test_early_unlock_penalty_to_protocol() {
+        let mut contract = testing::setup_contract();
+        let owner = user1();
+        let pool_id = locked_pool(&mut contract, &owner, PoolType::NFT);
+
+        testing::set_caller(&owner, 1);
+        assert_eq!(contract.early_unlock_pool(pool_id).0, testing::NEAR);
+        let pool = &contract.pools[pool_id as usize];
+        assert_eq!(pool.near_balance, 9 * testing::NEAR);
+        assert_eq!(pool.released_time, env::block_timestamp_ms() / 1000);
+        assert_eq!(contract.protocol_fee_credit, testing::NEAR);
+    }
+
+    #[test]
+    fn test_early_unlock_penalty_to_trade_pool_lps() {
+        let mut contract = testing::setup_contract();
+        let owner = user1();
+        let lp = testing::account("lp.near");
+        let pool_id = locked_pool(&mut contract, &owner, PoolType::Trade);
+        testing::register(&mut contract, &lp, 10u128.pow(24));
+        testing::deposit_nfts(&mut contract, &lp, &["3"]);
+        testing::set_caller(&lp, 5 * testing::NEAR);
+        contract.add_liquidity(pool_id, vec!["3".to_string()], None);
+        let pool = &contract.pools[pool_id as usize];
+        let (owner_lp, lp_lp, lp_supply) = (pool.lp_balances.get(&owner).unwrap(), pool.lp_balances.get(&lp).unwrap(), pool.lp_supply);
+
+        testing::set_caller(&owner, 1);
+        let penalty = contract.early_unlock_pool(pool_id).0;
+        let pool = &contract.pools[pool_id as usize];
+        // a tenth of the owner lp burned, valued at its share of 15 near and 3 nfts at 1 near
+        let forfeited_lp = owner_lp / 10;
+        let value = crate::curves::U256::from(18 * testing::NEAR) * crate::curves::U256::from(forfeited_lp) / crate::curves::U256::from(lp_supply);
+        assert_eq!(penalty, value.as_u128());
+        assert_eq!(pool.lp_balances.get(&owner), Some(owner_lp - forfeited_lp));
+        assert_eq!(pool.lp_balances.get(&lp), Some(lp_lp));
+        assert_eq!(pool.lp_supply, lp_supply - forfeited_lp);
+        assert_eq!(pool.near_balance, 15 * testing::NEAR);
+        assert_eq!(contract.protocol_fee_credit, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Pool liquidity already released")]
+    fn test_early_unlock_of_released_pool() {
+        let mut contract = testing::setup_contract();
+        let owner = user1();
+        let pool_id = locked_pool(&mut contract, &owner, PoolType::NFT);
+        testing::advance_time_sec(1000);
+        testing::set_caller(&owner, 1);
+        contract.early_unlock_pool(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "near only liquidity mints no lp, add nfts along")]
+    fn test_add_liquidity_batch_near_only_to_trade_pool() {
+        let mut contract = testing::setup_contract();
+        let owner = user1();
+        let lp = testing::account("lp.near");
+        testing::register(&mut contract, &owner, 10u128.pow(24));
+        let pool_id = testing::create_pool(&mut contract, &owner, PoolType::Trade, &["1"], 2 * testing::NEAR);
+        testing::register(&mut contract, &lp, 10u128.pow(24));
+        testing::deposit_nfts(&mut contract, &lp, &["2"]);
+
+        testing::set_caller(&lp, 3 * testing::NEAR);
+        contract.add_liquidity_batch(vec![
+            LiquidityDeposit { pool_id, token_ids: vec!["2".to_string()], near_amount: U128(2 * testing::NEAR), min_prices: None },
+            LiquidityDeposit { pool_id, token_ids: vec![], near_amount: U128(testing::NEAR), min_prices: None },
+        ]);
+    }
+
+    #[test]
+    fn test_add_liquidity_batch() {
+        let mut contract = testing::setup_contract();
+        let owner = user1();
+        let lp = testing::account("lp.near");
+        testing::register(&mut contract, &owner, 10u128.pow(24));
+        let pool_id = testing::create_pool(&mut contract, &owner, PoolType::Trade, &["1"], 2 * testing::NEAR);
+        let lp_supply = contract.pools[pool_id as usize].lp_supply;
+        testing::register(&mut contract, &lp, 10u128.pow(24));
+        testing::deposit_nfts(&mut contract, &lp, &["2"]);
+
+        testing::set_caller(&lp, 3 * testing::NEAR);
+        contract.add_liquidity_batch(vec![LiquidityDeposit {
+            pool_id,
+            token_ids: vec!["2".to_string()],
+            near_amount: U128(2 * testing::NEAR),
+            min_prices: None,
+        }]);
+        let pool = &contract.pools[pool_id as usize];
+        assert_eq!(pool.lp_balances.get(&lp), Some(lp_supply / 2));
+        assert_eq!(pool.near_balance, 4 * testing::NEAR);
+        // the near above the deposits is credited to the caller
+        assert!(contract.account_deposits.get(&lp).unwrap().available_balance > testing::NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "near only liquidity mints no lp, add nfts along")]
+    fn test_add_liquidity_near_only_to_trade_pool_without_nfts() {
+        let mut contract = testing::setup_contract();
+        let owner = user1();
+        testing::register(&mut contract, &owner, 10u128.pow(24));
+        testing::deposit_nfts(&mut contract, &owner, &["1"]);
+        let pool_id = testing::create_pool(&mut contract, &owner, PoolType::Trade, &[], 10 * testing::NEAR);
+
+        testing::set_caller(&owner, 5 * testing::NEAR);
+        contract.add_liquidity(pool_id, vec![], None);
+    }
+
+    // nft pool locked for 30 days, with a force release of governance queued
+    fn force_released_pool(contract: &mut Contract) -> u64 {
+        let owner = user1();
+        testing::register(contract, &owner, 10u128.pow(24));
+        let pool_id = testing::create_pool(contract, &owner, PoolType::NFT, &["1"], 0);
+        testing::set_caller(&owner, 0);
+        contract.extend_pool_lock(pool_id, env::block_timestamp_ms() / 1000 + 30 * 24 * 3600);
+        testing::set_caller(&testing::governance(), 0);
+        let force_release_at = contract.queue_force_release(pool_id);
+        assert_eq!(force_release_at, env::block_timestamp_ms() / 1000 + pair::FORCE_RELEASE_DELAY_SEC);
+        pool_id
+    }
+
+    #[test]
+    fn test_force_release() {
+        let mut contract = testing::setup_contract();
+        let pool_id = force_released_pool(&mut contract);
+        testing::advance_time_sec(pair::FORCE_RELEASE_DELAY_SEC);
+        contract.force_release(pool_id);
+        let pool = &contract.pools[pool_id as usize];
+        assert_eq!(pool.released_time, env::block_timestamp_ms() / 1000);
+        assert!(pool.force_release_at.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "force release not effective yet")]
+    fn test_force_release_before_delay() {
+        let mut contract = testing::setup_contract();
+        let pool_id = force_released_pool(&mut contract);
+        testing::advance_time_sec(pair::FORCE_RELEASE_DELAY_SEC - 1);
+        contract.force_release(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "only governance")]
+    fn test_force_release_by_owner() {
+        let mut contract = testing::setup_contract();
+        let pool_id = force_released_pool(&mut contract);
+        testing::advance_time_sec(pair::FORCE_RELEASE_DELAY_SEC);
+        testing::set_caller(&user1(), 0);
+        contract.force_release(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "no force release queued")]
+    fn test_force_release_cancelled() {
+        let mut contract = testing::setup_contract();
+        let pool_id = force_released_pool(&mut contract);
+        contract.cancel_force_release(pool_id);
+        testing::advance_time_sec(pair::FORCE_RELEASE_DELAY_SEC);
+        contract.force_release(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "force release already queued")]
+    fn test_queue_force_release_twice() {
+        let mut contract = testing::setup_contract();
+        let pool_id = force_released_pool(&mut contract);
+        contract.queue_force_release(pool_id);
+    }
+
     // #[test]
     // #[should_panic(expected = "The contract is not initialized")]
     // fn test_default() {
@@ -0,0 +1,112 @@
+use crate::*;
+use crate::curves::{expect_u128, U256};
+use near_sdk::json_types::I128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// value of the deposits and withdrawals of an lp in a pool, nfts valued at the spot price of the
+// pool when they moved
+#[derive(BorshDeserialize, BorshSerialize, Clone, Default)]
+pub struct LpCostBasis {
+    pub deposited: Balance,
+    pub withdrawn: Balance,
+    // deposited value still held as lp, reduced in proportion to the lp burnt or transferred
+    pub cost_basis: Balance,
+    // withdrawn value minus the cost basis it took out
+    pub realized_pnl: i128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LpPnl {
+    pub lp_balance: U128,
+    pub deposited: U128,
+    pub withdrawn: U128,
+    pub cost_basis: U128,
+    // lp share of the pool near and nfts at spot price
+    pub position_value: U128,
+    pub realized_pnl: I128,
+    pub unrealized_pnl: I128,
+}
+
+// cost basis of each (pool id, lp), shared by every pool with `Contract::lp_cost_bases`
+fn lp_cost_basis_index() -> LookupMap<(u64, AccountId), LpCostBasis> {
+    LookupMap::new(StorageKey::LpCostBases)
+}
+
+// part of `value` matching `part` out of `whole`
+fn share(value: Balance, part: Balance, whole: Balance) -> Balance {
+    if whole == 0 {
+        return 0;
+    }
+    expect_u128(U256::from(value) * U256::from(part) / U256::from(whole), "cost basis overflow")
+}
+
+impl Pair {
+    fn lp_cost_basis(&self, account_id: &AccountId) -> LpCostBasis {
+        lp_cost_basis_index().get(&(self.pool_id, account_id.clone())).unwrap_or_default()
+    }
+
+    fn set_lp_cost_basis(&self, account_id: &AccountId, cost_basis: &LpCostBasis) {
+        lp_cost_basis_index().insert(&(self.pool_id, account_id.clone()), cost_basis);
+    }
+
+    // near plus nfts at the current spot price
+    pub(crate) fn lp_value_of(&self, near_amount: Balance, num_nfts: u64) -> Balance {
+        expect_u128(U256::from(near_amount) + U256::from(num_nfts) * U256::from(self.spot_price), "lp value overflow")
+    }
+
+    pub(crate) fn record_lp_deposit(&self, account_id: &AccountId, value: Balance) {
+        let mut cost_basis = self.lp_cost_basis(account_id);
+        cost_basis.deposited += value;
+        cost_basis.cost_basis += value;
+        self.set_lp_cost_basis(account_id, &cost_basis);
+    }
+
+    // `lp` out of the `prev_lp` of the account burnt for `value`
+    pub(crate) fn record_lp_withdrawal(&self, account_id: &AccountId, lp: Balance, prev_lp: Balance, value: Balance) {
+        let mut cost_basis = self.lp_cost_basis(account_id);
+        let removed_basis = share(cost_basis.cost_basis, lp, prev_lp);
+        cost_basis.withdrawn += value;
+        cost_basis.cost_basis -= removed_basis;
+        cost_basis.realized_pnl += value as i128 - removed_basis as i128;
+        self.set_lp_cost_basis(account_id, &cost_basis);
+    }
+
+    // the receiver of `amount` out of the `prev_lp` of the sender takes its share of the cost basis
+    pub(crate) fn move_lp_cost_basis(&self, sender_id: &AccountId, receiver_id: &AccountId, amount: Balance, prev_lp: Balance) {
+        let mut sender_basis = self.lp_cost_basis(sender_id);
+        let moved_basis = share(sender_basis.cost_basis, amount, prev_lp);
+        if moved_basis == 0 {
+            return;
+        }
+        sender_basis.cost_basis -= moved_basis;
+        self.set_lp_cost_basis(sender_id, &sender_basis);
+        let mut receiver_basis = self.lp_cost_basis(receiver_id);
+        receiver_basis.cost_basis += moved_basis;
+        self.set_lp_cost_basis(receiver_id, &receiver_basis);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Deposited and withdrawn value of the lp in the pool with its realized and unrealized pnl,
+    /// the position being valued at the current spot price. Lp held before the cost basis was
+    /// tracked has no cost basis, its whole value showing as pnl.
+    pub fn get_lp_pnl(&self, pool_id: u64, account_id: AccountId) -> LpPnl {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let lp_balance = pool.lp_balances.get(&account_id).unwrap_or(0);
+        let cost_basis = self.lp_cost_bases.get(&(pool_id, account_id)).unwrap_or_default();
+        let pool_value = pool.lp_value_of(pool.near_balance.saturating_sub(pool.dust), pool.num_items());
+        let position_value = share(pool_value, lp_balance, pool.lp_supply);
+        LpPnl {
+            lp_balance: lp_balance.into(),
+            deposited: cost_basis.deposited.into(),
+            withdrawn: cost_basis.withdrawn.into(),
+            cost_basis: cost_basis.cost_basis.into(),
+            position_value: position_value.into(),
+            realized_pnl: cost_basis.realized_pnl.into(),
+            unrealized_pnl: (position_value as i128 - cost_basis.cost_basis as i128).into(),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::*;
+use near_sdk::serde::Serialize;
+use near_sdk::near_bindgen;
+
+/// Part of an lp balance that cannot be transferred, and why. Owner liquidity of a locked pool
+/// cannot be burned either.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LpRestriction {
+    pub reason: String,
+    pub amount: U128,
+    // in seconds, none if only lifted by an action
+    pub until: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferableLp {
+    pub balance: U128,
+    pub transferable: U128,
+    pub restrictions: Vec<LpRestriction>,
+}
+
+impl Contract {
+    // every restriction on the lp of the account, each check adding the amount it holds back
+    fn internal_lp_restrictions(&self, pool_id: u64, account_id: &AccountId, balance: Balance) -> Vec<LpRestriction> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let mut restrictions = vec![];
+        let now = env::block_timestamp_ms() / 1000;
+        if account_id == &pool.owner && pool.released_time > now {
+            restrictions.push(LpRestriction {
+                reason: "owner liquidity of a locked pool".to_string(),
+                amount: balance.into(),
+                until: Some(pool.released_time),
+            });
+        }
+        if account_id == &env::current_account_id() {
+            restrictions.push(LpRestriction {
+                reason: "treasury lp only moves through treasury operations".to_string(),
+                amount: balance.into(),
+                until: None,
+            });
+        }
+        restrictions
+    }
+
+    fn internal_transferable_lp(&self, pool_id: u64, account_id: &AccountId) -> TransferableLp {
+        let balance = self.internal_lp_balance(pool_id, account_id);
+        let restrictions = self.internal_lp_restrictions(pool_id, account_id, balance);
+        let restricted = restrictions.iter().map(|restriction| restriction.amount.0).max().unwrap_or(0);
+        TransferableLp {
+            balance: balance.into(),
+            transferable: balance.saturating_sub(restricted).into(),
+            restrictions,
+        }
+    }
+
+    // panics with the restrictions holding back lp the sender has but cannot transfer
+    pub(crate) fn assert_lp_transferable(&self, pool_id: u64, sender_id: &AccountId, amount: Balance) {
+        let lp = self.internal_transferable_lp(pool_id, sender_id);
+        if amount <= lp.transferable.0 || amount > lp.balance.0 {
+            return;
+        }
+        let reasons: Vec<String> = lp.restrictions.iter().map(|restriction| restriction.reason.clone()).collect();
+        env::panic_str(&format!(
+            "only {} of {} lp transferable: {}",
+            lp.transferable.0,
+            lp.balance.0,
+            reasons.join(", ")
+        ));
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_transferable_lp(&self, pool_id: u64, account_id: AccountId) -> TransferableLp {
+        self.internal_transferable_lp(pool_id, &account_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    const LOCK_SEC: u64 = 3600;
+
+    // trade pool of the owner locked for LOCK_SEC
+    fn setup_locked_pool() -> (Contract, u64, AccountId) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        register(&mut contract, &owner, NEAR);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Trade, &["1"], 2 * NEAR);
+        set_caller(&owner, 0);
+        contract.extend_pool_lock(pool_id, env::block_timestamp_ms() / 1000 + LOCK_SEC);
+        (contract, pool_id, owner)
+    }
+
+    #[test]
+    fn test_owner_lp_of_locked_pool() {
+        let (contract, pool_id, owner) = setup_locked_pool();
+        let lp = contract.get_transferable_lp(pool_id, owner);
+        assert!(lp.balance.0 > 0);
+        assert_eq!(lp.transferable.0, 0);
+        assert_eq!(lp.restrictions.len(), 1);
+        assert_eq!(lp.restrictions[0].amount, lp.balance);
+        assert_eq!(lp.restrictions[0].until, Some(env::block_timestamp_ms() / 1000 + LOCK_SEC));
+    }
+
+    #[test]
+    fn test_owner_lp_after_lock() {
+        let (contract, pool_id, owner) = setup_locked_pool();
+        advance_time_sec(LOCK_SEC);
+        let lp = contract.get_transferable_lp(pool_id, owner);
+        assert_eq!(lp.transferable, lp.balance);
+        assert!(lp.restrictions.is_empty());
+    }
+
+    #[test]
+    fn test_treasury_lp() {
+        let (mut contract, pool_id, _) = setup_locked_pool();
+        let pool = &mut contract.pools[pool_id as usize];
+        pool.lp_balances.insert(&contract_id(), &NEAR);
+        pool.lp_supply += NEAR;
+        let lp = contract.get_transferable_lp(pool_id, contract_id());
+        assert_eq!((lp.balance.0, lp.transferable.0), (NEAR, 0));
+        assert_eq!(lp.restrictions[0].until, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "lp transferable: owner liquidity of a locked pool")]
+    fn test_transfer_owner_lp_of_locked_pool() {
+        let (mut contract, pool_id, owner) = setup_locked_pool();
+        set_caller(&owner, 1);
+        contract.lp_transfer(pool_id, account("other.near"), U128(1), None);
+    }
+}
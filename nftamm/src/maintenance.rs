@@ -0,0 +1,122 @@
+use crate::*;
+use crate::events::emit_event;
+use near_sdk::serde::Serialize;
+use near_sdk::near_bindgen;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolSync {
+    pub pool_id: u64,
+    pub num_nfts: u64,
+    pub lp_supply: U128,
+    // counters and lists that did not match the pool tokens and lp balances
+    pub corrections: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SkimEvent<'a> {
+    pub pool_id: u64,
+    pub receiver_id: &'a AccountId,
+    pub near_amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DustCollection {
+    pub pool_id: u64,
+    pub near_amount: U128,
+}
+
+impl Contract {
+    // near the contract owes, refunds of pending near transfers included
+    fn internal_tracked_near(&self) -> Balance {
+        let pools: Balance = self.pools.iter().map(|pool| pool.near_balance + pool.proceeds).sum();
+        let deposits: Balance = self.account_deposits.values().map(|deposit| deposit.total_balance()).sum();
+        let bonds: Balance = self.reservations.values().map(|reservation| reservation.bond.0).sum();
+        let pending: Balance = self
+            .pending_operations
+            .values()
+            .filter(|operation| {
+                matches!(
+                    operation.kind,
+                    OperationKind::PayoutTransfer
+                        | OperationKind::ProtocolFeeClaim
+                        | OperationKind::MarketplaceBuy
+                        | OperationKind::ApprovalBuy
+                )
+            })
+            .map(|operation| operation.amount.0)
+            .sum();
+        pools + deposits + bonds + pending + self.protocol_fee_credit
+            + self.internal_referral_liabilities()
+            + self.internal_bootstrap_bids_near()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Recomputes the counters of the pool from its token map, drops listed ids it no longer
+    /// holds, sets its lp supply to the lp balances and refreshes its collection stats.
+    pub fn sync_pool(&mut self, pool_id: u64) -> PoolSync {
+        self.assert_governance();
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let mut corrections = pool.recount_tokens();
+        let lp_supply = pool.locked_lp + pool.lp_balances.values().sum::<Balance>();
+        if lp_supply != pool.lp_supply {
+            pool.lp_supply = lp_supply;
+            corrections += 1;
+        }
+        let sync = PoolSync { pool_id, num_nfts: pool.num_items(), lp_supply: lp_supply.into(), corrections };
+        self.internal_update_pool_activity(pool_id);
+        emit_event("sync_pool", &sync);
+        sync
+    }
+
+    /// Near of the contract account backing no pool, deposit, bond, pending transfer or
+    /// protocol fee, e.g. refunds of transfers whose callback expired.
+    pub fn get_untracked_near(&self) -> U128 {
+        // storage paid from account deposits is counted twice, skimming never touches storage
+        let storage_cost = env::storage_usage() as Balance * env::storage_byte_cost();
+        env::account_balance()
+            .saturating_sub(storage_cost)
+            .saturating_sub(self.internal_tracked_near())
+            .into()
+    }
+
+    /// Syncs the pool the drift came from and sends the untracked near to the receiver.
+    /// Reads every account deposit, so gas grows with the number of accounts.
+    pub fn skim(&mut self, pool_id: u64, receiver_id: AccountId) -> U128 {
+        self.sync_pool(pool_id);
+        let near_amount = self.get_untracked_near();
+        if near_amount.0 > 0 {
+            Promise::new(receiver_id.clone()).transfer(near_amount.0);
+        }
+        emit_event("skim", SkimEvent { pool_id, receiver_id: &receiver_id, near_amount });
+        near_amount
+    }
+
+    /// Moves the dust of every pool to the protocol fee credit, where the treasury draws from.
+    pub fn collect_dust(&mut self) -> U128 {
+        self.assert_governance();
+        let mut collected = vec![];
+        for pool in self.pools.iter_mut().filter(|pool| pool.dust > 0) {
+            // trades since the last burn may have spent part of it
+            let near_amount = pool.dust.min(pool.near_balance);
+            pool.near_balance -= near_amount;
+            pool.dust = 0;
+            collected.push(DustCollection { pool_id: pool.pool_id, near_amount: near_amount.into() });
+        }
+        let total: Balance = collected.iter().map(|collection| collection.near_amount.0).sum();
+        self.protocol_fee_credit += total;
+        for collection in &collected {
+            self.internal_update_pool_activity(collection.pool_id);
+            emit_event("collect_dust", collection);
+        }
+        total.into()
+    }
+
+    pub fn get_pool_dust(&self, pool_id: u64) -> U128 {
+        self.pools.get(pool_id as usize).expect("pool id invalid").dust.into()
+    }
+}
@@ -0,0 +1,257 @@
+use crate::*;
+use crate::metadata_cache::ext_nft_view;
+use near_contract_standards::non_fungible_token::Token;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{ext_contract, near_bindgen, serde_json, PromiseOrValue, PromiseResult};
+
+const GAS_FOR_NFT_TOKEN_VIEW: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_MARKETPLACE_RESOLVE: Gas = Gas(10_000_000_000_000);
+// checks the purchase then schedules the ownership view and its resolver
+const GAS_FOR_MARKETPLACE_BOUGHT: Gas = Gas(GAS_FOR_NFT_TOKEN_VIEW.0 + 2 * GAS_FOR_MARKETPLACE_RESOLVE.0);
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MarketplaceAdapter {
+    // method buying a listing for the attached near, called with `nft_contract_id`, `token_id`
+    // and `price`, the bought nft being sent to the caller
+    pub buy_method: String,
+    // in tgas
+    pub buy_gas: u64,
+}
+
+#[ext_contract(ext_marketplace_resolver)]
+pub trait MarketplaceResolver {
+    fn fulfill_listing_bought(&mut self, pool_id: u64, marketplace_id: AccountId, token_id: TokenId, price: U128, operation_id: u64) -> PromiseOrValue<bool>;
+    fn fulfill_listing_resolve(&mut self, pool_id: u64, marketplace_id: AccountId, token_id: TokenId, price: U128, operation_id: u64) -> bool;
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Adds or replaces the adapter of a marketplace pools can buy listings from, none removes it.
+    /// Marketplaces are trusted to deliver the nft or refund the near.
+    pub fn set_marketplace_adapter(&mut self, marketplace_id: AccountId, adapter: Option<MarketplaceAdapter>) {
+        self.assert_governance();
+        match adapter {
+            Some(adapter) => {
+                require!(!adapter.buy_method.is_empty(), "empty buy method");
+                self.marketplace_adapters.insert(&marketplace_id, &adapter);
+            }
+            None => {
+                self.marketplace_adapters.remove(&marketplace_id);
+            }
+        }
+    }
+
+    pub fn get_marketplace_adapters(&self) -> Vec<(AccountId, MarketplaceAdapter)> {
+        self.marketplace_adapters.to_vec()
+    }
+
+    /// Lets keepers spend the pool near on marketplace listings priced under the pool bid.
+    pub fn set_marketplace_fulfillment(&mut self, pool_id: u64, enabled: bool) {
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        pool.marketplace_fulfillment = enabled;
+    }
+
+    /// Buys a listing of the pool collection from a marketplace with the pool near when `price`
+    /// is below what the pool pays a seller for one nft, the nft joining the pool as an acquired
+    /// one. The curve is left unchanged. Callable by anyone for pools that opted in.
+    pub fn fulfill_listing(&mut self, pool_id: u64, marketplace_id: AccountId, token_id: TokenId, price: U128) -> Promise {
+        let adapter = self
+            .marketplace_adapters
+            .get(&marketplace_id)
+            .unwrap_or_else(|| env::panic_str("unknown marketplace"));
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pool_id);
+        self.assert_not_flagged(pool_id, std::slice::from_ref(&token_id));
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        require!(pool.marketplace_fulfillment, "marketplace fulfillment disabled");
+        pool.assert_sells_enabled();
+        pool.assert_nft_pool();
+        require!(pool.pool_type != PoolType::NFT, "nft pools do not buy nfts");
+        require!(pool.asset_recipient.is_none(), "pool sends bought nfts to its asset recipient");
+        require!(!pool.token_ids_in_pools.contains(&token_id), "token already in pool");
+        require!(
+            self.token_locations.get(&(pool.nft_token.clone(), token_id.clone())).is_none(),
+            "token already held by the contract"
+        );
+        require!(pool.max_nfts.map_or(true, |max| pool.num_items() < max), "pool nft cap reached");
+        let quote = pool
+            .quote_sell(1, protocol_fee_multiplier)
+            .unwrap_or_else(|error_code| env::panic_str(&format!("failed to get sell info: {:?}", error_code)));
+        require!(price.0 < quote.near_amount, "listing not below pool bid");
+        require!(price.0 <= pool.near_balance, "not enough near in pool");
+        // reserved until resolved, given back to the pool if the nft is not delivered
+        pool.near_balance -= price.0;
+        let nft_token = pool.nft_token.clone();
+        self.internal_update_pool_activity(pool_id);
+
+        let operation_id = self.internal_start_operation(OperationKind::MarketplaceBuy, &marketplace_id, price.0);
+        let args = serde_json::json!({
+            "nft_contract_id": nft_token,
+            "token_id": token_id,
+            "price": price,
+        });
+        Promise::new(marketplace_id.clone())
+            .function_call(
+                adapter.buy_method,
+                args.to_string().into_bytes(),
+                price.0,
+                Gas(adapter.buy_gas * 10u64.pow(12)),
+            )
+            .then(
+                ext_marketplace_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_MARKETPLACE_BOUGHT)
+                    .fulfill_listing_bought(pool_id, marketplace_id, token_id, price, operation_id),
+            )
+    }
+
+    /// Checks the ownership of the nft if the marketplace purchase succeeded, otherwise returns
+    /// the reserved near to the pool.
+    #[private]
+    pub fn fulfill_listing_bought(&mut self, pool_id: u64, marketplace_id: AccountId, token_id: TokenId, price: U128, operation_id: u64) -> PromiseOrValue<bool> {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            let nft_token = self.get_nft_asset_id(pool_id);
+            return PromiseOrValue::Promise(
+                ext_nft_view::ext(nft_token)
+                    .with_static_gas(GAS_FOR_NFT_TOKEN_VIEW)
+                    .nft_token(token_id.clone())
+                    .then(
+                        ext_marketplace_resolver::ext(env::current_account_id())
+                            .with_static_gas(GAS_FOR_MARKETPLACE_RESOLVE)
+                            .fulfill_listing_resolve(pool_id, marketplace_id, token_id, price, operation_id),
+                    ),
+            );
+        }
+        self.internal_finish_operation(operation_id, OperationKind::MarketplaceBuy, &marketplace_id, price.0);
+        self.pools[pool_id as usize].near_balance += price.0;
+        self.internal_update_pool_activity(pool_id);
+        PromiseOrValue::Value(false)
+    }
+
+    /// Adds the nft to the pool if the contract now owns it, otherwise returns the reserved near
+    /// to the pool.
+    #[private]
+    pub fn fulfill_listing_resolve(&mut self, pool_id: u64, marketplace_id: AccountId, token_id: TokenId, price: U128, operation_id: u64) -> bool {
+        self.internal_finish_operation(operation_id, OperationKind::MarketplaceBuy, &marketplace_id, price.0);
+        let owned = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<Option<Token>>(&value)
+                .ok()
+                .flatten()
+                .map_or(false, |token| token.owner_id == env::current_account_id()),
+            _ => false,
+        };
+        // a token already held elsewhere by the contract was not delivered by the marketplace
+        let asset_id = self.get_nft_asset_id(pool_id);
+        let owned = owned && self.token_locations.get(&(asset_id, token_id.clone())).is_none();
+        let pool = &mut self.pools[pool_id as usize];
+        if owned {
+            pool.add_acquired_token(&token_id);
+            log!("pool {} bought {} from {} for {}", pool_id, token_id, marketplace_id, price.0);
+        } else {
+            pool.near_balance += price.0;
+        }
+        self.internal_update_pool_activity(pool_id);
+        owned
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    const PRICE: Balance = 4 * NEAR / 10;
+
+    fn market() -> AccountId {
+        account("market.near")
+    }
+
+    // token pool with 10 near fulfilling listings of `market()`
+    fn setup_fulfillment() -> (Contract, u64) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        register(&mut contract, &owner, NEAR);
+        // creating a pool withdraws from the deposit of the collection, even without nfts
+        deposit_nfts(&mut contract, &owner, &["1"]);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Token, &[], 10 * NEAR);
+        set_caller(&governance(), 0);
+        contract.set_marketplace_adapter(market(), Some(MarketplaceAdapter { buy_method: "buy".to_string(), buy_gas: 50 }));
+        set_caller(&owner, 0);
+        contract.set_marketplace_fulfillment(pool_id, true);
+        (contract, pool_id)
+    }
+
+    // purchase of nft 7 at PRICE by a keeper, the marketplace call ending with `result`
+    fn fulfill(contract: &mut Contract, pool_id: u64, result: PromiseResult) -> PromiseOrValue<bool> {
+        set_caller(&account("keeper.near"), 0);
+        let operation_id = contract.next_operation_id;
+        contract.fulfill_listing(pool_id, market(), "7".to_string(), U128(PRICE));
+        assert_eq!(contract.pools[pool_id as usize].near_balance, 10 * NEAR - PRICE);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == market()));
+        set_promise_result(result);
+        contract.fulfill_listing_bought(pool_id, market(), "7".to_string(), U128(PRICE), operation_id)
+    }
+
+    // ownership view of nft 7 answering `owner_id`
+    fn resolve(contract: &mut Contract, pool_id: u64, owner_id: AccountId) -> bool {
+        let token = Token { token_id: "7".to_string(), owner_id, metadata: None, approved_account_ids: None };
+        set_promise_result(PromiseResult::Successful(serde_json::to_vec(&Some(token)).unwrap()));
+        contract.fulfill_listing_resolve(pool_id, market(), "7".to_string(), U128(PRICE), contract.next_operation_id - 1)
+    }
+
+    #[test]
+    fn test_fulfill_listing() {
+        let (mut contract, pool_id) = setup_fulfillment();
+        assert!(matches!(fulfill(&mut contract, pool_id, PromiseResult::Successful(vec![])), PromiseOrValue::Promise(_)));
+        assert!(resolve(&mut contract, pool_id, contract_id()));
+        let pool = &contract.pools[pool_id as usize];
+        assert!(pool.token_ids_in_pools.contains(&"7".to_string()));
+        assert_eq!(pool.num_acquired_tokens, 1);
+        assert_eq!(pool.near_balance, 10 * NEAR - PRICE);
+    }
+
+    #[test]
+    fn test_fulfill_listing_failed_purchase() {
+        let (mut contract, pool_id) = setup_fulfillment();
+        assert!(matches!(fulfill(&mut contract, pool_id, PromiseResult::Failed), PromiseOrValue::Value(false)));
+        assert_eq!(contract.pools[pool_id as usize].near_balance, 10 * NEAR);
+        assert!(contract.pending_operations.get(&(contract.next_operation_id - 1)).is_none());
+    }
+
+    #[test]
+    fn test_fulfill_listing_not_delivered() {
+        let (mut contract, pool_id) = setup_fulfillment();
+        fulfill(&mut contract, pool_id, PromiseResult::Successful(vec![]));
+        assert!(!resolve(&mut contract, pool_id, account("buyer.near")));
+        let pool = &contract.pools[pool_id as usize];
+        assert!(!pool.token_ids_in_pools.contains(&"7".to_string()));
+        assert_eq!(pool.near_balance, 10 * NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "listing not below pool bid")]
+    fn test_fulfill_listing_above_pool_bid() {
+        let (mut contract, pool_id) = setup_fulfillment();
+        set_caller(&account("keeper.near"), 0);
+        contract.fulfill_listing(pool_id, market(), "7".to_string(), U128(SPOT_PRICE));
+    }
+
+    #[test]
+    #[should_panic(expected = "token already held by the contract")]
+    fn test_fulfill_listing_of_deposited_token() {
+        let (mut contract, pool_id) = setup_fulfillment();
+        set_caller(&account("keeper.near"), 0);
+        contract.fulfill_listing(pool_id, market(), "1".to_string(), U128(PRICE));
+    }
+
+    #[test]
+    #[should_panic(expected = "marketplace fulfillment disabled")]
+    fn test_fulfill_listing_disabled() {
+        let (mut contract, pool_id) = setup_fulfillment();
+        set_caller(&account("owner.near"), 0);
+        contract.set_marketplace_fulfillment(pool_id, false);
+        set_caller(&account("keeper.near"), 0);
+        contract.fulfill_listing(pool_id, market(), "7".to_string(), U128(PRICE));
+    }
+}
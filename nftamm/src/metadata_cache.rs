@@ -0,0 +1,142 @@
+use crate::*;
+use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
+use near_contract_standards::non_fungible_token::Token;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{ext_contract, near_bindgen, serde_json, PromiseResult};
+
+const GAS_FOR_NFT_TOKEN_VIEW: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_METADATA_RESOLVE: Gas = Gas(10_000_000_000_000);
+
+// subset of the token metadata shown by frontends
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CachedTokenMetadata {
+    pub title: Option<String>,
+    pub media: Option<String>,
+    // false if provided by the depositor in the transfer msg, true once read from the nft contract
+    #[serde(default)]
+    pub fetched: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolTokenWithMetadata {
+    pub token_id: TokenId,
+    pub metadata: Option<CachedTokenMetadata>,
+}
+
+#[ext_contract(ext_nft_view)]
+pub trait NftView {
+    fn nft_token(&self, token_id: TokenId) -> Option<Token>;
+}
+
+#[ext_contract(ext_metadata_resolver)]
+pub trait MetadataResolver {
+    fn token_metadata_resolve(&mut self, account_id: AccountId, asset_id: AssetId, token_id: TokenId, operation_id: u64) -> bool;
+}
+
+impl Contract {
+    // the caller pays the storage of the cached entry
+    pub(crate) fn internal_cache_token_metadata(
+        &mut self,
+        account_id: &AccountId,
+        asset_id: &AssetId,
+        token_id: &TokenId,
+        metadata: &CachedTokenMetadata,
+    ) {
+        let prev_storage = env::storage_usage();
+        self.token_metadata.insert(&(asset_id.clone(), token_id.clone()), metadata);
+        self.assert_storage(account_id, prev_storage, None);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Reads the token metadata from the nft contract and caches it, replacing metadata
+    /// provided at deposit.
+    pub fn refresh_token_metadata(&mut self, asset_id: AssetId, token_id: TokenId) -> Promise {
+        let account_id = env::predecessor_account_id();
+        self.internal_get_account_or_revert(&account_id);
+        let operation_id = self.internal_start_operation(OperationKind::TokenMetadata, &account_id, 0);
+        ext_nft_view::ext(asset_id.clone())
+            .with_static_gas(GAS_FOR_NFT_TOKEN_VIEW)
+            .nft_token(token_id.clone())
+            .then(
+                ext_metadata_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_METADATA_RESOLVE)
+                    .token_metadata_resolve(account_id, asset_id, token_id, operation_id),
+            )
+    }
+
+    #[private]
+    pub fn token_metadata_resolve(&mut self, account_id: AccountId, asset_id: AssetId, token_id: TokenId, operation_id: u64) -> bool {
+        self.internal_finish_operation(operation_id, OperationKind::TokenMetadata, &account_id, 0);
+        let token = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<Option<Token>>(&value).ok().flatten(),
+            _ => None,
+        };
+        let metadata = match token.and_then(|token| token.metadata) {
+            Some(metadata) => metadata,
+            None => return false,
+        };
+        self.internal_cache_token_metadata(
+            &account_id,
+            &asset_id,
+            &token_id,
+            &CachedTokenMetadata { title: metadata.title, media: metadata.media, fetched: true },
+        );
+        true
+    }
+
+    pub fn get_token_metadata(&self, asset_id: AssetId, token_id: TokenId) -> Option<CachedTokenMetadata> {
+        self.token_metadata.get(&(asset_id, token_id))
+    }
+
+    pub fn get_pool_tokens_with_metadata(&self, pool_id: u64, from_index: Option<u64>, limit: Option<u64>) -> Vec<PoolTokenWithMetadata> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.token_ids_in_pools
+            .keys()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .map(|token_id| PoolTokenWithMetadata {
+                metadata: self.token_metadata.get(&(pool.nft_token.clone(), token_id.clone())),
+                token_id,
+            })
+            .collect()
+    }
+
+    /// Pool tokens in the shape of NEP-181 `nft_tokens`, owned by the contract holding them for
+    /// the pool, with the cached title and media as metadata.
+    pub fn nft_tokens_for_pool(&self, pool_id: u64, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.token_ids_in_pools
+            .keys()
+            .skip(from_index.map_or(0, |index| index.0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .map(|token_id| Token {
+                metadata: self.token_metadata.get(&(pool.nft_token.clone(), token_id.clone())).map(|cached| TokenMetadata {
+                    title: cached.title,
+                    description: None,
+                    media: cached.media,
+                    media_hash: None,
+                    copies: None,
+                    issued_at: None,
+                    expires_at: None,
+                    starts_at: None,
+                    updated_at: None,
+                    extra: None,
+                    reference: None,
+                    reference_hash: None,
+                }),
+                token_id,
+                owner_id: env::current_account_id(),
+                approved_account_ids: None,
+            })
+            .collect()
+    }
+
+    pub fn nft_supply_for_pool(&self, pool_id: u64) -> U128 {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        U128(pool.token_ids_in_pools.len() as u128)
+    }
+}
@@ -0,0 +1,394 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{near_bindgen, IntoStorageKey};
+
+use crate::*;
+use crate::curves::curve::Curve;
+use crate::token_locations::insert_token_location;
+
+// Layouts of the contract state as deployed before `migrate`, read once by it.
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacyContract {
+    pub governance_id: AccountId,
+    pub protocol_fee_receiver_id: AccountId,
+    pub protocol_fee_credit: Balance,
+    pub pools: Vec<LegacyPair>,
+    pub protocol_fee_multiplier: Balance,
+    pub account_deposits: UnorderedMap<AccountId, LegacyAccountDeposit>,
+    // remeasured by `migrate` for the new layouts
+    #[allow(dead_code)]
+    pub storage_per_account_creation: StorageUsage,
+    #[allow(dead_code)]
+    pub storage_per_nft_deposit: StorageUsage,
+    #[allow(dead_code)]
+    pub storage_per_pair_creation: StorageUsage,
+    pub created_pool_ids: UnorderedMap<AccountId, Vec<u64>>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacyPair {
+    pub curve: Curve,
+    pub pool_type: PoolType,
+    pub nft_token: AssetId,
+    pub spot_price: u128,
+    pub delta: u128,
+    pub fee: u128,
+    pub owner: AccountId,
+    pub asset_recipient: Option<AccountId>,
+    pub near_balance: Balance,
+    // under StorageKey::TokenIdsInPools, moved to `PoolTokens` by `migrate`
+    pub token_ids_in_pools: UnorderedMap<TokenId, LegacyDepositedToken>,
+    pub released_time: u64,
+    pub pool_id: u64,
+    pub lp_balances: UnorderedMap<AccountId, Balance>,
+    pub lp_supply: Balance,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacyAccountDeposit {
+    pub assets: UnorderedMap<AssetId, UnorderedMap<TokenId, bool>>,
+    // storage reserve and available balance together
+    pub near_balance: Balance,
+    pub storage_usage: StorageUsage,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct LegacyDepositedToken {
+    pub depositor: AccountId,
+    pub min_price: Balance,
+}
+
+impl LegacyPair {
+    // the pool with its balances, lps and tokens, the settings added since taking their defaults
+    fn migrate(mut self) -> Pair {
+        let mut pair = Pair::new(
+            self.curve.curve_type,
+            self.pool_type,
+            self.nft_token,
+            self.spot_price,
+            self.delta,
+            self.fee,
+            self.owner,
+            self.asset_recipient,
+            self.near_balance,
+            self.released_time,
+            self.pool_id,
+        );
+        pair.lp_balances = self.lp_balances;
+        pair.lp_supply = self.lp_supply;
+        for (token_id, legacy_token) in self.token_ids_in_pools.to_vec() {
+            pair.token_ids_in_pools.insert(&token_id, &legacy_token.into());
+            pair.internal_index_token(&token_id);
+        }
+        self.token_ids_in_pools.clear();
+        pair.recount_tokens();
+        pair
+    }
+}
+
+impl LegacyAccountDeposit {
+    // splits the near balance into the reserve covering the storage usage and the rest
+    fn migrate(self) -> AccountDeposit {
+        let mut account_deposit = AccountDeposit {
+            assets: self.assets,
+            storage_reserve: 0,
+            available_balance: self.near_balance,
+            storage_usage: self.storage_usage,
+        };
+        // a balance short of the storage cost is kept whole as reserve
+        account_deposit.settle_storage_reserve();
+        account_deposit
+    }
+}
+
+// handle of the same stored map read with another value layout, its length included
+fn retype_map<K, V, W>(map: &UnorderedMap<K, V>) -> UnorderedMap<K, W>
+where
+    K: BorshSerialize + BorshDeserialize,
+    V: BorshSerialize + BorshDeserialize,
+    W: BorshSerialize + BorshDeserialize,
+{
+    UnorderedMap::try_from_slice(&map.try_to_vec().unwrap()).unwrap()
+}
+
+// An UnorderedMap serializes its `prefix + 'i'` index prefix first, which tells the key it was created with.
+fn map_has_prefix<K, V>(map: &UnorderedMap<K, V>, prefix: &[u8]) -> bool
+where
+    K: BorshSerialize + BorshDeserialize,
+    V: BorshSerialize + BorshDeserialize,
+{
+    let mut expected = prefix.to_vec();
+    expected.push(b'i');
+    let expected = expected.try_to_vec().unwrap();
+    map.try_to_vec().unwrap().starts_with(&expected)
+}
+
+// Moves all entries into a new map created with `prefix`, unless the map already uses it.
+fn rekey_map<K, V>(map: &mut UnorderedMap<K, V>, prefix: Vec<u8>) -> bool
+where
+    K: BorshSerialize + BorshDeserialize,
+    V: BorshSerialize + BorshDeserialize,
+{
+    if map_has_prefix(map, &prefix) {
+        return false;
+    }
+    let mut new_map = UnorderedMap::new(prefix);
+    for (key, value) in map.iter() {
+        new_map.insert(&key, &value);
+    }
+    map.clear();
+    *map = new_map;
+    true
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Rebuilds the state deployed before the pools, accounts and settings added since, the new
+    /// settings taking their `new` defaults. Pool tokens are moved from the legacy token maps and
+    /// indexed, the other indexes being then filled by `index_active_pools` and
+    /// `index_deposit_tokens`.
+    #[init(ignore_state)]
+    #[private]
+    pub fn migrate() -> Self {
+        let legacy: LegacyContract = env::state_read().unwrap_or_else(|| env::panic_str("no state to migrate"));
+        let mut this = Self::with_defaults(legacy.governance_id, legacy.protocol_fee_receiver_id, legacy.protocol_fee_multiplier);
+        this.protocol_fee_credit = legacy.protocol_fee_credit;
+        // the stored maps keep their length in their handle, only the legacy handles can be written
+        this.created_pool_ids = legacy.created_pool_ids;
+        // each record rewritten in place
+        this.account_deposits = retype_map(&legacy.account_deposits);
+        for (account_id, legacy_deposit) in legacy.account_deposits.to_vec() {
+            this.account_deposits.insert(&account_id, &legacy_deposit.migrate());
+        }
+        // measured before the pools are restored, the measurement clearing them
+        this.measure_storage_usage();
+        this.pools = legacy.pools.into_iter().map(LegacyPair::migrate).collect();
+        for pool_id in 0..this.pools.len() {
+            let asset_id = this.pools[pool_id].nft_token.clone();
+            this.internal_add_asset_pool(&asset_id, pool_id as u64);
+        }
+        this
+    }
+
+    /// Moves deposits of accounts `[from_index, from_index + limit)` created with the legacy
+    /// account id based storage keys to hashed keys, lowering their storage usage.
+    /// Returns the number of accounts migrated.
+    pub fn migrate_storage_keys(&mut self, from_index: Option<u64>, limit: Option<u64>) -> u64 {
+        self.assert_governance();
+        let account_ids = self
+            .account_deposits
+            .keys()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .collect::<Vec<AccountId>>();
+
+        let mut migrated = 0u64;
+        for account_id in &account_ids {
+            if self.internal_migrate_account_storage_keys(account_id) {
+                migrated += 1;
+            }
+        }
+        migrated
+    }
+
+    /// Adds pools `[from_index, from_index + limit)` created before the active pool and collection
+    /// indexes to them.
+    pub fn index_active_pools(&mut self, from_index: Option<u64>, limit: Option<u64>) {
+        self.assert_governance();
+        let from_index = from_index.unwrap_or(0);
+        let to_index = from_index.saturating_add(limit.unwrap_or(u64::MAX)).min(self.pools.len() as u64);
+        for pool_id in from_index..to_index {
+            self.internal_update_pool_activity(pool_id);
+        }
+    }
+
+    /// Adds tokens `[from_index, from_index + limit)` of a pool, deposited before the token
+    /// index, to it. Tokens of the legacy token maps are indexed by `migrate` as it moves them.
+    pub fn index_pool_tokens(&mut self, pool_id: u64, from_index: Option<u64>, limit: Option<u64>) {
+        self.assert_governance();
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        for token_id in pool
+            .token_ids_in_pools
+            .keys()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+        {
+            pool.internal_index_token(&token_id);
+        }
+    }
+
+    /// Records the location of the account deposited tokens of a collection, deposited before
+    /// the token location index.
+    pub fn index_deposit_tokens(&mut self, account_id: AccountId, asset_id: AssetId) {
+        self.assert_governance();
+        let account_deposit = self.internal_get_account_or_revert(&account_id);
+        if let Some(token_ids) = account_deposit.assets.get(&asset_id) {
+            for token_id in token_ids.keys() {
+                insert_token_location(&asset_id, &token_id, TokenLocation::AccountDeposit(account_id.clone()));
+            }
+        }
+    }
+}
+
+impl Contract {
+    fn internal_migrate_account_storage_keys(&mut self, account_id: &AccountId) -> bool {
+        let prev_storage = env::storage_usage();
+        let mut account_deposit = self.internal_get_account_or_revert(account_id);
+        let mut migrated = false;
+
+        for asset_id in account_deposit.assets.keys_as_vector().to_vec() {
+            let mut token_ids = account_deposit.assets.get(&asset_id).unwrap();
+            let prefix = StorageKey::asset_deposit(account_id, &asset_id).into_storage_key();
+            if rekey_map(&mut token_ids, prefix) {
+                account_deposit.assets.insert(&asset_id, &token_ids);
+                migrated = true;
+            }
+        }
+        let prefix = StorageKey::account_deposit_assets(account_id).into_storage_key();
+        migrated |= rekey_map(&mut account_deposit.assets, prefix);
+
+        if migrated {
+            self.account_deposits.insert(account_id, &account_deposit);
+            self.internal_release_storage(account_id, prev_storage);
+        }
+        migrated
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::pair::DepositedToken;
+
+    fn account(name: &str) -> AccountId {
+        name.parse().unwrap()
+    }
+
+    // state of the contract as deployed before `migrate`, with one trade pool and one account
+    fn write_legacy_state() {
+        let owner = account("owner.near");
+        let mut lp_balances = UnorderedMap::new(StorageKey::PoolShare { pool_id: 0 });
+        lp_balances.insert(&owner, &1_000);
+        let mut created_pool_ids = UnorderedMap::new(StorageKey::CreatedPoolIds);
+        created_pool_ids.insert(&owner, &vec![0]);
+        let mut token_ids_in_pools = UnorderedMap::new(StorageKey::TokenIdsInPools { pool_id: 0 });
+        token_ids_in_pools.insert(&"1".to_string(), &LegacyDepositedToken { depositor: owner.clone(), min_price: 5 });
+        token_ids_in_pools.insert(&"2".to_string(), &LegacyDepositedToken { depositor: owner.clone(), min_price: 0 });
+        let mut account_deposits = UnorderedMap::new(StorageKey::AccountDeposits);
+        account_deposits.insert(
+            &owner,
+            &LegacyAccountDeposit {
+                assets: UnorderedMap::new(StorageKey::AccountDepositAsset { account_id: owner.clone() }),
+                near_balance: 10u128.pow(24),
+                storage_usage: 500,
+            },
+        );
+        let legacy = LegacyContract {
+            governance_id: account("gov.near"),
+            protocol_fee_receiver_id: account("fees.near"),
+            protocol_fee_credit: 7,
+            pools: vec![LegacyPair {
+                curve: Curve::new(BondingCurve::LinearCurve),
+                pool_type: PoolType::Trade,
+                nft_token: account("nft.near"),
+                spot_price: 10u128.pow(24),
+                delta: 10u128.pow(22),
+                fee: 10u128.pow(16),
+                owner: owner.clone(),
+                asset_recipient: None,
+                near_balance: 5 * 10u128.pow(24),
+                token_ids_in_pools,
+                released_time: 100,
+                pool_id: 0,
+                lp_balances,
+                lp_supply: 1_000,
+            }],
+            protocol_fee_multiplier: 10u128.pow(16),
+            account_deposits,
+            storage_per_account_creation: 1,
+            storage_per_nft_deposit: 1,
+            storage_per_pair_creation: 1,
+            created_pool_ids,
+        };
+        env::state_write(&legacy);
+    }
+
+    #[test]
+    fn test_migrate_legacy_deposited_token() {
+        let legacy = LegacyDepositedToken { depositor: account("lp.near"), min_price: 3 };
+        let bytes = legacy.try_to_vec().unwrap();
+        // the legacy layout does not decode as the current one
+        assert!(DepositedToken::try_from_slice(&bytes).is_err());
+
+        let deposited_token = DepositedToken::from(LegacyDepositedToken::try_from_slice(&bytes).unwrap());
+        assert!(!deposited_token.is_acquired());
+        assert_eq!(deposited_token.try_to_vec().unwrap()[0], 1);
+    }
+
+    #[test]
+    fn test_migrate_legacy_state() {
+        let contract_id = account("amm.near");
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(contract_id.clone())
+            .predecessor_account_id(contract_id)
+            .build());
+        write_legacy_state();
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.governance_id, account("gov.near"));
+        assert_eq!(contract.protocol_fee_receiver_id, account("fees.near"));
+        assert_eq!(contract.protocol_fee_credit, 7);
+        assert_eq!(contract.protocol_fee_multiplier, 10u128.pow(16));
+        assert_eq!(contract.created_pool_ids.get(&account("owner.near")), Some(vec![0]));
+        assert!(contract.storage_per_pair_creation > 1);
+        assert_eq!(contract.fee_tiers, DEFAULT_FEE_TIERS.to_vec());
+
+        assert_eq!(contract.pools.len(), 1);
+        let pool = &contract.pools[0];
+        assert!(pool.pool_type == PoolType::Trade);
+        assert_eq!(pool.owner, account("owner.near"));
+        assert_eq!(pool.spot_price, 10u128.pow(24));
+        assert_eq!(pool.delta, 10u128.pow(22));
+        assert_eq!(pool.fee.0, 10u128.pow(16));
+        assert_eq!(pool.near_balance, 5 * 10u128.pow(24));
+        assert_eq!(pool.released_time, 100);
+        assert_eq!(pool.lp_supply, 1_000);
+        assert_eq!(pool.lp_balances.get(&account("owner.near")), Some(1_000));
+        assert_eq!(pool.locked_lp, 0);
+        assert!(!pool.deny_flagged);
+        assert_eq!(pool.token_ids_in_pools.len(), 2);
+        assert!(pool.token_ids_in_pools.contains(&"1".to_string()));
+        assert!(pool.token_ids_in_pools.contains(&"2".to_string()));
+        assert_eq!(pool.num_priced_tokens, 1);
+        assert_eq!(pool.num_acquired_tokens, 0);
+        let legacy_tokens = UnorderedMap::<TokenId, LegacyDepositedToken>::new(StorageKey::TokenIdsInPools { pool_id: 0 });
+        assert!(legacy_tokens.get(&"1".to_string()).is_none());
+        assert!(legacy_tokens.get(&"2".to_string()).is_none());
+        assert!(contract.token_locations.get(&(account("nft.near"), "1".to_string())) == Some(TokenLocation::Pool(0)));
+        assert_eq!(contract.asset_pool_ids.get(&account("nft.near")), Some(vec![0]));
+
+        let account_deposit = contract.account_deposits.get(&account("owner.near")).unwrap();
+        let storage_cost = 500 * env::storage_byte_cost();
+        assert_eq!(contract.account_deposits.len(), 1);
+        assert_eq!(account_deposit.storage_usage, 500);
+        assert_eq!(account_deposit.storage_reserve, storage_cost);
+        assert_eq!(account_deposit.available_balance, 10u128.pow(24) - storage_cost);
+    }
+
+    #[test]
+    fn test_rekey_map() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut map = UnorderedMap::<u64, u64>::new(b"old".to_vec());
+        map.insert(&1, &10);
+        assert!(map_has_prefix(&map, b"old"));
+        assert!(!map_has_prefix(&map, b"new"));
+
+        assert!(rekey_map(&mut map, b"new".to_vec()));
+        assert!(!rekey_map(&mut map, b"new".to_vec()));
+        assert_eq!(map.get(&1), Some(10));
+        assert!(UnorderedMap::<u64, u64>::new(b"old".to_vec()).get(&1).is_none());
+    }
+}
@@ -1,9 +1,11 @@
 use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     assert_one_yocto, ext_contract, near_bindgen, Balance, PromiseOrValue, PromiseResult,
 };
 
 use crate::*;
+use crate::events::{emit_event, LpBatchTransferEvent, LpTransferEntry};
 use crate::{GAS_FOR_NFT_TRANSFER_CALL, GAS_FOR_RESOLVE_TRANSFER};
 
 #[ext_contract(ext_self)]
@@ -14,6 +16,7 @@ trait MFTTokenResolver {
         sender_id: AccountId,
         receiver_id: AccountId,
         amount: U128,
+        operation_id: u64,
     ) -> U128;
 }
 
@@ -28,6 +31,14 @@ pub trait MFTTokenReceiver {
     ) -> PromiseOrValue<U128>;
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LpSplit {
+    pub pool_id: u64,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+}
+
 #[near_bindgen]
 impl Contract {
     fn internal_lp_transfer(
@@ -40,6 +51,7 @@ impl Contract {
     ) {
         // [AUDIT_07]
         require!(sender_id != receiver_id, "Cannot transfer to self");
+        self.assert_lp_transferable(pool_id, sender_id, amount);
 
         let pool = &mut self.pools[pool_id as usize];
         pool.lp_transfer(sender_id, receiver_id, amount);
@@ -56,7 +68,45 @@ impl Contract {
         }
     }
 
-    fn internal_lp_balance(&self, pool_id: u64, account_id: &AccountId) -> Balance {
+    // registers receivers on the fly and logs a single event for all the transfers
+    fn internal_lp_batch_transfer(&mut self, sender_id: &AccountId, transfers: &[LpSplit]) {
+        require!(env::attached_deposit() >= 1, "requires attached deposit of at least 1 yoctoNEAR");
+        let prev_storage = env::storage_usage();
+        for transfer in transfers {
+            require!(sender_id != &transfer.receiver_id, "Cannot transfer to self");
+            self.assert_lp_transferable(transfer.pool_id, sender_id, transfer.amount.0);
+            let pool = self.pools.get_mut(transfer.pool_id as usize).expect("invalid pool_id");
+            pool.internal_register_account_lp(&transfer.receiver_id);
+            pool.lp_transfer(sender_id, &transfer.receiver_id, transfer.amount.0);
+        }
+        self.internal_charge_lp_storage(prev_storage);
+        emit_event("lp_batch_transfer", LpBatchTransferEvent {
+            sender_id,
+            transfers: transfers
+                .iter()
+                .map(|transfer| LpTransferEntry {
+                    pool_id: transfer.pool_id,
+                    receiver_id: &transfer.receiver_id,
+                    amount: transfer.amount,
+                })
+                .collect(),
+        });
+    }
+
+    // pays the storage used since `prev_storage` with the attached deposit, refunding the rest
+    fn internal_charge_lp_storage(&self, prev_storage: StorageUsage) {
+        let used_storage = env::storage_usage().saturating_sub(prev_storage);
+        let used_near = used_storage as u128 * env::storage_byte_cost();
+        require!(
+            env::attached_deposit() >= used_near,
+            "used near exceed atttached deposit"
+        );
+        if env::attached_deposit() > used_near {
+            Promise::new(env::predecessor_account_id()).transfer(env::attached_deposit() - used_near);
+        }
+    }
+
+    pub(crate) fn internal_lp_balance(&self, pool_id: u64, account_id: &AccountId) -> Balance {
         let pool = self.pools.get(pool_id as usize).expect("invalid pool_id");
         pool.lp_balances.get(account_id).unwrap_or(0)
     }
@@ -89,6 +139,23 @@ impl Contract {
         Promise::new(env::predecessor_account_id()).transfer(env::attached_deposit() - used_near);
     }
 
+    /// Registers LP tokens of all given pools for the account, charging the attached deposit
+    /// once for the total storage used and refunding the rest.
+    #[payable]
+    pub fn lp_register_batch(&mut self, pool_ids: Vec<u64>, account_id: AccountId) {
+        let prev_storage = env::storage_usage();
+        for pool_id in pool_ids {
+            let pool = self.pools.get_mut(pool_id as usize).expect("invalid pool_id");
+            pool.internal_register_account_lp(&account_id);
+        }
+        self.internal_charge_lp_storage(prev_storage);
+    }
+
+    pub fn lp_is_registered(&self, pool_id: u64, account_id: AccountId) -> bool {
+        let pool = self.pools.get(pool_id as usize).expect("invalid pool_id");
+        pool.lp_balances.get(&account_id).is_some()
+    }
+
     /// Transfer one of internal tokens: LP or balances.
     /// `token_id` can either by account of the token or pool number.
     #[payable]
@@ -109,6 +176,36 @@ impl Contract {
         );
     }
 
+    /// Splits the caller lp of one or several pools between receivers, e.g. a treasury handing
+    /// positions out to its members. Unregistered receivers are registered, the attached deposit
+    /// paying their storage, and the rest of it is refunded.
+    #[payable]
+    pub fn lp_split(&mut self, transfers: Vec<LpSplit>) {
+        require!(!transfers.is_empty(), "no transfers");
+        self.internal_lp_batch_transfer(&env::predecessor_account_id(), &transfers);
+    }
+
+    /// Sends the whole caller lp of each of `pool_ids` to `receiver_id`, merging positions held
+    /// by child accounts back into a parent. The receiver is registered where needed as in
+    /// `lp_split`. Returns the lp moved for each pool, by increasing pool id.
+    #[payable]
+    pub fn lp_merge(&mut self, mut pool_ids: Vec<u64>, receiver_id: AccountId) -> Vec<U128> {
+        require!(!pool_ids.is_empty(), "no pools");
+        pool_ids.sort();
+        pool_ids.dedup();
+        let sender_id = env::predecessor_account_id();
+        let transfers = pool_ids
+            .into_iter()
+            .map(|pool_id| LpSplit {
+                pool_id,
+                receiver_id: receiver_id.clone(),
+                amount: self.internal_lp_balance(pool_id, &sender_id).into(),
+            })
+            .collect::<Vec<_>>();
+        self.internal_lp_batch_transfer(&sender_id, &transfers);
+        transfers.into_iter().map(|transfer| transfer.amount).collect()
+    }
+
     #[payable]
     pub fn lp_transfer_call(
         &mut self,
@@ -121,13 +218,14 @@ impl Contract {
         assert_one_yocto();
         let sender_id = env::predecessor_account_id();
         self.internal_lp_transfer(pool_id, &sender_id, &receiver_id, amount.0, memo);
+        let operation_id = self.internal_start_operation(OperationKind::LpTransfer, &sender_id, amount.0);
         ext_lp_token_receiver::ext(receiver_id.clone())
             .with_static_gas(GAS_FOR_NFT_TRANSFER_CALL)
             .lp_on_transfer(pool_id, sender_id.clone(), amount, msg)
             .then(
                 ext_self::ext(env::current_account_id())
                     .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
-                    .lp_resolve_transfer(pool_id, sender_id, receiver_id, amount),
+                    .lp_resolve_transfer(pool_id, sender_id, receiver_id, amount, operation_id),
             )
             .into()
     }
@@ -142,7 +240,9 @@ impl Contract {
         sender_id: AccountId,
         receiver_id: &AccountId,
         amount: U128,
+        operation_id: u64,
     ) -> U128 {
+        self.internal_finish_operation(operation_id, OperationKind::LpTransfer, &sender_id, amount.0);
         let unused_amount = match env::promise_result(0) {
             PromiseResult::NotReady => unreachable!(),
             PromiseResult::Successful(value) => {
@@ -158,7 +258,9 @@ impl Contract {
             let receiver_balance = self.internal_lp_balance(pool_id, &receiver_id);
             if receiver_balance > 0 {
                 let refund_amount = std::cmp::min(receiver_balance, unused_amount);
-                self.internal_lp_transfer(pool_id, &receiver_id, &sender_id, refund_amount, None);
+                // refunds are not restricted, the lp going back where it came from
+                self.pools[pool_id as usize].lp_transfer(receiver_id, &sender_id, refund_amount);
+                log!("Refund lp {} pool: {} from {} to {}", pool_id, refund_amount, receiver_id, sender_id);
             }
         }
         U128(unused_amount)
@@ -0,0 +1,276 @@
+// create_mt_pair mirrors the flat arguments of create_pair
+#![allow(clippy::too_many_arguments)]
+
+use crate::*;
+use crate::events::{emit_event, CurveState, SwapEvent};
+use crate::swap::SwapType;
+use near_sdk::{ext_contract, near_bindgen, PromiseOrValue, PromiseResult};
+
+#[ext_contract(ext_mt_core)]
+pub trait MultiTokenCore {
+    fn mt_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+    );
+}
+
+#[ext_contract(ext_mt_resolver)]
+pub trait MultiTokenResolver {
+    fn mt_transfer_resolve(&mut self, account_id: AccountId, asset_id: AssetId, token_id: TokenId, amount: U128, operation_id: u64);
+}
+
+// curves price items one by one, so traded amounts must fit in u64
+fn mt_amount_to_items(amount: U128) -> u64 {
+    require!(amount.0 <= u64::MAX as u128, "amount too large");
+    amount.0 as u64
+}
+
+impl Contract {
+    pub(crate) fn internal_mt_balance(&self, account_id: &AccountId, asset_id: &AssetId, token_id: &TokenId) -> Balance {
+        self.mt_deposits
+            .get(&(account_id.clone(), asset_id.clone(), token_id.clone()))
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn internal_deposit_mt(&mut self, account_id: &AccountId, asset_id: &AssetId, token_id: &TokenId, amount: Balance) {
+        self.internal_get_account_or_revert(account_id);
+        let balance = self.internal_mt_balance(account_id, asset_id, token_id);
+        self.mt_deposits.insert(&(account_id.clone(), asset_id.clone(), token_id.clone()), &(balance + amount));
+    }
+
+    pub(crate) fn internal_withdraw_mt(&mut self, account_id: &AccountId, asset_id: &AssetId, token_id: &TokenId, amount: Balance) {
+        let balance = self.internal_mt_balance(account_id, asset_id, token_id);
+        require!(balance >= amount, format!("not enough deposited amount of token id {}", token_id));
+        let key = (account_id.clone(), asset_id.clone(), token_id.clone());
+        if balance == amount {
+            self.mt_deposits.remove(&key);
+        } else {
+            self.mt_deposits.insert(&key, &(balance - amount));
+        }
+    }
+
+    // amounts whose transfer fails are deposited back to `refund_id`
+    pub(crate) fn transfer_mt_with_refund(
+        &mut self,
+        receiver_id: &AccountId,
+        refund_id: &AccountId,
+        asset_id: &AssetId,
+        token_id: &TokenId,
+        amount: Balance,
+    ) {
+        let operation_id = self.internal_start_operation(OperationKind::MtTransfer, refund_id, amount);
+        ext_mt_core::ext(asset_id.clone())
+            .with_static_gas(GAS_FOR_NFT_TRANSFER_CALL)
+            .with_attached_deposit(1)
+            .mt_transfer(receiver_id.clone(), token_id.clone(), amount.into(), None, None)
+            .then(ext_mt_resolver::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .mt_transfer_resolve(refund_id.clone(), asset_id.clone(), token_id.clone(), amount.into(), operation_id));
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// NEP-245 receiver. Transferred amounts are credited to the deposit of their previous owner,
+    /// who must be registered with enough storage.
+    pub fn mt_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_ids: Vec<AccountId>,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        let _ = (sender_id, msg);
+        require!(
+            previous_owner_ids.len() == token_ids.len() && token_ids.len() == amounts.len(),
+            "invalid transfer arguments"
+        );
+        let asset_id = env::predecessor_account_id();
+        for ((account_id, token_id), amount) in previous_owner_ids.iter().zip(token_ids.iter()).zip(amounts.iter()) {
+            let prev_storage = env::storage_usage();
+            self.internal_deposit_mt(account_id, &asset_id, token_id, amount.0);
+            self.assert_storage(account_id, prev_storage, None);
+        }
+        PromiseOrValue::Value(vec![U128(0); token_ids.len()])
+    }
+
+    #[private]
+    pub fn mt_transfer_resolve(&mut self, account_id: AccountId, asset_id: AssetId, token_id: TokenId, amount: U128, operation_id: u64) {
+        self.internal_finish_operation(operation_id, OperationKind::MtTransfer, &account_id, amount.0);
+        if let PromiseResult::Failed = env::promise_result(0) {
+            self.internal_deposit_mt(&account_id, &asset_id, &token_id, amount.0);
+        }
+    }
+
+    /// Creates a non-trade pool trading `token_id` of the NEP-245 contract `asset_id`, funded with
+    /// `initial_amount` from the caller deposit and the attached near. Curve quotes for these pools
+    /// are per unit, so `num_items` of the quote views is the traded amount.
+    #[payable]
+    pub fn create_mt_pair(
+        &mut self,
+        pool_type: u8,
+        bonding_curve: u8,
+        asset_id: AssetId,
+        token_id: TokenId,
+        spot_price: U128,
+        delta: U128,
+        asset_recipient: Option<AccountId>,
+        initial_amount: U128,
+        locked_til: u64,
+    ) -> u64 {
+        self.assert_collection_allowed(&asset_id);
+        let pool_type = PoolType::from(pool_type);
+        require!(pool_type != PoolType::Trade, "multi token pools cannot be trade pools");
+        let near_deposit = self.internal_pay_asset_recipient_registration(&asset_recipient, env::attached_deposit());
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let pool_id = self.pools.len() as u64;
+        let mut new_pair = Pair::new(
+            bonding_curve.into(),
+            pool_type,
+            asset_id.clone(),
+            spot_price.0,
+            delta.0,
+            0u128,
+            account_id.clone(),
+            asset_recipient,
+            near_deposit,
+            locked_til,
+            pool_id,
+        );
+        self.internal_withdraw_mt(&account_id, &asset_id, &token_id, initial_amount.0);
+        new_pair.mt_token_id = Some(token_id);
+        new_pair.mt_balance = initial_amount.0;
+        self.pools.push(new_pair);
+        self.internal_add_created_pool(&account_id, pool_id);
+        self.internal_update_pool_activity(pool_id);
+        self.assert_storage(&account_id, prev_storage, Some(0));
+        pool_id
+    }
+
+    /// Buys `amount` tokens from a multi token pool with the attached near, refunding the rest.
+    #[payable]
+    pub fn swap_near_for_mt(&mut self, pool_id: u64, amount: U128) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.assert_pool_access(pool_id, &account_id);
+        let items = mt_amount_to_items(amount);
+        let protocol_fee_multiplier = self.trade_protocol_fee_multiplier(pool_id, &account_id);
+        let pool = &mut self.pools[pool_id as usize];
+        let pre_state = CurveState::of(pool);
+        let (protocol_fee, input_amount) =
+            pool.swap_near_for_mt(env::attached_deposit(), items, protocol_fee_multiplier);
+        self.assert_compliance(&account_id, input_amount);
+        let pool = &mut self.pools[pool_id as usize];
+        pool.record_trade(true, input_amount, protocol_fee);
+        let token_id = pool.mt_token_id.clone().unwrap();
+        emit_event("swap", SwapEvent {
+            pool_id,
+            trade_seq: pool.trade_seq,
+            account_id: &account_id,
+            swap_type: SwapType::NearToNFT as u8,
+            token_ids: std::slice::from_ref(&token_id),
+            near_amount: input_amount.into(),
+            protocol_fee: protocol_fee.into(),
+            pre_state,
+            post_state: CurveState::of(pool),
+        });
+        let asset_id = pool.nft_token.clone();
+        let asset_recipient = pool.asset_recipient.clone();
+        self.internal_credit_trade_protocol_fee(pool_id, protocol_fee);
+        self.internal_record_pool_trade(pool_id);
+        self.internal_record_activity_in_pool(&account_id, ActivityKind::Buy, pool_id, items, input_amount);
+        self.internal_notify_trade_hook(pool_id, &account_id, SwapType::NearToNFT as u8, std::slice::from_ref(&token_id), input_amount);
+
+        if let Some(asset_recipient) = asset_recipient {
+            let mut asset_recipient_deposit = self.internal_get_account_or_revert(&asset_recipient);
+            asset_recipient_deposit.available_balance += input_amount - protocol_fee;
+            self.account_deposits.insert(&asset_recipient, &asset_recipient_deposit);
+        }
+        let royalty = self.royalty_amount(&asset_id, input_amount);
+        require!(env::attached_deposit() >= input_amount + royalty, "not enough near payment");
+        self.internal_pay_royalty(&account_id, &asset_id, royalty);
+        if env::attached_deposit() > input_amount + royalty {
+            Promise::new(account_id.clone()).transfer(env::attached_deposit() - input_amount - royalty);
+        }
+        self.transfer_mt_with_refund(&account_id, &account_id, &asset_id, &token_id, amount.0);
+        input_amount.into()
+    }
+
+    /// Sells `amount` deposited tokens to a multi token pool.
+    #[payable]
+    pub fn swap_mt_for_near(&mut self, pool_id: u64, amount: U128, min_output_near: U128) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.assert_pool_access(pool_id, &account_id);
+        let items = mt_amount_to_items(amount);
+        let pool = &self.pools[pool_id as usize];
+        let asset_id = pool.nft_token.clone();
+        let token_id = pool.mt_token_id.clone().unwrap_or_else(|| env::panic_str("not a multi token pool"));
+        self.internal_withdraw_mt(&account_id, &asset_id, &token_id, amount.0);
+
+        let protocol_fee_multiplier = self.trade_protocol_fee_multiplier(pool_id, &account_id);
+        let pool = &mut self.pools[pool_id as usize];
+        let pre_state = CurveState::of(pool);
+        let (protocol_fee, output_amount) =
+            pool.swap_mt_for_near(items, min_output_near.0, protocol_fee_multiplier);
+        self.assert_compliance(&account_id, output_amount);
+        let pool = &mut self.pools[pool_id as usize];
+        pool.record_trade(false, output_amount, protocol_fee);
+        emit_event("swap", SwapEvent {
+            pool_id,
+            trade_seq: pool.trade_seq,
+            account_id: &account_id,
+            swap_type: SwapType::NFTToNear as u8,
+            token_ids: std::slice::from_ref(&token_id),
+            near_amount: output_amount.into(),
+            protocol_fee: protocol_fee.into(),
+            pre_state,
+            post_state: CurveState::of(pool),
+        });
+        if let Some(asset_recipient) = pool.asset_recipient.clone() {
+            self.internal_deposit_mt(&asset_recipient, &asset_id, &token_id, amount.0);
+        }
+        self.internal_credit_trade_protocol_fee(pool_id, protocol_fee);
+        self.internal_record_pool_trade(pool_id);
+        self.internal_record_activity_in_pool(&account_id, ActivityKind::Sell, pool_id, items, output_amount);
+        self.internal_notify_trade_hook(pool_id, &account_id, SwapType::NFTToNear as u8, std::slice::from_ref(&token_id), output_amount);
+
+        let royalty = self.royalty_amount(&asset_id, output_amount);
+        require!(output_amount - royalty >= min_output_near.0, "insufficient output after royalty");
+        self.internal_pay_royalty(&account_id, &asset_id, royalty);
+        if output_amount > royalty {
+            Promise::new(account_id).transfer(output_amount - royalty);
+        }
+        (output_amount - royalty).into()
+    }
+
+    /// Withdraws tokens held by a multi token pool to its owner.
+    #[payable]
+    pub fn withdraw_mt(&mut self, pool_id: u64, amount: U128) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let pool = &mut self.pools[pool_id as usize];
+        pool.withdraw_mt(amount.0);
+        let asset_id = pool.nft_token.clone();
+        let token_id = pool.mt_token_id.clone().unwrap();
+        self.internal_update_pool_activity(pool_id);
+        self.transfer_mt_with_refund(&account_id, &account_id, &asset_id, &token_id, amount.0);
+    }
+
+    #[payable]
+    pub fn withdraw_mt_from_deposit(&mut self, asset_id: AssetId, token_id: TokenId, amount: U128) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.internal_withdraw_mt(&account_id, &asset_id, &token_id, amount.0);
+        self.transfer_mt_with_refund(&account_id, &account_id, &asset_id, &token_id, amount.0);
+    }
+
+    pub fn get_mt_deposit(&self, account_id: AccountId, asset_id: AssetId, token_id: TokenId) -> U128 {
+        self.internal_mt_balance(&account_id, &asset_id, &token_id).into()
+    }
+}
@@ -14,5 +14,13 @@ pub trait NFTCore {
 
 #[ext_contract(ext_self)]
 pub trait NonFungibleTokenResolver {
-    fn nft_transfer_resolve(&mut self, account_id: AccountId, asset_id: AssetId, token_id: TokenId);
+    fn nft_transfer_resolve(
+        &mut self,
+        account_id: AccountId,
+        asset_id: AssetId,
+        token_id: TokenId,
+        operation_id: u64,
+        receiver_id: Option<AccountId>,
+        attempts: Option<u32>,
+    );
 }
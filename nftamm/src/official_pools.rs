@@ -0,0 +1,88 @@
+use crate::*;
+use crate::events::emit_event;
+use near_sdk::serde::Serialize;
+use near_sdk::near_bindgen;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfficialPoolEvent<'a> {
+    pub pool_id: u64,
+    pub nft_token: &'a AssetId,
+    pub account_id: &'a AccountId,
+}
+
+impl Contract {
+    // account approving official pools of the collection, the collection contract by default
+    fn collection_official_account(&self, asset_id: &AssetId) -> AccountId {
+        self.collection_official_accounts.get(asset_id).unwrap_or_else(|| asset_id.clone())
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the account approving official pools of the collection, none restores the
+    /// collection contract account.
+    pub fn set_collection_official_account(&mut self, asset_id: AssetId, account_id: Option<AccountId>) {
+        self.assert_governance();
+        match account_id {
+            Some(account_id) => self.collection_official_accounts.insert(&asset_id, &account_id),
+            None => self.collection_official_accounts.remove(&asset_id),
+        };
+    }
+
+    pub fn get_collection_official_account(&self, asset_id: AssetId) -> AccountId {
+        self.collection_official_account(&asset_id)
+    }
+
+    /// Asks the official account of the pool collection to approve the pool as official.
+    pub fn request_official_pool(&mut self, pool_id: u64) {
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        pool.assert_owner();
+        require!(!pool.official, "pool already official");
+        pool.official_requested = true;
+        emit_event("official_pool_request", OfficialPoolEvent {
+            pool_id,
+            nft_token: &pool.nft_token,
+            account_id: &pool.owner,
+        });
+    }
+
+    #[payable]
+    pub fn approve_official_pool(&mut self, pool_id: u64) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let official_account_id = self.collection_official_account(&self.get_nft_asset_id(pool_id));
+        require!(account_id == official_account_id, "only the collection official account");
+        let pool = &mut self.pools[pool_id as usize];
+        require!(pool.official_requested, "pool owner did not request approval");
+        pool.official_requested = false;
+        pool.official = true;
+        emit_event("official_pool_approve", OfficialPoolEvent {
+            pool_id,
+            nft_token: &pool.nft_token,
+            account_id: &account_id,
+        });
+    }
+
+    /// Withdraws the official status or a pending request, by the collection official account,
+    /// the pool owner or governance.
+    #[payable]
+    pub fn revoke_official_pool(&mut self, pool_id: u64) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let official_account_id = self.collection_official_account(&self.get_nft_asset_id(pool_id));
+        let is_governance = self.is_governance(&account_id);
+        let pool = &mut self.pools[pool_id as usize];
+        require!(
+            account_id == official_account_id || account_id == pool.owner || is_governance,
+            "only the collection official account, pool owner or governance"
+        );
+        pool.official = false;
+        pool.official_requested = false;
+        emit_event("official_pool_revoke", OfficialPoolEvent {
+            pool_id,
+            nft_token: &pool.nft_token,
+            account_id: &account_id,
+        });
+    }
+}
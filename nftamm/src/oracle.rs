@@ -0,0 +1,82 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolOracle {
+    // governance approved account pushing the spot price of the pool
+    pub oracle_id: AccountId,
+    // trading is paused when the last price is older than this
+    pub max_staleness_sec: u64,
+    #[serde(default)]
+    pub updated_at: u64,
+    // last nonce pushed, updates must use greater ones
+    #[serde(default)]
+    pub nonce: u64,
+}
+
+impl Pair {
+    pub(crate) fn is_oracle_price_fresh(&self) -> bool {
+        match &self.oracle {
+            Some(oracle) => oracle.updated_at + oracle.max_staleness_sec >= env::block_timestamp_ms() / 1000,
+            None => true,
+        }
+    }
+
+    pub(crate) fn assert_oracle_price_fresh(&self) {
+        require!(self.is_oracle_price_fresh(), "oracle price is stale");
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_oracle_approved(&mut self, oracle_id: AccountId, approved: bool) {
+        self.assert_governance();
+        if approved {
+            self.approved_oracles.insert(&oracle_id);
+        } else {
+            self.approved_oracles.remove(&oracle_id);
+        }
+    }
+
+    /// Pegs the spot price of the pool to an approved oracle, or removes the peg. Trades of
+    /// pegged pools do not move the spot price and are paused until the oracle pushes a price.
+    pub fn set_pool_oracle(&mut self, pool_id: u64, oracle_id: Option<AccountId>, max_staleness_sec: u64) {
+        if let Some(oracle_id) = &oracle_id {
+            require!(self.approved_oracles.contains(oracle_id), "oracle not approved");
+        }
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        require!(pool.virtual_reserves.is_none(), "pools with virtual reserves cannot be oracle pegged");
+        pool.oracle = oracle_id.map(|oracle_id| PoolOracle {
+            oracle_id,
+            max_staleness_sec,
+            updated_at: 0,
+            nonce: 0,
+        });
+    }
+
+    /// Called by the pool oracle to set the pool spot price.
+    pub fn update_oracle_price(&mut self, pool_id: u64, price: U128, nonce: u64) {
+        let oracle_id = env::predecessor_account_id();
+        require!(self.approved_oracles.contains(&oracle_id), "oracle not approved");
+        let pool = &mut self.pools[pool_id as usize];
+        let oracle = pool.oracle.as_mut().unwrap_or_else(|| env::panic_str("pool is not oracle pegged"));
+        require!(oracle.oracle_id == oracle_id, "not the pool oracle");
+        require!(nonce > oracle.nonce, "oracle nonce already used");
+        require!(pool.curve.validate_spot_price(price.0), "Invalid new spot price for curve");
+        oracle.nonce = nonce;
+        oracle.updated_at = env::block_timestamp_ms() / 1000;
+        pool.spot_price = price.0;
+    }
+
+    pub fn get_pool_oracle(&self, pool_id: u64) -> Option<PoolOracle> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.oracle.clone()
+    }
+
+    pub fn is_oracle_approved(&self, oracle_id: AccountId) -> bool {
+        self.approved_oracles.contains(&oracle_id)
+    }
+}
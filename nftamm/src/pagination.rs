@@ -0,0 +1,76 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+const DEFAULT_PAGE_LIMIT: u64 = 100;
+
+/// Items of a cursor paginated view. `next_cursor` resumes after the last item and is none once
+/// the data is exhausted. Removals swap the last item into the removed slot, so a page read
+/// across removals may skip or repeat items.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+// the cursor is opaque to clients, only the index of the next item
+fn decode_cursor(cursor: Option<String>) -> u64 {
+    cursor.map_or(0, |cursor| cursor.parse().unwrap_or_else(|_| env::panic_str("invalid cursor")))
+}
+
+// reads `[cursor, cursor + limit)` of `len` items with `get_at`
+fn paginate<T>(len: u64, cursor: Option<String>, limit: Option<u64>, get_at: impl Fn(u64) -> Option<T>) -> Page<T> {
+    let start = decode_cursor(cursor);
+    let end = start.saturating_add(limit.unwrap_or(DEFAULT_PAGE_LIMIT)).min(len);
+    let items: Vec<T> = (start..end).filter_map(get_at).collect();
+    Page {
+        items,
+        next_cursor: if end < len { Some(end.to_string()) } else { None },
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Token ids of one collection deposited by the account.
+    pub fn get_deposits_page(&self, account_id: AccountId, asset_id: AssetId, cursor: Option<String>, limit: Option<u64>) -> Page<TokenId> {
+        let account_deposit = self.internal_get_account_or_revert(&account_id);
+        match account_deposit.assets.get(&asset_id) {
+            Some(token_ids) => {
+                let keys = token_ids.keys_as_vector();
+                paginate(keys.len(), cursor, limit, |index| keys.get(index))
+            }
+            None => Page { items: vec![], next_cursor: None },
+        }
+    }
+
+    /// Lp holders of the pool with their balances.
+    pub fn get_lp_holders(&self, pool_id: u64, cursor: Option<String>, limit: Option<u64>) -> Page<(AccountId, U128)> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let (holders, balances) = (pool.lp_balances.keys_as_vector(), pool.lp_balances.values_as_vector());
+        paginate(holders.len(), cursor, limit, |index| Some((holders.get(index)?, U128(balances.get(index)?))))
+    }
+
+    pub fn get_pool_token_ids(&self, pool_id: u64, cursor: Option<String>, limit: Option<u64>) -> Page<TokenId> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        paginate(pool.token_ids_in_pools.len(), cursor, limit, |index| pool.token_ids_in_pools.get_at(index))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate_resumes_from_cursor() {
+        let data: Vec<u64> = (0..5).collect();
+        let get_at = |index: u64| data.get(index as usize).copied();
+        let first = paginate(5, None, Some(2), get_at);
+        assert_eq!(first.items, vec![0, 1]);
+        let second = paginate(5, first.next_cursor, Some(2), get_at);
+        assert_eq!(second.items, vec![2, 3]);
+        let last = paginate(5, second.next_cursor, Some(2), get_at);
+        assert_eq!(last.items, vec![4]);
+        assert!(last.next_cursor.is_none());
+    }
+}
@@ -1,16 +1,49 @@
+use std::collections::HashSet;
+
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{UnorderedMap};
+use near_sdk::collections::{UnorderedMap, UnorderedSet};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{env, require, AccountId, Balance, PanicOnDefault, near_bindgen};
+use near_sdk::{env, require, AccountId, Balance, PanicOnDefault, PublicKey, near_bindgen};
 
 use near_contract_standards::non_fungible_token::TokenId;
 
 use crate::curves::curve::{BondingCurve, Curve};
+use crate::curves::{expect_u128, U256, WAD};
+use crate::gating::PoolGate;
+use crate::param_change::PendingParamChange;
+use crate::oracle::PoolOracle;
+use crate::circuit_breaker::{CircuitBreakerState, PoolCircuitBreaker};
+use crate::curves::{virtual_reserves, BuyInfo, SellInfo};
+use crate::curves::units::{Wad, Yocto};
 use crate::curves::errorcodes::CurveErrorCode;
-use crate::curves::{U256, WAD};
+use crate::pricing::{self, buy_trade_quote, sell_trade_quote, trade_pool_fee, TradeQuote};
+use crate::virtual_reserves::VirtualReserves;
+use crate::pool_tokens::PoolTokens;
+use crate::pool_sides::{PendingPoolSides, PoolSides};
+use crate::bootstrap_auction::BootstrapAuction;
+use crate::token_locations::{insert_token_location, remove_token_location, TokenLocation};
+use crate::migration::LegacyDepositedToken;
 use crate::{AssetId, StorageKey};
 
 pub const MAX_FEE: u128 = 9 * (10u128.pow(17)); //max 90%
+pub const MAX_PROTOCOL_FEE: u128 = 10u128.pow(17); //max 10%
+// near only lp burns must leave the pool with near worth at least this share (base 1e18) of its
+// nfts at spot price
+pub const NEAR_ONLY_BURN_HEALTH_RATIO: u128 = WAD;
+// number of latest trades kept per pool for fee statistics
+pub const RECENT_TRADES_KEPT: usize = 20;
+// longest list of token ids sold first by any nft buys
+pub const MAX_PRIORITY_TOKENS: usize = 100;
+// lp locked forever on the first mint of a trade pool, so its supply never goes back to a few
+// units whose rounding the first lp could abuse
+pub const MINIMUM_LIQUIDITY: Balance = 1_000;
+// seconds a governance forced release of a pool lock is queued for before it can be applied
+pub const FORCE_RELEASE_DELAY_SEC: u64 = 7 * 24 * 3600;
+// most token ids of a pool reserved at once
+pub const MAX_RESERVED_TOKENS: usize = 50;
+// most pool nfts a buy of any nfts looks at when some cannot be taken, for their min price, a
+// reservation or a flag
+pub const MAX_ANY_BUY_SCAN: u64 = 500;
 
 #[near_bindgen]
 #[repr(u8)]
@@ -35,10 +68,91 @@ impl From<u8> for PoolType {
 
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct DepositedToken {
-    depositor: AccountId,
+    // lp who deposited the token, none for tokens the pool bought from sellers
+    depositor: Option<AccountId>,
     min_price: Balance,
 }
 
+impl DepositedToken {
+    pub(crate) fn acquired() -> Self {
+        DepositedToken {
+            depositor: None,
+            min_price: 0,
+        }
+    }
+
+    // acquired tokens without a min price need no stored record
+    pub(crate) fn is_acquired(&self) -> bool {
+        self.depositor.is_none() && self.min_price == 0
+    }
+}
+
+// tokens stored before the depositor became optional all had one
+impl From<LegacyDepositedToken> for DepositedToken {
+    fn from(legacy: LegacyDepositedToken) -> Self {
+        DepositedToken {
+            depositor: Some(legacy.depositor),
+            min_price: legacy.min_price,
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub struct TradeRecord {
+    pub timestamp_sec: u64,
+    pub near_amount: Balance,
+    // part of the near amount earned by the pool, trade pools only
+    pub pool_fee: Balance,
+    pub protocol_fee: Balance,
+}
+
+// Result of redeeming an amount of LP tokens of a pool
+pub struct LpRedemption {
+    pub token_ids: Vec<TokenId>,
+    pub near_amount: Balance,
+    // near deducted from the withdrawal to pay for rounding the nft amount up
+    pub fraction_nft_value: Balance,
+    pub protocol_fee: Balance,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolParamError {
+    pub param: String,
+    pub reason: String,
+}
+
+impl PoolParamError {
+    pub fn new(param: &str, reason: &str) -> PoolParamError {
+        PoolParamError { param: param.to_string(), reason: reason.to_string() }
+    }
+}
+
+// every reason `Pair::new` would reject the parameters for, in the order it checks them
+pub(crate) fn validate_pool_params(
+    curve: &Curve,
+    pool_type: PoolType,
+    spot_price: Yocto,
+    delta: u128,
+    fee: Wad,
+) -> Vec<PoolParamError> {
+    let mut errors = Vec::<PoolParamError>::new();
+    if pool_type == PoolType::Token || pool_type == PoolType::NFT {
+        if fee != Wad::ZERO {
+            errors.push(PoolParamError::new("fee", "only trade pools can have non zero fees"));
+        }
+    } else if fee >= Wad(MAX_FEE) {
+        errors.push(PoolParamError::new("fee", "trade fee exceed max"));
+    }
+    if !curve.validate_delta(delta) {
+        errors.push(PoolParamError::new("delta", "invalid delta"));
+    }
+    if !curve.validate_spot_price(spot_price.0) {
+        errors.push(PoolParamError::new("spot_price", "Invalid new spot price for curve"));
+    }
+    errors
+}
+
 // The spread between buy and sell prices, set to be a multiplier we apply to the buy price
 // Fee is only relevant for TRADE pools
 // Units are in base 1e18
@@ -49,17 +163,91 @@ pub struct Pair {
     pub nft_token: AssetId,
     pub spot_price: u128,
     pub delta: u128,
-    pub fee: u128,
+    pub fee: Wad,
     pub owner: AccountId,
     // If set to none, NFTs/tokens sent by traders during trades will be sent to the pair.
     // Otherwise, assets will be sent to the set address. Not available for TRADE pools
     pub asset_recipient: Option<AccountId>,
     pub near_balance: Balance,
-    pub token_ids_in_pools: UnorderedMap<TokenId, DepositedToken>,
+    pub token_ids_in_pools: PoolTokens,
     pub released_time: u64,
     pub pool_id: u64,
     pub lp_balances: UnorderedMap<AccountId, Balance>,
-    pub lp_supply: Balance
+    pub lp_supply: Balance,
+    // near earned from sales by non-trade pools without asset recipient, kept apart from the
+    // near deposited by the owner
+    pub proceeds: Balance,
+    // number of trades executed against the pool, used as event sequence number
+    pub trade_seq: u64,
+    // set for pools of a NEP-245 token, which hold an amount of a single token id instead of
+    // unique nfts
+    pub mt_token_id: Option<TokenId>,
+    pub mt_balance: Balance,
+    // holding requirement to trade against the pool
+    pub gate: Option<PoolGate>,
+    pub gate_nonce: u64,
+    // key allowed to sign quotes redeemable against the pool, besides the governance one
+    pub quote_signer: Option<PublicKey>,
+    pub created_at: u64,
+    // near traded against the pool since creation
+    pub total_volume: Balance,
+    pub recent_trades: Vec<TradeRecord>,
+    // number of pool tokens with a min price, buys skip the min price checks when there are none
+    pub num_priced_tokens: u64,
+    // seconds parameter changes are queued for before they can be applied
+    pub param_change_delay: u64,
+    pub pending_param_change: Option<PendingParamChange>,
+    // bounds set by the owner on the items the pool holds and on the near deposited to it
+    pub max_nfts: Option<u64>,
+    pub max_near: Option<Balance>,
+    // token ids any nft buys take first, in order, ids no longer in the pool are skipped
+    pub priority_token_ids: Vec<TokenId>,
+    // set for pools whose spot price is pushed by an oracle instead of moved by trades
+    pub oracle: Option<PoolOracle>,
+    pub circuit_breaker: PoolCircuitBreaker,
+    pub breaker_state: CircuitBreakerState,
+    // trade pools priced as if these reserves were added to their real balances
+    pub virtual_reserves: Option<VirtualReserves>,
+    // (items, near balance) last added to the collection stats, none until indexed
+    pub indexed_liquidity: Option<(u64, Balance)>,
+    // pool nfts bought from sellers rather than deposited by lps
+    pub num_acquired_tokens: u64,
+    // part of lp_supply owned by no one, see MINIMUM_LIQUIDITY
+    pub locked_lp: Balance,
+    // time from which governance can release the pool lock, none unless queued
+    pub force_release_at: Option<u64>,
+    // keepers can buy marketplace listings under the pool bid with the pool near
+    pub marketplace_fulfillment: bool,
+    // delta and fee of sells to a trade pool when different from its buy ones
+    pub sell_delta: Option<u128>,
+    pub sell_fee: Option<Wad>,
+    // contract notified of the swaps with the pool
+    pub on_trade_hook: Option<AccountId>,
+    // (token id, expiry in seconds) of the tokens only their reservation can buy
+    pub reserved_token_ids: Vec<(TokenId, u64)>,
+    // rejects flagged nfts and keeps the flagged ones it holds out of buys of any nfts
+    pub deny_flagged: bool,
+    pub flagged_token_ids: UnorderedSet<TokenId>,
+    // approved by the official account of the collection, on request of the owner
+    pub official: bool,
+    pub official_requested: bool,
+    // near of a trade pool left once its last redeemable lp burned, owned by no lp until
+    // governance collects it
+    pub dust: Balance,
+    // value of one lp in base 1e18, the near balance plus the nfts at spot price over the lp
+    // supply, as of the last trade or liquidity event
+    pub lp_index: u128,
+    // in seconds
+    pub lp_index_updated_at: u64,
+    // sides the pool trades, and the change queued for trade pools
+    pub sides: PoolSides,
+    pub pending_sides: Option<PendingPoolSides>,
+    // auction pricing the pool before it trades on its curve
+    pub bootstrap: Option<BootstrapAuction>,
+    // most the pool pays keepers buying approved nfts into it, none disabling those buys
+    pub approval_buy_cap: Option<Balance>,
+    // whether quotes of the governance quote signer can be redeemed against the pool
+    pub governance_quotes: bool,
 }
 
 impl Pair {
@@ -82,38 +270,71 @@ impl Pair {
             nft_token: nft_token,
             spot_price: spot_price,
             delta: delta,
-            fee: fee,
+            fee: Wad(fee),
             owner: owner,
             asset_recipient: None,
             near_balance: initial_near_balance,
-            token_ids_in_pools: UnorderedMap::new(StorageKey::TokenIdsInPools {pool_id: pool_id}),
+            token_ids_in_pools: PoolTokens::new(pool_id),
             released_time: released_time,
             pool_id: pool_id,
             lp_balances: UnorderedMap::new(StorageKey::PoolShare { pool_id: pool_id }),
-            lp_supply: 0
+            lp_supply: 0,
+            proceeds: 0,
+            trade_seq: 0,
+            mt_token_id: None,
+            mt_balance: 0,
+            gate: None,
+            gate_nonce: 0,
+            quote_signer: None,
+            created_at: env::block_timestamp_ms() / 1000,
+            total_volume: 0,
+            recent_trades: vec![],
+            num_priced_tokens: 0,
+            param_change_delay: 0,
+            pending_param_change: None,
+            max_nfts: None,
+            max_near: None,
+            priority_token_ids: vec![],
+            oracle: None,
+            circuit_breaker: PoolCircuitBreaker::Default,
+            breaker_state: CircuitBreakerState::new(spot_price),
+            virtual_reserves: None,
+            indexed_liquidity: None,
+            num_acquired_tokens: 0,
+            locked_lp: 0,
+            force_release_at: None,
+            marketplace_fulfillment: false,
+            sell_delta: None,
+            sell_fee: None,
+            on_trade_hook: None,
+            reserved_token_ids: vec![],
+            deny_flagged: false,
+            flagged_token_ids: UnorderedSet::new(StorageKey::PoolFlaggedTokens { pool_id }),
+            official: false,
+            official_requested: false,
+            dust: 0,
+            lp_index: 0,
+            lp_index_updated_at: 0,
+            sides: PoolSides { buys_enabled: true, sells_enabled: true },
+            pending_sides: None,
+            bootstrap: None,
+            approval_buy_cap: None,
+            governance_quotes: false,
         };
 
+        if let Some(error) = validate_pool_params(&this.curve, pool_type, Yocto(spot_price), delta, Wad(fee)).first() {
+            env::panic_str(&error.reason);
+        }
         if pool_type == PoolType::Token || pool_type == PoolType::NFT {
-            require!(fee == 0u128, "only trade pools can have non zero fees");
-            require!(
-                asset_recipient.is_some(),
-                "invalid asset recipient account id"
-            );
+            // without asset recipient, the pool itself keeps the traded assets
             this.asset_recipient = asset_recipient;
         } else {
-            require!(fee < MAX_FEE, "trade fee exceed max");
             require!(
                 asset_recipient.is_none(),
                 "asset recipient must be none for trade pools"
             );
         }
 
-        require!(this.curve.validate_delta(this.delta), "invalid delta");
-        require!(
-            this.curve.validate_spot_price(this.spot_price),
-            "Invalid new spot price for curve"
-        );
-
         this
     }
 
@@ -121,44 +342,156 @@ impl Pair {
         &mut self,
         depositor: AccountId,
         receiver_id: AccountId,
-        token_ids: &Vec<TokenId>,
+        token_ids: &[TokenId],
         near_balance: &Balance,
-    ) {
+        min_prices: &[Balance],
+    ) -> Balance {
+        self.assert_nft_pool();
         if self.pool_type == PoolType::Trade {
             require!(token_ids.len() as u128 * self.spot_price <= near_balance.clone(), "invalid added liquidity");
+            // lp is minted for the nfts added along the near once the pool has lps, lp burns
+            // paying near for the nfts share
+            require!(self.lp_supply == 0 || !token_ids.is_empty(), "near only liquidity mints no lp, add nfts along");
         }
-        for token_id in token_ids {
+        require!(min_prices.is_empty() || min_prices.len() == token_ids.len(), "invalid min prices size");
+        for (i, token_id) in token_ids.iter().enumerate() {
+            let min_price = min_prices.get(i).copied().unwrap_or(0);
+            if min_price > 0 {
+                self.num_priced_tokens += 1;
+            }
             self.token_ids_in_pools.insert(
                 token_id,
                 &DepositedToken {
-                    depositor: depositor.clone(),
-                    min_price: 0u128,
+                    depositor: Some(depositor.clone()),
+                    min_price,
                 },
             );
+            self.internal_index_token(token_id);
         }
         self.near_balance += near_balance;
+        self.assert_nft_cap();
+        self.assert_near_cap();
 
         // compute LP
 
-        let mut lp_amount = self.near_balance;
+        // dust stays out of the lp of the first deposit after it
+        let mut lp_amount = self.near_balance.saturating_sub(self.dust);
         if self.lp_supply != 0 && !self.token_ids_in_pools.is_empty() {
             lp_amount = self.lp_supply * token_ids.len() as u128 / self.token_ids_in_pools.len() as u128;
+        } else if self.lp_supply == 0 && lp_amount > 0 && self.pool_type == PoolType::Trade {
+            require!(lp_amount > MINIMUM_LIQUIDITY, "initial liquidity below minimum liquidity");
+            self.lp_supply = MINIMUM_LIQUIDITY;
+            self.locked_lp = MINIMUM_LIQUIDITY;
+            lp_amount -= MINIMUM_LIQUIDITY;
         }
         self.mint_lp(&receiver_id, lp_amount);
+        self.record_lp_deposit(&receiver_id, self.lp_value_of(*near_balance, token_ids.len() as u64));
+        lp_amount
     }
 
-    fn internal_add_token_ids(
-        &mut self,
-        depositor: AccountId,
-        token_ids: &Vec<TokenId>
-    ) {
+    // nfts sold to the pool, kept apart from lp deposits
+    fn internal_add_acquired_token_ids(&mut self, token_ids: &Vec<TokenId>) {
         for token_id in token_ids {
-            self.token_ids_in_pools.insert(
-                token_id,
-                &DepositedToken {
-                    depositor: depositor.clone(),
-                    min_price: 0u128,
-                },
+            self.token_ids_in_pools.insert(token_id, &DepositedToken::acquired());
+            self.internal_index_token(token_id);
+        }
+        self.num_acquired_tokens += token_ids.len() as u64;
+    }
+
+    // nft bought for the pool outside of a trade
+    pub(crate) fn add_acquired_token(&mut self, token_id: &TokenId) {
+        self.internal_add_acquired_token_ids(&vec![token_id.clone()]);
+    }
+
+    fn internal_remove_token(&mut self, token_id: &TokenId) -> Option<DepositedToken> {
+        let deposited_token = self.token_ids_in_pools.remove(token_id);
+        if let Some(deposited_token) = &deposited_token {
+            if deposited_token.min_price > 0 {
+                self.num_priced_tokens -= 1;
+            }
+            if deposited_token.depositor.is_none() {
+                self.num_acquired_tokens -= 1;
+            }
+            self.flagged_token_ids.remove(token_id);
+            remove_token_location(&self.nft_token, token_id, &TokenLocation::Pool(self.pool_id));
+        }
+        deposited_token
+    }
+
+    pub(crate) fn internal_index_token(&self, token_id: &TokenId) {
+        insert_token_location(&self.nft_token, token_id, TokenLocation::Pool(self.pool_id));
+    }
+
+    // keeps the flagged list in sync with the registry for pools denying flagged nfts
+    pub(crate) fn set_token_flagged(&mut self, token_id: &TokenId, flagged: bool) {
+        if !self.deny_flagged || !self.token_ids_in_pools.contains(token_id) {
+            return;
+        }
+        if flagged {
+            self.flagged_token_ids.insert(token_id);
+        } else {
+            self.flagged_token_ids.remove(token_id);
+        }
+    }
+
+    // recomputes the token counters from the token map and drops listed ids the pool no longer
+    // holds, returning the number of corrections
+    pub(crate) fn recount_tokens(&mut self) -> u64 {
+        let (mut num_priced_tokens, mut num_acquired_tokens) = (0, 0);
+        for (_, deposited_token) in self.token_ids_in_pools.iter() {
+            if deposited_token.min_price > 0 {
+                num_priced_tokens += 1;
+            }
+            if deposited_token.depositor.is_none() {
+                num_acquired_tokens += 1;
+            }
+        }
+        let mut corrections = (num_priced_tokens != self.num_priced_tokens) as u64
+            + (num_acquired_tokens != self.num_acquired_tokens) as u64;
+        self.num_priced_tokens = num_priced_tokens;
+        self.num_acquired_tokens = num_acquired_tokens;
+
+        let stale_flagged: Vec<TokenId> =
+            self.flagged_token_ids.iter().filter(|id| !self.token_ids_in_pools.contains(id)).collect();
+        for token_id in &stale_flagged {
+            self.flagged_token_ids.remove(token_id);
+        }
+        let num_priority = self.priority_token_ids.len();
+        let token_ids_in_pools = &self.token_ids_in_pools;
+        self.priority_token_ids.retain(|id| token_ids_in_pools.contains(id));
+        corrections += (stale_flagged.len() + num_priority - self.priority_token_ids.len()) as u64;
+        corrections
+    }
+
+    // the depositor of a pool token can change the price under which it cannot be bought
+    pub fn set_min_price(&mut self, account_id: &AccountId, token_id: &TokenId, min_price: Balance) {
+        let mut deposited_token = self
+            .token_ids_in_pools
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str(&format!("token id {} not in pool", token_id)));
+        require!(deposited_token.depositor.as_ref() == Some(account_id), "only the token depositor can set its min price");
+        match (deposited_token.min_price > 0, min_price > 0) {
+            (false, true) => self.num_priced_tokens += 1,
+            (true, false) => self.num_priced_tokens -= 1,
+            _ => {}
+        }
+        deposited_token.min_price = min_price;
+        self.token_ids_in_pools.insert(token_id, &deposited_token);
+    }
+
+    pub fn get_min_price(&self, token_id: &TokenId) -> Option<Balance> {
+        self.token_ids_in_pools.get(token_id).map(|t| t.min_price)
+    }
+
+    fn assert_min_prices(&self, token_ids: &[TokenId], price_per_nft: Balance) {
+        if self.num_priced_tokens == 0 {
+            return;
+        }
+        for token_id in token_ids {
+            let min_price = self.token_ids_in_pools.get(token_id).map(|t| t.min_price).unwrap_or(0);
+            require!(
+                min_price <= price_per_nft,
+                format!("token id {} cannot be sold under its min price {}", token_id, min_price)
             );
         }
     }
@@ -176,16 +509,36 @@ impl Pair {
         ret
     }
 
+    // nfts the owner moves from its deposit into a non-trade pool, without minting lp
+    pub fn deposit_nfts(&mut self, token_ids: &[TokenId]) {
+        self.assert_nft_pool();
+        require!(self.pool_type != PoolType::Trade, "trade pools take nfts through add_liquidity");
+        self.assert_owner();
+        for token_id in token_ids {
+            self.token_ids_in_pools.insert(
+                token_id,
+                &DepositedToken {
+                    depositor: Some(self.owner.clone()),
+                    min_price: 0,
+                },
+            );
+            self.internal_index_token(token_id);
+        }
+        self.assert_nft_cap();
+    }
+
     pub fn withdraw_nfts(&mut self, token_ids: &Vec<TokenId>) {
+        self.assert_nft_pool();
         require!(self.pool_type != PoolType::Trade, "not allowed to withdraw nfts directly from trading pool, need to burn lp token");
         self.assert_owner();
         self.assert_release();
         for token_id in token_ids {
             require!(
-                self.token_ids_in_pools.get(token_id).is_some(),
+                self.token_ids_in_pools.contains(token_id),
                 format!("token id {} not in pool", token_id)
             );
-            self.token_ids_in_pools.remove(&token_id);
+            require!(!self.is_reserved(token_id), format!("token id {} reserved", token_id));
+            self.internal_remove_token(token_id);
         }
     }
 
@@ -194,13 +547,16 @@ impl Pair {
         deposit_near_amount: Balance,
         num_nfts: u64,
         protocol_fee_multiplier: u128,
+        nonce: Option<u64>,
     ) -> (Balance, Balance, Vec<TokenId>) {
+        self.assert_buys_enabled();
+        self.assert_nft_pool();
         require!(
             self.pool_type == PoolType::NFT || self.pool_type == PoolType::Trade,
             "wrong pool type"
         );
         require!(
-            num_nfts > 0 && num_nfts <= self.token_ids_in_pools.len(),
+            num_nfts > 0 && num_nfts <= self.num_buyable_by_any(),
             "ask for > 0 or less than equal nfts in pool"
         );
 
@@ -209,34 +565,162 @@ impl Pair {
             deposit_near_amount,
             protocol_fee_multiplier,
         );
-        let token_ids = self
-            .token_ids_in_pools
-            .keys()
-            .take(num_nfts as usize)
-            .collect::<Vec<TokenId>>();
-        for token_id in &token_ids {
-            self.token_ids_in_pools.remove(token_id);
+        let price_per_nft = (input_amount - protocol_fee) / num_nfts as u128;
+        let mut token_ids = self.internal_take_priority_token_ids(num_nfts, price_per_nft);
+        let num_left = num_nfts - token_ids.len() as u64;
+        if num_left > 0 && (self.num_priced_tokens > 0 || self.num_excluded_from_any() > 0) {
+            token_ids.extend(self.internal_take_token_ids_above_min_price(num_left, price_per_nft, nonce));
+        } else if num_left > 0 {
+            match nonce {
+                Some(nonce) => token_ids.extend(self.internal_take_random_token_ids(num_left, nonce)),
+                None => {
+                    let taken = self
+                        .token_ids_in_pools
+                        .keys()
+                        .take(num_left as usize)
+                        .collect::<Vec<TokenId>>();
+                    for token_id in &taken {
+                        self.internal_remove_token(token_id);
+                    }
+                    token_ids.extend(taken);
+                }
+            }
         }
-        let protocol_fee = protocol_fee.as_u128();
         if self.asset_recipient.is_none() {
-            //trade pool, add the near input to the pool balance
-            self.near_balance += input_amount - protocol_fee;
-        } 
+            self.internal_receive_near(input_amount - protocol_fee);
+        }
 
         (protocol_fee, input_amount, token_ids)
     }
 
+    // token ids a buy of `num_nfts` any nfts without nonce would take at `price_per_nft`, fewer
+    // when not enough can be sold at that price
+    pub(crate) fn preview_any_token_ids(&self, num_nfts: u64, price_per_nft: Balance) -> Vec<TokenId> {
+        let mut token_ids = Vec::<TokenId>::new();
+        for token_id in &self.priority_token_ids {
+            if token_ids.len() as u64 == num_nfts {
+                break;
+            }
+            if self.token_ids_in_pools.get(token_id).map_or(false, |t| t.min_price <= price_per_nft) && !self.is_excluded_from_any(token_id) {
+                token_ids.push(token_id.clone());
+            }
+        }
+        let num_left = (num_nfts - token_ids.len() as u64) as usize;
+        if num_left > 0 && (self.num_priced_tokens > 0 || self.num_excluded_from_any() > 0) {
+            // same scan as internal_take_token_ids_above_min_price without nonce
+            let taken = self.find_any_token_ids(num_left as u64, price_per_nft, 0, &token_ids);
+            token_ids.extend(taken);
+        } else if num_left > 0 {
+            let taken = self
+                .token_ids_in_pools
+                .keys()
+                .filter(|token_id| !token_ids.contains(token_id))
+                .take(num_left)
+                .collect::<Vec<TokenId>>();
+            token_ids.extend(taken);
+        }
+        token_ids
+    }
+
+    // removes up to `num_nfts` token ids of the priority list sellable at `price_per_nft`, dropping
+    // ids that left the pool from the list
+    fn internal_take_priority_token_ids(&mut self, num_nfts: u64, price_per_nft: Balance) -> Vec<TokenId> {
+        if self.priority_token_ids.is_empty() {
+            return vec![];
+        }
+        let mut token_ids = Vec::<TokenId>::new();
+        let mut kept = Vec::<TokenId>::new();
+        for token_id in std::mem::take(&mut self.priority_token_ids) {
+            match self.token_ids_in_pools.get(&token_id) {
+                Some(deposited_token) if (token_ids.len() as u64) < num_nfts && deposited_token.min_price <= price_per_nft && !self.is_excluded_from_any(&token_id) => {
+                    self.internal_remove_token(&token_id);
+                    token_ids.push(token_id);
+                }
+                Some(_) => kept.push(token_id),
+                None => {}
+            }
+        }
+        self.priority_token_ids = kept;
+        token_ids
+    }
+
+    pub fn set_token_priority(&mut self, token_ids: Vec<TokenId>) {
+        self.assert_owner();
+        self.assert_nft_pool();
+        require!(token_ids.len() <= MAX_PRIORITY_TOKENS, "too many priority token ids");
+        for token_id in &token_ids {
+            require!(self.token_ids_in_pools.contains(token_id), format!("token id {} not in pool", token_id));
+        }
+        self.priority_token_ids = token_ids;
+    }
+
+    // removes `num_nfts` token ids picked from the block random seed mixed with the buyer nonce,
+    // so depositors cannot predict which tokens a buyer of any nfts receives
+    fn internal_take_random_token_ids(&mut self, num_nfts: u64, nonce: u64) -> Vec<TokenId> {
+        let mut token_ids = Vec::<TokenId>::new();
+        for i in 0..num_nfts {
+            let index = self.random_index(nonce, i, self.token_ids_in_pools.len());
+            let token_id = self.token_ids_in_pools.get_at(index).unwrap();
+            self.internal_remove_token(&token_id);
+            token_ids.push(token_id);
+        }
+        token_ids
+    }
+
+    // same as above among the tokens any-buys can take at `price_per_nft`, scanning from a random
+    // position with a nonce, from the first token otherwise
+    fn internal_take_token_ids_above_min_price(&mut self, num_nfts: u64, price_per_nft: Balance, nonce: Option<u64>) -> Vec<TokenId> {
+        let start = nonce.map_or(0, |nonce| self.random_index(nonce, 0, self.token_ids_in_pools.len()));
+        let token_ids = self.find_any_token_ids(num_nfts, price_per_nft, start, &[]);
+        require!(token_ids.len() as u64 == num_nfts, "not enough nfts above their min price");
+        for token_id in &token_ids {
+            self.internal_remove_token(token_id);
+        }
+        token_ids
+    }
+
+    // up to `num_nfts` token ids not in `skipped`, neither reserved nor flagged, with a min price
+    // up to `price_per_nft`, looking at MAX_ANY_BUY_SCAN pool tokens at most from index `start`
+    fn find_any_token_ids(&self, num_nfts: u64, price_per_nft: Balance, start: u64, skipped: &[TokenId]) -> Vec<TokenId> {
+        let len = self.token_ids_in_pools.len();
+        (0..len.min(MAX_ANY_BUY_SCAN))
+            .filter_map(|i| self.token_ids_in_pools.get_at((start + i) % len))
+            .filter(|token_id| {
+                !skipped.contains(token_id)
+                    && !self.is_excluded_from_any(token_id)
+                    && self.token_ids_in_pools.get(token_id).map_or(false, |t| t.min_price <= price_per_nft)
+            })
+            .take(num_nfts as usize)
+            .collect()
+    }
+
+    fn random_index(&self, nonce: u64, i: u64, len: u64) -> u64 {
+        let mut seed = env::random_seed();
+        seed.extend_from_slice(&self.pool_id.to_le_bytes());
+        seed.extend_from_slice(&nonce.to_le_bytes());
+        seed.extend_from_slice(&i.to_le_bytes());
+        let hash = env::sha256_array(&seed);
+        let mut index_bytes = [0u8; 8];
+        index_bytes.copy_from_slice(&hash[..8]);
+        u64::from_le_bytes(index_bytes) % len
+    }
+
     pub fn swap_near_for_specific_nfts(
         &mut self,
         deposit_near_amount: Balance,
         nft_ids: &Vec<TokenId>,
         protocol_fee_multiplier: u128,
     ) -> (Balance, Balance) {
+        self.assert_buys_enabled();
+        self.assert_nft_pool();
         require!(
             self.pool_type == PoolType::NFT || self.pool_type == PoolType::Trade,
             "wrong pool type"
         );
         require!(nft_ids.len() > 0, "must ask for > 0 nfts");
+        for token_id in nft_ids {
+            require!(!self.is_reserved(token_id), format!("token id {} reserved", token_id));
+        }
 
         let (protocol_fee, input_amount) = self.calculate_buy_info_and_update_pool(
             nft_ids.len() as u64,
@@ -244,36 +728,69 @@ impl Pair {
             protocol_fee_multiplier,
         );
 
+        self.assert_min_prices(nft_ids, (input_amount - protocol_fee) / nft_ids.len() as u128);
         for token_id in nft_ids {
             require!(
-                self.token_ids_in_pools.get(token_id).is_some(),
+                self.internal_remove_token(token_id).is_some(),
                 format!("token id {} not in pool", token_id)
             );
-            self.token_ids_in_pools.remove(&token_id);
         }
 
-        let protocol_fee = protocol_fee.as_u128();
         if self.asset_recipient.is_none() {
-            //trade pool, add the near input to the pool balance
-            self.near_balance += input_amount - protocol_fee;
-        } 
+            self.internal_receive_near(input_amount - protocol_fee);
+        }
 
         (protocol_fee, input_amount)
     }
 
+    // buy of `nft_ids` from the pool for `price` without moving the curve, for quotes signed
+    // off-chain
+    pub fn buy_nfts_at_price(
+        &mut self,
+        nft_ids: &Vec<TokenId>,
+        price: Balance,
+        protocol_fee_multiplier: u128,
+    ) -> Balance {
+        self.assert_buys_enabled();
+        self.assert_nft_pool();
+        require!(
+            self.pool_type == PoolType::NFT || self.pool_type == PoolType::Trade,
+            "wrong pool type"
+        );
+        require!(nft_ids.len() > 0, "must ask for > 0 nfts");
+        for token_id in nft_ids {
+            require!(!self.is_reserved(token_id), format!("token id {} reserved", token_id));
+        }
+        let protocol_fee = expect_u128(U256::from(price) * U256::from(protocol_fee_multiplier) / WAD, "protocol fee overflow");
+        self.assert_min_prices(nft_ids, (price - protocol_fee) / nft_ids.len() as u128);
+        for token_id in nft_ids {
+            require!(
+                self.internal_remove_token(token_id).is_some(),
+                format!("token id {} not in pool", token_id)
+            );
+        }
+
+        if self.asset_recipient.is_none() {
+            self.internal_receive_near(price - protocol_fee);
+        }
+        protocol_fee
+    }
+
     pub fn swap_nfts_for_near(
         &mut self,
         nft_ids: &Vec<TokenId>,
         min_near_out: Balance,
         protocol_fee_multiplier: u128,
     ) -> (Balance, Balance) {
+        self.assert_sells_enabled();
+        self.assert_nft_pool();
         require!(
             self.pool_type == PoolType::Token || self.pool_type == PoolType::Trade,
             "wrong pool type"
         );
         require!(nft_ids.len() > 0, "ask for > 0");
 
-        let (protocol_fee, mut output_amount) = self.calculate_sell_info_and_update_pool(
+        let (mut protocol_fee, mut output_amount) = self.calculate_sell_info_and_update_pool(
             nft_ids.len() as u64,
             min_near_out,
             protocol_fee_multiplier,
@@ -285,7 +802,6 @@ impl Pair {
             output_amount = self.near_balance;
             self.near_balance = 0;
         }
-        let mut protocol_fee = protocol_fee.as_u128();
         if self.near_balance >= protocol_fee {
             self.near_balance -= protocol_fee;
         } else {
@@ -295,75 +811,238 @@ impl Pair {
 
         if self.asset_recipient.is_none() {
             //trading
-            self.internal_add_token_ids(env::predecessor_account_id(), nft_ids);
+            self.internal_add_acquired_token_ids(nft_ids);
+            self.assert_nft_cap();
         }
 
         require!(output_amount >= min_near_out, "insufficient liquidity");
         (protocol_fee, output_amount)
     }
 
-    fn calculate_buy_info_and_update_pool(
+    pub fn swap_near_for_mt(
         &mut self,
-        num_nfts: u64,
-        max_expected_near_input: Balance,
+        deposit_near_amount: Balance,
+        amount: u64,
         protocol_fee_multiplier: u128,
-    ) -> (U256, Balance) {
-        let current_spot_price = self.spot_price;
-        let current_delta = self.delta;
-        let buy_info = self.curve.get_buy_info(
-            current_spot_price,
-            current_delta,
-            num_nfts,
-            self.fee,
+    ) -> (Balance, Balance) {
+        self.assert_buys_enabled();
+        self.assert_mt_pool();
+        require!(self.pool_type == PoolType::NFT, "wrong pool type");
+        require!(
+            amount > 0 && amount as u128 <= self.mt_balance,
+            "ask for > 0 or less than equal tokens in pool"
+        );
+
+        let (protocol_fee, input_amount) = self.calculate_buy_info_and_update_pool(
+            amount,
+            deposit_near_amount,
             protocol_fee_multiplier,
         );
-        if buy_info.error_code != CurveErrorCode::Ok {
-            env::panic_str("failed to get buy info");
+        self.mt_balance -= amount as u128;
+
+        if self.asset_recipient.is_none() {
+            self.internal_receive_near(input_amount - protocol_fee);
         }
+
+        (protocol_fee, input_amount)
+    }
+
+    pub fn swap_mt_for_near(
+        &mut self,
+        amount: u64,
+        min_near_out: Balance,
+        protocol_fee_multiplier: u128,
+    ) -> (Balance, Balance) {
+        self.assert_sells_enabled();
+        self.assert_mt_pool();
+        require!(self.pool_type == PoolType::Token, "wrong pool type");
+        require!(amount > 0, "ask for > 0");
+
+        let (protocol_fee, output_amount) = self.calculate_sell_info_and_update_pool(
+            amount,
+            min_near_out,
+            protocol_fee_multiplier,
+        );
         require!(
-            buy_info.input_value <= U256::from(max_expected_near_input),
-            "not enough near payment"
+            self.near_balance >= output_amount + protocol_fee,
+            "insufficient liquidity"
         );
+        self.near_balance -= output_amount + protocol_fee;
 
-        if current_spot_price != buy_info.new_spot_price || current_delta != buy_info.new_delta {
-            self.spot_price = buy_info.new_spot_price;
-            self.delta = buy_info.new_delta;
+        if self.asset_recipient.is_none() {
+            self.mt_balance += amount as u128;
+            self.assert_nft_cap();
         }
 
-        (buy_info.protocol_fee, buy_info.input_value.as_u128())
+        (protocol_fee, output_amount)
     }
 
-    fn calculate_sell_info_and_update_pool(
+    pub fn withdraw_mt(&mut self, amount: Balance) {
+        self.assert_mt_pool();
+        self.assert_owner();
+        self.assert_release();
+        require!(amount <= self.mt_balance, "not enough tokens in pool");
+        self.mt_balance -= amount;
+    }
+
+    // number of items the curve trades against, the held amount for multi token pools
+    // keeps the last index of pools left without lp
+    pub(crate) fn update_lp_index(&mut self) {
+        if self.lp_supply == 0 {
+            return;
+        }
+        let nfts_value = U256::from(self.num_items()) * U256::from(self.spot_price);
+        let value = U256::from(self.near_balance.saturating_sub(self.dust)) + nfts_value;
+        self.lp_index = expect_u128(value * U256::from(WAD) / U256::from(self.lp_supply), "lp index overflow");
+        self.lp_index_updated_at = env::block_timestamp_ms() / 1000;
+    }
+
+    pub fn num_items(&self) -> u64 {
+        match self.mt_token_id {
+            Some(_) => self.mt_balance as u64,
+            None => self.token_ids_in_pools.len(),
+        }
+    }
+
+    // pricing of buying `num_items` from the pool, from its curve or its virtual reserves
+    pub(crate) fn get_buy_info(&self, num_items: u64, protocol_fee_multiplier: u128) -> BuyInfo {
+        match &self.virtual_reserves {
+            None => self.curve.get_buy_info(Yocto(self.spot_price), self.delta, num_items, self.fee, Wad(protocol_fee_multiplier)),
+            Some(reserves) => {
+                if num_items > self.token_ids_in_pools.len() {
+                    return BuyInfo::overflow(CurveErrorCode::InsufficientLiquidity);
+                }
+                virtual_reserves::get_buy_info(
+                    self.near_balance + reserves.near.0,
+                    self.token_ids_in_pools.len() + reserves.nfts,
+                    self.delta,
+                    num_items,
+                    self.fee.0,
+                    protocol_fee_multiplier,
+                )
+                .checked()
+            }
+        }
+    }
+
+    pub(crate) fn get_sell_info(&self, num_items: u64, protocol_fee_multiplier: u128) -> SellInfo {
+        match &self.virtual_reserves {
+            None => self.curve.get_sell_info(Yocto(self.spot_price), self.get_sell_delta(), num_items, self.get_sell_fee(), Wad(protocol_fee_multiplier)),
+            Some(reserves) => {
+                let sell_info = virtual_reserves::get_sell_info(
+                    self.near_balance + reserves.near.0,
+                    self.token_ids_in_pools.len() + reserves.nfts,
+                    self.get_sell_delta(),
+                    num_items,
+                    self.get_sell_fee().0,
+                    protocol_fee_multiplier,
+                )
+                .checked();
+                // the virtual near is not there to be paid out
+                if sell_info.error_code == CurveErrorCode::Ok
+                    && sell_info.output_value + sell_info.protocol_fee > U256::from(self.near_balance)
+                {
+                    return SellInfo::overflow(CurveErrorCode::InsufficientLiquidity);
+                }
+                sell_info
+            }
+        }
+    }
+
+    pub(crate) fn quote_buy(&self, num_items: u64, protocol_fee_multiplier: u128) -> Result<TradeQuote, CurveErrorCode> {
+        if self.virtual_reserves.is_some() {
+            return buy_trade_quote(self.get_buy_info(num_items, protocol_fee_multiplier));
+        }
+        pricing::quote_buy(&self.curve, Yocto(self.spot_price), self.delta, self.fee, Wad(protocol_fee_multiplier), num_items)
+    }
+
+    pub(crate) fn quote_sell(&self, num_items: u64, protocol_fee_multiplier: u128) -> Result<TradeQuote, CurveErrorCode> {
+        if self.virtual_reserves.is_some() {
+            return sell_trade_quote(self.get_sell_info(num_items, protocol_fee_multiplier));
+        }
+        pricing::quote_sell(&self.curve, Yocto(self.spot_price), self.get_sell_delta(), self.get_sell_fee(), Wad(protocol_fee_multiplier), num_items)
+    }
+
+    // bumps the trade sequence and keeps trade statistics, `near_amount` being what the trader
+    // paid for a buy or received for a sell
+    pub fn record_trade(&mut self, is_buy: bool, near_amount: Balance, protocol_fee: Balance) {
+        self.trade_seq += 1;
+        let fee = if is_buy { self.fee } else { self.get_sell_fee() };
+        let pool_fee = trade_pool_fee(fee, is_buy, near_amount, protocol_fee)
+            .unwrap_or_else(|_| env::panic_str("pool fee overflow"));
+        self.total_volume += near_amount;
+        if self.recent_trades.len() == RECENT_TRADES_KEPT {
+            self.recent_trades.remove(0);
+        }
+        self.recent_trades.push(TradeRecord {
+            timestamp_sec: env::block_timestamp_ms() / 1000,
+            near_amount,
+            pool_fee,
+            protocol_fee,
+        });
+    }
+
+    fn internal_receive_near(&mut self, amount: Balance) {
+        if self.pool_type == PoolType::Trade {
+            //trade pool, add the near input to the pool balance
+            self.near_balance += amount;
+        } else {
+            self.proceeds += amount;
+        }
+    }
+
+    pub fn claim_proceeds(&mut self) -> Balance {
+        self.assert_owner();
+        let proceeds = self.proceeds;
+        self.proceeds = 0;
+        proceeds
+    }
+
+    fn calculate_buy_info_and_update_pool(
         &mut self,
         num_nfts: u64,
-        min_expected_near_output: Balance,
+        max_expected_near_input: Balance,
         protocol_fee_multiplier: u128,
-    ) -> (U256, Balance) {
-        let current_spot_price = self.spot_price;
-        let current_delta = self.delta;
-
-        let sell_info = self.curve.get_sell_info(
-            current_spot_price,
-            current_delta,
-            num_nfts,
-            self.fee,
-            protocol_fee_multiplier,
+    ) -> (Balance, Balance) {
+        self.assert_oracle_price_fresh();
+        self.assert_circuit_breaker_closed();
+        let quote = self.quote_buy(num_nfts, protocol_fee_multiplier)
+            .unwrap_or_else(|error_code| env::panic_str(&format!("failed to get buy info: {:?}", error_code)));
+        require!(
+            quote.near_amount <= max_expected_near_input,
+            "not enough near payment"
         );
-        if sell_info.error_code != CurveErrorCode::Ok {
-            env::panic_str("failed to get sell info");
+
+        if self.oracle.is_none() {
+            self.spot_price = quote.new_spot_price;
+            self.delta = quote.new_delta;
         }
+        (quote.protocol_fee, quote.near_amount)
+    }
 
+    fn calculate_sell_info_and_update_pool(
+        &mut self,
+        num_nfts: u64,
+        min_expected_near_output: Balance,
+        protocol_fee_multiplier: u128,
+    ) -> (Balance, Balance) {
+        self.assert_oracle_price_fresh();
+        self.assert_circuit_breaker_closed();
+        let quote = self.quote_sell(num_nfts, protocol_fee_multiplier)
+            .unwrap_or_else(|error_code| env::panic_str(&format!("failed to get sell info: {:?}", error_code)));
         require!(
-            sell_info.output_value.as_u128() >= min_expected_near_output,
+            quote.near_amount >= min_expected_near_output,
             "out too little near"
         );
 
-        if current_spot_price != sell_info.new_spot_price || current_delta != sell_info.new_delta {
-            self.spot_price = sell_info.new_spot_price;
-            self.delta = sell_info.new_delta;
+        if self.oracle.is_none() {
+            self.spot_price = quote.new_spot_price;
+            match self.sell_delta {
+                Some(_) => self.sell_delta = Some(quote.new_delta),
+                None => self.delta = quote.new_delta,
+            }
         }
-
-        (sell_info.protocol_fee, sell_info.output_value.as_u128())
+        (quote.protocol_fee, quote.near_amount)
     }
 
     pub fn lp_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
@@ -378,6 +1057,7 @@ impl Pair {
             .get(&receiver_id)
             .expect("receiver account not registered");
         self.lp_balances.insert(&receiver_id, &(balance_out + amount));
+        self.move_lp_cost_basis(sender_id, receiver_id, amount, balance);
     }
 
     fn mint_lp(&mut self, account_id: &AccountId, lp: Balance) {
@@ -391,7 +1071,16 @@ impl Pair {
 
     // the idea is if the amount of NFTs to withdraw is fraction, we round up the amount of NFT, and reduce the 
     // corresponding value of the fraction portion in the amount in near to withdraw
-    pub fn burn_lp(&mut self, account_id: &AccountId, lp: Balance, protocol_fee_multiplier: u128) -> (Balance, Balance, Vec<TokenId>) {
+    // the redeemed nfts start with `preferred_token_ids`, which must be in the pool and no more
+    // than the nfts `lp` is entitled to
+    pub fn burn_lp(
+        &mut self,
+        account_id: &AccountId,
+        lp: Balance,
+        protocol_fee_multiplier: u128,
+        prefer_near: bool,
+        preferred_token_ids: &[TokenId],
+    ) -> (Balance, Balance, Vec<TokenId>) {
         if lp == 0u128 {
             return (0, 0, vec![]);
         }
@@ -400,50 +1089,141 @@ impl Pair {
         if lp > prev_value {
             env::panic_str("insufficient lp");
         }
+        // the owner liquidity stays in the pool until the lock is released
+        if account_id == &self.owner {
+            self.assert_release();
+        }
 
-        // compute withdrawnable nfts and liquidity
-        let withdrawable_near = U256::from(self.token_ids_in_pools.len()) * U256::from(self.spot_price) * U256::from(lp) / U256::from(self.lp_supply);
-        let mut withdrawable_near = withdrawable_near.as_u128();
-        let mut num_nfts_to_withdraw = self.token_ids_in_pools.len() as u128 * lp / self.lp_supply;
-        let mut value_in_fraction_nft = 0u128;
-        if num_nfts_to_withdraw * self.lp_supply != lp * self.token_ids_in_pools.len() as u128 {
-            num_nfts_to_withdraw += 1;
-            let buy_info = self.curve.get_buy_info(
-                self.spot_price,
-                self.delta,
-                1,
-                self.fee,
-                protocol_fee_multiplier.clone(),
-            );
-            // num_nfts_to_withdraw - 1 nfts with current spot price
-            // the rounded up of fraction nft with spot price after buying 1 nft
-            value_in_fraction_nft = (num_nfts_to_withdraw - 1) * self.spot_price + 1 * buy_info.new_spot_price;
-            require!(value_in_fraction_nft >= withdrawable_near.clone(), "internal error in handling liquidity");
-            value_in_fraction_nft -= withdrawable_near.clone();
+        let mut redemption = self.compute_lp_redemption(Some(account_id), lp, protocol_fee_multiplier, prefer_near);
+        if !preferred_token_ids.is_empty() {
+            redemption.token_ids = self.internal_pick_redeemed_token_ids(redemption.token_ids.len(), preferred_token_ids);
+        }
+        for token_id in &redemption.token_ids {
+            self.internal_remove_token(token_id);
         }
 
-        if value_in_fraction_nft > withdrawable_near {
-            // TODO: should we allow to withdraw in near only?
-            env::panic_str("cannot withdraw as liquidity value in near is too small compared to nft spot price");
+        self.lp_balances.insert(account_id, &(prev_value - lp));
+        self.lp_supply -= lp;
+        self.near_balance -= redemption.near_amount;
+        let withdrawn_value = self.lp_value_of(redemption.near_amount, redemption.token_ids.len() as u64);
+        self.record_lp_withdrawal(account_id, lp, prev_value, withdrawn_value);
+        if self.pool_type == PoolType::Trade && self.lp_supply == self.locked_lp {
+            // redemptions round down, what they left behind is claimed by no lp
+            self.dust = self.near_balance;
         }
+        (redemption.protocol_fee, redemption.near_amount, redemption.token_ids)
+    }
 
-        // TODO: take fee 
-        withdrawable_near -= value_in_fraction_nft;
+    fn internal_pick_redeemed_token_ids(&self, num_nfts: usize, preferred_token_ids: &[TokenId]) -> Vec<TokenId> {
+        require!(preferred_token_ids.len() <= num_nfts, "preferred tokens exceed the redeemed nfts");
+        let preferred: HashSet<&TokenId> = preferred_token_ids.iter().collect();
+        require!(preferred.len() == preferred_token_ids.len(), "duplicate preferred token ids");
+        for token_id in preferred_token_ids {
+            require!(self.token_ids_in_pools.contains(token_id), format!("token {} not in pool", token_id));
+            require!(!self.is_reserved(token_id), format!("token {} reserved", token_id));
+        }
+        let mut token_ids = preferred_token_ids.to_vec();
+        token_ids.extend(
+            self.token_ids_in_pools
+                .keys()
+                .filter(|token_id| !preferred.contains(token_id) && !self.is_reserved(token_id))
+                .take(num_nfts - preferred_token_ids.len()),
+        );
+        require!(token_ids.len() == num_nfts, "not enough unreserved nfts to redeem");
+        token_ids
+    }
 
-        let token_ids = self
-            .token_ids_in_pools
-            .keys()
-            .take(num_nfts_to_withdraw as usize)
-            .collect::<Vec<TokenId>>();
-        for token_id in &token_ids {
-            self.token_ids_in_pools.remove(token_id);
+    // computes what burning `lp` would return without touching the pool state
+    // with `prefer_near`, the lp share of the nfts is paid in near at spot price when the pool stays
+    // healthy, see `compute_near_only_redemption`
+    pub(crate) fn compute_lp_redemption(
+        &self,
+        account_id: Option<&AccountId>,
+        lp: Balance,
+        protocol_fee_multiplier: u128,
+        prefer_near: bool,
+    ) -> LpRedemption {
+        if lp == 0u128 {
+            return LpRedemption { token_ids: vec![], near_amount: 0, fraction_nft_value: 0, protocol_fee: 0 };
+        }
+        require!(lp <= self.lp_supply - self.locked_lp, "insufficient lp");
+        if prefer_near {
+            if let Some(redemption) = self.compute_near_only_redemption(lp, protocol_fee_multiplier) {
+                return redemption;
+            }
         }
 
-        self.lp_balances.insert(account_id, &(prev_value - lp));
-        self.lp_supply -= lp;
-        self.near_balance -= withdrawable_near.clone();
+        // the rounded up fraction of an nft is valued at the spot price after buying 1 nft
+        let redemption = pricing::nft_redemption(
+            self.token_ids_in_pools.len(),
+            self.spot_price,
+            || {
+                let buy_info = self.get_buy_info(1, protocol_fee_multiplier);
+                (buy_info.error_code == CurveErrorCode::Ok).then(|| buy_info.new_spot_price)
+            },
+            lp,
+            self.lp_supply,
+        )
+        .unwrap_or_else(|error| env::panic_str(error));
+        let num_nfts_to_withdraw = redemption.num_nfts;
+        let withdrawable_near = redemption.near_amount;
+        let value_in_fraction_nft = redemption.fraction_nft_value;
+
+        let token_ids = self.internal_redeemed_token_ids(account_id, num_nfts_to_withdraw as usize);
+        require!(token_ids.len() == num_nfts_to_withdraw as usize, "not enough unreserved nfts to redeem");
+
         let protocol_fee = U256::from(withdrawable_near) * U256::from(protocol_fee_multiplier) / WAD;
-        (protocol_fee.as_u128(), withdrawable_near, token_ids)
+        LpRedemption {
+            token_ids,
+            near_amount: withdrawable_near,
+            fraction_nft_value: value_in_fraction_nft,
+            protocol_fee: expect_u128(protocol_fee, "protocol fee overflow"),
+        }
+    }
+
+    // nfts returned by a burn of `account_id`: its own deposits first, then the nfts the pool
+    // bought, then the deposits of other lps
+    fn internal_redeemed_token_ids(&self, account_id: Option<&AccountId>, num_nfts: usize) -> Vec<TokenId> {
+        let (mut own, mut acquired, mut others) = (vec![], vec![], vec![]);
+        for (token_id, token) in self.token_ids_in_pools.iter() {
+            if self.is_reserved(&token_id) {
+                continue;
+            }
+            match token.depositor.as_ref() {
+                None => acquired.push(token_id),
+                Some(depositor) if Some(depositor) == account_id => own.push(token_id),
+                Some(_) => others.push(token_id),
+            }
+            if own.len() == num_nfts {
+                break;
+            }
+        }
+        own.into_iter().chain(acquired).chain(others).take(num_nfts).collect()
+    }
+
+    fn compute_near_only_redemption(&self, lp: Balance, protocol_fee_multiplier: u128) -> Option<LpRedemption> {
+        let num_nfts = U256::from(self.token_ids_in_pools.len());
+        let spot_price = U256::from(self.spot_price);
+        let near_balance = self.near_balance.saturating_sub(self.dust);
+        // share of the near the pool holds plus share of the nfts valued at spot price
+        let pool_value = U256::from(near_balance) + num_nfts * spot_price;
+        let near_amount = pool_value * U256::from(lp) / U256::from(self.lp_supply);
+        let near_amount = expect_u128(near_amount, "withdrawable near overflow");
+        if near_amount == 0 || near_amount > near_balance {
+            return None;
+        }
+        let min_near_left = num_nfts * spot_price * U256::from(NEAR_ONLY_BURN_HEALTH_RATIO) / WAD;
+        if U256::from(near_balance - near_amount) < min_near_left {
+            return None;
+        }
+
+        let protocol_fee = U256::from(near_amount) * U256::from(protocol_fee_multiplier) / WAD;
+        Some(LpRedemption {
+            token_ids: vec![],
+            near_amount,
+            fraction_nft_value: 0,
+            protocol_fee: expect_u128(protocol_fee, "protocol fee overflow"),
+        })
     }
 
     pub fn internal_register_account_lp(& mut self, account_id: &AccountId) {
@@ -454,11 +1234,79 @@ impl Pair {
 
     //only owner functions
     pub(crate) fn assert_owner(&self) {
-        if env::predecessor_account_id() != env::predecessor_account_id() {
+        if env::predecessor_account_id() != self.owner {
             env::panic_str("This method can be called only by pool owner")
         }
     }
 
+    pub(crate) fn is_reserved(&self, token_id: &TokenId) -> bool {
+        let now = env::block_timestamp_ms() / 1000;
+        self.reserved_token_ids.iter().any(|(id, expires_at)| id == token_id && *expires_at > now)
+    }
+
+    // reserved or flagged, only buys of specific nfts can take it
+    fn is_excluded_from_any(&self, token_id: &TokenId) -> bool {
+        self.is_reserved(token_id) || self.flagged_token_ids.contains(token_id)
+    }
+
+    fn num_excluded_from_any(&self) -> u64 {
+        let now = env::block_timestamp_ms() / 1000;
+        let reserved = self.reserved_token_ids.iter().filter(|(_, expires_at)| *expires_at > now);
+        let (num_reserved, num_reserved_flagged) = reserved.fold((0, 0), |(num_reserved, num_flagged), (token_id, _)| {
+            (num_reserved + 1, num_flagged + self.flagged_token_ids.contains(token_id) as u64)
+        });
+        num_reserved + self.flagged_token_ids.len() - num_reserved_flagged
+    }
+
+    // nfts a buy of any nfts can take
+    pub(crate) fn num_buyable_by_any(&self) -> u64 {
+        self.token_ids_in_pools.len().saturating_sub(self.num_excluded_from_any())
+    }
+
+    // expired reservations are dropped on the way
+    pub(crate) fn reserve_tokens(&mut self, token_ids: &[TokenId], expires_at: u64) {
+        let now = env::block_timestamp_ms() / 1000;
+        self.reserved_token_ids.retain(|(_, expires_at)| *expires_at > now);
+        for token_id in token_ids {
+            require!(self.token_ids_in_pools.contains(token_id), format!("token id {} not in pool", token_id));
+            require!(!self.is_reserved(token_id), format!("token id {} reserved", token_id));
+            self.reserved_token_ids.push((token_id.clone(), expires_at));
+        }
+        require!(self.reserved_token_ids.len() <= MAX_RESERVED_TOKENS, "too many reserved token ids");
+    }
+
+    pub(crate) fn unreserve_tokens(&mut self, token_ids: &[TokenId]) {
+        let now = env::block_timestamp_ms() / 1000;
+        self.reserved_token_ids.retain(|(id, expires_at)| *expires_at > now && !token_ids.contains(id));
+    }
+
+    pub(crate) fn assert_nft_pool(&self) {
+        require!(self.mt_token_id.is_none(), "not supported for multi token pools");
+    }
+
+    pub(crate) fn assert_nft_cap(&self) {
+        if let Some(max_nfts) = self.max_nfts {
+            require!(self.num_items() <= max_nfts, format!("pool cannot hold more than {} nfts", max_nfts));
+        }
+    }
+
+    // trade proceeds are not bounded, only near deposits
+    pub(crate) fn assert_near_cap(&self) {
+        if let Some(max_near) = self.max_near {
+            require!(self.near_balance <= max_near, format!("pool cannot hold more than {} near", max_near));
+        }
+    }
+
+    pub fn set_caps(&mut self, max_nfts: Option<u64>, max_near: Option<Balance>) {
+        self.assert_owner();
+        self.max_nfts = max_nfts;
+        self.max_near = max_near;
+    }
+
+    fn assert_mt_pool(&self) {
+        require!(self.mt_token_id.is_some(), "not a multi token pool");
+    }
+
     fn assert_not_trading_pool(&self) {
         assert!(self.pool_type != PoolType::Trade, "must not be trading pool");
     }
@@ -471,6 +1319,79 @@ impl Pair {
         );
     }
 
+    pub fn extend_lock(&mut self, new_released_time: u64) {
+        self.assert_owner();
+        require!(
+            new_released_time >= self.released_time,
+            "lock can only be extended"
+        );
+        self.released_time = new_released_time;
+    }
+
+    // unlocks the pool now, charging `penalty_multiplier` (base 1e18) of the owner liquidity.
+    // The owner of a trade pool forfeits that share of its lp, which goes to the other lps, the
+    // near of other pools is the owner's own and the share is taken from it for the protocol.
+    // Returns the penalty in near, forfeited lp valued at its share of the near and nfts at spot
+    // price.
+    pub fn early_unlock(&mut self, penalty_multiplier: u128) -> Balance {
+        self.assert_owner();
+        let timestamp_sec = env::block_timestamp_ms() / 1000;
+        require!(self.released_time > timestamp_sec, "Pool liquidity already released");
+        self.released_time = timestamp_sec;
+        if self.pool_type != PoolType::Trade {
+            let penalty = expect_u128(U256::from(self.near_balance) * U256::from(penalty_multiplier) / WAD, "penalty overflow");
+            self.near_balance -= penalty;
+            return penalty;
+        }
+
+        let owner_lp = self.lp_balances.get(&self.owner).unwrap_or(0);
+        let forfeited_lp = expect_u128(U256::from(owner_lp) * U256::from(penalty_multiplier) / WAD, "penalty overflow");
+        let pool_value = U256::from(self.near_balance.saturating_sub(self.dust)) + U256::from(self.num_items()) * U256::from(self.spot_price);
+        let penalty = expect_u128(pool_value * U256::from(forfeited_lp) / U256::from(self.lp_supply), "penalty overflow");
+        self.lp_balances.insert(&self.owner, &(owner_lp - forfeited_lp));
+        self.lp_supply -= forfeited_lp;
+        penalty
+    }
+
+    pub(crate) fn queue_force_release(&mut self) -> u64 {
+        let timestamp_sec = env::block_timestamp_ms() / 1000;
+        require!(self.released_time > timestamp_sec, "Pool liquidity already released");
+        require!(self.force_release_at.is_none(), "force release already queued");
+        let force_release_at = timestamp_sec + FORCE_RELEASE_DELAY_SEC;
+        self.force_release_at = Some(force_release_at);
+        force_release_at
+    }
+
+    pub(crate) fn force_release(&mut self) {
+        let timestamp_sec = env::block_timestamp_ms() / 1000;
+        let force_release_at = self.force_release_at.take().unwrap_or_else(|| env::panic_str("no force release queued"));
+        require!(force_release_at <= timestamp_sec, "force release not effective yet");
+        self.released_time = self.released_time.min(timestamp_sec);
+    }
+
+    pub(crate) fn get_sell_delta(&self) -> u128 {
+        self.sell_delta.unwrap_or(self.delta)
+    }
+
+    pub(crate) fn get_sell_fee(&self) -> Wad {
+        self.sell_fee.unwrap_or(self.fee)
+    }
+
+    // only set when creating the pool, so lps join at the spread they see
+    pub(crate) fn set_sell_side(&mut self, sell_delta: Option<u128>, sell_fee: Option<u128>) {
+        if sell_delta.is_none() && sell_fee.is_none() {
+            return;
+        }
+        require!(self.pool_type == PoolType::Trade, "only trade pools can have sell side params");
+        let delta = sell_delta.unwrap_or(self.delta);
+        let fee = sell_fee.map(Wad).unwrap_or(self.fee);
+        if let Some(error) = validate_pool_params(&self.curve, self.pool_type, Yocto(self.spot_price), delta, fee).first() {
+            env::panic_str(&format!("invalid sell_{}: {}", error.param, error.reason));
+        }
+        self.sell_delta = sell_delta;
+        self.sell_fee = sell_fee.map(Wad);
+    }
+
     pub fn change_spot_price(&mut self, new_spot_price: u128) {
         self.assert_owner();
         self.assert_not_trading_pool();
@@ -486,7 +1407,7 @@ impl Pair {
     pub fn change_fee(&mut self, new_fee: u128) {
         self.assert_owner();
         self.assert_not_trading_pool();
-        self.fee = new_fee;
+        self.fee = Wad(new_fee);
     }
 
     pub fn change_asset_recipient(&mut self, new_asset_recipient: Option<AccountId>) {
@@ -495,3 +1416,115 @@ impl Pair {
         self.asset_recipient = new_asset_recipient;
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_near_only_redemption_after_spot_price_moved() {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        register(&mut contract, &owner, NEAR);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Trade, &["1", "2"], 10 * NEAR);
+        let pool = &mut contract.pools[pool_id as usize];
+        assert_eq!(pool.lp_supply, 10 * NEAR);
+
+        // a tenth of the 10 near held and of the 2 nfts at spot price
+        pool.spot_price = 2 * SPOT_PRICE;
+        let redemption = pool.compute_lp_redemption(None, NEAR, 0, true);
+        assert!(redemption.token_ids.is_empty());
+        assert_eq!(redemption.near_amount, NEAR + 4 * NEAR / 10);
+
+        pool.spot_price = SPOT_PRICE / 2;
+        let redemption = pool.compute_lp_redemption(None, NEAR, 0, true);
+        assert!(redemption.token_ids.is_empty());
+        assert_eq!(redemption.near_amount, NEAR + NEAR / 10);
+    }
+
+    #[test]
+    fn test_near_only_redemption_leaving_pool_unhealthy() {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        register(&mut contract, &owner, NEAR);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Trade, &["1", "2"], 10 * NEAR);
+        let pool = &mut contract.pools[pool_id as usize];
+
+        // 8 near left for 10 near of nfts
+        pool.spot_price = 5 * SPOT_PRICE;
+        assert!(pool.compute_near_only_redemption(NEAR, 0).is_none());
+    }
+
+    // pool of nfts 1 to 4, 1 priced above the spot price and 2 reserved
+    fn pool_with_excluded_tokens(contract: &mut Contract) -> &mut Pair {
+        let owner = account("owner.near");
+        register(contract, &owner, NEAR);
+        let pool_id = create_pool(contract, &owner, PoolType::NFT, &["1", "2", "3", "4"], 0);
+        let pool = &mut contract.pools[pool_id as usize];
+        pool.token_ids_in_pools.insert(&"1".to_string(), &DepositedToken { depositor: Some(owner), min_price: 100 * NEAR });
+        pool.num_priced_tokens += 1;
+        pool.reserve_tokens(&token_ids(&["2"]), env::block_timestamp_ms() / 1000 + 600);
+        pool
+    }
+
+    #[test]
+    fn test_any_buy_skips_priced_and_reserved_tokens() {
+        let mut contract = setup_contract();
+        let pool = pool_with_excluded_tokens(&mut contract);
+        assert_eq!(pool.num_buyable_by_any(), 3);
+        assert_eq!(pool.preview_any_token_ids(2, SPOT_PRICE), token_ids(&["3", "4"]));
+        assert_eq!(pool.internal_take_token_ids_above_min_price(2, SPOT_PRICE, None), token_ids(&["3", "4"]));
+        assert_eq!(pool.token_ids_in_pools.len(), 2);
+        assert!(pool.preview_any_token_ids(1, SPOT_PRICE).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough nfts above their min price")]
+    fn test_any_buy_of_more_tokens_than_sellable_at_price() {
+        let mut contract = setup_contract();
+        let pool = pool_with_excluded_tokens(&mut contract);
+        pool.internal_take_token_ids_above_min_price(3, SPOT_PRICE, Some(7));
+    }
+
+    #[test]
+    fn test_minimum_liquidity_locked_on_first_mint() {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        register(&mut contract, &owner, NEAR);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Trade, &["1", "2"], 10 * NEAR);
+        let pool = &contract.pools[pool_id as usize];
+        assert_eq!((pool.lp_supply, pool.locked_lp), (10 * NEAR, MINIMUM_LIQUIDITY));
+        assert_eq!(pool.lp_balances.get(&owner), Some(10 * NEAR - MINIMUM_LIQUIDITY));
+        assert_eq!(contract.get_pool_info(pool_id, None).locked_lp.0, MINIMUM_LIQUIDITY);
+
+        // burning every lp of the owner leaves the locked lp
+        set_caller(&owner, 1);
+        contract.remove_liquidity(pool_id, U128(10 * NEAR - MINIMUM_LIQUIDITY), None);
+        let pool = &contract.pools[pool_id as usize];
+        assert_eq!((pool.lp_supply, pool.locked_lp), (MINIMUM_LIQUIDITY, MINIMUM_LIQUIDITY));
+        assert_eq!(pool.dust, pool.near_balance);
+    }
+
+    #[test]
+    #[should_panic(expected = "insufficient lp")]
+    fn test_locked_liquidity_not_redeemable() {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        register(&mut contract, &owner, NEAR);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Trade, &["1", "2"], 10 * NEAR);
+        let pool = &contract.pools[pool_id as usize];
+        pool.compute_lp_redemption(None, pool.lp_supply, 0, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "initial liquidity below minimum liquidity")]
+    fn test_first_mint_below_minimum_liquidity() {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        register(&mut contract, &owner, NEAR);
+        // creating a pool withdraws from the deposit of the collection, even without nfts
+        deposit_nfts(&mut contract, &owner, &["1"]);
+        create_pool(&mut contract, &owner, PoolType::Trade, &[], MINIMUM_LIQUIDITY);
+    }
+}
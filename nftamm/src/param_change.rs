@@ -0,0 +1,109 @@
+use crate::*;
+use crate::curves::units::{Wad, Yocto};
+use crate::pair::validate_pool_params;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// curve parameters set by the owner of a non-trade pool, unset ones are left unchanged
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolParamChange {
+    pub spot_price: Option<U128>,
+    pub delta: Option<U128>,
+    pub fee: Option<U128>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingParamChange {
+    pub change: PoolParamChange,
+    // in seconds
+    pub effective_at: u64,
+}
+
+impl Pair {
+    // (spot price, delta, fee) of the pool after the change
+    pub(crate) fn changed_params(&self, change: &PoolParamChange) -> (u128, u128, Wad) {
+        let spot_price = change.spot_price.map(|p| p.0).unwrap_or(self.spot_price);
+        let delta = change.delta.map(|d| d.0).unwrap_or(self.delta);
+        let fee = change.fee.map(Wad::from).unwrap_or(self.fee);
+        if let Some(error) = validate_pool_params(&self.curve, self.pool_type, Yocto(spot_price), delta, fee).first() {
+            env::panic_str(&error.reason);
+        }
+        (spot_price, delta, fee)
+    }
+
+    pub(crate) fn apply_param_change(&mut self, change: &PoolParamChange) {
+        let (spot_price, delta, fee) = self.changed_params(change);
+        self.spot_price = spot_price;
+        self.delta = delta;
+        self.fee = fee;
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets how long parameter changes of the pool are queued before they can be applied. The
+    /// delay can only be increased, so traders can rely on it.
+    pub fn set_param_change_delay(&mut self, pool_id: u64, delay_sec: u64) {
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        require!(delay_sec >= pool.param_change_delay, "delay can only be increased");
+        pool.param_change_delay = delay_sec;
+    }
+
+    /// Changes the curve parameters of a non-trade pool, right away when the pool has no change
+    /// delay, otherwise replacing the pending change with one applicable after the delay.
+    pub fn change_pool_params(&mut self, pool_id: u64, change: PoolParamChange) {
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        require!(pool.pool_type != PoolType::Trade, "must not be trading pool");
+        if pool.param_change_delay == 0 {
+            pool.apply_param_change(&change);
+            return;
+        }
+        pool.changed_params(&change);
+        pool.pending_param_change = Some(PendingParamChange {
+            change,
+            effective_at: env::block_timestamp_ms() / 1000 + pool.param_change_delay,
+        });
+        self.assert_storage(&account_id, prev_storage, None);
+    }
+
+    /// Applies the pending parameter change of the pool once its delay passed. Callable by anyone.
+    pub fn apply_pool_param_change(&mut self, pool_id: u64) {
+        let pool = &mut self.pools[pool_id as usize];
+        let pending = pool.pending_param_change.take().unwrap_or_else(|| env::panic_str("no pending param change"));
+        require!(pending.effective_at <= env::block_timestamp_ms() / 1000, "param change not effective yet");
+        pool.apply_param_change(&pending.change);
+    }
+
+    pub fn cancel_pool_param_change(&mut self, pool_id: u64) {
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        require!(pool.pending_param_change.take().is_some(), "no pending param change");
+    }
+
+    pub fn get_param_change_delay(&self, pool_id: u64) -> u64 {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.param_change_delay
+    }
+
+    pub fn get_pending_param_change(&self, pool_id: u64) -> Option<PendingParamChange> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.pending_param_change.clone()
+    }
+
+    /// Pools with a pending parameter change among `[from_index, from_index + limit)`, for
+    /// frontends to warn traders.
+    pub fn get_pending_param_changes(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<(u64, PendingParamChange)> {
+        self.pools
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .filter_map(|pool| pool.pending_param_change.clone().map(|pending| (pool.pool_id, pending)))
+            .collect()
+    }
+}
@@ -0,0 +1,110 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// callbacks normally resolve within a few blocks, records older than this are stale
+const PENDING_OPERATION_TTL_SEC: u64 = 24 * 3600;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum OperationKind {
+    NftTransfer,
+    MtTransfer,
+    PayoutTransfer,
+    ProtocolFeeClaim,
+    LpTransfer,
+    GateAttestation,
+    ComplianceApproval,
+    TokenMetadata,
+    MarketplaceBuy,
+    NftPull,
+    ApprovalBuy,
+}
+
+/// Promise awaiting its resolver. The resolver only mutates state if it matches the record,
+/// which it consumes.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingOperation {
+    pub operation_id: u64,
+    pub kind: OperationKind,
+    // account credited or updated by the resolver
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    pub expires_at: u64,
+}
+
+impl Contract {
+    // records an operation before scheduling the promise it is resolved by
+    pub(crate) fn internal_start_operation(&mut self, kind: OperationKind, receiver_id: &AccountId, amount: Balance) -> u64 {
+        let operation_id = self.next_operation_id;
+        self.next_operation_id += 1;
+        self.pending_operations.insert(
+            &operation_id,
+            &PendingOperation {
+                operation_id,
+                kind,
+                receiver_id: receiver_id.clone(),
+                amount: amount.into(),
+                expires_at: env::block_timestamp_ms() / 1000 + PENDING_OPERATION_TTL_SEC,
+            },
+        );
+        operation_id
+    }
+
+    // called first by resolvers, panics unless the callback matches a live pending operation
+    pub(crate) fn internal_finish_operation(
+        &mut self,
+        operation_id: u64,
+        kind: OperationKind,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        let operation = self
+            .pending_operations
+            .remove(&operation_id)
+            .unwrap_or_else(|| env::panic_str("unknown pending operation"));
+        require!(
+            operation.kind == kind && &operation.receiver_id == receiver_id && operation.amount.0 == amount,
+            "callback does not match pending operation"
+        );
+        require!(operation.expires_at >= env::block_timestamp_ms() / 1000, "pending operation expired");
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Removes expired operations among `[from_index, from_index + limit)`, their callbacks can
+    /// no longer resolve. Returns the number removed.
+    pub fn prune_pending_operations(&mut self, from_index: Option<u64>, limit: Option<u64>) -> u64 {
+        let now = env::block_timestamp_ms() / 1000;
+        let expired: Vec<u64> = self
+            .pending_operations
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(100) as usize)
+            .filter(|operation| operation.expires_at < now)
+            .map(|operation| operation.operation_id)
+            .collect();
+        for operation_id in &expired {
+            self.pending_operations.remove(operation_id);
+        }
+        expired.len() as u64
+    }
+
+    pub fn get_pending_operation(&self, operation_id: u64) -> Option<PendingOperation> {
+        self.pending_operations.get(&operation_id)
+    }
+
+    pub fn get_pending_operations(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<PendingOperation> {
+        self.pending_operations
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(100) as usize)
+            .collect()
+    }
+
+    pub fn get_num_pending_operations(&self) -> u64 {
+        self.pending_operations.len()
+    }
+}
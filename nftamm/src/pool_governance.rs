@@ -0,0 +1,267 @@
+use crate::*;
+use crate::curves::U256;
+use crate::events::emit_event;
+use crate::param_change::PoolParamChange;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// seconds lps can vote on a parameter change of their trade pool
+const POOL_VOTE_PERIOD_SEC: u64 = 3 * 24 * 3600;
+// seconds after the vote before the change applies, for lps disagreeing to exit
+const POOL_VOTE_TIMELOCK_SEC: u64 = 2 * 24 * 3600;
+// seconds after which an unexecuted proposal can be replaced
+const POOL_PROPOSAL_EXPIRY_SEC: u64 = 7 * 24 * 3600;
+// share of the redeemable lp supply, base 1e18, voters must hold for the change to apply
+const POOL_VOTE_QUORUM: u128 = WAD / 2;
+// share of the redeemable lp supply, base 1e18, the proposer must hold
+const POOL_PROPOSAL_THRESHOLD: u128 = WAD / 100;
+// most lps voting for one proposal
+const MAX_POOL_VOTERS: usize = 100;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolParamProposal {
+    pub pool_id: u64,
+    pub proposer_id: AccountId,
+    pub change: PoolParamChange,
+    pub voters: Vec<AccountId>,
+    // in seconds
+    pub voting_ends_at: u64,
+    pub executable_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolParamProposalInfo {
+    pub proposal: PoolParamProposal,
+    // current lp of the voters, votes counting with the lp held when the change is executed
+    pub votes: U128,
+    pub quorum: U128,
+}
+
+impl Contract {
+    // lp currently held by the voters and the lp needed for the change to apply
+    fn pool_proposal_tally(&self, proposal: &PoolParamProposal) -> (Balance, Balance) {
+        let pool = &self.pools[proposal.pool_id as usize];
+        let votes = proposal
+            .voters
+            .iter()
+            .map(|voter_id| pool.lp_balances.get(voter_id).unwrap_or(0))
+            .sum::<Balance>();
+        let redeemable = U256::from(pool.lp_supply - pool.locked_lp);
+        let quorum = (redeemable * U256::from(POOL_VOTE_QUORUM) / U256::from(WAD)).as_u128();
+        (votes, quorum)
+    }
+
+    // gives back to each voter the storage of its vote, the proposer getting the rest
+    fn internal_remove_pool_proposal(&mut self, proposal: &PoolParamProposal) {
+        let prev_storage = env::storage_usage();
+        self.pool_param_proposals.remove(&proposal.pool_id);
+        let mut released = prev_storage.saturating_sub(env::storage_usage());
+        for voter_id in proposal.voters.iter().filter(|voter_id| *voter_id != &proposal.proposer_id) {
+            // borsh length prefix and account id
+            let vote_storage = 4 + voter_id.as_str().len() as StorageUsage;
+            if let Some(mut account_deposit) = self.account_deposits.get(voter_id) {
+                account_deposit.storage_usage = account_deposit.storage_usage.saturating_sub(vote_storage);
+                account_deposit.settle_storage_reserve();
+                self.account_deposits.insert(voter_id, &account_deposit);
+            }
+            released = released.saturating_sub(vote_storage);
+        }
+        if let Some(mut account_deposit) = self.account_deposits.get(&proposal.proposer_id) {
+            account_deposit.storage_usage = account_deposit.storage_usage.saturating_sub(released);
+            account_deposit.settle_storage_reserve();
+            self.account_deposits.insert(&proposal.proposer_id, &account_deposit);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Proposes a spot price and delta change of a trade pool to its lps, the proposer voting for
+    /// it. The proposer must hold POOL_PROPOSAL_THRESHOLD of the lp. It applies once lps holding
+    /// POOL_VOTE_QUORUM of the lp voted for it by the end of the vote and still hold it after the
+    /// timelock. The proposer pays the storage.
+    pub fn propose_pool_param_change(&mut self, pool_id: u64, change: PoolParamChange) {
+        let account_id = env::predecessor_account_id();
+        let now = env::block_timestamp_ms() / 1000;
+        if let Some(proposal) = self.pool_param_proposals.get(&pool_id) {
+            require!(now > proposal.executable_at + POOL_PROPOSAL_EXPIRY_SEC, "pool has an active proposal");
+            self.internal_remove_pool_proposal(&proposal);
+        }
+        let prev_storage = env::storage_usage();
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        require!(pool.pool_type == PoolType::Trade, "only trade pools vote on params");
+        require!(change.fee.is_none(), "trade pool fees are fee tiers");
+        let lp = pool.lp_balances.get(&account_id).unwrap_or(0);
+        require!(lp > 0, "only lps can propose");
+        let redeemable = U256::from(pool.lp_supply - pool.locked_lp);
+        require!(
+            U256::from(lp) * U256::from(WAD) >= redeemable * U256::from(POOL_PROPOSAL_THRESHOLD),
+            "proposer lp below the proposal threshold"
+        );
+        pool.changed_params(&change);
+
+        let voting_ends_at = now + POOL_VOTE_PERIOD_SEC;
+        let proposal = PoolParamProposal {
+            pool_id,
+            proposer_id: account_id.clone(),
+            change,
+            voters: vec![account_id.clone()],
+            voting_ends_at,
+            executable_at: voting_ends_at + POOL_VOTE_TIMELOCK_SEC,
+        };
+        self.pool_param_proposals.insert(&pool_id, &proposal);
+        self.assert_storage(&account_id, prev_storage, None);
+        emit_event("pool_param_proposal", &proposal);
+    }
+
+    /// Votes for the pending parameter change of the pool with the lp held at execution.
+    pub fn vote_pool_param_change(&mut self, pool_id: u64) {
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let mut proposal = self.pool_param_proposals.get(&pool_id).unwrap_or_else(|| env::panic_str("no proposal"));
+        require!(env::block_timestamp_ms() / 1000 < proposal.voting_ends_at, "voting ended");
+        require!(!proposal.voters.contains(&account_id), "already voted");
+        require!(proposal.voters.len() < MAX_POOL_VOTERS, "too many voters");
+        require!(self.pools[pool_id as usize].lp_balances.get(&account_id).unwrap_or(0) > 0, "only lps can vote");
+        proposal.voters.push(account_id.clone());
+        self.pool_param_proposals.insert(&pool_id, &proposal);
+        self.assert_storage(&account_id, prev_storage, None);
+        emit_event("pool_param_vote", &proposal);
+    }
+
+    /// Applies the proposed change once the timelock passed if the voters reach the quorum.
+    /// Callable by anyone.
+    pub fn execute_pool_param_change(&mut self, pool_id: u64) {
+        let proposal = self.pool_param_proposals.get(&pool_id).unwrap_or_else(|| env::panic_str("no proposal"));
+        let now = env::block_timestamp_ms() / 1000;
+        require!(now >= proposal.executable_at, "proposal not executable yet");
+        require!(now <= proposal.executable_at + POOL_PROPOSAL_EXPIRY_SEC, "proposal expired");
+        let (votes, quorum) = self.pool_proposal_tally(&proposal);
+        require!(votes > 0 && votes >= quorum, "quorum not reached");
+        self.internal_remove_pool_proposal(&proposal);
+        self.pools[pool_id as usize].apply_param_change(&proposal.change);
+        emit_event("pool_param_executed", &proposal);
+    }
+
+    /// Withdraws the proposal, by its proposer.
+    pub fn cancel_pool_param_change_proposal(&mut self, pool_id: u64) {
+        let proposal = self.pool_param_proposals.get(&pool_id).unwrap_or_else(|| env::panic_str("no proposal"));
+        require!(proposal.proposer_id == env::predecessor_account_id(), "only the proposer");
+        self.internal_remove_pool_proposal(&proposal);
+    }
+
+    pub fn get_pool_param_proposal(&self, pool_id: u64) -> Option<PoolParamProposalInfo> {
+        self.pool_param_proposals.get(&pool_id).map(|proposal| {
+            let (votes, quorum) = self.pool_proposal_tally(&proposal);
+            PoolParamProposalInfo { proposal, votes: votes.into(), quorum: quorum.into() }
+        })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    // trade pool of the owner with about 2/3 of the lp and of an lp with the other third, the lp
+    // proposing to double the spot price
+    fn setup_proposal() -> (Contract, u64) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        let lp = account("lp.near");
+        for account_id in [&owner, &lp, &account("other.near")] {
+            register(&mut contract, account_id, NEAR);
+        }
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Trade, &["1"], 2 * NEAR);
+        deposit_nfts(&mut contract, &lp, &["2"]);
+        set_caller(&lp, NEAR);
+        contract.add_liquidity(pool_id, token_ids(&["2"]), None);
+
+        set_caller(&lp, 0);
+        contract.propose_pool_param_change(pool_id, change());
+        (contract, pool_id)
+    }
+
+    fn change() -> PoolParamChange {
+        PoolParamChange { spot_price: Some(U128(2 * SPOT_PRICE)), delta: None, fee: None }
+    }
+
+    fn execute(contract: &mut Contract, pool_id: u64) {
+        advance_time_sec(POOL_VOTE_PERIOD_SEC + POOL_VOTE_TIMELOCK_SEC);
+        set_caller(&account("other.near"), 0);
+        contract.execute_pool_param_change(pool_id);
+    }
+
+    #[test]
+    fn test_pool_param_change_executed() {
+        let (mut contract, pool_id) = setup_proposal();
+        set_caller(&account("owner.near"), 0);
+        contract.vote_pool_param_change(pool_id);
+        let info = contract.get_pool_param_proposal(pool_id).unwrap();
+        assert!(info.votes.0 >= info.quorum.0);
+
+        execute(&mut contract, pool_id);
+        assert_eq!(contract.pools[pool_id as usize].spot_price, 2 * SPOT_PRICE);
+        assert!(contract.get_pool_param_proposal(pool_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "quorum not reached")]
+    fn test_pool_param_change_below_quorum() {
+        let (mut contract, pool_id) = setup_proposal();
+        execute(&mut contract, pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposal not executable yet")]
+    fn test_pool_param_change_before_timelock() {
+        let (mut contract, pool_id) = setup_proposal();
+        set_caller(&account("owner.near"), 0);
+        contract.vote_pool_param_change(pool_id);
+        advance_time_sec(POOL_VOTE_PERIOD_SEC);
+        contract.execute_pool_param_change(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "voting ended")]
+    fn test_vote_after_voting_period() {
+        let (mut contract, pool_id) = setup_proposal();
+        advance_time_sec(POOL_VOTE_PERIOD_SEC);
+        set_caller(&account("owner.near"), 0);
+        contract.vote_pool_param_change(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "only lps can vote")]
+    fn test_vote_without_lp() {
+        let (mut contract, pool_id) = setup_proposal();
+        set_caller(&account("other.near"), 0);
+        contract.vote_pool_param_change(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "already voted")]
+    fn test_vote_twice() {
+        let (mut contract, pool_id) = setup_proposal();
+        set_caller(&account("lp.near"), 0);
+        contract.vote_pool_param_change(pool_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "proposer lp below the proposal threshold")]
+    fn test_propose_below_threshold() {
+        let (mut contract, pool_id) = setup_proposal();
+        set_caller(&account("lp.near"), 0);
+        contract.cancel_pool_param_change_proposal(pool_id);
+        assert!(contract.get_pool_param_proposal(pool_id).is_none());
+
+        let other = account("other.near");
+        let pool = &mut contract.pools[pool_id as usize];
+        pool.lp_balances.insert(&other, &1);
+        pool.lp_supply += 1;
+        set_caller(&other, 0);
+        contract.propose_pool_param_change(pool_id, change());
+    }
+}
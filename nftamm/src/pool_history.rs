@@ -0,0 +1,98 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+// days of checkpoints kept per pool until governance changes it
+pub(crate) const DEFAULT_POOL_HISTORY_DAYS: u64 = 30;
+const MAX_POOL_HISTORY_DAYS: u64 = 365;
+
+/// Value of one lp of a pool in base 1e18. The growth of the index between two observations
+/// is the return of the lp over that time.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LpIndex {
+    pub index: U128,
+    // in seconds, 0 before the first lp mint
+    pub updated_at: u64,
+}
+
+/// Trades of a pool during a utc day. Days without trades have no checkpoint.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DailyCheckpoint {
+    // days since the unix epoch
+    pub day: u64,
+    pub volume: U128,
+    pub pool_fees: U128,
+    pub protocol_fees: U128,
+    pub num_trades: u64,
+    // after the last trade of the day
+    pub spot_price: U128,
+}
+
+impl Contract {
+    // adds the last trade of the pool to the checkpoint of the day, dropping the checkpoints
+    // older than the history kept
+    pub(crate) fn internal_record_pool_checkpoint(&mut self, pool_id: u64) {
+        let pool = &self.pools[pool_id as usize];
+        let trade = match pool.recent_trades.last() {
+            Some(trade) => trade,
+            None => return,
+        };
+        let day = trade.timestamp_sec / SECONDS_PER_DAY;
+        let mut checkpoints = self.pool_checkpoints.get(&pool_id).unwrap_or_default();
+        match checkpoints.last_mut() {
+            Some(checkpoint) if checkpoint.day == day => {
+                checkpoint.volume = (checkpoint.volume.0 + trade.near_amount).into();
+                checkpoint.pool_fees = (checkpoint.pool_fees.0 + trade.pool_fee).into();
+                checkpoint.protocol_fees = (checkpoint.protocol_fees.0 + trade.protocol_fee).into();
+                checkpoint.num_trades += 1;
+                checkpoint.spot_price = pool.spot_price.into();
+            }
+            _ => checkpoints.push(DailyCheckpoint {
+                day,
+                volume: trade.near_amount.into(),
+                pool_fees: trade.pool_fee.into(),
+                protocol_fees: trade.protocol_fee.into(),
+                num_trades: 1,
+                spot_price: pool.spot_price.into(),
+            }),
+        }
+        let first_day = (day + 1).saturating_sub(self.pool_history_days);
+        checkpoints.retain(|checkpoint| checkpoint.day >= first_day);
+        self.pool_checkpoints.insert(&pool_id, &checkpoints);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Days of checkpoints kept per pool, pools dropping the older ones on their next trade.
+    pub fn set_pool_history_days(&mut self, days: u64) {
+        self.assert_governance();
+        require!(days > 0 && days <= MAX_POOL_HISTORY_DAYS, format!("history must be between 1 and {} days", MAX_POOL_HISTORY_DAYS));
+        self.pool_history_days = days;
+    }
+
+    pub fn get_pool_history_days(&self) -> u64 {
+        self.pool_history_days
+    }
+
+    pub fn get_lp_index(&self, pool_id: u64) -> LpIndex {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        LpIndex { index: pool.lp_index.into(), updated_at: pool.lp_index_updated_at }
+    }
+
+    /// Checkpoints of the pool for the last `days` days, oldest first.
+    pub fn get_pool_history(&self, pool_id: u64, days: u64) -> Vec<DailyCheckpoint> {
+        require!((pool_id as usize) < self.pools.len(), "pool id invalid");
+        let today = env::block_timestamp_ms() / 1000 / SECONDS_PER_DAY;
+        let first_day = (today + 1).saturating_sub(days);
+        self.pool_checkpoints
+            .get(&pool_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|checkpoint| checkpoint.day >= first_day)
+            .collect()
+    }
+}
@@ -0,0 +1,82 @@
+use crate::*;
+use crate::events::emit_event;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// delay before a side change of a trade pool applies, so its lps can react
+pub(crate) const TRADE_POOL_SIDES_DELAY_SEC: u64 = 24 * 3600;
+
+pub(crate) const ERR_POOL_BUYS_DISABLED: &str = "E_POOL_BUYS_DISABLED: buys from the pool are disabled";
+pub(crate) const ERR_POOL_SELLS_DISABLED: &str = "E_POOL_SELLS_DISABLED: sells to the pool are disabled";
+
+// buys are of nfts from the pool, sells of nfts to the pool
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolSides {
+    pub buys_enabled: bool,
+    pub sells_enabled: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingPoolSides {
+    pub sides: PoolSides,
+    // in seconds
+    pub effective_at: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolSidesEvent<'a> {
+    pub pool_id: u64,
+    pub sides: &'a PoolSides,
+}
+
+impl Pair {
+    pub(crate) fn assert_buys_enabled(&self) {
+        self.assert_not_bootstrapping();
+        require!(self.sides.buys_enabled, ERR_POOL_BUYS_DISABLED);
+    }
+
+    pub(crate) fn assert_sells_enabled(&self) {
+        self.assert_not_bootstrapping();
+        require!(self.sides.sells_enabled, ERR_POOL_SELLS_DISABLED);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Enables or disables buys from and sells to the pool. Right away for non-trade pools,
+    /// after `TRADE_POOL_SIDES_DELAY_SEC` for trade pools, replacing their pending change.
+    pub fn set_pool_sides(&mut self, pool_id: u64, buys_enabled: bool, sells_enabled: bool) {
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        pool.assert_owner();
+        let sides = PoolSides { buys_enabled, sells_enabled };
+        if pool.pool_type != PoolType::Trade {
+            emit_event("pool_sides", PoolSidesEvent { pool_id, sides: &sides });
+            pool.sides = sides;
+            return;
+        }
+        pool.pending_sides = Some(PendingPoolSides {
+            sides,
+            effective_at: env::block_timestamp_ms() / 1000 + TRADE_POOL_SIDES_DELAY_SEC,
+        });
+        self.assert_storage(&account_id, prev_storage, None);
+    }
+
+    /// Applies the pending side change of the pool once its delay passed. Callable by anyone.
+    pub fn apply_pool_sides(&mut self, pool_id: u64) {
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let pending = pool.pending_sides.take().unwrap_or_else(|| env::panic_str("no pending side change"));
+        require!(pending.effective_at <= env::block_timestamp_ms() / 1000, "side change not effective yet");
+        emit_event("pool_sides", PoolSidesEvent { pool_id, sides: &pending.sides });
+        pool.sides = pending.sides;
+    }
+
+    pub fn get_pending_pool_sides(&self, pool_id: u64) -> Option<PendingPoolSides> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.pending_sides.clone()
+    }
+}
@@ -0,0 +1,135 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, Vector};
+
+use near_contract_standards::non_fungible_token::TokenId;
+
+use crate::pair::DepositedToken;
+use crate::StorageKey;
+
+/// Token ids held by a pool. Ids live in a vector for random picks, with their position kept
+/// by id. Deposit records are only stored for tokens with a depositor or a min price, nfts the
+/// pool bought cost a single vector entry and index entry.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PoolTokens {
+    token_ids: Vector<TokenId>,
+    index: LookupMap<TokenId, u64>,
+    deposits: LookupMap<TokenId, DepositedToken>,
+}
+
+impl PoolTokens {
+    pub fn new(pool_id: u64) -> Self {
+        PoolTokens {
+            token_ids: Vector::new(StorageKey::PoolTokenIds { pool_id }),
+            index: LookupMap::new(StorageKey::PoolTokenIndex { pool_id }),
+            deposits: LookupMap::new(StorageKey::PoolTokenDeposits { pool_id }),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.token_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.token_ids.is_empty()
+    }
+
+    pub fn contains(&self, token_id: &TokenId) -> bool {
+        self.index.contains_key(token_id)
+    }
+
+    pub fn get(&self, token_id: &TokenId) -> Option<DepositedToken> {
+        if !self.contains(token_id) {
+            return None;
+        }
+        Some(self.deposits.get(token_id).unwrap_or_else(DepositedToken::acquired))
+    }
+
+    pub fn insert(&mut self, token_id: &TokenId, deposited_token: &DepositedToken) {
+        if !self.contains(token_id) {
+            self.index.insert(token_id, &self.token_ids.len());
+            self.token_ids.push(token_id);
+        }
+        if deposited_token.is_acquired() {
+            self.deposits.remove(token_id);
+        } else {
+            self.deposits.insert(token_id, deposited_token);
+        }
+    }
+
+    pub fn remove(&mut self, token_id: &TokenId) -> Option<DepositedToken> {
+        let index = self.index.remove(token_id)?;
+        self.token_ids.swap_remove(index);
+        // the last id now fills the removed slot
+        if let Some(moved_token_id) = self.token_ids.get(index) {
+            self.index.insert(&moved_token_id, &index);
+        }
+        Some(self.deposits.remove(token_id).unwrap_or_else(DepositedToken::acquired))
+    }
+
+    pub fn get_at(&self, index: u64) -> Option<TokenId> {
+        self.token_ids.get(index)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = TokenId> + '_ {
+        self.token_ids.iter()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TokenId, DepositedToken)> + '_ {
+        self.token_ids.iter().map(move |token_id| {
+            let deposited_token = self.deposits.get(&token_id).unwrap_or_else(DepositedToken::acquired);
+            (token_id, deposited_token)
+        })
+    }
+
+    pub fn to_vec(&self) -> Vec<TokenId> {
+        self.token_ids.to_vec()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::collections::UnorderedMap;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{env, testing_env};
+
+    use super::*;
+
+    // storage used by `f`, in bytes
+    fn storage_used(f: impl FnOnce()) -> u64 {
+        let prev_storage = env::storage_usage();
+        f();
+        env::storage_usage() - prev_storage
+    }
+
+    #[test]
+    fn test_pool_tokens_storage() {
+        testing_env!(VMContextBuilder::new().build());
+        let token_ids: Vec<TokenId> = (0..100).map(|i| format!("token-{}", i)).collect();
+
+        let mut map = UnorderedMap::<TokenId, DepositedToken>::new(b"map".to_vec());
+        let map_storage = storage_used(|| {
+            for token_id in &token_ids {
+                map.insert(token_id, &DepositedToken::acquired());
+            }
+        });
+        let mut pool_tokens = PoolTokens::new(0);
+        let pool_tokens_storage = storage_used(|| {
+            for token_id in &token_ids {
+                pool_tokens.insert(token_id, &DepositedToken::acquired());
+            }
+        });
+        // acquired nfts skip the value entry of the map
+        assert!(pool_tokens_storage < map_storage, "{} >= {}", pool_tokens_storage, map_storage);
+
+        assert!(pool_tokens.remove(&token_ids[0]).is_some());
+        assert!(pool_tokens.remove(&token_ids[0]).is_none());
+        assert_eq!(pool_tokens.len(), 99);
+        assert_eq!(pool_tokens.get_at(0), Some(token_ids[99].clone()));
+        assert!(pool_tokens.contains(&token_ids[99]));
+        assert!(pool_tokens.remove(&token_ids[99]).is_some());
+        assert_eq!(pool_tokens.get_at(0), Some(token_ids[98].clone()));
+        let remaining: Vec<TokenId> = pool_tokens.keys().collect();
+        assert_eq!(remaining.len(), 98);
+        assert!(remaining.iter().all(|token_id| pool_tokens.contains(token_id)));
+    }
+}
@@ -0,0 +1,259 @@
+// Pure trade math shared by pairs and the `sim` feature, free of env calls so it runs off-chain.
+use near_sdk::Balance;
+
+use crate::curves::curve::Curve;
+use crate::curves::errorcodes::CurveErrorCode;
+use crate::curves::units::{Wad, Yocto};
+use crate::curves::{checked_as_u128, BuyInfo, SellInfo, U256, WAD};
+
+pub struct TradeQuote {
+    pub new_spot_price: u128,
+    pub new_delta: u128,
+    // near paid by the buyer or received by the seller, protocol fee included for buys
+    pub near_amount: Balance,
+    pub protocol_fee: Balance,
+}
+
+pub(crate) fn quote_buy(
+    curve: &Curve,
+    spot_price: Yocto,
+    delta: u128,
+    fee: Wad,
+    protocol_fee_multiplier: Wad,
+    num_items: u64,
+) -> Result<TradeQuote, CurveErrorCode> {
+    buy_trade_quote(curve.get_buy_info(spot_price, delta, num_items, fee, protocol_fee_multiplier))
+}
+
+pub(crate) fn buy_trade_quote(buy_info: BuyInfo) -> Result<TradeQuote, CurveErrorCode> {
+    if buy_info.error_code != CurveErrorCode::Ok {
+        return Err(buy_info.error_code);
+    }
+    Ok(TradeQuote {
+        new_spot_price: buy_info.new_spot_price,
+        new_delta: buy_info.new_delta,
+        near_amount: checked_as_u128(buy_info.input_value)?,
+        protocol_fee: checked_as_u128(buy_info.protocol_fee)?,
+    })
+}
+
+pub(crate) fn quote_sell(
+    curve: &Curve,
+    spot_price: Yocto,
+    delta: u128,
+    fee: Wad,
+    protocol_fee_multiplier: Wad,
+    num_items: u64,
+) -> Result<TradeQuote, CurveErrorCode> {
+    sell_trade_quote(curve.get_sell_info(spot_price, delta, num_items, fee, protocol_fee_multiplier))
+}
+
+pub(crate) fn sell_trade_quote(sell_info: SellInfo) -> Result<TradeQuote, CurveErrorCode> {
+    if sell_info.error_code != CurveErrorCode::Ok {
+        return Err(sell_info.error_code);
+    }
+    Ok(TradeQuote {
+        new_spot_price: sell_info.new_spot_price,
+        new_delta: sell_info.new_delta,
+        near_amount: checked_as_u128(sell_info.output_value)?,
+        protocol_fee: checked_as_u128(sell_info.protocol_fee)?,
+    })
+}
+
+// part of a trade of `near_amount` kept by the pool as its trade fee
+pub(crate) fn trade_pool_fee(fee: Wad, is_buy: bool, near_amount: Balance, protocol_fee: Balance) -> Result<Balance, CurveErrorCode> {
+    if fee == Wad::ZERO {
+        return Ok(0);
+    }
+    let fee = fee.0;
+    if is_buy {
+        let paid = U256::from(near_amount.saturating_sub(protocol_fee));
+        checked_as_u128(paid - paid * U256::from(WAD) / U256::from(WAD + fee))
+    } else {
+        let base = U256::from(near_amount + protocol_fee) * U256::from(WAD) / U256::from(WAD - fee);
+        checked_as_u128(base - U256::from(near_amount + protocol_fee))
+    }
+}
+
+// nfts and near a burn of `lp` out of `lp_supply` redeems from a pool of `num_pool_nfts` valued at
+// `spot_price` each, whose near balance matches its nft value
+pub(crate) struct NftRedemption {
+    pub num_nfts: u64,
+    pub near_amount: Balance,
+    // value of the rounded up nft above the lp share of the nfts, deducted from the near
+    pub fraction_nft_value: Balance,
+}
+
+// The lp share of the nfts is rounded up to whole nfts, the last one valued at the spot price
+// after buying one item, `next_spot_price`, only called when there is such a fraction. The lp
+// gets nfts and near worth twice its share of the nft value in total.
+pub(crate) fn nft_redemption(
+    num_pool_nfts: u64,
+    spot_price: u128,
+    next_spot_price: impl FnOnce() -> Option<u128>,
+    lp: Balance,
+    lp_supply: Balance,
+) -> Result<NftRedemption, &'static str> {
+    if lp == 0 || lp > lp_supply {
+        return Err("insufficient lp");
+    }
+    let supply = U256::from(lp_supply);
+    let nft_share = U256::from(num_pool_nfts) * U256::from(lp);
+    let withdrawable_near = nft_share * U256::from(spot_price) / supply;
+    let mut num_nfts = nft_share / supply;
+    let mut fraction_nft_value = U256::zero();
+    if num_nfts * supply != nft_share {
+        let next_spot_price = next_spot_price().ok_or("cannot price the fractional nft")?;
+        // num_nfts nfts at the spot price and the rounded up fraction at the next one
+        let nfts_value = num_nfts * U256::from(spot_price) + U256::from(next_spot_price);
+        num_nfts += U256::one();
+        if nfts_value < withdrawable_near {
+            return Err("internal error in handling liquidity");
+        }
+        fraction_nft_value = nfts_value - withdrawable_near;
+    }
+    if fraction_nft_value > withdrawable_near {
+        return Err("cannot withdraw as liquidity value in near is too small compared to nft spot price");
+    }
+    let near_amount = checked_as_u128(withdrawable_near - fraction_nft_value).map_err(|_| "withdrawable near overflow")?;
+    Ok(NftRedemption {
+        num_nfts: num_nfts.as_u64(),
+        near_amount,
+        fraction_nft_value: checked_as_u128(fraction_nft_value).map_err(|_| "withdrawable near overflow")?,
+    })
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::curves::curve::BondingCurve;
+    use crate::curves::{div_up, mul_wad_up};
+    use proptest::prelude::*;
+
+    fn redeem(num_pool_nfts: u64, spot_price: u128, next_spot_price: u128, lp: Balance, lp_supply: Balance) -> Result<NftRedemption, &'static str> {
+        nft_redemption(num_pool_nfts, spot_price, || Some(next_spot_price), lp, lp_supply)
+    }
+
+    #[test]
+    fn test_nft_redemption_whole_supply() {
+        let redemption = redeem(7, 10u128.pow(24), 2 * 10u128.pow(24), 1_000, 1_000).unwrap();
+        assert_eq!(redemption.num_nfts, 7);
+        assert_eq!(redemption.near_amount, 7 * 10u128.pow(24));
+        assert_eq!(redemption.fraction_nft_value, 0);
+    }
+
+    #[test]
+    fn test_nft_redemption_fraction() {
+        // half of 3 nfts at 10 is 1.5 nfts worth 15, rounded up to 2 nfts worth 10 + 12
+        let redemption = redeem(3, 10, 12, 50, 100).unwrap();
+        assert_eq!(redemption.num_nfts, 2);
+        assert_eq!(redemption.fraction_nft_value, 7);
+        assert_eq!(redemption.near_amount, 8);
+    }
+
+    #[test]
+    fn test_nft_redemption_fraction_too_valuable() {
+        // a tenth of an nft is worth 1 while the rounded up nft is worth 10
+        assert!(redeem(1, 10, 10, 10, 100).is_err());
+    }
+
+    #[test]
+    fn test_nft_redemption_without_next_price() {
+        assert_eq!(nft_redemption(3, 10, || None, 50, 100).err(), Some("cannot price the fractional nft"));
+        // not needed without fraction
+        assert!(nft_redemption(4, 10, || None, 50, 100).is_ok());
+    }
+
+    #[test]
+    fn test_nft_redemption_no_u128_overflow() {
+        // num_pool_nfts * lp and the nft value overflow u128 before dividing
+        let redemption = redeem(1_000_000, 10u128.pow(30), 10u128.pow(30), u128::MAX / 2, u128::MAX).unwrap();
+        assert_eq!(redemption.num_nfts, 500_000);
+    }
+
+    #[test]
+    fn test_div_up() {
+        assert_eq!(div_up(U256::zero(), U256::from(3u8)), U256::zero());
+        assert_eq!(div_up(U256::from(6u8), U256::from(3u8)), U256::from(2u8));
+        assert_eq!(div_up(U256::from(7u8), U256::from(3u8)), U256::from(3u8));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_mul_wad_up_within_a_unit(a in 0u128..10u128.pow(30), b in 0u128..10u128.pow(20)) {
+            let exact = U256::from(a) * U256::from(b);
+            let rounded = mul_wad_up(U256::from(a), U256::from(b)) * U256::from(WAD);
+            prop_assert!(rounded >= exact);
+            prop_assert!(rounded < exact + U256::from(WAD));
+        }
+
+        #[test]
+        fn prop_trade_fees_round_in_pool_favor(
+            spot_price in 1u128..10u128.pow(27),
+            num_items in 1u64..50,
+            fee in 0u128..WAD / 2,
+            protocol_fee_multiplier in 0u128..WAD / 10,
+        ) {
+            // flat curve, the trade value before fees is the spot price of every item
+            let curve = Curve::new(BondingCurve::LinearCurve);
+            let value = U256::from(spot_price) * U256::from(num_items);
+            let buy = quote_buy(&curve, Yocto(spot_price), 0, Wad(fee), Wad(protocol_fee_multiplier), num_items).unwrap();
+            prop_assert!(U256::from(buy.near_amount - buy.protocol_fee) * U256::from(WAD) >= value * U256::from(WAD + fee));
+            let sell = quote_sell(&curve, Yocto(spot_price), 0, Wad(fee), Wad(protocol_fee_multiplier), num_items).unwrap();
+            prop_assert!(U256::from(sell.near_amount + sell.protocol_fee) * U256::from(WAD) <= value * U256::from(WAD - fee));
+        }
+
+        #[test]
+        fn prop_nft_redemption_conserves_value(
+            num_pool_nfts in 0u64..10_000,
+            spot_price in 1u128..10u128.pow(27),
+            price_step in 0u128..10u128.pow(26),
+            lp_supply in 1u128..10u128.pow(30),
+            lp_ratio in 0u128..=1_000_000,
+        ) {
+            let lp = (lp_supply * lp_ratio / 1_000_000).max(1);
+            let next_spot_price = spot_price + price_step;
+            let share = U256::from(num_pool_nfts) * U256::from(lp);
+            let supply = U256::from(lp_supply);
+            let withdrawable_near = share * U256::from(spot_price) / supply;
+            if let Ok(redemption) = redeem(num_pool_nfts, spot_price, next_spot_price, lp, lp_supply) {
+                let num_nfts = U256::from(redemption.num_nfts);
+                // whole nfts rounded up from the lp share
+                prop_assert!(num_nfts * supply >= share);
+                prop_assert!(num_nfts.is_zero() || (num_nfts - 1) * supply < share);
+                prop_assert!(redemption.num_nfts <= num_pool_nfts);
+                // the near never exceeds the lp share of the near balance
+                prop_assert!(U256::from(redemption.near_amount) <= withdrawable_near);
+                // nfts, valued as priced by the burn, and near make up twice the nft share
+                let nfts_value = if num_nfts * supply == share {
+                    num_nfts * U256::from(spot_price)
+                } else {
+                    (num_nfts - 1) * U256::from(spot_price) + U256::from(next_spot_price)
+                };
+                prop_assert_eq!(nfts_value + U256::from(redemption.near_amount), withdrawable_near * 2);
+                prop_assert_eq!(nfts_value, withdrawable_near + U256::from(redemption.fraction_nft_value));
+            }
+        }
+
+        #[test]
+        fn prop_nft_redemption_split_burns_get_no_more(
+            num_pool_nfts in 1u64..1_000,
+            spot_price in 1u128..10u128.pow(27),
+            lp_supply in 2u128..10u128.pow(30),
+            lp_ratio in 1u128..1_000_000,
+        ) {
+            // burning the whole supply in two parts rounds each part, never redeeming more nfts
+            // than the pool holds nor more near than its balance
+            let lp = (lp_supply * lp_ratio / 1_000_000).clamp(1, lp_supply - 1);
+            if let Ok(first) = redeem(num_pool_nfts, spot_price, spot_price, lp, lp_supply) {
+                prop_assert!(first.num_nfts <= num_pool_nfts);
+                let num_left = num_pool_nfts - first.num_nfts;
+                if num_left > 0 {
+                    let rest = redeem(num_left, spot_price, spot_price, lp_supply - lp, lp_supply - lp).unwrap();
+                    prop_assert_eq!(first.num_nfts + rest.num_nfts, num_pool_nfts);
+                    prop_assert!(first.near_amount + rest.near_amount <= num_pool_nfts as u128 * spot_price);
+                }
+            }
+        }
+    }
+}
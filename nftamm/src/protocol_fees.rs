@@ -0,0 +1,157 @@
+use crate::*;
+use crate::curves::U256;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{ext_contract, near_bindgen, PromiseResult};
+
+const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimableProtocolFee {
+    // none for near
+    pub token_id: Option<AccountId>,
+    pub amount: U128,
+}
+
+/// Part of the protocol fee of a pool trades credited to a partner, e.g. the collection dao.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolFeeShare {
+    pub receiver_id: AccountId,
+    // base 1e18
+    pub share: U128,
+    // credited to the receiver deposit since the share was set
+    pub accrued: U128,
+}
+
+#[ext_contract(ext_ft)]
+pub trait FungibleTokenCore {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
+#[ext_contract(ext_protocol_fee_resolver)]
+pub trait ProtocolFeeResolver {
+    fn protocol_fee_claim_resolve(&mut self, token_id: AccountId, receiver_id: AccountId, amount: U128, operation_id: u64);
+}
+
+impl Contract {
+    // protocol fees of pools quoted in a fungible token are credited here
+    pub(crate) fn internal_credit_ft_protocol_fee(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        let credit = self.ft_protocol_fee_credits.get(token_id).unwrap_or(0);
+        self.ft_protocol_fee_credits.insert(token_id, &(credit + amount));
+    }
+
+    // credits the protocol fee of a trade of the pool, the pool fee share going to the deposit
+    // of its receiver while registered
+    pub(crate) fn internal_credit_trade_protocol_fee(&mut self, pool_id: u64, protocol_fee: Balance) {
+        let mut protocol_fee = protocol_fee;
+        if let Some(mut fee_share) = self.pool_fee_shares.get(&pool_id) {
+            if let Some(mut receiver_deposit) = self.account_deposits.get(&fee_share.receiver_id) {
+                let amount = (U256::from(protocol_fee) * U256::from(fee_share.share.0) / WAD).as_u128();
+                receiver_deposit.available_balance += amount;
+                self.account_deposits.insert(&fee_share.receiver_id, &receiver_deposit);
+                fee_share.accrued = (fee_share.accrued.0 + amount).into();
+                self.pool_fee_shares.insert(&pool_id, &fee_share);
+                protocol_fee -= amount;
+            }
+        }
+        self.protocol_fee_credit += protocol_fee;
+    }
+
+    fn assert_protocol_fee_claimer(&self) {
+        let account_id = env::predecessor_account_id();
+        require!(
+            self.is_governance(&account_id) || account_id == self.protocol_fee_receiver_id,
+            "only governance or protocol fee receiver"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sends `amount` (all by default) of the protocol fees accrued in `token_id`, or in near if
+    /// none, to the protocol fee receiver.
+    #[payable]
+    pub fn claim_protocol_fees(&mut self, token_id: Option<AccountId>, amount: Option<U128>) -> U128 {
+        assert_one_yocto();
+        self.assert_protocol_fee_claimer();
+        let receiver_id = self.protocol_fee_receiver_id.clone();
+        match token_id {
+            None => {
+                let amount = amount.map(|a| a.0).unwrap_or(self.protocol_fee_credit);
+                require!(amount <= self.protocol_fee_credit, "not enough protocol fee credit");
+                self.protocol_fee_credit -= amount;
+                if amount > 0 {
+                    Promise::new(receiver_id).transfer(amount);
+                }
+                amount.into()
+            }
+            Some(token_id) => {
+                let credit = self.ft_protocol_fee_credits.get(&token_id).unwrap_or(0);
+                let amount = amount.map(|a| a.0).unwrap_or(credit);
+                require!(amount <= credit, "not enough protocol fee credit");
+                if amount == credit {
+                    self.ft_protocol_fee_credits.remove(&token_id);
+                } else {
+                    self.ft_protocol_fee_credits.insert(&token_id, &(credit - amount));
+                }
+                if amount > 0 {
+                    let operation_id = self.internal_start_operation(OperationKind::ProtocolFeeClaim, &receiver_id, amount);
+                    ext_ft::ext(token_id.clone())
+                        .with_static_gas(GAS_FOR_FT_TRANSFER)
+                        .with_attached_deposit(1)
+                        .ft_transfer(receiver_id.clone(), amount.into(), None)
+                        .then(
+                            ext_protocol_fee_resolver::ext(env::current_account_id())
+                                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                                .protocol_fee_claim_resolve(token_id, receiver_id, amount.into(), operation_id),
+                        );
+                }
+                amount.into()
+            }
+        }
+    }
+
+    #[private]
+    pub fn protocol_fee_claim_resolve(&mut self, token_id: AccountId, receiver_id: AccountId, amount: U128, operation_id: u64) {
+        self.internal_finish_operation(operation_id, OperationKind::ProtocolFeeClaim, &receiver_id, amount.0);
+        if let PromiseResult::Failed = env::promise_result(0) {
+            self.internal_credit_ft_protocol_fee(&token_id, amount.0);
+        }
+    }
+
+    /// Shares the protocol fee of the pool trades with a receiver, none stops sharing. The share
+    /// is credited to the receiver deposit, withdrawn with `storage_withdraw`.
+    pub fn set_pool_fee_share(&mut self, pool_id: u64, receiver_id: Option<AccountId>, share: Option<U128>) {
+        self.assert_governance();
+        require!((pool_id as usize) < self.pools.len(), "pool id invalid");
+        match receiver_id {
+            Some(receiver_id) => {
+                let share = share.unwrap_or_else(|| env::panic_str("share required"));
+                require!(share.0 <= WAD, "share exceeds 100%");
+                require!(self.account_deposits.get(&receiver_id).is_some(), "receiver unregistered");
+                self.pool_fee_shares.insert(&pool_id, &PoolFeeShare { receiver_id, share, accrued: U128(0) });
+            }
+            None => {
+                self.pool_fee_shares.remove(&pool_id);
+            }
+        }
+    }
+
+    pub fn get_pool_fee_share(&self, pool_id: u64) -> Option<PoolFeeShare> {
+        self.pool_fee_shares.get(&pool_id)
+    }
+
+    /// Protocol fees accrued in near and in every fungible token.
+    pub fn get_claimable_protocol_fees(&self) -> Vec<ClaimableProtocolFee> {
+        let mut fees = vec![ClaimableProtocolFee { token_id: None, amount: self.protocol_fee_credit.into() }];
+        fees.extend(self.ft_protocol_fee_credits.iter().map(|(token_id, amount)| ClaimableProtocolFee {
+            token_id: Some(token_id),
+            amount: amount.into(),
+        }));
+        fees
+    }
+}
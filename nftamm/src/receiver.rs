@@ -1,4 +1,5 @@
 use crate::*;
+use crate::metadata_cache::CachedTokenMetadata;
 use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
 use near_sdk::{
     env,
@@ -10,8 +11,27 @@ use near_sdk::{
 #[serde(crate = "near_sdk::serde")]
 #[serde(untagged)]
 pub enum TokenReceiverMessage {
+    /// Creates a pool with the transferred token and `near_amount` from the sender deposit, the
+    /// sender paying its storage from its deposit. Precedes `Metadata`, which matches any object.
+    CreatePair {
+        #[serde(rename = "CreatePair")]
+        args: CreatePairMessage,
+    },
+    /// Other tokens of the batch the collection transferred before calling `nft_on_transfer` once,
+    /// only for batch transfer collections.
+    BatchDeposit { token_ids: Vec<TokenId> },
     /// Alternative to deposit + execute actions call.
     Deposit { pool_id: u32 },
+    /// Caches the title and media of the deposited token for pool views.
+    Metadata { title: Option<String>, media: Option<String> },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CreatePairMessage {
+    #[serde(flatten)]
+    pub params: PairParams,
+    pub near_amount: Option<U128>,
 }
 
 #[near_bindgen]
@@ -25,7 +45,30 @@ impl NonFungibleTokenReceiver for Contract {
     ) -> near_sdk::PromiseOrValue<bool> {
         let asset_id = env::predecessor_account_id();
         let account_id = previous_owner_id.clone();
+        self.assert_token_not_held(&asset_id, &token_id);
         self.internal_deposit_nft_with_storage_check(&account_id, &asset_id, &token_id);
+        match near_sdk::serde_json::from_str(&msg) {
+            Ok(TokenReceiverMessage::CreatePair { args }) => {
+                // nft_transfer_call cannot attach near, the pool near comes from the deposit
+                let near_amount = args.near_amount.map(|a| a.0).unwrap_or(0);
+                self.internal_withdraw_near(&account_id, near_amount);
+                let pool_id = self.internal_create_pair(&account_id, asset_id, args.params, &[token_id], near_amount);
+                log!("created pool {}", pool_id);
+            }
+            Ok(TokenReceiverMessage::BatchDeposit { token_ids }) => {
+                require!(self.batch_transfer_collections.contains(&asset_id), "collection does not batch transfers");
+                let token_ids: Vec<TokenId> = token_ids.into_iter().filter(|id| *id != token_id).collect();
+                self.internal_batch_deposit_nfts(&account_id, &asset_id, &token_ids);
+            }
+            Ok(TokenReceiverMessage::Metadata { title, media }) => {
+                // metadata read from the nft contract is not replaced by the one of the depositor
+                let fetched = self.token_metadata.get(&(asset_id.clone(), token_id.clone())).is_some_and(|m| m.fetched);
+                if !fetched {
+                    self.internal_cache_token_metadata(&account_id, &asset_id, &token_id, &CachedTokenMetadata { title, media, fetched: false });
+                }
+            }
+            _ => {}
+        }
         PromiseOrValue::Value(true)
     }
 }
@@ -0,0 +1,257 @@
+use crate::*;
+use crate::curves::U256;
+use crate::events::emit_event;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+const MIN_REFERRAL_CODE_LEN: usize = 3;
+const MAX_REFERRAL_CODE_LEN: usize = 32;
+
+/// Frontend registered under a referral code, earning a share of the protocol fee of the swap
+/// actions carrying the code.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Referral {
+    pub owner_id: AccountId,
+    // receives the claimed fees
+    pub payout_id: AccountId,
+    pub num_trades: u64,
+    pub volume: U128,
+    // fee share not claimed yet
+    pub accrued: U128,
+    pub total_earned: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralFeeEvent<'a> {
+    pub code: &'a str,
+    pub pool_id: u64,
+    pub near_amount: U128,
+    pub fee: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralClaimEvent<'a> {
+    pub code: &'a str,
+    pub payout_id: &'a AccountId,
+    pub amount: U128,
+}
+
+fn assert_valid_referral_code(code: &str) {
+    require!(
+        code.len() >= MIN_REFERRAL_CODE_LEN && code.len() <= MAX_REFERRAL_CODE_LEN,
+        format!("referral code must be between {} and {} characters", MIN_REFERRAL_CODE_LEN, MAX_REFERRAL_CODE_LEN)
+    );
+    require!(
+        code.bytes().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'_' || c == b'-'),
+        "referral code must be lowercase letters, digits, - or _"
+    );
+}
+
+impl Contract {
+    pub(crate) fn assert_referral_code(&self, code: &str) {
+        require!(self.referrals.get(&code.to_string()).is_some(), format!("unknown referral code {}", code));
+    }
+
+    // accrues the referral share of the protocol fee of a trade to the code, returning the
+    // protocol fee left
+    pub(crate) fn internal_accrue_referral_fee(&mut self, code: Option<&String>, pool_id: u64, near_amount: Balance, protocol_fee: Balance) -> Balance {
+        let code = match code {
+            Some(code) => code,
+            None => return protocol_fee,
+        };
+        let mut referral = self.referrals.get(code).unwrap_or_else(|| env::panic_str("unknown referral code"));
+        let fee = (U256::from(protocol_fee) * U256::from(self.referral_fee_share) / WAD).as_u128();
+        referral.num_trades += 1;
+        referral.volume = (referral.volume.0 + near_amount).into();
+        referral.accrued = (referral.accrued.0 + fee).into();
+        referral.total_earned = (referral.total_earned.0 + fee).into();
+        self.referrals.insert(code, &referral);
+        emit_event("referral_fee", ReferralFeeEvent { code, pool_id, near_amount: near_amount.into(), fee: fee.into() });
+        protocol_fee - fee
+    }
+
+    // near accrued to referral codes and not claimed yet
+    pub(crate) fn internal_referral_liabilities(&self) -> Balance {
+        self.referrals.values().map(|referral| referral.accrued.0).sum()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers `code` for the caller, paying the storage from their deposit. The fees it earns
+    /// are claimed to `payout_id`.
+    pub fn register_referral_code(&mut self, code: String, payout_id: AccountId) {
+        assert_valid_referral_code(&code);
+        require!(self.referrals.get(&code).is_none(), "referral code taken");
+        let owner_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        self.referrals.insert(&code, &Referral {
+            owner_id: owner_id.clone(),
+            payout_id,
+            num_trades: 0,
+            volume: U128(0),
+            accrued: U128(0),
+            total_earned: U128(0),
+        });
+        self.assert_storage(&owner_id, prev_storage, None);
+    }
+
+    pub fn set_referral_payout(&mut self, code: String, payout_id: AccountId) {
+        let mut referral = self.referrals.get(&code).unwrap_or_else(|| env::panic_str("unknown referral code"));
+        require!(referral.owner_id == env::predecessor_account_id(), "only the referral code owner");
+        referral.payout_id = payout_id;
+        self.referrals.insert(&code, &referral);
+    }
+
+    /// Share of the protocol fee of swap actions carrying a referral code, base 1e18.
+    pub fn set_referral_fee_share(&mut self, share: U128) {
+        self.assert_governance();
+        require!(share.0 <= WAD, "share exceeds 100%");
+        self.referral_fee_share = share.0;
+    }
+
+    pub fn get_referral_fee_share(&self) -> U128 {
+        self.referral_fee_share.into()
+    }
+
+    /// Sends the fees accrued to the code to its payout account. Callable by the owner or the
+    /// payout account of the code.
+    #[payable]
+    pub fn claim_referral_fees(&mut self, code: String) -> U128 {
+        assert_one_yocto();
+        let mut referral = self.referrals.get(&code).unwrap_or_else(|| env::panic_str("unknown referral code"));
+        let account_id = env::predecessor_account_id();
+        require!(account_id == referral.owner_id || account_id == referral.payout_id, "only the referral code owner or payout account");
+        let amount = referral.accrued;
+        referral.accrued = U128(0);
+        self.referrals.insert(&code, &referral);
+        if amount.0 > 0 {
+            Promise::new(referral.payout_id.clone()).transfer(amount.0);
+        }
+        emit_event("referral_claim", ReferralClaimEvent { code: &code, payout_id: &referral.payout_id, amount });
+        amount
+    }
+
+    pub fn get_referral(&self, code: String) -> Option<Referral> {
+        self.referrals.get(&code)
+    }
+
+    /// Referral codes `[from_index, from_index + limit)` with their stats.
+    pub fn get_referrals(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<(String, Referral)> {
+        self.referrals
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::swap::Action;
+    use crate::testing::*;
+    use near_sdk::serde_json::{self, json};
+
+    fn frontend() -> AccountId {
+        account("frontend.near")
+    }
+
+    fn payout() -> AccountId {
+        account("payout.near")
+    }
+
+    // nft pool of nfts 1 and 2, code `front` of the frontend earning half the protocol fee and
+    // a registered buyer
+    fn setup_referral() -> (Contract, u64) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        for account_id in [&owner, &frontend(), &account("buyer.near")] {
+            register(&mut contract, account_id, NEAR);
+        }
+        let pool_id = create_pool(&mut contract, &owner, PoolType::NFT, &["1", "2"], 0);
+        set_caller(&governance(), 0);
+        contract.set_referral_fee_share(U128(WAD / 2));
+        set_caller(&frontend(), 0);
+        contract.register_referral_code("front".to_string(), payout());
+        (contract, pool_id)
+    }
+
+    fn referred_buy(pool_id: u64, code: &str) -> Action {
+        serde_json::from_value(json!({
+            "pool_id": pool_id,
+            "swap_type": 1,
+            "input_token_ids": [],
+            "output_token_ids": ["1"],
+            "num_out_nfts": 1,
+            "referral_code": code,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_referral_fee_accrued_and_claimed() {
+        let (mut contract, pool_id) = setup_referral();
+        set_caller(&account("buyer.near"), 2 * NEAR);
+        let result = contract.swap(vec![referred_buy(pool_id, "front")], Some(true));
+        let (near_amount, protocol_fee) = (result.actions[0].near_amount.0, result.actions[0].protocol_fee.0);
+        assert!(protocol_fee > 0);
+
+        let referral = contract.get_referral("front".to_string()).unwrap();
+        assert_eq!(referral.num_trades, 1);
+        assert_eq!(referral.volume.0, near_amount);
+        assert_eq!(referral.accrued.0, protocol_fee / 2);
+        assert_eq!(contract.internal_referral_liabilities(), protocol_fee / 2);
+
+        set_caller(&payout(), 1);
+        assert_eq!(contract.claim_referral_fees("front".to_string()).0, protocol_fee / 2);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == payout()));
+        let referral = contract.get_referral("front".to_string()).unwrap();
+        assert_eq!((referral.accrued.0, referral.total_earned.0), (0, protocol_fee / 2));
+        assert_eq!(contract.internal_referral_liabilities(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown referral code back")]
+    fn test_buy_with_unknown_referral_code() {
+        let (mut contract, pool_id) = setup_referral();
+        set_caller(&account("buyer.near"), 2 * NEAR);
+        contract.swap(vec![referred_buy(pool_id, "back")], Some(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "only the referral code owner or payout account")]
+    fn test_claim_referral_fees_by_other() {
+        let (mut contract, _) = setup_referral();
+        set_caller(&account("buyer.near"), 1);
+        contract.claim_referral_fees("front".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "referral code taken")]
+    fn test_register_taken_referral_code() {
+        let (mut contract, _) = setup_referral();
+        set_caller(&account("buyer.near"), 0);
+        contract.register_referral_code("front".to_string(), payout());
+    }
+
+    #[test]
+    #[should_panic(expected = "referral code must be lowercase letters, digits, - or _")]
+    fn test_register_invalid_referral_code() {
+        let (mut contract, _) = setup_referral();
+        set_caller(&frontend(), 0);
+        contract.register_referral_code("Front".to_string(), payout());
+    }
+
+    #[test]
+    fn test_set_referral_payout() {
+        let (mut contract, _) = setup_referral();
+        set_caller(&frontend(), 0);
+        contract.set_referral_payout("front".to_string(), frontend());
+        assert_eq!(contract.get_referral("front".to_string()).unwrap().payout_id, frontend());
+    }
+}
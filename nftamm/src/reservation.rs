@@ -0,0 +1,267 @@
+use crate::*;
+use crate::events::emit_event;
+use crate::swap::{Action, SwapResult};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// longest reservation, in seconds
+const MAX_RESERVATION_SEC: u64 = 3600;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Reservation {
+    pub reservation_id: u64,
+    pub pool_id: u64,
+    pub facilitator_id: AccountId,
+    pub token_ids: Vec<TokenId>,
+    pub bond: U128,
+    // in seconds
+    pub expires_at: u64,
+}
+
+impl Contract {
+    fn internal_remove_reservation(&mut self, reservation_id: u64) -> Reservation {
+        let reservation = self
+            .reservations
+            .remove(&reservation_id)
+            .unwrap_or_else(|| env::panic_str("unknown reservation"));
+        self.pools[reservation.pool_id as usize].unreserve_tokens(&reservation.token_ids);
+        reservation
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Allows or disallows the account to reserve pool nfts, typically fiat or cross-chain
+    /// checkout providers.
+    pub fn set_reservation_facilitator(&mut self, account_id: AccountId, enabled: bool) {
+        self.assert_governance();
+        if enabled {
+            self.reservation_facilitators.insert(&account_id);
+        } else {
+            self.reservation_facilitators.remove(&account_id);
+        }
+    }
+
+    pub fn is_reservation_facilitator(&self, account_id: AccountId) -> bool {
+        self.reservation_facilitators.contains(&account_id)
+    }
+
+    pub fn set_reservation_bond(&mut self, bond_per_nft: U128) {
+        self.assert_governance();
+        self.reservation_bond_per_nft = bond_per_nft.0;
+    }
+
+    pub fn get_reservation_bond(&self) -> U128 {
+        self.reservation_bond_per_nft.into()
+    }
+
+    /// Reserves nfts of the pool for `duration_sec`, during which only `settle_reservation` can
+    /// buy them and lps cannot redeem them. The attached bond, `reservation_bond_per_nft` for each
+    /// nft, is refunded on settlement or release before expiry, otherwise credited to the protocol.
+    /// The facilitator pays the storage from its deposit. Returns the reservation id.
+    #[payable]
+    pub fn reserve_tokens(&mut self, pool_id: u64, token_ids: Vec<TokenId>, duration_sec: u64) -> u64 {
+        let facilitator_id = env::predecessor_account_id();
+        require!(self.reservation_facilitators.contains(&facilitator_id), "not a reservation facilitator");
+        require!(!token_ids.is_empty(), "no tokens to reserve");
+        require!(duration_sec > 0 && duration_sec <= MAX_RESERVATION_SEC, "invalid reservation duration");
+        let bond = self.reservation_bond_per_nft * token_ids.len() as u128;
+        require!(env::attached_deposit() == bond, format!("reservation bond of {} required", bond));
+        let prev_storage = env::storage_usage();
+        let expires_at = env::block_timestamp_ms() / 1000 + duration_sec;
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        pool.assert_nft_pool();
+        require!(pool.pool_type != PoolType::Token, "token pools hold no nfts");
+        pool.reserve_tokens(&token_ids, expires_at);
+
+        let reservation_id = self.next_reservation_id;
+        self.next_reservation_id += 1;
+        let reservation = Reservation {
+            reservation_id,
+            pool_id,
+            facilitator_id: facilitator_id.clone(),
+            token_ids,
+            bond: bond.into(),
+            expires_at,
+        };
+        self.reservations.insert(&reservation_id, &reservation);
+        self.assert_storage(&facilitator_id, prev_storage, None);
+        emit_event("reserve", &reservation);
+        reservation_id
+    }
+
+    /// Buys the reserved nfts at the current pool price like a `swap` buy, paid with the attached
+    /// near, and refunds the bond. Only by the facilitator before expiry.
+    #[payable]
+    pub fn settle_reservation(&mut self, reservation_id: u64, buy_to_escrow: Option<bool>) -> SwapResult {
+        let facilitator_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        let reservation = self.internal_remove_reservation(reservation_id);
+        require!(reservation.facilitator_id == facilitator_id, "not the reservation facilitator");
+        require!(reservation.expires_at > env::block_timestamp_ms() / 1000, "reservation expired");
+        self.internal_release_storage(&facilitator_id, prev_storage);
+        let result = self.swap(vec![Action::buy_tokens(reservation.pool_id, reservation.token_ids.clone())], buy_to_escrow);
+        if reservation.bond.0 > 0 {
+            Promise::new(facilitator_id).transfer(reservation.bond.0);
+        }
+        emit_event("settle_reservation", &reservation);
+        result
+    }
+
+    /// Ends the reservation without buying. The facilitator gets the bond back before expiry,
+    /// after which anyone can release it, the bond going to the protocol.
+    pub fn release_reservation(&mut self, reservation_id: u64) {
+        let prev_storage = env::storage_usage();
+        let reservation = self.internal_remove_reservation(reservation_id);
+        self.internal_release_storage(&reservation.facilitator_id, prev_storage);
+        if reservation.expires_at > env::block_timestamp_ms() / 1000 {
+            require!(reservation.facilitator_id == env::predecessor_account_id(), "reservation not expired");
+            if reservation.bond.0 > 0 {
+                Promise::new(reservation.facilitator_id.clone()).transfer(reservation.bond.0);
+            }
+        } else {
+            self.protocol_fee_credit += reservation.bond.0;
+        }
+        emit_event("release_reservation", &reservation);
+    }
+
+    pub fn get_reservation(&self, reservation_id: u64) -> Option<Reservation> {
+        self.reservations.get(&reservation_id)
+    }
+
+    pub fn get_reservations(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Reservation> {
+        self.reservations
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(100) as usize)
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::swap::SwapType;
+    use crate::testing::*;
+    use near_sdk::serde_json::json;
+
+    const BOND: Balance = NEAR / 10;
+
+    // nft pool of nfts 1 and 2 with nft 1 reserved for 600s by the returned facilitator
+    fn setup_reservation() -> (Contract, u64, AccountId) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        let facilitator = account("facilitator.near");
+        for account_id in [&owner, &facilitator, &account("buyer.near")] {
+            register(&mut contract, account_id, NEAR);
+        }
+        let pool_id = create_pool(&mut contract, &owner, PoolType::NFT, &["1", "2"], 0);
+        set_caller(&governance(), 0);
+        contract.set_reservation_facilitator(facilitator.clone(), true);
+        contract.set_reservation_bond(U128(BOND));
+        set_caller(&facilitator, BOND);
+        assert_eq!(contract.reserve_tokens(pool_id, token_ids(&["1"]), 600), 0);
+        (contract, pool_id, facilitator)
+    }
+
+    fn any_buy(pool_id: u64, num_nfts: u64) -> Action {
+        near_sdk::serde_json::from_value(json!({
+            "pool_id": pool_id,
+            "swap_type": SwapType::NearToNFT as u8,
+            "input_token_ids": [],
+            "output_token_ids": [],
+            "num_out_nfts": num_nfts,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_settle_reservation() {
+        let (mut contract, pool_id, facilitator) = setup_reservation();
+
+        set_caller(&facilitator, 2 * NEAR);
+        let result = contract.settle_reservation(0, Some(true));
+        assert_eq!(result.actions[0].token_ids, token_ids(&["1"]));
+        assert!(contract.get_reservation(0).is_none());
+        assert!(contract.pools[pool_id as usize].reserved_token_ids.is_empty());
+        let bought = contract.account_deposits.get(&facilitator).unwrap().assets.get(&nft()).unwrap();
+        assert!(bought.get(&"1".to_string()).is_some());
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == facilitator));
+    }
+
+    #[test]
+    #[should_panic(expected = "reservation expired")]
+    fn test_settle_expired_reservation() {
+        let (mut contract, _, facilitator) = setup_reservation();
+        advance_time_sec(600);
+        set_caller(&facilitator, 2 * NEAR);
+        contract.settle_reservation(0, Some(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "token id 1 reserved")]
+    fn test_buy_reserved_token() {
+        let (mut contract, pool_id, _) = setup_reservation();
+        set_caller(&account("buyer.near"), 2 * NEAR);
+        contract.swap(vec![Action::buy_tokens(pool_id, token_ids(&["1"]))], Some(true));
+    }
+
+    #[test]
+    fn test_buy_token_after_reservation_expiry() {
+        let (mut contract, pool_id, _) = setup_reservation();
+        advance_time_sec(600);
+        set_caller(&account("buyer.near"), 2 * NEAR);
+        let result = contract.swap(vec![Action::buy_tokens(pool_id, token_ids(&["1"]))], Some(true));
+        assert_eq!(result.actions[0].token_ids, token_ids(&["1"]));
+    }
+
+    #[test]
+    fn test_any_buy_skips_reserved_token_until_released() {
+        let (mut contract, pool_id, facilitator) = setup_reservation();
+        let buyer = account("buyer.near");
+
+        set_caller(&buyer, 2 * NEAR);
+        let result = contract.swap(vec![any_buy(pool_id, 1)], Some(true));
+        assert_eq!(result.actions[0].token_ids, token_ids(&["2"]));
+
+        set_caller(&facilitator, 0);
+        contract.release_reservation(0);
+        assert_eq!(contract.protocol_fee_credit, 0);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == facilitator));
+
+        set_caller(&buyer, 2 * NEAR);
+        let result = contract.swap(vec![any_buy(pool_id, 1)], Some(true));
+        assert_eq!(result.actions[0].token_ids, token_ids(&["1"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "reservation not expired")]
+    fn test_release_before_expiry_by_other() {
+        let (mut contract, _, _) = setup_reservation();
+        set_caller(&account("buyer.near"), 0);
+        contract.release_reservation(0);
+    }
+
+    #[test]
+    fn test_release_expired_reservation() {
+        let (mut contract, pool_id, _) = setup_reservation();
+        advance_time_sec(600);
+        set_caller(&account("buyer.near"), 0);
+        contract.release_reservation(0);
+        assert!(contract.get_reservation(0).is_none());
+        assert!(!contract.pools[pool_id as usize].is_reserved(&"1".to_string()));
+        // the bond of a reservation left to expire goes to the protocol
+        assert_eq!(contract.protocol_fee_credit, BOND);
+    }
+
+    #[test]
+    #[should_panic(expected = "reservation bond of 100000000000000000000000 required")]
+    fn test_reserve_without_bond() {
+        let (mut contract, pool_id, facilitator) = setup_reservation();
+        set_caller(&facilitator, 0);
+        contract.reserve_tokens(pool_id, token_ids(&["2"]), 600);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::*;
+use crate::swap::ext_payout_resolver;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// royalties are expressed in basis points
+pub const ROYALTY_BPS_BASE: u128 = 10_000;
+pub const MAX_ROYALTY_BPS: u16 = 2_500;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoyaltyOverride {
+    pub receiver_id: AccountId,
+    pub bps: u16,
+}
+
+impl Contract {
+    // royalty due on a trade of `near_amount` of the collection, added to buys and deducted
+    // from sells
+    pub(crate) fn royalty_amount(&self, asset_id: &AssetId, near_amount: Balance) -> Balance {
+        match self.royalty_overrides.get(asset_id) {
+            Some(royalty) => near_amount * royalty.bps as u128 / ROYALTY_BPS_BASE,
+            None => 0,
+        }
+    }
+
+    // sends the royalty to the collection receiver, crediting the payer deposit if it fails
+    pub(crate) fn internal_pay_royalty(&mut self, payer_id: &AccountId, asset_id: &AssetId, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        let royalty = self.royalty_overrides.get(asset_id).unwrap();
+        let operation_id = self.internal_start_operation(OperationKind::PayoutTransfer, &royalty.receiver_id, amount);
+        Promise::new(royalty.receiver_id.clone())
+            .transfer(amount)
+            .then(
+                ext_payout_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .payout_transfer_resolve(payer_id.clone(), royalty.receiver_id, amount.into(), operation_id),
+            );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the royalty charged on trades of a collection whose contract does not provide
+    /// payouts, or removes it.
+    pub fn set_royalty_override(&mut self, asset_id: AssetId, royalty: Option<RoyaltyOverride>) {
+        self.assert_governance();
+        match royalty {
+            Some(royalty) => {
+                require!(royalty.bps <= MAX_ROYALTY_BPS, "royalty exceeds cap");
+                self.royalty_overrides.insert(&asset_id, &royalty);
+            }
+            None => {
+                self.royalty_overrides.remove(&asset_id);
+            }
+        }
+    }
+
+    pub fn get_royalty_override(&self, asset_id: AssetId) -> Option<RoyaltyOverride> {
+        self.royalty_overrides.get(&asset_id)
+    }
+}
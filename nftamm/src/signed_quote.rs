@@ -0,0 +1,235 @@
+use std::convert::TryFrom;
+
+use crate::*;
+use crate::events::{emit_event, CurveState, SwapEvent};
+use crate::swap::SwapType;
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, CurveType, PublicKey};
+
+// Negotiated sale of pool nfts at a fixed price, signed by the pool quote signer or, for pools
+// opting in, the governance quote signer over the borsh serialization of the quote. Trade pools
+// never sell under their curve, their nfts being owned by their lps.
+#[derive(BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignedQuote {
+    // this contract, so the quote cannot be replayed on another deployment
+    pub contract_id: AccountId,
+    pub pool_id: u64,
+    pub token_ids: Vec<TokenId>,
+    // total near paid for the token ids, protocol fee included and collection royalty excluded
+    pub price: U128,
+    // in seconds
+    pub expires_at: u64,
+    // only this account can redeem the quote if set
+    pub buyer_id: Option<AccountId>,
+    // distinguishes otherwise identical quotes, each quote can be redeemed once
+    pub nonce: u64,
+}
+
+pub(crate) fn verify_signature(public_key: &PublicKey, message: &[u8], signature: &[u8]) -> bool {
+    if public_key.curve_type() != CurveType::ED25519 {
+        return false;
+    }
+    let public_key = match ed25519_dalek::PublicKey::from_bytes(&public_key.as_bytes()[1..]) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    match ed25519_dalek::Signature::try_from(signature) {
+        Ok(signature) => public_key.verify_strict(message, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn set_quote_signer(&mut self, public_key: Option<PublicKey>) {
+        self.assert_governance();
+        self.quote_signer = public_key;
+    }
+
+    pub fn set_pool_quote_signer(&mut self, pool_id: u64, public_key: Option<PublicKey>) {
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        pool.quote_signer = public_key;
+    }
+
+    /// Lets the governance quote signer sign quotes of the pool.
+    pub fn set_governance_quotes(&mut self, pool_id: u64, enabled: bool) {
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        pool.assert_owner();
+        pool.governance_quotes = enabled;
+    }
+
+    pub fn get_pool_quote_signer(&self, pool_id: u64) -> Option<PublicKey> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.quote_signer.clone()
+    }
+
+    pub fn is_quote_used(&self, quote: SignedQuote) -> bool {
+        self.used_quotes.contains(&env::sha256_array(&quote.try_to_vec().unwrap()))
+    }
+
+    /// Buys the quoted token ids from the pool at the quoted price, bypassing the curve. The
+    /// caller pays the storage of the quote redemption record from their deposit.
+    #[payable]
+    pub fn execute_signed_quote(&mut self, quote: SignedQuote, signature: Base64VecU8) {
+        let account_id = env::predecessor_account_id();
+        require!(quote.contract_id == env::current_account_id(), "quote for another contract");
+        require!(quote.expires_at > env::block_timestamp_ms() / 1000, "quote expired");
+        if let Some(buyer_id) = &quote.buyer_id {
+            require!(buyer_id == &account_id, "quote issued to another buyer");
+        }
+        require!(env::attached_deposit() >= quote.price.0, "not enough near payment");
+        self.assert_pool_access(quote.pool_id, &account_id);
+        self.assert_compliance(&account_id, quote.price.0);
+
+        let message = quote.try_to_vec().unwrap();
+        let pool = self.pools.get(quote.pool_id as usize).expect("pool id invalid");
+        pool.assert_circuit_breaker_closed();
+        let governance_signer = if pool.governance_quotes { self.quote_signer.as_ref() } else { None };
+        let signed = [pool.quote_signer.as_ref(), governance_signer]
+            .iter()
+            .flatten()
+            .any(|public_key| verify_signature(public_key, &message, &signature.0));
+        require!(signed, "invalid quote signature");
+
+        let prev_storage = env::storage_usage();
+        require!(self.used_quotes.insert(&env::sha256_array(&message)), "quote already used");
+        self.assert_storage(&account_id, prev_storage, None);
+
+        let protocol_fee_multiplier = self.trade_protocol_fee_multiplier(quote.pool_id, &account_id);
+        let pool = &mut self.pools[quote.pool_id as usize];
+        if pool.pool_type == PoolType::Trade {
+            let curve_quote = pool
+                .quote_buy(quote.token_ids.len() as u64, protocol_fee_multiplier)
+                .unwrap_or_else(|error_code| env::panic_str(&format!("failed to get buy info: {:?}", error_code)));
+            require!(quote.price.0 >= curve_quote.near_amount, "quote below the curve price of a trade pool");
+        }
+        let pre_state = CurveState::of(pool);
+        let protocol_fee = pool.buy_nfts_at_price(&quote.token_ids, quote.price.0, protocol_fee_multiplier);
+        pool.record_trade(true, quote.price.0, protocol_fee);
+        emit_event("swap", SwapEvent {
+            pool_id: quote.pool_id,
+            trade_seq: pool.trade_seq,
+            account_id: &account_id,
+            swap_type: SwapType::NearToNFT as u8,
+            token_ids: &quote.token_ids,
+            near_amount: quote.price,
+            protocol_fee: protocol_fee.into(),
+            pre_state,
+            post_state: CurveState::of(pool),
+        });
+        let asset_id = pool.nft_token.clone();
+        let asset_recipient = pool.asset_recipient.clone();
+        self.internal_credit_trade_protocol_fee(quote.pool_id, protocol_fee);
+        self.internal_record_pool_trade(quote.pool_id);
+        self.internal_record_activity_in_pool(&account_id, ActivityKind::Buy, quote.pool_id, quote.token_ids.len() as u64, quote.price.0);
+        self.internal_notify_trade_hook(quote.pool_id, &account_id, SwapType::NearToNFT as u8, &quote.token_ids, quote.price.0);
+
+        if let Some(asset_recipient) = asset_recipient {
+            let mut asset_recipient_deposit = self.internal_get_account_or_revert(&asset_recipient);
+            asset_recipient_deposit.available_balance += quote.price.0 - protocol_fee;
+            self.account_deposits.insert(&asset_recipient, &asset_recipient_deposit);
+        }
+        let royalty = self.royalty_amount(&asset_id, quote.price.0);
+        require!(env::attached_deposit() >= quote.price.0 + royalty, "not enough near payment");
+        self.internal_pay_royalty(&account_id, &asset_id, royalty);
+        if env::attached_deposit() > quote.price.0 + royalty {
+            Promise::new(account_id.clone()).transfer(env::attached_deposit() - quote.price.0 - royalty);
+        }
+        self.transfer_nfts(&account_id, &asset_id, &quote.token_ids);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    // nft pool of nfts 1 and 2 whose quotes are signed with the test key, and a registered buyer
+    fn setup_quote_pool() -> (Contract, u64, AccountId) {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        let buyer = account("buyer.near");
+        register(&mut contract, &owner, NEAR);
+        register(&mut contract, &buyer, NEAR);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::NFT, &["1", "2"], 0);
+        set_caller(&owner, 0);
+        contract.set_pool_quote_signer(pool_id, Some(signer_public_key()));
+        (contract, pool_id, buyer)
+    }
+
+    // nft 1 for half a near, valid for a minute
+    fn quote(pool_id: u64) -> SignedQuote {
+        SignedQuote {
+            contract_id: contract_id(),
+            pool_id,
+            token_ids: token_ids(&["1"]),
+            price: U128(NEAR / 2),
+            expires_at: env::block_timestamp_ms() / 1000 + 60,
+            buyer_id: None,
+            nonce: 1,
+        }
+    }
+
+    #[test]
+    fn test_execute_signed_quote() {
+        let (mut contract, pool_id, buyer) = setup_quote_pool();
+        let signature = sign(&quote(pool_id));
+        set_caller(&buyer, NEAR);
+        contract.execute_signed_quote(quote(pool_id), signature);
+
+        let pool = &contract.pools[pool_id as usize];
+        assert!(!pool.token_ids_in_pools.contains(&"1".to_string()));
+        assert!(pool.token_ids_in_pools.contains(&"2".to_string()));
+        assert_eq!(pool.spot_price, SPOT_PRICE);
+        assert!(contract.is_quote_used(quote(pool_id)));
+    }
+
+    #[test]
+    #[should_panic(expected = "quote already used")]
+    fn test_execute_signed_quote_replay() {
+        let (mut contract, pool_id, buyer) = setup_quote_pool();
+        let signature = sign(&quote(pool_id));
+        set_caller(&buyer, NEAR);
+        contract.execute_signed_quote(quote(pool_id), signature.clone());
+        contract.execute_signed_quote(quote(pool_id), signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "quote expired")]
+    fn test_execute_signed_quote_expired() {
+        let (mut contract, pool_id, buyer) = setup_quote_pool();
+        let quote = quote(pool_id);
+        let signature = sign(&quote);
+        advance_time_sec(60);
+        set_caller(&buyer, NEAR);
+        contract.execute_signed_quote(quote, signature);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid quote signature")]
+    fn test_execute_signed_quote_tampered() {
+        let (mut contract, pool_id, buyer) = setup_quote_pool();
+        let signature = sign(&quote(pool_id));
+        let mut tampered = quote(pool_id);
+        tampered.price = U128(1);
+        set_caller(&buyer, NEAR);
+        contract.execute_signed_quote(tampered, signature);
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[7u8; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        let signature = ed25519_dalek::ExpandedSecretKey::from(&secret).sign(b"quote", &public);
+
+        let mut key_bytes = vec![0u8];
+        key_bytes.extend_from_slice(public.as_bytes());
+        let public_key = PublicKey::try_from(key_bytes).unwrap();
+        assert!(verify_signature(&public_key, b"quote", &signature.to_bytes()));
+        assert!(!verify_signature(&public_key, b"other quote", &signature.to_bytes()));
+        assert!(!verify_signature(&public_key, b"quote", &[0u8; 10]));
+    }
+}
@@ -0,0 +1,77 @@
+// Off-chain copy of the pool pricing for market makers and tests, built with the `sim` feature.
+// Nothing here reads the blockchain env, so it can run outside of a contract context.
+use near_sdk::Balance;
+
+use crate::curves::curve::{BondingCurve, Curve};
+use crate::curves::errorcodes::CurveErrorCode;
+use crate::curves::units::{Wad, Yocto};
+use crate::pair::{self, PoolParamError, PoolType};
+use crate::pricing;
+
+pub use crate::pricing::TradeQuote;
+
+/// Curve state of a pool, updated by simulated trades the same way the contract updates it.
+pub struct SimPool {
+    pub curve_type: BondingCurve,
+    pub pool_type: PoolType,
+    pub spot_price: u128,
+    pub delta: u128,
+    pub fee: u128,
+    pub protocol_fee_multiplier: u128,
+}
+
+impl SimPool {
+    pub fn new(
+        curve_type: BondingCurve,
+        pool_type: PoolType,
+        spot_price: u128,
+        delta: u128,
+        fee: u128,
+        protocol_fee_multiplier: u128,
+    ) -> Result<SimPool, Vec<PoolParamError>> {
+        let errors = validate_pool_params(curve_type, pool_type, spot_price, delta, fee);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(SimPool { curve_type, pool_type, spot_price, delta, fee, protocol_fee_multiplier })
+    }
+
+    /// Quote of buying `num_items` from the pool, without changing it.
+    pub fn quote_buy(&self, num_items: u64) -> Result<TradeQuote, CurveErrorCode> {
+        pricing::quote_buy(&Curve::new(self.curve_type), Yocto(self.spot_price), self.delta, Wad(self.fee), Wad(self.protocol_fee_multiplier), num_items)
+    }
+
+    /// Quote of selling `num_items` to the pool, without changing it.
+    pub fn quote_sell(&self, num_items: u64) -> Result<TradeQuote, CurveErrorCode> {
+        pricing::quote_sell(&Curve::new(self.curve_type), Yocto(self.spot_price), self.delta, Wad(self.fee), Wad(self.protocol_fee_multiplier), num_items)
+    }
+
+    pub fn buy(&mut self, num_items: u64) -> Result<TradeQuote, CurveErrorCode> {
+        let quote = self.quote_buy(num_items)?;
+        self.spot_price = quote.new_spot_price;
+        self.delta = quote.new_delta;
+        Ok(quote)
+    }
+
+    pub fn sell(&mut self, num_items: u64) -> Result<TradeQuote, CurveErrorCode> {
+        let quote = self.quote_sell(num_items)?;
+        self.spot_price = quote.new_spot_price;
+        self.delta = quote.new_delta;
+        Ok(quote)
+    }
+
+    /// Part of a trade kept by the pool as its trade fee, as recorded in the pool health view.
+    pub fn trade_pool_fee(&self, is_buy: bool, quote: &TradeQuote) -> Result<Balance, CurveErrorCode> {
+        pricing::trade_pool_fee(Wad(self.fee), is_buy, quote.near_amount, quote.protocol_fee)
+    }
+}
+
+pub fn validate_pool_params(
+    curve_type: BondingCurve,
+    pool_type: PoolType,
+    spot_price: u128,
+    delta: u128,
+    fee: u128,
+) -> Vec<PoolParamError> {
+    pair::validate_pool_params(&Curve::new(curve_type), pool_type, Yocto(spot_price), delta, Wad(fee))
+}
@@ -21,7 +21,7 @@ impl Contract {
         if self.account_deposits.get(account_id).is_some() {
             let storage_account = self.account_deposits.get(account_id).unwrap();
             Some(StorageBalance {
-                total: U128(storage_account.near_balance),
+                total: U128(storage_account.total_balance()),
                 available: U128(self.storage_available(account_id.clone()).0),
             })
         } else {
@@ -39,16 +39,15 @@ impl Contract {
             self.account_deposits.insert(
                 account_id,
                 &AccountDeposit {
-                    assets: UnorderedMap::new(StorageKey::AccountDepositAsset {
-                        account_id: account_id.clone(),
-                    }),
-                    near_balance: 0,
+                    assets: UnorderedMap::new(StorageKey::account_deposit_assets(account_id)),
+                    storage_reserve: 0,
+                    available_balance: 0,
                     storage_usage: 0,
                 },
             );
         }
         let mut storage_account = self.account_deposits.get(account_id).unwrap();
-        storage_account.near_balance += amount;
+        storage_account.available_balance += amount;
 
         self.account_deposits.insert(account_id, &storage_account);
 
@@ -77,8 +76,12 @@ impl StorageManagement for Contract {
             .map(|a| a.into())
             .unwrap_or_else(|| env::predecessor_account_id());
 
-        let bounds_for_account = self.storage_balance_bounds_for_account(account_id.clone());
-        let min = bounds_for_account.min.0;
+        // registered accounts can top up any amount
+        let min = if self.account_deposits.get(&account_id).is_some() {
+            0
+        } else {
+            self.storage_balance_bounds().min.0
+        };
 
         if amount < min {
             env::panic_str("The attached deposit is less than the minimum storage balance");
@@ -93,40 +96,41 @@ impl StorageManagement for Contract {
         self.internal_storage_balance_of(&account_id).unwrap()
     }
 
-    /// While storage_withdraw normally allows the caller to retrieve `available` balance, the basic
-    /// Fungible Token implementation sets storage_balance_bounds.min == storage_balance_bounds.max,
-    /// which means available balance will always be 0. So this implementation:
-    /// * panics if `amount > 0`
-    /// * never transfers Ⓝ to caller
-    /// * returns a `storage_balance` struct if `amount` is 0
+    /// Transfers `amount` of the deposited near above the cost of the current storage usage to
+    /// the caller, all of it if not given.
+    #[payable]
     fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
         assert_one_yocto();
         let predecessor_account_id = env::predecessor_account_id();
-        if let Some(storage_balance) = self.internal_storage_balance_of(&predecessor_account_id) {
-            match amount {
-                Some(amount) if amount.0 > 0 => {
-                    env::panic_str("The amount is greater than the available storage balance");
-                }
-                _ => storage_balance,
-            }
-        } else {
+        if self.account_deposits.get(&predecessor_account_id).is_none() {
             env::panic_str(
                 format!("The account {} is not registered", &predecessor_account_id).as_str(),
             );
         }
+        let available = self.internal_available_near(&predecessor_account_id);
+        let amount = amount.map(|a| a.0).unwrap_or(available);
+        if amount > available {
+            env::panic_str("The amount is greater than the available storage balance");
+        }
+        if amount > 0 {
+            self.internal_withdraw_near(&predecessor_account_id, amount);
+            self.internal_record_activity(&predecessor_account_id, ActivityKind::Withdraw, None, None, 0, amount);
+            Promise::new(predecessor_account_id.clone()).transfer(amount);
+        }
+        self.internal_storage_balance_of(&predecessor_account_id).unwrap()
     }
 
     fn storage_unregister(&mut self, force: Option<bool>) -> bool {
         self.internal_storage_unregister(force).is_some()
     }
 
+    /// The minimum only registers the account, each deposited nft then needing
+    /// `storage_cost_per_nft` more, so there is no maximum.
     fn storage_balance_bounds(&self) -> StorageBalanceBounds {
-        let min = Balance::from(self.storage_per_account_creation + self.storage_per_nft_deposit)
-            * env::storage_byte_cost();
-        let max = min;
+        let min = Balance::from(self.storage_per_account_creation) * env::storage_byte_cost();
         StorageBalanceBounds {
             min: U128(min),
-            max: Some(U128(max)),
+            max: None,
         }
     }
 
@@ -137,32 +141,31 @@ impl StorageManagement for Contract {
 
 #[near_bindgen]
 impl Contract {
+    /// Same as `storage_balance_bounds`, the minimum of registered accounts being the cost of
+    /// their current storage usage.
     pub fn storage_balance_bounds_for_account(
         &self,
-        _account_id: AccountId,
+        account_id: AccountId,
     ) -> StorageBalanceBounds {
-        let required_storage_balance =
-            Balance::from(self.storage_per_account_creation + self.storage_per_nft_deposit)
-                * env::storage_byte_cost();
-        let min = Balance::from(self.storage_per_account_creation + self.storage_per_nft_deposit)
-            * env::storage_byte_cost();
+        let storage_usage = match self.account_deposits.get(&account_id) {
+            Some(account_deposit) => account_deposit.storage_usage,
+            None => self.storage_per_account_creation,
+        };
         StorageBalanceBounds {
-            min: U128(min),
-            max: Some(U128(required_storage_balance)),
+            min: U128(Balance::from(storage_usage) * env::storage_byte_cost()),
+            max: None,
         }
     }
 
+    /// Storage cost of each nft held in an account deposit.
+    pub fn storage_cost_per_nft(&self) -> U128 {
+        U128(Balance::from(self.storage_per_nft_deposit) * env::storage_byte_cost())
+    }
+
     pub fn storage_available(&self, account_id: AccountId) -> U128 {
         let storage_account = self.account_deposits.get(&account_id);
         match storage_account {
-            Some(storage_account) => {
-                let usage = storage_account.storage_usage as u128 * env::storage_byte_cost();
-                if storage_account.near_balance >= usage {
-                    U128(storage_account.near_balance - usage)
-                } else {
-                    U128(0)
-                }
-            }
+            Some(storage_account) => U128(storage_account.available_balance),
             None => U128(0),
         }
     }
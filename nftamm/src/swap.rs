@@ -1,11 +1,33 @@
 use std::{collections::{HashMap, HashSet}, iter::FromIterator};
 
 use crate::*;
+use crate::curves::{expect_u128, U256};
+use crate::curves::errorcodes::CurveErrorCode;
+use crate::events::{emit_event, CurveState, SwapEvent};
+use crate::trade_hook::GAS_FOR_TRADE_HOOK;
+use near_sdk::json_types::U64;
 use near_sdk::{
-    near_bindgen,
+    ext_contract, near_bindgen, PromiseResult,
     serde::{Deserialize, Serialize},
 };
 
+// most nfts bought by one buy_out_pool call, larger pools are bought out over several calls
+const MAX_BUY_OUT_NFTS: u64 = 50;
+// most nfts sold by one sell_tokens call
+const MAX_SELL_TOKENS: usize = 50;
+// denominator of max_price_impact_bps
+const BPS_BASE: u128 = 10_000;
+// most actions of a swap until governance changes it
+pub(crate) const DEFAULT_MAX_SWAP_ACTIONS: u64 = 20;
+// rough gas of the swap call itself and of executing each action, promises excluded
+const GAS_FOR_SWAP: Gas = Gas(10_000_000_000_000);
+const GAS_PER_SWAP_ACTION: Gas = Gas(15_000_000_000_000);
+
+#[ext_contract(ext_payout_resolver)]
+pub trait PayoutResolver {
+    fn payout_transfer_resolve(&mut self, seller_id: AccountId, receiver_id: AccountId, amount: U128, operation_id: u64);
+}
+
 #[repr(u8)]
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -31,14 +53,291 @@ impl From<u8> for SwapType {
 pub struct Action {
     pool_id: u64,
     swap_type: u8,
+    // near received for sells, after the collection royalty
     min_output_near: Option<U128>,
     input_token_ids: Vec<TokenId>,
     output_token_ids: Vec<TokenId>,
     num_out_nfts: Option<u64>,
+    // for NFTToNear, splits the near output between these accounts instead of returning it to the seller
+    payout: Option<Vec<PayoutShare>>,
+    // for NearToNFT without output_token_ids, picks the bought tokens pseudo-randomly using this nonce
+    random_nonce: Option<u64>,
+    // for NearToNFT, fails the buy if the pool spot price moved more than max_price_impact_bps
+    // (default 0) away from this quoted one
+    expected_spot_price: Option<U128>,
+    max_price_impact_bps: Option<u16>,
+    // registered code of the frontend, earning a share of the protocol fee
+    referral_code: Option<String>,
+    // for NearToNFT, most near the buy may take from the caller deposit when the attached near
+    // and earlier sells fall short, none taking nothing
+    max_deposit_near: Option<U128>,
+}
+
+impl Action {
+    // buy of exactly `token_ids` from the pool
+    pub(crate) fn buy_tokens(pool_id: u64, token_ids: Vec<TokenId>) -> Action {
+        Action {
+            pool_id,
+            swap_type: SwapType::NearToNFT as u8,
+            min_output_near: None,
+            input_token_ids: vec![],
+            num_out_nfts: Some(token_ids.len() as u64),
+            output_token_ids: token_ids,
+            payout: None,
+            random_nonce: None,
+            expected_spot_price: None,
+            max_price_impact_bps: None,
+            referral_code: None,
+            max_deposit_near: None,
+        }
+    }
+}
+
+/// Execution of one action of a `swap`.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionResult {
+    pub pool_id: u64,
+    pub swap_type: u8,
+    // nfts sold to or bought from the pool
+    pub token_ids: Vec<TokenId>,
+    // near paid to the pool for buys, paid by the pool for sells, protocol fee included
+    pub near_amount: U128,
+    pub protocol_fee: U128,
+    pub royalty: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapResult {
+    pub actions: Vec<ActionResult>,
+    // near sent back to the caller, or credited to the signer deposit for signed intents
+    pub refund: U128,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PayoutShare {
+    pub account_id: AccountId,
+    // base 1e18, shares of an action must sum up to 1e18
+    pub share: U128,
+}
+
+impl Contract {
+    // Checks that every action is well formed before anything is executed. NFTs sold by an action
+    // come either from its input_token_ids (bought earlier in the batch or held in the caller
+    // deposit), or, if none are given, from everything bought earlier in the batch of the same
+    // collection. NEAR spent by buys comes from the attached deposit, earlier sells and then, up
+    // to the max_deposit_near of the buy, the caller deposit.
+    fn validate_actions(&self, account_id: &AccountId, actions: &[Action]) {
+        require!(!actions.is_empty(), "no actions");
+        require!(
+            actions.len() as u64 <= self.max_swap_actions,
+            format!("cannot execute more than {} actions", self.max_swap_actions)
+        );
+        let mut bought_assets = HashSet::<AssetId>::new();
+        for action in actions {
+            self.assert_pool_access(action.pool_id, account_id);
+            let nft_token = self.get_nft_asset_id(action.pool_id);
+            if let Some(code) = &action.referral_code {
+                self.assert_referral_code(code);
+            }
+            let pool = &self.pools[action.pool_id as usize];
+            match SwapType::from(action.swap_type) {
+                SwapType::NFTToNear => {
+                    pool.assert_sells_enabled();
+                    require!(action.min_output_near.is_some(), "min output near required");
+                    if let Some(payout) = &action.payout {
+                        require!(!payout.is_empty(), "empty payout");
+                        let total_share = payout.iter().try_fold(0u128, |total, p| total.checked_add(p.share.0));
+                        require!(total_share == Some(WAD), "payout shares must sum to 100%");
+                        // failed payout transfers fall back to the seller deposit
+                        require!(self.account_deposits.get(account_id).is_some(), "account unregistered");
+                    }
+                    require!(
+                        !action.input_token_ids.is_empty() || bought_assets.contains(&nft_token),
+                        "input token ids invalid"
+                    );
+                }
+                SwapType::NearToNFT => {
+                    pool.assert_buys_enabled();
+                    require!(action.num_out_nfts.is_some(), "num out nfts required");
+                    if let Some(max_price_impact_bps) = action.max_price_impact_bps {
+                        require!(action.expected_spot_price.is_some(), "expected spot price required");
+                        require!(max_price_impact_bps as u128 <= BPS_BASE, "price impact exceeds 100%");
+                    }
+                    bought_assets.insert(nft_token);
+                }
+            }
+        }
+    }
+}
+
+impl Contract {
+    // executes the actions of `account_id` paid with `attached_near` and its deposit, the near
+    // left either sent back or, with `refund_to_deposit`, credited to its deposit
+    pub(crate) fn internal_swap(
+        &mut self,
+        account_id: &AccountId,
+        actions: &[Action],
+        buy_to_escrow: bool,
+        attached_near: Balance,
+        refund_to_deposit: bool,
+    ) -> SwapResult {
+        let mut remain_near_amount = attached_near;
+        let mut results = Vec::with_capacity(actions.len());
+        let mut cached_token_ids = HashMap::<AssetId, HashSet<TokenId>>::new();
+        self.validate_actions(account_id, actions);
+        for action in actions {
+            let (result, remain) = self.internal_swap_with_action(
+                account_id,
+                action,
+                &mut cached_token_ids,
+                &remain_near_amount,
+            );
+            results.push(result);
+            remain_near_amount = remain;
+        }
+
+        if remain_near_amount > 0 {
+            if refund_to_deposit {
+                self.internal_credit_near(account_id, remain_near_amount);
+            } else {
+                Promise::new(account_id.clone()).transfer(remain_near_amount);
+            }
+        }
+        let swap_result = SwapResult { actions: results, refund: remain_near_amount.into() };
+
+        if buy_to_escrow {
+            let prev_storage = env::storage_usage();
+            for (nft_token, token_ids) in cached_token_ids.into_iter() {
+                for token_id in &token_ids {
+                    self.internal_deposit_nft(account_id, &nft_token, token_id);
+                }
+            }
+            self.assert_storage(account_id, prev_storage, None);
+            return swap_result;
+        }
+
+        for (nft_token, token_ids) in cached_token_ids.into_iter() {
+            if token_ids.len() > 0 {
+                self.transfer_nfts(account_id, &nft_token, &Vec::from_iter(token_ids));
+            }
+        }
+
+        //should not need to check storage here as swap function only works on assets already deposited
+        swap_result
+    }
+
+    // panics if the spot price moved from the quoted one by more than the action tolerance
+    fn assert_spot_price_unchanged(&self, action: &Action) {
+        let expected_spot_price = match action.expected_spot_price {
+            Some(expected_spot_price) => expected_spot_price.0,
+            None => return,
+        };
+        let spot_price = self.pools[action.pool_id as usize].spot_price;
+        let price_change = if spot_price > expected_spot_price {
+            spot_price - expected_spot_price
+        } else {
+            expected_spot_price - spot_price
+        };
+        let max_price_impact_bps = action.max_price_impact_bps.unwrap_or(0);
+        require!(
+            U256::from(price_change) * U256::from(BPS_BASE)
+                <= U256::from(expected_spot_price) * U256::from(max_price_impact_bps),
+            "spot price moved beyond tolerance"
+        );
+    }
+
+    // Splits a sale of `num_tokens` nfts of `asset_id` across the pools buying them, giving each
+    // nft to the pool paying the most for one more item, at the protocol fee the account trades
+    // at. Returns the pool ids with the number of nfts sold to each and the near they pay,
+    // royalty not deducted.
+    fn plan_token_sale(&self, account_id: &AccountId, asset_id: &AssetId, num_tokens: u64) -> Vec<(u64, u64, Balance)> {
+        let mut sales: Vec<(u64, u64, Balance)> = self
+            .asset_pool_ids
+            .get(asset_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|pool_id| &self.pools[pool_id as usize])
+            .filter(|p| p.pool_type != PoolType::NFT)
+            .filter(|p| p.sides.sells_enabled && p.bootstrap.is_none() && p.is_oracle_price_fresh() && p.is_circuit_breaker_closed())
+            .filter(|p| self.has_pool_access(p.pool_id, account_id.clone()))
+            .map(|p| (p.pool_id, 0, 0))
+            .collect();
+        for _ in 0..num_tokens {
+            let best = sales
+                .iter()
+                .enumerate()
+                .filter_map(|(i, (pool_id, num_sold, output))| {
+                    let pool = &self.pools[*pool_id as usize];
+                    if pool.asset_recipient.is_none() && pool.max_nfts.is_some_and(|max| pool.num_items() + num_sold >= max) {
+                        return None;
+                    }
+                    let protocol_fee_multiplier = self.trade_protocol_fee_multiplier(*pool_id, account_id);
+                    let quote = pool.quote_sell(num_sold + 1, protocol_fee_multiplier).ok()?;
+                    if quote.near_amount + quote.protocol_fee > pool.near_balance || quote.near_amount <= *output {
+                        return None;
+                    }
+                    Some((i, quote.near_amount - output, quote.near_amount))
+                })
+                .max_by_key(|(_, marginal, _)| *marginal)
+                .unwrap_or_else(|| env::panic_str("not enough liquidity to sell all tokens"));
+            let (i, _, output) = best;
+            sales[i].1 += 1;
+            sales[i].2 = output;
+        }
+        sales.retain(|(_, num_sold, _)| *num_sold > 0);
+        sales
+    }
+
+    fn internal_payout(&mut self, seller_id: &AccountId, amount: Balance, payout: &[PayoutShare]) {
+        let total_share = payout.iter().try_fold(0u128, |total, share| total.checked_add(share.share.0));
+        require!(total_share.map_or(false, |total| total <= WAD), "payout shares exceed 100%");
+        let mut remaining = amount;
+        for (i, share) in payout.iter().enumerate() {
+            // the last receiver gets the rounding dust
+            let share_amount = if i == payout.len() - 1 {
+                remaining
+            } else {
+                expect_u128(U256::from(amount) * U256::from(share.share.0) / WAD, "payout share overflow")
+            };
+            remaining -= share_amount;
+            if share_amount == 0 {
+                continue;
+            }
+            let operation_id = self.internal_start_operation(OperationKind::PayoutTransfer, &share.account_id, share_amount);
+            Promise::new(share.account_id.clone())
+                .transfer(share_amount)
+                .then(
+                    ext_payout_resolver::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                        .payout_transfer_resolve(seller_id.clone(), share.account_id.clone(), share_amount.into(), operation_id),
+                );
+        }
+    }
 }
 
 #[near_bindgen]
 impl Contract {
+    /// Credits a failed payout transfer to the receiver deposit, or the seller one if the
+    /// receiver is not registered.
+    #[private]
+    pub fn payout_transfer_resolve(&mut self, seller_id: AccountId, receiver_id: AccountId, amount: U128, operation_id: u64) {
+        self.internal_finish_operation(operation_id, OperationKind::PayoutTransfer, &receiver_id, amount.0);
+        if let PromiseResult::Failed = env::promise_result(0) {
+            let account_id = if self.account_deposits.get(&receiver_id).is_some() {
+                receiver_id
+            } else {
+                seller_id
+            };
+            let mut account_deposit = self.internal_get_account_or_revert(&account_id);
+            account_deposit.available_balance += amount.0;
+            self.account_deposits.insert(&account_id, &account_deposit);
+        }
+    }
+
     // #[payable]
     // pub fn swap_near_for_nfts(
     //     &mut self,
@@ -104,7 +403,7 @@ impl Contract {
         action: &Action,
         cached_token_ids: &mut HashMap<AssetId, HashSet<TokenId>>,
         input_near_value: &Balance,
-    ) -> (Balance, Balance) {
+    ) -> (ActionResult, Balance) {
         let nft_token = self.get_nft_asset_id(action.pool_id);
         match SwapType::from(action.swap_type) {
             SwapType::NFTToNear => {
@@ -128,29 +427,64 @@ impl Contract {
                 }
                 self.internal_withdraw_nft(account_id, &nft_token, &remain_token_ids_to_withdraw);
 
+                let pre_state = CurveState::of(&self.pools[action.pool_id as usize]);
                 let (protocol_fee, output_amount) = self.internal_swap_nfts_for_near(
+                    account_id,
                     action.pool_id,
                     &nft_ids,
                     &action.min_output_near.unwrap().0,
                 );
+                self.assert_compliance(account_id, output_amount);
 
                 let pool = &mut self.pools[action.pool_id as usize];
+                pool.record_trade(false, output_amount, protocol_fee);
+                emit_event("swap", SwapEvent {
+                    pool_id: action.pool_id,
+                    trade_seq: pool.trade_seq,
+                    account_id,
+                    swap_type: action.swap_type,
+                    token_ids: &nft_ids,
+                    near_amount: output_amount.into(),
+                    protocol_fee: protocol_fee.into(),
+                    pre_state,
+                    post_state: CurveState::of(pool),
+                });
                 let asset_recipient = pool.asset_recipient.clone();
+                self.internal_record_activity_in_pool(account_id, ActivityKind::Sell, action.pool_id, nft_ids.len() as u64, output_amount);
+                self.internal_notify_trade_hook(action.pool_id, account_id, action.swap_type, &nft_ids, output_amount);
                 if asset_recipient.clone().is_some() {
                     // near pool, deposit nft tok asset recipient
-                    for token_id in nft_ids {
+                    for token_id in &nft_ids {
                         self.internal_deposit_nft(
                             &asset_recipient.clone().unwrap(),
                             &nft_token,
-                            &token_id,
+                            token_id,
                         );
                     }
                 }
 
-                let remain_near_amount = input_near_value + output_amount;
+                self.internal_record_pool_trade(action.pool_id);
 
-                self.protocol_fee_credit += protocol_fee;
-                (protocol_fee, remain_near_amount)
+                let royalty = self.royalty_amount(&nft_token, output_amount);
+                require!(output_amount - royalty >= action.min_output_near.unwrap().0, "insufficient output after royalty");
+                self.internal_pay_royalty(account_id, &nft_token, royalty);
+                let mut remain_near_amount = input_near_value + output_amount - royalty;
+                if let Some(payout) = &action.payout {
+                    self.internal_payout(account_id, output_amount - royalty, payout);
+                    remain_near_amount = *input_near_value;
+                }
+
+                let protocol_fee_left = self.internal_accrue_referral_fee(action.referral_code.as_ref(), action.pool_id, output_amount, protocol_fee);
+                self.internal_credit_trade_protocol_fee(action.pool_id, protocol_fee_left);
+                let result = ActionResult {
+                    pool_id: action.pool_id,
+                    swap_type: action.swap_type,
+                    token_ids: nft_ids,
+                    near_amount: output_amount.into(),
+                    protocol_fee: protocol_fee.into(),
+                    royalty: royalty.into(),
+                };
+                (result, remain_near_amount)
             }
             SwapType::NearToNFT => {
                 let nft_ids;
@@ -164,9 +498,28 @@ impl Contract {
                 } else {
                     nft_ids = None;
                 }
+                self.assert_spot_price_unchanged(action);
+                // near missing from the running balance is taken from the caller deposit, up to the
+                // amount the action allows
+                let deposit_near = action
+                    .max_deposit_near
+                    .map_or(0, |max| max.0.min(self.internal_available_near(account_id)));
+                let max_near_input = input_near_value + deposit_near;
+                let pre_state = CurveState::of(&self.pools[action.pool_id as usize]);
                 let (protocol_fee, input_amount, token_ids) =
-                    self.internal_swap_near_for_nfts(action.pool_id, nft_ids, action.num_out_nfts.unwrap());
-                self.protocol_fee_credit += protocol_fee;
+                    self.internal_swap_near_for_nfts(account_id, action.pool_id, nft_ids, action.num_out_nfts.unwrap(), max_near_input, action.random_nonce);
+                self.assert_compliance(account_id, input_amount);
+                let protocol_fee_left = self.internal_accrue_referral_fee(action.referral_code.as_ref(), action.pool_id, input_amount, protocol_fee);
+                self.internal_credit_trade_protocol_fee(action.pool_id, protocol_fee_left);
+                let royalty = self.royalty_amount(&nft_token, input_amount);
+                let total_paid = input_amount + royalty;
+                require!(total_paid <= max_near_input, "not enough near payment");
+                let mut remain_near_amount = *input_near_value;
+                if total_paid > remain_near_amount {
+                    self.internal_withdraw_near(account_id, total_paid - remain_near_amount);
+                    remain_near_amount = total_paid;
+                }
+                self.internal_pay_royalty(account_id, &nft_token, royalty);
 
                 let mut token_set = cached_token_ids.get(&nft_token).unwrap_or(&HashSet::new()).clone();
                 for token_id in &token_ids {
@@ -174,53 +527,269 @@ impl Contract {
                 }
                 cached_token_ids.insert(nft_token.clone(), token_set);
 
+                let pool = &mut self.pools[action.pool_id as usize];
+                pool.record_trade(true, input_amount, protocol_fee);
+                emit_event("swap", SwapEvent {
+                    pool_id: action.pool_id,
+                    trade_seq: pool.trade_seq,
+                    account_id,
+                    swap_type: action.swap_type,
+                    token_ids: &token_ids,
+                    near_amount: input_amount.into(),
+                    protocol_fee: protocol_fee.into(),
+                    pre_state,
+                    post_state: CurveState::of(pool),
+                });
+                self.internal_record_pool_trade(action.pool_id);
+                self.internal_record_activity_in_pool(account_id, ActivityKind::Buy, action.pool_id, token_ids.len() as u64, input_amount);
+                self.internal_notify_trade_hook(action.pool_id, account_id, action.swap_type, &token_ids, input_amount);
                 let pool = &self.pools[action.pool_id as usize];
 
                 if pool.asset_recipient.clone().is_some() {
                     //deposit near to asset recipient
                     let mut asset_recipient_deposit =
                         self.internal_get_account_or_revert(&pool.asset_recipient.clone().unwrap());
-                    asset_recipient_deposit.near_balance += input_amount - protocol_fee;
+                    asset_recipient_deposit.available_balance += input_amount - protocol_fee;
                     self.account_deposits.insert(
                         &pool.asset_recipient.clone().unwrap(),
                         &asset_recipient_deposit,
                     );
                 }
-                let remain_near_amount = input_near_value - input_amount;
-                (protocol_fee, remain_near_amount)
+                let result = ActionResult {
+                    pool_id: action.pool_id,
+                    swap_type: action.swap_type,
+                    token_ids,
+                    near_amount: input_amount.into(),
+                    protocol_fee: protocol_fee.into(),
+                    royalty: royalty.into(),
+                };
+                (result, remain_near_amount - total_paid)
             }
         }
     }
 
+    pub fn set_max_swap_actions(&mut self, max_swap_actions: u64) {
+        self.assert_governance();
+        require!(max_swap_actions > 0, "max swap actions must be positive");
+        self.max_swap_actions = max_swap_actions;
+    }
+
+    pub fn get_max_swap_actions(&self) -> u64 {
+        self.max_swap_actions
+    }
+
+    /// Rough upper bound of the gas `swap` needs for the actions: the actions themselves, the
+    /// transfers of the bought nfts unless bought to escrow, the royalty and payout transfers and
+    /// the trade hooks. Clients split batches above the gas they can attach.
+    pub fn estimate_swap_gas(&self, actions: Vec<Action>, buy_to_escrow: Option<bool>) -> U64 {
+        let mut gas = GAS_FOR_SWAP.0;
+        for action in &actions {
+            let pool = self.pools.get(action.pool_id as usize).expect("pool id invalid");
+            gas += GAS_PER_SWAP_ACTION.0;
+            // the royalty transfer and its resolver
+            gas += GAS_FOR_RESOLVE_TRANSFER.0;
+            if pool.on_trade_hook.is_some() {
+                gas += GAS_FOR_TRADE_HOOK.0;
+            }
+            match SwapType::from(action.swap_type) {
+                SwapType::NFTToNear => {
+                    let num_payouts = action.payout.as_ref().map_or(0, |payout| payout.len() as u64);
+                    gas += num_payouts * GAS_FOR_RESOLVE_TRANSFER.0;
+                }
+                SwapType::NearToNFT => {
+                    if !buy_to_escrow.unwrap_or(false) {
+                        // nft_transfer and its resolver
+                        gas += action.num_out_nfts.unwrap_or(0) * 2 * GAS_FOR_NFT_TRANSFER_CALL.0;
+                    }
+                }
+            }
+        }
+        gas.into()
+    }
+
+    /// Executes the given actions in order. When `buy_to_escrow` is set, purchased NFTs are kept
+    /// in the caller's internal deposit instead of being transferred out, and can later be pulled
+    /// with `claim_nfts`. This lets contract buyers avoid handling NFT transfer callbacks.
+    /// Returns what each action executed and the near refunded.
     #[payable]
-    pub fn swap(&mut self, actions: Vec<Action>) {
+    pub fn swap(&mut self, actions: Vec<Action>, buy_to_escrow: Option<bool>) -> SwapResult {
         let account_id = env::predecessor_account_id();
-        let mut remain_near_amount = env::attached_deposit();
-        let mut _protocol_fee = 0u128;
-        let mut cached_token_ids = HashMap::<AssetId, HashSet<TokenId>>::new();
-        let first_action = actions.get(0).unwrap();
-        if first_action.swap_type == SwapType::NFTToNear as u8 {
-            require!(first_action.input_token_ids.len() > 0, "inpput token ids invalid");
+        self.internal_swap(&account_id, &actions, buy_to_escrow.unwrap_or(false), env::attached_deposit(), false)
+    }
+
+    /// Buys up to MAX_BUY_OUT_NFTS of the nfts left in the pool for at most `max_total_near`,
+    /// royalty included, paid with the attached near and then the caller deposit. Reserved and
+    /// flagged nfts are left in the pool. Returns the number of nfts still in the pool.
+    /// With `close_pool`, the owner of an unlocked non-trade pool emptied by the call also
+    /// withdraws its near balance and proceeds, other callers only buying.
+    #[payable]
+    pub fn buy_out_pool(&mut self, pool_id: u64, max_total_near: U128, buy_to_escrow: Option<bool>, close_pool: Option<bool>) -> u64 {
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pool_id);
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.assert_nft_pool();
+        require!(pool.pool_type != PoolType::Token, "token pools hold no nfts");
+        let num_nfts = pool.num_buyable_by_any().min(MAX_BUY_OUT_NFTS);
+        require!(num_nfts > 0, "pool has no nfts to buy");
+        let buy_info = pool.get_buy_info(num_nfts, protocol_fee_multiplier);
+        require!(buy_info.error_code == CurveErrorCode::Ok, "cannot buy out pool at current price");
+        let input_value = expect_u128(buy_info.input_value, "buy out price overflow");
+        let total_near = input_value + self.royalty_amount(&pool.nft_token, input_value);
+        require!(total_near <= max_total_near.0, "buy out exceeds max total near");
+        // only the owner of an unlocked non-trade pool can withdraw its near, others just buy
+        let close_pool = close_pool.unwrap_or(false)
+            && pool.owner == env::predecessor_account_id()
+            && pool.pool_type != PoolType::Trade
+            && pool.released_time <= env::block_timestamp_ms() / 1000;
+
+        self.swap(
+            vec![Action {
+                pool_id,
+                swap_type: SwapType::NearToNFT as u8,
+                min_output_near: None,
+                input_token_ids: vec![],
+                output_token_ids: vec![],
+                num_out_nfts: Some(num_nfts),
+                payout: None,
+                random_nonce: None,
+                expected_spot_price: None,
+                max_price_impact_bps: None,
+                referral_code: None,
+                max_deposit_near: Some(max_total_near),
+            }],
+            buy_to_escrow,
+        );
+
+        let pool = &mut self.pools[pool_id as usize];
+        let num_left = pool.token_ids_in_pools.len();
+        if num_left == 0 && close_pool {
+            let near_amount = pool.withdraw_near(&pool.near_balance.clone()) + pool.claim_proceeds();
+            self.internal_update_pool_activity(pool_id);
+            if near_amount > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(near_amount);
+            }
         }
-        for action in &actions {
-            (_protocol_fee, remain_near_amount) = self.internal_swap_with_action(
-                &account_id,
-                action,
-                &mut cached_token_ids,
-                &remain_near_amount,
-            );
+        num_left
+    }
+
+    /// Sells nfts of `asset_id` held in the caller deposit across the pools of the collection for
+    /// the best proceeds, settled in one `swap`. Fails unless the proceeds after royalty reach
+    /// `min_total_near`. Returns the proceeds.
+    pub fn sell_tokens(&mut self, asset_id: AssetId, token_ids: Vec<TokenId>, min_total_near: U128) -> U128 {
+        require!(!token_ids.is_empty(), "no tokens to sell");
+        require!(token_ids.len() <= MAX_SELL_TOKENS, format!("cannot sell more than {} tokens", MAX_SELL_TOKENS));
+        require!(
+            HashSet::<&TokenId>::from_iter(token_ids.iter()).len() == token_ids.len(),
+            "duplicate token ids"
+        );
+        let account_id = env::predecessor_account_id();
+        let sales = self.plan_token_sale(&account_id, &asset_id, token_ids.len() as u64);
+
+        let mut total_near = 0;
+        let mut actions = vec![];
+        let mut token_ids = token_ids.into_iter();
+        for (pool_id, num_sold, output) in sales {
+            let proceeds = output - self.royalty_amount(&asset_id, output);
+            total_near += proceeds;
+            actions.push(Action {
+                pool_id,
+                swap_type: SwapType::NFTToNear as u8,
+                min_output_near: Some(proceeds.into()),
+                input_token_ids: token_ids.by_ref().take(num_sold as usize).collect(),
+                output_token_ids: vec![],
+                num_out_nfts: None,
+                payout: None,
+                random_nonce: None,
+                expected_spot_price: None,
+                max_price_impact_bps: None,
+                referral_code: None,
+                max_deposit_near: None,
+            });
         }
+        require!(total_near >= min_total_near.0, "sale proceeds below min total near");
 
-        if remain_near_amount > 0 {
-            Promise::new(account_id.clone()).transfer(remain_near_amount);
+        self.swap(actions, None);
+        total_near.into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn test_buy_out_pool_leaves_reserved_and_flagged_tokens() {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        let buyer = account("buyer.near");
+        let facilitator = account("facilitator.near");
+        for account_id in [&governance(), &owner, &buyer, &facilitator] {
+            register(&mut contract, account_id, NEAR);
         }
-        
-        for (nft_token, token_ids) in cached_token_ids.into_iter() {
-            if token_ids.len() > 0 {
-                self.transfer_nfts(&account_id, &nft_token, &Vec::from_iter(token_ids));
-            }
+        let pool_id = create_pool(&mut contract, &owner, PoolType::NFT, &["1", "2", "3", "4"], 0);
+        set_caller(&owner, 0);
+        contract.set_pool_deny_flagged(pool_id, true);
+        set_caller(&governance(), 0);
+        contract.set_flagged_tokens(nft(), token_ids(&["1"]), true);
+        contract.set_reservation_facilitator(facilitator.clone(), true);
+        set_caller(&facilitator, 0);
+        contract.reserve_tokens(pool_id, token_ids(&["2"]), 600);
+
+        set_caller(&buyer, 10 * NEAR);
+        assert_eq!(contract.buy_out_pool(pool_id, U128(10 * NEAR), Some(true), None), 2);
+        let pool = &contract.pools[pool_id as usize];
+        assert!(pool.token_ids_in_pools.contains(&"1".to_string()));
+        assert!(pool.token_ids_in_pools.contains(&"2".to_string()));
+        let bought = contract.account_deposits.get(&buyer).unwrap().assets.get(&nft()).unwrap();
+        assert_eq!(bought.len(), 2);
+        assert!(bought.get(&"3".to_string()).is_some() && bought.get(&"4".to_string()).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "pool has no nfts to buy")]
+    fn test_buy_out_pool_of_reserved_tokens_only() {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        let facilitator = account("facilitator.near");
+        for account_id in [&owner, &facilitator] {
+            register(&mut contract, account_id, NEAR);
         }
+        let pool_id = create_pool(&mut contract, &owner, PoolType::NFT, &["1"], 0);
+        set_caller(&governance(), 0);
+        contract.set_reservation_facilitator(facilitator.clone(), true);
+        set_caller(&facilitator, 0);
+        contract.reserve_tokens(pool_id, token_ids(&["1"]), 600);
 
-        //should not need to check storage here as swap function only works on assets already deposited
+        set_caller(&account("buyer.near"), 10 * NEAR);
+        contract.buy_out_pool(pool_id, U128(10 * NEAR), None, None);
+    }
+
+    #[test]
+    fn test_plan_token_sale_at_owner_trade_protocol_fee() {
+        let mut contract = setup_contract();
+        let owner = account("owner.near");
+        register(&mut contract, &owner, NEAR);
+        // creating a pool withdraws from the deposit of the collection, even without nfts
+        deposit_nfts(&mut contract, &owner, &["1"]);
+        let pool_id = create_pool(&mut contract, &owner, PoolType::Token, &[], 10 * NEAR);
+        set_caller(&governance(), 0);
+        contract.set_protocol_fee_multiplier(U128(10u128.pow(16)));
+        contract.set_owner_trade_protocol_fee(Some(U128(0)));
+
+        let owner_sale = contract.plan_token_sale(&owner, &nft(), 1);
+        let other_sale = contract.plan_token_sale(&account("seller.near"), &nft(), 1);
+        assert_eq!(owner_sale, vec![(pool_id, 1, SPOT_PRICE)]);
+        assert_eq!(other_sale, vec![(pool_id, 1, SPOT_PRICE - SPOT_PRICE / 100)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "payout shares exceed 100%")]
+    fn test_payout_shares_above_total() {
+        let mut contract = setup_contract();
+        let payout = vec![
+            PayoutShare { account_id: account("a.near"), share: U128(WAD) },
+            PayoutShare { account_id: account("b.near"), share: U128(1) },
+        ];
+        contract.internal_payout(&account("seller.near"), NEAR, &payout);
     }
 }
@@ -0,0 +1,132 @@
+//! Set up shared by the unit tests of the contract methods: accounts, calls and pools created
+//! through the public methods.
+use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::testing_env;
+use near_sdk::{PromiseResult, PublicKey, RuntimeFeesConfig, VMConfig};
+use std::convert::TryFrom;
+
+use crate::*;
+
+pub(crate) const NEAR: Balance = 10u128.pow(24);
+// spot price, delta and fee of the pools created by `create_pool`
+pub(crate) const SPOT_PRICE: Balance = NEAR;
+pub(crate) const DELTA: Balance = NEAR / 10;
+pub(crate) const TRADE_FEE: Balance = 10u128.pow(16);
+// ed25519 secret key of the quotes and intents signed by `sign`
+const SIGNER_SECRET: [u8; 32] = [9u8; 32];
+
+pub(crate) fn account(name: &str) -> AccountId {
+    name.parse().unwrap()
+}
+
+pub(crate) fn contract_id() -> AccountId {
+    account("amm.near")
+}
+
+pub(crate) fn governance() -> AccountId {
+    account("governance.near")
+}
+
+pub(crate) fn nft() -> AccountId {
+    account("nft.near")
+}
+
+pub(crate) fn token_ids(ids: &[&str]) -> Vec<TokenId> {
+    ids.iter().map(|id| id.to_string()).collect()
+}
+
+pub(crate) fn signer_public_key() -> PublicKey {
+    let secret = ed25519_dalek::SecretKey::from_bytes(&SIGNER_SECRET).unwrap();
+    let mut key_bytes = vec![0u8];
+    key_bytes.extend_from_slice(ed25519_dalek::PublicKey::from(&secret).as_bytes());
+    PublicKey::try_from(key_bytes).unwrap()
+}
+
+// signature of the borsh serialization of `message`
+pub(crate) fn sign(message: &impl BorshSerialize) -> Base64VecU8 {
+    let secret = ed25519_dalek::SecretKey::from_bytes(&SIGNER_SECRET).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let signature = ed25519_dalek::ExpandedSecretKey::from(&secret).sign(&message.try_to_vec().unwrap(), &public);
+    Base64VecU8(signature.to_bytes().to_vec())
+}
+
+// next call is made by `predecessor_id` attaching `deposit`, the storage and time carried over
+pub(crate) fn set_caller(predecessor_id: &AccountId, deposit: Balance) {
+    testing_env!(VMContextBuilder::new()
+        .current_account_id(contract_id())
+        .signer_account_id(predecessor_id.clone())
+        .predecessor_account_id(predecessor_id.clone())
+        .attached_deposit(deposit)
+        .storage_usage(env::storage_usage())
+        .block_timestamp(env::block_timestamp())
+        .build());
+}
+
+// next call is a callback of the contract on a promise ending with `result`
+pub(crate) fn set_promise_result(result: PromiseResult) {
+    testing_env!(
+        VMContextBuilder::new()
+            .current_account_id(contract_id())
+            .signer_account_id(contract_id())
+            .predecessor_account_id(contract_id())
+            .storage_usage(env::storage_usage())
+            .block_timestamp(env::block_timestamp())
+            .build(),
+        VMConfig::test(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        vec![result]
+    );
+}
+
+// moves the block time `sec` seconds forward, keeping the caller
+pub(crate) fn advance_time_sec(sec: u64) {
+    testing_env!(VMContextBuilder::new()
+        .current_account_id(contract_id())
+        .signer_account_id(env::signer_account_id())
+        .predecessor_account_id(env::predecessor_account_id())
+        .storage_usage(env::storage_usage())
+        .block_timestamp(env::block_timestamp() + sec * 10u64.pow(9))
+        .build());
+}
+
+pub(crate) fn setup_contract() -> Contract {
+    set_caller(&governance(), 0);
+    advance_time_sec(1_000_000);
+    Contract::new(Some(governance()), Some(account("fees.near")), None)
+}
+
+// registers the account with `amount` in its deposit
+pub(crate) fn register(contract: &mut Contract, account_id: &AccountId, amount: Balance) {
+    set_caller(account_id, amount);
+    contract.storage_deposit(None, None);
+}
+
+// transfers the nfts of `nft()` from the owner to its deposit
+pub(crate) fn deposit_nfts(contract: &mut Contract, owner: &AccountId, ids: &[&str]) {
+    set_caller(&nft(), 0);
+    for token_id in token_ids(ids) {
+        contract.nft_on_transfer(owner.clone(), owner.clone(), token_id, String::new());
+    }
+}
+
+// creates a linear pool of `nft()` with the nfts, deposited on the way, and `near_amount`
+pub(crate) fn create_pool(contract: &mut Contract, owner: &AccountId, pool_type: PoolType, ids: &[&str], near_amount: Balance) -> u64 {
+    deposit_nfts(contract, owner, ids);
+    let fee = if pool_type == PoolType::Trade { TRADE_FEE } else { 0 };
+    set_caller(owner, near_amount);
+    contract.create_pair(
+        pool_type as u8,
+        BondingCurve::LinearCurve as u8,
+        nft(),
+        U128(SPOT_PRICE),
+        U128(DELTA),
+        U128(fee),
+        None,
+        token_ids(ids),
+        0,
+    )
+}
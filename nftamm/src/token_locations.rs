@@ -0,0 +1,94 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+pub(crate) const ERR_TOKEN_LOCATION_CONFLICT: &str = "E_TOKEN_LOCATION_CONFLICT: token already held elsewhere";
+
+// where the contract holds a (collection, token id)
+#[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TokenLocation {
+    AccountDeposit(AccountId),
+    Pool(u64),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenLocationReport {
+    pub indexed: Option<TokenLocation>,
+    // pools of the collection holding the token, and the indexed account if it holds it
+    pub holders: Vec<TokenLocation>,
+    pub consistent: bool,
+}
+
+// location of each (collection, token id), shared by the pools and the account deposits with
+// `Contract::token_locations`
+fn token_location_index() -> LookupMap<(AssetId, TokenId), TokenLocation> {
+    LookupMap::new(StorageKey::TokenPools)
+}
+
+// records the token at `location`, where the contract now holds it. Reached from callbacks, so a
+// token recorded elsewhere is logged rather than rejected, deposits rejecting such tokens up front
+// with `assert_token_not_held`
+pub(crate) fn insert_token_location(asset_id: &AssetId, token_id: &TokenId, location: TokenLocation) {
+    let key = (asset_id.clone(), token_id.clone());
+    let mut index = token_location_index();
+    if let Some(existing) = index.insert(&key, &location) {
+        if existing != location {
+            log!("{} ({} {})", ERR_TOKEN_LOCATION_CONFLICT, asset_id, token_id);
+        }
+    }
+}
+
+// forgets the token if recorded at `location`, tokens held before the index having no entry
+pub(crate) fn remove_token_location(asset_id: &AssetId, token_id: &TokenId, location: &TokenLocation) {
+    let key = (asset_id.clone(), token_id.clone());
+    let mut index = token_location_index();
+    if index.get(&key).as_ref() == Some(location) {
+        index.remove(&key);
+    }
+}
+
+impl Contract {
+    // panics if the contract already holds the token, for deposits outside of callbacks
+    pub(crate) fn assert_token_not_held(&self, asset_id: &AssetId, token_id: &TokenId) {
+        require!(
+            self.token_locations.get(&(asset_id.clone(), token_id.clone())).is_none(),
+            format!("{} ({} {})", ERR_TOKEN_LOCATION_CONFLICT, asset_id, token_id)
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_token_location(&self, asset_id: AssetId, token_id: TokenId) -> Option<TokenLocation> {
+        self.token_locations.get(&(asset_id, token_id))
+    }
+
+    /// Compares the indexed location of the token with the pools of its collection and the
+    /// indexed account deposit actually holding it.
+    pub fn reconcile_token_location(&self, asset_id: AssetId, token_id: TokenId) -> TokenLocationReport {
+        let indexed = self.token_locations.get(&(asset_id.clone(), token_id.clone()));
+        let mut holders: Vec<TokenLocation> = self
+            .pools
+            .iter()
+            .filter(|pool| pool.nft_token == asset_id && pool.token_ids_in_pools.contains(&token_id))
+            .map(|pool| TokenLocation::Pool(pool.pool_id))
+            .collect();
+        if let Some(TokenLocation::AccountDeposit(account_id)) = &indexed {
+            let deposited = self
+                .account_deposits
+                .get(account_id)
+                .and_then(|account_deposit| account_deposit.assets.get(&asset_id))
+                .map_or(false, |token_ids| token_ids.get(&token_id).is_some());
+            if deposited {
+                holders.push(TokenLocation::AccountDeposit(account_id.clone()));
+            }
+        }
+        let consistent = match &indexed {
+            Some(location) => holders.len() == 1 && &holders[0] == location,
+            None => holders.is_empty(),
+        };
+        TokenLocationReport { indexed, holders, consistent }
+    }
+}
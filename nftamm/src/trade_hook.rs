@@ -0,0 +1,57 @@
+use crate::*;
+use near_sdk::{ext_contract, near_bindgen};
+
+// gas of the trade notification, paid by the trader
+pub(crate) const GAS_FOR_TRADE_HOOK: Gas = Gas(5_000_000_000_000);
+
+#[ext_contract(ext_trade_hook)]
+pub trait TradeHook {
+    fn on_trade(
+        &mut self,
+        pool_id: u64,
+        trade_seq: u64,
+        account_id: AccountId,
+        swap_type: u8,
+        token_ids: Vec<TokenId>,
+        near_amount: U128,
+    );
+}
+
+impl Contract {
+    // notifies the pool trade hook of a swap, without callback so that a failing or missing hook
+    // contract never reverts the trade
+    pub(crate) fn internal_notify_trade_hook(
+        &self,
+        pool_id: u64,
+        account_id: &AccountId,
+        swap_type: u8,
+        token_ids: &[TokenId],
+        near_amount: Balance,
+    ) {
+        let pool = &self.pools[pool_id as usize];
+        if let Some(hook_id) = pool.on_trade_hook.clone() {
+            ext_trade_hook::ext(hook_id)
+                .with_static_gas(GAS_FOR_TRADE_HOOK)
+                .on_trade(pool_id, pool.trade_seq, account_id.clone(), swap_type, token_ids.to_vec(), near_amount.into());
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the contract notified with `on_trade` after each swap with the pool, none removes it.
+    /// Notifications are best effort, their failure does not affect the trade.
+    pub fn set_pool_trade_hook(&mut self, pool_id: u64, hook_id: Option<AccountId>) {
+        let prev_storage = env::storage_usage();
+        let account_id = env::predecessor_account_id();
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        pool.on_trade_hook = hook_id;
+        self.assert_storage(&account_id, prev_storage, None);
+    }
+
+    pub fn get_pool_trade_hook(&self, pool_id: u64) -> Option<AccountId> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.on_trade_hook.clone()
+    }
+}
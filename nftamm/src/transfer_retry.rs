@@ -0,0 +1,240 @@
+use crate::*;
+use crate::events::emit_event;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// attempts of a transfer before its nft goes to the refund account deposit
+const MAX_TRANSFER_ATTEMPTS: u32 = 3;
+// most transfers retried by one call, each with its resolver
+const MAX_RETRIED_TRANSFERS: u64 = 4;
+
+/// Outbound nft transfer that failed, held by the contract until retried or claimed.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FailedTransfer {
+    pub transfer_id: u64,
+    pub receiver_id: AccountId,
+    // owner of the nft while the transfer is pending, getting it in its deposit after the
+    // last attempt
+    pub refund_id: AccountId,
+    pub asset_id: AssetId,
+    pub token_id: TokenId,
+    pub attempts: u32,
+    // in seconds
+    pub failed_at: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FailedTransferEvent<'a> {
+    pub transfer_id: Option<u64>,
+    pub receiver_id: &'a AccountId,
+    pub asset_id: &'a AssetId,
+    pub token_id: &'a TokenId,
+    pub attempts: u32,
+    // the nft went to the deposit of the refund account instead of the queue
+    pub deposited: bool,
+}
+
+impl Contract {
+    pub(crate) fn internal_queue_failed_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        refund_id: AccountId,
+        asset_id: AssetId,
+        token_id: TokenId,
+        attempts: u32,
+    ) {
+        if attempts >= MAX_TRANSFER_ATTEMPTS {
+            self.internal_deposit_nft(&refund_id, &asset_id, &token_id);
+            emit_event("nft_transfer_failed", FailedTransferEvent {
+                transfer_id: None,
+                receiver_id: &receiver_id,
+                asset_id: &asset_id,
+                token_id: &token_id,
+                attempts,
+                deposited: true,
+            });
+            return;
+        }
+        let transfer_id = self.next_failed_transfer_id;
+        self.next_failed_transfer_id += 1;
+        emit_event("nft_transfer_failed", FailedTransferEvent {
+            transfer_id: Some(transfer_id),
+            receiver_id: &receiver_id,
+            asset_id: &asset_id,
+            token_id: &token_id,
+            attempts,
+            deposited: false,
+        });
+        self.failed_transfers.insert(&transfer_id, &FailedTransfer {
+            transfer_id,
+            receiver_id,
+            refund_id,
+            asset_id,
+            token_id,
+            attempts,
+            failed_at: env::block_timestamp_ms() / 1000,
+        });
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Retries up to `limit` failed transfers, oldest first. Callable by anyone, the caller
+    /// paying the gas. Returns the ids of the retried transfers.
+    pub fn retry_failed_transfers(&mut self, limit: Option<u64>) -> Vec<u64> {
+        let limit = limit.unwrap_or(MAX_RETRIED_TRANSFERS).min(MAX_RETRIED_TRANSFERS);
+        let transfers: Vec<FailedTransfer> = self.failed_transfers.values().take(limit as usize).collect();
+        for transfer in &transfers {
+            self.failed_transfers.remove(&transfer.transfer_id);
+            self.internal_transfer_nft(
+                &transfer.receiver_id,
+                &transfer.refund_id,
+                &transfer.asset_id,
+                &transfer.token_id,
+                transfer.attempts + 1,
+            );
+        }
+        transfers.iter().map(|transfer| transfer.transfer_id).collect()
+    }
+
+    /// Moves the nft of a failed transfer to the deposit of the caller, its refund account,
+    /// which pays the storage.
+    pub fn claim_failed_transfer(&mut self, transfer_id: u64) {
+        let account_id = env::predecessor_account_id();
+        let transfer = self.failed_transfers.get(&transfer_id).unwrap_or_else(|| env::panic_str("failed transfer not found"));
+        require!(transfer.refund_id == account_id, "only the refund account");
+        let prev_storage = env::storage_usage();
+        self.failed_transfers.remove(&transfer_id);
+        self.internal_deposit_nft(&account_id, &transfer.asset_id, &transfer.token_id);
+        self.assert_storage(&account_id, prev_storage, None);
+    }
+
+    pub fn get_failed_transfer_count(&self) -> u64 {
+        self.failed_transfers.len()
+    }
+
+    /// Failed transfers to or refunded to the account among `[from_index, from_index + limit)`
+    /// of the queue.
+    pub fn get_failed_transfers(&self, account_id: AccountId, from_index: Option<u64>, limit: Option<u64>) -> Vec<FailedTransfer> {
+        self.failed_transfers
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .filter(|transfer| transfer.receiver_id == account_id || transfer.refund_id == account_id)
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use near_sdk::PromiseResult;
+
+    fn receiver() -> AccountId {
+        account("receiver.near")
+    }
+
+    fn refund() -> AccountId {
+        account("refund.near")
+    }
+
+    fn setup_transfer() -> Contract {
+        let mut contract = setup_contract();
+        register(&mut contract, &refund(), NEAR);
+        contract
+    }
+
+    // transfer of nft 1 to the receiver on behalf of the refund account, `attempts` counting
+    // it, failing
+    fn fail_transfer(contract: &mut Contract, attempts: u32) {
+        let operation_id = contract.next_operation_id;
+        contract.internal_transfer_nft(&receiver(), &refund(), &nft(), &"1".to_string(), attempts);
+        set_promise_result(PromiseResult::Failed);
+        contract.nft_transfer_resolve(refund(), nft(), "1".to_string(), operation_id, Some(receiver()), Some(attempts));
+    }
+
+    fn has_deposited_nft(contract: &Contract, account_id: &AccountId) -> bool {
+        let account_deposit = contract.account_deposits.get(account_id).unwrap();
+        account_deposit.assets.get(&nft()).map_or(false, |token_ids| token_ids.get(&"1".to_string()).is_some())
+    }
+
+    #[test]
+    fn test_failed_transfer_queued() {
+        let mut contract = setup_transfer();
+        fail_transfer(&mut contract, 1);
+        let transfers = contract.get_failed_transfers(receiver(), None, None);
+        assert_eq!(transfers.len(), 1);
+        assert_eq!((transfers[0].transfer_id, transfers[0].attempts), (0, 1));
+        assert_eq!(transfers[0].refund_id, refund());
+        assert!(!has_deposited_nft(&contract, &refund()));
+    }
+
+    #[test]
+    fn test_successful_transfer_not_queued() {
+        let mut contract = setup_transfer();
+        contract.internal_transfer_nft(&receiver(), &refund(), &nft(), &"1".to_string(), 1);
+        set_promise_result(PromiseResult::Successful(vec![]));
+        contract.nft_transfer_resolve(refund(), nft(), "1".to_string(), 0, Some(receiver()), Some(1));
+        assert_eq!(contract.get_failed_transfer_count(), 0);
+        assert!(contract.pending_operations.get(&0).is_none());
+    }
+
+    #[test]
+    fn test_failed_legacy_transfer_deposited() {
+        let mut contract = setup_transfer();
+        let operation_id = contract.internal_start_operation(OperationKind::NftTransfer, &refund(), 1);
+        set_promise_result(PromiseResult::Failed);
+        contract.nft_transfer_resolve(refund(), nft(), "1".to_string(), operation_id, None, None);
+        assert_eq!(contract.get_failed_transfer_count(), 0);
+        assert!(has_deposited_nft(&contract, &refund()));
+    }
+
+    #[test]
+    fn test_failed_transfer_retried() {
+        let mut contract = setup_transfer();
+        fail_transfer(&mut contract, 1);
+
+        set_caller(&account("keeper.near"), 0);
+        assert_eq!(contract.retry_failed_transfers(None), vec![0]);
+        assert_eq!(contract.get_failed_transfer_count(), 0);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == nft()));
+
+        // the retry failing too is queued again with its attempt counted
+        set_promise_result(PromiseResult::Failed);
+        contract.nft_transfer_resolve(refund(), nft(), "1".to_string(), 1, Some(receiver()), Some(2));
+        let transfers = contract.get_failed_transfers(refund(), None, None);
+        assert_eq!((transfers[0].transfer_id, transfers[0].attempts), (1, 2));
+    }
+
+    #[test]
+    fn test_failed_last_attempt_deposited() {
+        let mut contract = setup_transfer();
+        fail_transfer(&mut contract, MAX_TRANSFER_ATTEMPTS);
+        assert_eq!(contract.get_failed_transfer_count(), 0);
+        assert!(has_deposited_nft(&contract, &refund()));
+    }
+
+    #[test]
+    fn test_claim_failed_transfer() {
+        let mut contract = setup_transfer();
+        fail_transfer(&mut contract, 1);
+        set_caller(&refund(), 0);
+        contract.claim_failed_transfer(0);
+        assert_eq!(contract.get_failed_transfer_count(), 0);
+        assert!(has_deposited_nft(&contract, &refund()));
+    }
+
+    #[test]
+    #[should_panic(expected = "only the refund account")]
+    fn test_claim_failed_transfer_by_receiver() {
+        let mut contract = setup_transfer();
+        register(&mut contract, &receiver(), NEAR);
+        fail_transfer(&mut contract, 1);
+        set_caller(&receiver(), 0);
+        contract.claim_failed_transfer(0);
+    }
+}
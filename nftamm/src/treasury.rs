@@ -0,0 +1,244 @@
+use crate::*;
+use crate::events::emit_event;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+// timelock of treasury operations until governance raises it
+pub(crate) const DEFAULT_TREASURY_DELAY_SEC: u64 = 2 * 24 * 3600;
+
+/// Protocol owned liquidity is held as lp of the contract account, funded from and withdrawn
+/// to the near protocol fee credit.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TreasuryAction {
+    // adds near of the protocol fee credit to the pool
+    Deploy { pool_id: u64, near_amount: U128 },
+    // burns treasury lp of `from_pool` into `to_pool` of the same collection
+    Rebalance { from_pool: u64, to_pool: u64, lp: U128 },
+    // burns treasury lp, the near going back to the protocol fee credit and the nfts to governance
+    Withdraw { pool_id: u64, lp: U128 },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryOperation {
+    pub operation_id: u64,
+    pub action: TreasuryAction,
+    // in seconds
+    pub executable_at: u64,
+}
+
+impl Contract {
+    fn treasury_id(&self) -> AccountId {
+        env::current_account_id()
+    }
+
+    // burns treasury lp, returning the near and nfts redeemed. The treasury pays no protocol fee
+    // to itself, that fee not being taken from the redeemed near.
+    fn internal_treasury_burn(&mut self, pool_id: u64, lp: Balance) -> (Balance, Vec<TokenId>) {
+        let treasury_id = self.treasury_id();
+        let pool = self.pools.get_mut(pool_id as usize).expect("pool id invalid");
+        let (_, near_amount, token_ids) = pool.burn_lp(&treasury_id, lp, 0, false, &[]);
+        self.internal_update_pool_activity(pool_id);
+        (near_amount, token_ids)
+    }
+
+    fn internal_execute_treasury_action(&mut self, action: &TreasuryAction) {
+        let treasury_id = self.treasury_id();
+        match action {
+            TreasuryAction::Deploy { pool_id, near_amount } => {
+                require!(near_amount.0 <= self.protocol_fee_credit, "not enough protocol fee credit");
+                self.protocol_fee_credit -= near_amount.0;
+                let lp = self.internal_mint_liquidity(&treasury_id, *pool_id, &[], near_amount.0, &[]);
+                require!(lp > 0, "deployed liquidity mints no lp");
+            }
+            TreasuryAction::Rebalance { from_pool, to_pool, lp } => {
+                let (near_amount, token_ids) = self.internal_treasury_burn(*from_pool, lp.0);
+                self.internal_mint_liquidity(&treasury_id, *to_pool, &token_ids, near_amount, &[]);
+            }
+            TreasuryAction::Withdraw { pool_id, lp } => {
+                let (near_amount, token_ids) = self.internal_treasury_burn(*pool_id, lp.0);
+                self.protocol_fee_credit += near_amount;
+                if !token_ids.is_empty() {
+                    let nft_token = self.get_nft_asset_id(*pool_id);
+                    let governance_id = self.governance_id.clone();
+                    self.transfer_nfts(&governance_id, &nft_token, &token_ids);
+                }
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Queues a treasury action, executable by anyone once the treasury delay passed.
+    /// Returns the operation id.
+    pub fn queue_treasury_operation(&mut self, action: TreasuryAction) -> u64 {
+        self.assert_governance();
+        match &action {
+            TreasuryAction::Deploy { pool_id, .. } | TreasuryAction::Withdraw { pool_id, .. } => {
+                self.get_nft_asset_id(*pool_id);
+            }
+            TreasuryAction::Rebalance { from_pool, to_pool, .. } => {
+                require!(from_pool != to_pool, "cannot rebalance to the same pool");
+                require!(
+                    self.get_nft_asset_id(*from_pool) == self.get_nft_asset_id(*to_pool),
+                    "pools trade different collections"
+                );
+            }
+        }
+        let operation_id = self.next_treasury_operation_id;
+        self.next_treasury_operation_id += 1;
+        let operation = TreasuryOperation {
+            operation_id,
+            action,
+            executable_at: env::block_timestamp_ms() / 1000 + self.treasury_delay,
+        };
+        self.treasury_operations.insert(&operation_id, &operation);
+        emit_event("treasury_queue", &operation);
+        operation_id
+    }
+
+    pub fn execute_treasury_operation(&mut self, operation_id: u64) {
+        let operation = self
+            .treasury_operations
+            .remove(&operation_id)
+            .unwrap_or_else(|| env::panic_str("unknown treasury operation"));
+        require!(operation.executable_at <= env::block_timestamp_ms() / 1000, "treasury operation not executable yet");
+        self.internal_execute_treasury_action(&operation.action);
+        emit_event("treasury_execute", &operation);
+    }
+
+    pub fn cancel_treasury_operation(&mut self, operation_id: u64) {
+        self.assert_governance();
+        let operation = self
+            .treasury_operations
+            .remove(&operation_id)
+            .unwrap_or_else(|| env::panic_str("unknown treasury operation"));
+        emit_event("treasury_cancel", &operation);
+    }
+
+    /// The delay can only be increased, so it cannot be bypassed by governance.
+    pub fn set_treasury_delay(&mut self, delay_sec: u64) {
+        self.assert_governance();
+        require!(delay_sec >= self.treasury_delay, "delay can only be increased");
+        self.treasury_delay = delay_sec;
+    }
+
+    pub fn get_treasury_delay(&self) -> u64 {
+        self.treasury_delay
+    }
+
+    pub fn get_treasury_operations(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<TreasuryOperation> {
+        self.treasury_operations
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(100) as usize)
+            .collect()
+    }
+
+    /// Treasury lp of pools `[from_index, from_index + limit)` holding some, by pool id.
+    pub fn get_protocol_owned_liquidity(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<(u64, U128)> {
+        let treasury_id = self.treasury_id();
+        self.pools
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .filter_map(|pool| {
+                let lp = pool.lp_balances.get(&treasury_id).unwrap_or(0);
+                (lp > 0).then(|| (pool.pool_id, lp.into()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::{self, NEAR};
+
+    // nft pool of nfts 1 and 2 without near, so without lp
+    fn setup_pool() -> (Contract, u64) {
+        let mut contract = testing::setup_contract();
+        let owner = testing::account("owner.near");
+        testing::register(&mut contract, &owner, NEAR);
+        let pool_id = testing::create_pool(&mut contract, &owner, PoolType::NFT, &["1", "2"], 0);
+        contract.protocol_fee_credit = 3 * NEAR;
+        (contract, pool_id)
+    }
+
+    fn execute(contract: &mut Contract, action: TreasuryAction) {
+        testing::set_caller(&testing::governance(), 0);
+        let operation_id = contract.queue_treasury_operation(action);
+        testing::advance_time_sec(DEFAULT_TREASURY_DELAY_SEC);
+        testing::set_caller(&testing::account("anyone.near"), 0);
+        contract.execute_treasury_operation(operation_id);
+    }
+
+    #[test]
+    fn test_deploy_and_withdraw() {
+        let (mut contract, pool_id) = setup_pool();
+
+        execute(&mut contract, TreasuryAction::Deploy { pool_id, near_amount: U128(2 * NEAR) });
+        assert_eq!(contract.protocol_fee_credit, NEAR);
+        assert_eq!(contract.pools[pool_id as usize].near_balance, 2 * NEAR);
+        assert_eq!(contract.get_protocol_owned_liquidity(None, None), vec![(pool_id, U128(2 * NEAR))]);
+
+        // half the lp redeems 1 of the 2 nfts and its spot price in near, fee free
+        execute(&mut contract, TreasuryAction::Withdraw { pool_id, lp: U128(NEAR) });
+        assert_eq!(contract.protocol_fee_credit, 2 * NEAR);
+        let pool = &contract.pools[pool_id as usize];
+        assert_eq!(pool.near_balance, NEAR);
+        assert_eq!(pool.token_ids_in_pools.len(), 1);
+        assert_eq!(contract.get_protocol_owned_liquidity(None, None), vec![(pool_id, U128(NEAR))]);
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == testing::nft()));
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough protocol fee credit")]
+    fn test_deploy_above_credit() {
+        let (mut contract, pool_id) = setup_pool();
+        execute(&mut contract, TreasuryAction::Deploy { pool_id, near_amount: U128(4 * NEAR) });
+    }
+
+    #[test]
+    #[should_panic(expected = "only governance")]
+    fn test_queue_by_non_governance() {
+        let (mut contract, pool_id) = setup_pool();
+        testing::set_caller(&testing::account("anyone.near"), 0);
+        contract.queue_treasury_operation(TreasuryAction::Deploy { pool_id, near_amount: U128(NEAR) });
+    }
+
+    #[test]
+    #[should_panic(expected = "treasury operation not executable yet")]
+    fn test_execute_before_delay() {
+        let (mut contract, pool_id) = setup_pool();
+        testing::set_caller(&testing::governance(), 0);
+        let operation_id = contract.queue_treasury_operation(TreasuryAction::Deploy { pool_id, near_amount: U128(NEAR) });
+        testing::advance_time_sec(DEFAULT_TREASURY_DELAY_SEC - 1);
+        contract.execute_treasury_operation(operation_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown treasury operation")]
+    fn test_execute_cancelled() {
+        let (mut contract, pool_id) = setup_pool();
+        testing::set_caller(&testing::governance(), 0);
+        let operation_id = contract.queue_treasury_operation(TreasuryAction::Deploy { pool_id, near_amount: U128(NEAR) });
+        contract.cancel_treasury_operation(operation_id);
+        assert!(contract.get_treasury_operations(None, None).is_empty());
+        testing::advance_time_sec(DEFAULT_TREASURY_DELAY_SEC);
+        contract.execute_treasury_operation(operation_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "delay can only be increased")]
+    fn test_lower_treasury_delay() {
+        let (mut contract, _) = setup_pool();
+        testing::set_caller(&testing::governance(), 0);
+        contract.set_treasury_delay(DEFAULT_TREASURY_DELAY_SEC + 1);
+        assert_eq!(contract.get_treasury_delay(), DEFAULT_TREASURY_DELAY_SEC + 1);
+        contract.set_treasury_delay(DEFAULT_TREASURY_DELAY_SEC);
+    }
+}
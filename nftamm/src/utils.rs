@@ -12,7 +12,11 @@ impl Contract {
         account_id: near_sdk::AccountId,
         asset_id: AssetId,
         token_id: TokenId,
+        operation_id: u64,
+        receiver_id: Option<AccountId>,
+        attempts: Option<u32>,
     ) {
+        self.internal_finish_operation(operation_id, OperationKind::NftTransfer, &account_id, 1);
         // assert_eq!(
         //     env::promise_results_count(),
         //     1,
@@ -23,8 +27,11 @@ impl Contract {
             PromiseResult::NotReady => unreachable!(),
             PromiseResult::Successful(_) => {}
             PromiseResult::Failed => {
-                //insert nft into user accoutn deposit
-                self.internal_deposit_nft(&account_id, &asset_id, &token_id);
+                // transfers started before the retry queue have no receiver, their nft goes to the deposit
+                match receiver_id {
+                    Some(receiver_id) => self.internal_queue_failed_transfer(receiver_id, account_id, asset_id, token_id, attempts.unwrap_or(1)),
+                    None => self.internal_deposit_nft(&account_id, &asset_id, &token_id),
+                }
             }
         };
     }
@@ -35,17 +42,49 @@ impl Contract {
         &mut self,
         receiver_id: &AccountId,
         asset_id: &AssetId,
-        token_ids: &Vec<TokenId>,
+        token_ids: &[TokenId],
+    ) {
+        self.transfer_nfts_with_refund(receiver_id, receiver_id, asset_id, token_ids);
+    }
+
+    /// Same as `transfer_nfts`, but NFTs whose transfer fails are queued for retry on behalf of
+    /// `refund_id`, see `retry_failed_transfers`.
+    pub(crate) fn transfer_nfts_with_refund(
+        &mut self,
+        receiver_id: &AccountId,
+        refund_id: &AccountId,
+        asset_id: &AssetId,
+        token_ids: &[TokenId],
     ) {
         for token_id in token_ids {
-            let this_contract = env::current_account_id();
-            ext_nft_core::ext(asset_id.clone())
-                .with_static_gas(GAS_FOR_NFT_TRANSFER_CALL)
-                .with_attached_deposit(1)
-                .nft_transfer(receiver_id.clone(), token_id.clone(), None, None)
-                .then(ext_self::ext(this_contract)
-                    .with_static_gas(GAS_FOR_NFT_TRANSFER_CALL)
-                    .nft_transfer_resolve(receiver_id.clone(), asset_id.clone(), token_id.clone()));
+            self.internal_transfer_nft(receiver_id, refund_id, asset_id, token_id, 1);
         }
     }
+
+    // `attempts` counts this transfer
+    pub(crate) fn internal_transfer_nft(
+        &mut self,
+        receiver_id: &AccountId,
+        refund_id: &AccountId,
+        asset_id: &AssetId,
+        token_id: &TokenId,
+        attempts: u32,
+    ) {
+        let this_contract = env::current_account_id();
+        let operation_id = self.internal_start_operation(OperationKind::NftTransfer, refund_id, 1);
+        ext_nft_core::ext(asset_id.clone())
+            .with_static_gas(GAS_FOR_NFT_TRANSFER_CALL)
+            .with_attached_deposit(1)
+            .nft_transfer(receiver_id.clone(), token_id.clone(), None, None)
+            .then(ext_self::ext(this_contract)
+                .with_static_gas(GAS_FOR_NFT_TRANSFER_CALL)
+                .nft_transfer_resolve(
+                    refund_id.clone(),
+                    asset_id.clone(),
+                    token_id.clone(),
+                    operation_id,
+                    Some(receiver_id.clone()),
+                    Some(attempts),
+                ));
+    }
 }
@@ -0,0 +1,71 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+use crate::*;
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VerifiedCollection {
+    // max royalty (base 1e18) the collection is expected to charge, informational only
+    pub royalty_cap: Option<U128>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn verify_collection(&mut self, asset_id: AssetId, royalty_cap: Option<U128>) {
+        self.assert_governance();
+        if let Some(royalty_cap) = royalty_cap {
+            require!(royalty_cap.0 <= WAD, "royalty cap exceeds 100%");
+        }
+        self.verified_collections
+            .insert(&asset_id, &VerifiedCollection { royalty_cap });
+    }
+
+    pub fn unverify_collection(&mut self, asset_id: AssetId) {
+        self.assert_governance();
+        self.verified_collections.remove(&asset_id);
+    }
+
+    /// When enabled, `create_pair` only accepts collections from the verified registry.
+    pub fn set_only_verified_collections(&mut self, enabled: bool) {
+        self.assert_governance();
+        self.only_verified_collections = enabled;
+    }
+
+    pub fn is_collection_verified(&self, asset_id: AssetId) -> bool {
+        self.verified_collections.get(&asset_id).is_some()
+    }
+
+    pub fn get_verified_collection(&self, asset_id: AssetId) -> Option<VerifiedCollection> {
+        self.verified_collections.get(&asset_id)
+    }
+
+    pub fn get_verified_collections(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<(AssetId, VerifiedCollection)> {
+        let from = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(u64::MAX);
+        require!(limit != 0, "Cannot provide limit of 0.");
+
+        self.verified_collections
+            .iter()
+            .skip(from as usize)
+            .take(limit as usize)
+            .collect()
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_collection_allowed(&self, asset_id: &AssetId) {
+        if self.only_verified_collections {
+            require!(
+                self.verified_collections.get(asset_id).is_some(),
+                "collection not verified"
+            );
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use near_contract_standards::non_fungible_token::TokenId;
 use near_sdk::{serde::{Serialize, Deserialize}};
 
-use crate::{*, pair::{PoolType}, curves::{errorcodes::CurveErrorCode, curve::BondingCurve, BuyInfo}};
+use crate::{*, swap::SwapType, virtual_reserves::VirtualReserves, pair::{self, PoolType, PoolParamError}, curves::{errorcodes::CurveErrorCode, curve::{BondingCurve, Curve}, BuyInfo, checked_as_u128, U256, WAD}};
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -19,8 +19,43 @@ pub struct PairInfo {
     // Otherwise, assets will be sent to the set address. Not available for TRADE pools
     pub asset_recipient: Option<AccountId>,
     pub near_balance: U128,
-    pub pool_token_ids: Vec<TokenId>,
-    pub pool_id: u64
+    pub proceeds: U128,
+    pub num_nfts: u64,
+    // only filled when requested, as pools can hold many tokens
+    pub pool_token_ids: Option<Vec<TokenId>>,
+    pub pool_id: u64,
+    pub verified: bool,
+    pub trade_seq: u64,
+    // token id held by NEP-245 pools, whose num_nfts is the held amount
+    pub mt_token_id: Option<TokenId>,
+    pub gated: bool,
+    pub max_nfts: Option<u64>,
+    pub max_near: Option<U128>,
+    pub virtual_reserves: Option<VirtualReserves>,
+    // nfts bought from sellers, the rest of num_nfts being lp deposits
+    pub num_acquired_nfts: u64,
+    pub lp_supply: U128,
+    // lp locked on the first mint, never redeemable
+    pub locked_lp: U128,
+    // none when sells use delta and fee
+    pub sell_delta: Option<U128>,
+    pub sell_fee: Option<U128>,
+    // approved by the official account of the collection
+    pub official: bool,
+    pub buys_enabled: bool,
+    pub sells_enabled: bool,
+    // trades once its bootstrap auction is settled
+    pub bootstrapping: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolFilter {
+    pub pool_type: Option<u8>,
+    pub curve_type: Option<u8>,
+    pub collection: Option<AssetId>,
+    // near balance plus nfts at spot price
+    pub min_liquidity: Option<U128>,
 }
 
 #[near_bindgen]
@@ -32,6 +67,8 @@ pub struct BuyInfoPublic {
     pub new_delta: U128,
     pub input_value: U128,
     pub protocol_fee: U128,
+    // collection royalty paid on top of input_value
+    pub royalty: U128,
 }
 
 #[near_bindgen]
@@ -46,6 +83,10 @@ pub struct MetaData {
     pub storage_per_account_creation: StorageUsage,
     pub storage_per_nft_deposit: StorageUsage,
     pub storage_per_pair_creation: StorageUsage,
+    pub early_unlock_penalty: Option<U128>,
+    pub only_verified_collections: bool,
+    // indexed by pool type, none falls back to protocol_fee_multiplier
+    pub pool_type_protocol_fees: Vec<Option<U128>>,
 }
 
 #[near_bindgen]
@@ -57,6 +98,30 @@ pub struct SellInfoPublic {
     pub new_delta: U128,
     pub output_value: U128,
     pub protocol_fee: U128,
+    // collection royalty deducted from output_value
+    pub royalty: U128,
+}
+
+/// `BuyInfoPublic` of a pool for `num_items`, as of `timestamp_sec`.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BuyQuote {
+    pub pool_id: u64,
+    pub num_items: u64,
+    pub timestamp_sec: u64,
+    #[serde(flatten)]
+    pub info: BuyInfoPublic,
+}
+
+/// `SellInfoPublic` of a pool for `num_items`, as of `timestamp_sec`.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SellQuote {
+    pub pool_id: u64,
+    pub num_items: u64,
+    pub timestamp_sec: u64,
+    #[serde(flatten)]
+    pub info: SellInfoPublic,
 }
 
 #[near_bindgen]
@@ -64,55 +129,401 @@ pub struct SellInfoPublic {
 #[serde(crate = "near_sdk::serde")]
 pub struct AccountInfo {
     pub deposits: HashMap<AssetId, Vec<TokenId>>,
-    pub near_balance: U128,
+    // near covering the storage of the account, and the near it can trade and withdraw
+    pub storage_reserve: U128,
+    pub available_balance: U128,
     pub storage_usage: StorageUsage
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountSummary {
+    pub account_id: AccountId,
+    pub num_assets: u64,
+    pub num_tokens: u64,
+    pub storage_reserve: U128,
+    pub available_balance: U128,
+    pub storage_usage: StorageUsage,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RemoveLiquidityPreview {
+    pub token_ids: Vec<TokenId>,
+    pub near_amount: U128,
+    pub fraction_nft_value: U128,
+    pub protocol_fee: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TradedPoolInfo {
+    // in seconds
+    pub traded_at: u64,
+    pub pool: PairInfo,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RouteHop {
+    pub pool_id: u64,
+    pub swap_type: u8,
+    pub num_items: u64,
+    // near received for a sell net of royalty, near paid for a buy royalty included
+    pub near_amount: U128,
+    pub protocol_fee: U128,
+    pub royalty: U128,
+    // shortfall of the hop compared to trading every item at spot price, base 1e18
+    pub slippage: U128,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RouteQuote {
+    pub hops: Vec<RouteHop>,
+    // near left after the buy hop, or missing to pay for it
+    pub near_surplus: U128,
+    pub near_shortfall: U128,
+    pub total_slippage: U128,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolHealth {
+    // number of items the pool near balance can buy from sellers
+    pub sell_depth: u64,
+    // number of items buyers can take from the pool
+    pub buy_depth: u64,
+    // near received for selling one item to the pool, zero if it does not buy
+    pub floor_price: U128,
+    pub spot_price: U128,
+    pub created_at: u64,
+    pub trade_count: u64,
+    pub total_volume: U128,
+    // volume since creation over current tvl, base 1e18
+    pub utilization: U128,
+    // pool fees of the recent trades over tvl, annualized, base 1e18
+    pub fee_apr: U128,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CreatePairCost {
+    // charged to the creator deposit
+    pub pair_storage_cost: U128,
+    // must be attached on top of the pool near, registers the asset recipient deposit
+    pub asset_recipient_registration_cost: U128,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolParamsValidation {
+    pub valid: bool,
+    pub errors: Vec<PoolParamError>,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TvlInfo {
+    pub near_balance: U128,
+    // held items valued at what selling all of them to the pool curve would return
+    pub nft_value: U128,
+    pub tvl: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MaxBuyable {
+    pub num_nfts: u64,
+    // near paid to the pool, protocol fee included
+    pub near_amount: U128,
+    pub royalty: U128,
+    // taken by a buy of any nfts without random nonce, a nonce picking others
+    pub token_ids: Vec<TokenId>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceLadder {
+    // near paid for the i + 1-th item bought from the pool or received for the i + 1-th item
+    // sold to it, royalty excluded, up to the first item the curve cannot quote
+    pub buy_prices: Vec<U128>,
+    pub sell_prices: Vec<U128>,
+}
+
+const SECONDS_PER_YEAR: u128 = 365 * 24 * 3600;
+const MAX_LADDER_DEPTH: u64 = 100;
+// items beyond this are not counted in sell depths
+const MAX_SELL_DEPTH: u64 = 1 << 20;
+
+// differences between the quotes of 1..=depth items
+fn marginal_prices(depth: u64, quote: impl Fn(u64) -> Option<Balance>) -> Vec<U128> {
+    let mut prices = vec![];
+    let mut prev_total = 0u128;
+    for num_items in 1..=depth {
+        match quote(num_items) {
+            Some(total) => {
+                prices.push(U128(total.saturating_sub(prev_total)));
+                prev_total = total;
+            }
+            None => break,
+        }
+    }
+    prices
+}
+
+impl Contract {
+    // largest number of items whose sale to the pool its near balance covers
+    fn pool_sell_depth(&self, pair: &Pair) -> u64 {
+        if pair.pool_type == PoolType::NFT {
+            return 0;
+        }
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pair.pool_id);
+        let affordable = |num_items: u64| {
+            let sell_info = pair.get_sell_info(num_items, protocol_fee_multiplier);
+            sell_info.error_code == CurveErrorCode::Ok
+                && sell_info.output_value + sell_info.protocol_fee <= U256::from(pair.near_balance)
+        };
+        // sale outputs grow with the number of items, so search for the bound
+        let (mut low, mut high) = (0u64, MAX_SELL_DEPTH);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if affordable(mid) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low
+    }
+
+    // (hop, spot value of the items)
+    pub(crate) fn best_sell_hop(&self, asset_id: &AssetId, num_items: u64) -> Option<(RouteHop, Balance)> {
+        self.pools
+            .iter()
+            .filter(|p| &p.nft_token == asset_id && p.mt_token_id.is_none() && p.pool_type != PoolType::NFT)
+            .filter_map(|p| {
+                let sell_info = p.get_sell_info(num_items, self.pool_protocol_fee_multiplier(p.pool_id));
+                if sell_info.error_code != CurveErrorCode::Ok || sell_info.output_value + sell_info.protocol_fee > U256::from(p.near_balance) {
+                    return None;
+                }
+                let output_value = sell_info.output_value.as_u128();
+                let royalty = self.royalty_amount(asset_id, output_value);
+                let hop = RouteHop {
+                    pool_id: p.pool_id,
+                    swap_type: SwapType::NFTToNear as u8,
+                    num_items,
+                    near_amount: (output_value - royalty).into(),
+                    protocol_fee: sell_info.protocol_fee.as_u128().into(),
+                    royalty: royalty.into(),
+                    slippage: U128(0),
+                };
+                Some((hop, p.spot_price * num_items as u128))
+            })
+            .max_by_key(|(hop, _)| hop.near_amount.0)
+    }
+
+    pub(crate) fn best_buy_hop(&self, asset_id: &AssetId, num_items: u64) -> Option<(RouteHop, Balance)> {
+        self.pools
+            .iter()
+            .filter(|p| &p.nft_token == asset_id && p.mt_token_id.is_none() && p.pool_type != PoolType::Token)
+            .filter(|p| p.token_ids_in_pools.len() >= num_items)
+            .filter_map(|p| {
+                let buy_info = p.get_buy_info(num_items, self.pool_protocol_fee_multiplier(p.pool_id));
+                if buy_info.error_code != CurveErrorCode::Ok {
+                    return None;
+                }
+                let input_value = buy_info.input_value.as_u128();
+                let royalty = self.royalty_amount(asset_id, input_value);
+                let hop = RouteHop {
+                    pool_id: p.pool_id,
+                    swap_type: SwapType::NearToNFT as u8,
+                    num_items,
+                    near_amount: (input_value + royalty).into(),
+                    protocol_fee: buy_info.protocol_fee.as_u128().into(),
+                    royalty: royalty.into(),
+                    slippage: U128(0),
+                };
+                Some((hop, p.spot_price * num_items as u128))
+            })
+            .min_by_key(|(hop, _)| hop.near_amount.0)
+    }
+
+    fn pool_tvl(&self, pair: &Pair) -> (Balance, Balance) {
+        let num_items = pair.num_items();
+        if num_items == 0 {
+            return (pair.near_balance, 0);
+        }
+        let sell_info = pair.get_sell_info(num_items, self.pool_protocol_fee_multiplier(pair.pool_id));
+        let nft_value = if sell_info.error_code == CurveErrorCode::Ok {
+            checked_as_u128(sell_info.output_value).unwrap_or(u128::MAX)
+        } else {
+            0
+        };
+        (pair.near_balance, nft_value)
+    }
+}
+
 #[near_bindgen]
 impl Contract {
     pub fn get_protocol_fee_multiplier(&self) -> u128 {
         self.protocol_fee_multiplier
     }
 
+    /// Hard cap of every protocol fee multiplier, base 1e18.
+    pub fn get_max_protocol_fee_multiplier(&self) -> U128 {
+        MAX_PROTOCOL_FEE.into()
+    }
+
+    /// Protocol fee applied to the pool after pool type and collection overrides.
+    pub fn get_pool_protocol_fee_multiplier(&self, pool_id: u64) -> U128 {
+        self.pool_protocol_fee_multiplier(pool_id).into()
+    }
+
+    pub fn get_collection_protocol_fee(&self, asset_id: AssetId) -> Option<U128> {
+        self.collection_protocol_fees.get(&asset_id).map(U128)
+    }
+
+    /// Deprecated tuple form of `get_buy_nft_quote_v2`: (error code, new spot price, new delta,
+    /// input value, protocol fee).
     pub fn get_buy_nft_quote(&self, pool_id: u64, num_nfts: u64) -> (CurveErrorCode, U128, U128, U128, U128) {
         let pair = self.pools.get(pool_id as usize).unwrap();
-        let buy_info = pair.curve.get_buy_info(pair.spot_price, pair.delta, num_nfts, pair.fee, self.get_protocol_fee_multiplier());
+        let buy_info = pair.get_buy_info(num_nfts, self.pool_protocol_fee_multiplier(pool_id));
         (buy_info.error_code, buy_info.new_spot_price.into(), buy_info.new_delta.into(), buy_info.input_value.as_u128().into(), buy_info.protocol_fee.as_u128().into())
     }
 
+    /// Deprecated tuple form of `get_sell_nft_quote_v2`: (error code, new spot price, new delta,
+    /// output value, protocol fee).
     pub fn get_sell_nft_quote(&self, pool_id: u64, num_nfts: u64) -> (CurveErrorCode, U128, U128, U128, U128) {
         let pair = self.pools.get(pool_id as usize).unwrap();
-        let sell_info = pair.curve.get_sell_info(pair.spot_price, pair.delta, num_nfts, pair.fee, self.get_protocol_fee_multiplier());
+        let sell_info = pair.get_sell_info(num_nfts, self.pool_protocol_fee_multiplier(pool_id));
         (sell_info.error_code, sell_info.new_spot_price.into(), sell_info.new_delta.into(), sell_info.output_value.as_u128().into(), sell_info.protocol_fee.as_u128().into())
     }
 
     pub fn get_all_held_ids(&self, pool_id: u64) -> Vec<TokenId> {
         let pair = self.pools.get(pool_id as usize).unwrap();
-        pair.token_ids_in_pools.keys_as_vector().to_vec()
+        pair.token_ids_in_pools.to_vec()
     }
 
-    fn pool_to_pair_info(&self, pair: &Pair) -> PairInfo {
-        PairInfo { pool_id: pair.pool_id, curve_type: pair.curve.curve_type, pool_type: pair.pool_type, nft_token: pair.nft_token.clone(), spot_price: pair.spot_price.into(), delta: pair.delta.into(), fee: pair.fee.into(), owner: pair.owner.clone(), asset_recipient: pair.asset_recipient.clone(), near_balance: pair.near_balance.into(), pool_token_ids: self.get_all_held_ids(pair.pool_id) }
+    fn pool_matches(&self, pair: &Pair, filter: &PoolFilter) -> bool {
+        if filter.pool_type.map_or(false, |pool_type| PoolType::from(pool_type) != pair.pool_type)
+            || filter.curve_type.map_or(false, |curve_type| BondingCurve::from(curve_type) != pair.curve.curve_type)
+            || filter.collection.as_ref().map_or(false, |collection| collection != &pair.nft_token)
+        {
+            return false;
+        }
+        match filter.min_liquidity {
+            Some(min_liquidity) => {
+                let (near_balance, nft_value) = self.pool_tvl(pair);
+                near_balance.saturating_add(nft_value) >= min_liquidity.0
+            }
+            None => true,
+        }
     }
 
-    pub fn get_pool_info(&self, pool_id: u64) -> PairInfo {
-        let pair = self.pools.get(pool_id as usize).unwrap();
-        self.pool_to_pair_info(pair)
+    fn pool_to_pair_info(&self, pair: &Pair, with_token_ids: bool) -> PairInfo {
+        let pool_token_ids = if with_token_ids { Some(pair.token_ids_in_pools.to_vec()) } else { None };
+        PairInfo { pool_id: pair.pool_id, curve_type: pair.curve.curve_type, pool_type: pair.pool_type, nft_token: pair.nft_token.clone(), spot_price: pair.spot_price.into(), delta: pair.delta.into(), fee: pair.fee.into(), owner: pair.owner.clone(), asset_recipient: pair.asset_recipient.clone(), near_balance: pair.near_balance.into(), proceeds: pair.proceeds.into(), num_nfts: pair.num_items(), pool_token_ids, verified: self.verified_collections.get(&pair.nft_token).is_some(), trade_seq: pair.trade_seq, mt_token_id: pair.mt_token_id.clone(), gated: pair.gate.is_some(), max_nfts: pair.max_nfts, max_near: pair.max_near.map(U128), virtual_reserves: pair.virtual_reserves.clone(), num_acquired_nfts: pair.num_acquired_tokens, lp_supply: pair.lp_supply.into(), locked_lp: pair.locked_lp.into(), sell_delta: pair.sell_delta.map(U128), sell_fee: pair.sell_fee.map(U128::from), official: pair.official, buys_enabled: pair.sides.buys_enabled, sells_enabled: pair.sides.sells_enabled, bootstrapping: pair.bootstrap.is_some() }
+    }
+
+    pub fn get_fee_tiers(&self) -> Vec<U128> {
+        self.fee_tiers.iter().map(|f| U128(*f)).collect()
     }
 
-    pub fn get_pools_infos(&self, pool_ids: Vec<u64>) -> Vec<PairInfo> {
-        let mut pairs = Vec::<PairInfo>::new();
-        for pool_id in &pool_ids {
-            pairs.push(self.get_pool_info(pool_id.clone()));
+    /// Trade pools of the collection created with the fee tier `fee`.
+    pub fn get_fee_tier_pools(&self, asset_id: AssetId, fee: U128) -> Vec<PairInfo> {
+        self.fee_tier_pools
+            .get(&(asset_id, fee.0))
+            .unwrap_or_default()
+            .iter()
+            .map(|pool_id| self.pool_to_pair_info(&self.pools[*pool_id as usize], false))
+            .collect()
+    }
+
+    /// Pools holding near or nfts, in no particular order.
+    pub fn get_active_pools(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<PairInfo> {
+        self.active_pools
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .map(|pool_id| self.pool_to_pair_info(&self.pools[pool_id as usize], false))
+            .collect()
+    }
+
+    pub fn get_num_active_pools(&self) -> u64 {
+        self.active_pools.len()
+    }
+
+    /// Last traded pools, most recent first.
+    pub fn get_recently_traded_pools(&self, limit: Option<u64>) -> Vec<TradedPoolInfo> {
+        self.recently_traded_pools
+            .iter()
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .map(|(pool_id, traded_at)| TradedPoolInfo {
+                traded_at: *traded_at,
+                pool: self.pool_to_pair_info(&self.pools[*pool_id as usize], false),
+            })
+            .collect()
+    }
+
+    /// Priority token ids of the pool still held by it.
+    pub fn get_token_priority(&self, pool_id: u64) -> Vec<TokenId> {
+        let pair = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pair.priority_token_ids
+            .iter()
+            .filter(|token_id| pair.token_ids_in_pools.contains(token_id))
+            .cloned()
+            .collect()
+    }
+
+    pub fn has_token(&self, pool_id: u64, token_id: TokenId) -> bool {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.token_ids_in_pools.contains(&token_id)
+    }
+
+    /// Pool holding the token, if any.
+    pub fn in_any_pool(&self, asset_id: AssetId, token_id: TokenId) -> Option<u64> {
+        match self.token_locations.get(&(asset_id, token_id)) {
+            Some(TokenLocation::Pool(pool_id)) => Some(pool_id),
+            _ => None,
         }
-        pairs
+    }
+
+    pub fn get_token_min_price(&self, pool_id: u64, token_id: TokenId) -> Option<U128> {
+        let pair = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pair.get_min_price(&token_id).map(U128)
+    }
+
+    pub fn get_pool_proceeds(&self, pool_id: u64) -> U128 {
+        let pair = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pair.proceeds.into()
+    }
+
+    /// Token ids held by the pool are included unless `with_token_ids` is false.
+    pub fn get_pool_info(&self, pool_id: u64, with_token_ids: Option<bool>) -> PairInfo {
+        let pair = self.pools.get(pool_id as usize).unwrap();
+        self.pool_to_pair_info(pair, with_token_ids.unwrap_or(true))
+    }
+
+    /// Token ids held by the pools are only included if `with_token_ids` is true.
+    pub fn get_pools_infos(&self, pool_ids: Vec<u64>, with_token_ids: Option<bool>) -> Vec<PairInfo> {
+        let with_token_ids = with_token_ids.unwrap_or(false);
+        pool_ids
+            .iter()
+            .map(|pool_id| self.pool_to_pair_info(self.pools.get(*pool_id as usize).expect("pool id invalid"), with_token_ids))
+            .collect()
     }
 
     pub fn get_pool_count(&self) -> u64 {
         self.pools.len() as u64
     }
 
-    pub fn get_pools(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<PairInfo> {
+    /// Pools `[from_index, from_index + limit)`, in creation order, matching the filter. The range
+    /// bounds the pools scanned rather than returned, so a chunk can come back with fewer pools.
+    /// Token ids held by the pools are only included if `with_token_ids` is true.
+    pub fn get_pools(&self, from_index: Option<u64>, limit: Option<u64>, with_token_ids: Option<bool>, filter: Option<PoolFilter>) -> Vec<PairInfo> {
         let from = from_index.unwrap_or(0);
         if from >= self.pools.len() as u64 {
             return vec![];
@@ -125,7 +536,8 @@ impl Contract {
             .iter()
             .skip(from as usize)
             .take(limit as usize)
-            .map(|p| self.pool_to_pair_info(p))
+            .filter(|p| filter.as_ref().map_or(true, |filter| self.pool_matches(p, filter)))
+            .map(|p| self.pool_to_pair_info(p, with_token_ids.unwrap_or(false)))
             .collect::<Vec<_>>()
     }
 
@@ -137,7 +549,33 @@ impl Contract {
             hash_map.insert(asset_id, held_ids);
         }
 
-        AccountInfo { deposits: hash_map, near_balance: account_deposit.near_balance.into(), storage_usage: account_deposit.storage_usage }                                        
+        AccountInfo {
+            deposits: hash_map,
+            storage_reserve: account_deposit.storage_reserve.into(),
+            available_balance: account_deposit.available_balance.into(),
+            storage_usage: account_deposit.storage_usage,
+        }
+    }
+
+    pub fn get_account_count(&self) -> u64 {
+        self.account_deposits.len()
+    }
+
+    /// Registered accounts `[from_index, from_index + limit)` with a summary of their deposits.
+    pub fn get_registered_accounts(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<AccountSummary> {
+        self.account_deposits
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .map(|(account_id, account_deposit)| AccountSummary {
+                account_id,
+                num_assets: account_deposit.assets.len(),
+                num_tokens: account_deposit.assets.values().map(|token_ids| token_ids.len()).sum(),
+                storage_reserve: account_deposit.storage_reserve.into(),
+                available_balance: account_deposit.available_balance.into(),
+                storage_usage: account_deposit.storage_usage,
+            })
+            .collect()
     }
 
     pub fn get_buy_info(
@@ -146,16 +584,9 @@ impl Contract {
         num_items: u64,
     ) -> BuyInfoPublic {
         let pool = &self.pools[pool_id as usize];
-        let current_spot_price = pool.spot_price;
-        let current_delta = pool.delta;
-        let buy_info = pool.curve.get_buy_info(
-            current_spot_price,
-            current_delta,
-            num_items,
-            pool.fee,
-            self.protocol_fee_multiplier,
-        );
-        BuyInfoPublic { error_code: buy_info.error_code, new_spot_price: buy_info.new_spot_price.into(), new_delta: buy_info.new_delta.into(), input_value: buy_info.input_value.as_u128().into(), protocol_fee: buy_info.protocol_fee.as_u128().into() }
+        let buy_info = pool.get_buy_info(num_items, self.pool_protocol_fee_multiplier(pool_id));
+        let royalty = self.royalty_amount(&pool.nft_token, buy_info.input_value.as_u128());
+        BuyInfoPublic { error_code: buy_info.error_code, new_spot_price: buy_info.new_spot_price.into(), new_delta: buy_info.new_delta.into(), input_value: buy_info.input_value.as_u128().into(), protocol_fee: buy_info.protocol_fee.as_u128().into(), royalty: royalty.into() }
     }
 
     pub fn get_sell_info(
@@ -164,12 +595,222 @@ impl Contract {
         num_items: u64
     ) -> SellInfoPublic {
         let pool = &self.pools[pool_id as usize];
-        let sell_info = pool.curve.get_sell_info(pool.spot_price, pool.delta, num_items, pool.fee, self.protocol_fee_multiplier);
-        SellInfoPublic { error_code: sell_info.error_code, new_spot_price: sell_info.new_spot_price.into(), new_delta: sell_info.new_delta.into(), output_value: sell_info.output_value.as_u128().into(), protocol_fee: sell_info.protocol_fee.as_u128().into() }
+        let sell_info = pool.get_sell_info(num_items, self.pool_protocol_fee_multiplier(pool_id));
+        let royalty = self.royalty_amount(&pool.nft_token, sell_info.output_value.as_u128());
+        SellInfoPublic { error_code: sell_info.error_code, new_spot_price: sell_info.new_spot_price.into(), new_delta: sell_info.new_delta.into(), output_value: sell_info.output_value.as_u128().into(), protocol_fee: sell_info.protocol_fee.as_u128().into(), royalty: royalty.into() }
+    }
+
+    /// Quote of buying `num_nfts` from the pool, royalty included, stamped with the pool and time.
+    pub fn get_buy_nft_quote_v2(&self, pool_id: u64, num_nfts: u64) -> BuyQuote {
+        BuyQuote {
+            pool_id,
+            num_items: num_nfts,
+            timestamp_sec: env::block_timestamp_ms() / 1000,
+            info: self.get_buy_info(pool_id, num_nfts),
+        }
+    }
+
+    /// Quote of selling `num_nfts` to the pool, royalty included, stamped with the pool and time.
+    pub fn get_sell_nft_quote_v2(&self, pool_id: u64, num_nfts: u64) -> SellQuote {
+        SellQuote {
+            pool_id,
+            num_items: num_nfts,
+            timestamp_sec: env::block_timestamp_ms() / 1000,
+            info: self.get_sell_info(pool_id, num_nfts),
+        }
+    }
+
+    /// Returns what `remove_liquidity(pool_id, lp_amount, prefer_near)` called by `account_id` would
+    /// withdraw at the current pool state, lps getting their own deposits back first.
+    pub fn preview_remove_liquidity(&self, pool_id: u64, lp_amount: U128, prefer_near: Option<bool>, account_id: Option<AccountId>) -> RemoveLiquidityPreview {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let redemption = pool.compute_lp_redemption(account_id.as_ref(), lp_amount.0, self.pool_protocol_fee_multiplier(pool_id), prefer_near.unwrap_or(false));
+        RemoveLiquidityPreview { token_ids: redemption.token_ids, near_amount: redemption.near_amount.into(), fraction_nft_value: redemption.fraction_nft_value.into(), protocol_fee: redemption.protocol_fee.into() }
+    }
+
+    pub fn get_pool_tvl(&self, pool_id: u64) -> TvlInfo {
+        let pair = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let (near_balance, nft_value) = self.pool_tvl(pair);
+        TvlInfo { near_balance: near_balance.into(), nft_value: nft_value.into(), tvl: near_balance.saturating_add(nft_value).into() }
+    }
+
+    /// Quotes selling `num_items` of `asset_in` to the pool paying the most, then buying
+    /// `num_items` of `asset_out` from the cheapest pool, as executed by a two action `swap`.
+    pub fn get_route_quote(&self, asset_in: AssetId, asset_out: AssetId, num_items: u64) -> RouteQuote {
+        require!(num_items > 0, "num items must be > 0");
+        let (mut sell_hop, sell_spot_value) = self
+            .best_sell_hop(&asset_in, num_items)
+            .unwrap_or_else(|| env::panic_str("no pool buys asset in"));
+        let (mut buy_hop, buy_spot_value) = self
+            .best_buy_hop(&asset_out, num_items)
+            .unwrap_or_else(|| env::panic_str("no pool sells asset out"));
+
+        let ratio = |loss: u128, spot_value: u128| if spot_value == 0 { 0 } else { (U256::from(loss) * U256::from(WAD) / U256::from(spot_value)).as_u128() };
+        let sell_loss = sell_spot_value.saturating_sub(sell_hop.near_amount.0);
+        let buy_loss = buy_hop.near_amount.0.saturating_sub(buy_spot_value);
+        sell_hop.slippage = ratio(sell_loss, sell_spot_value).into();
+        buy_hop.slippage = ratio(buy_loss, buy_spot_value).into();
+
+        RouteQuote {
+            near_surplus: sell_hop.near_amount.0.saturating_sub(buy_hop.near_amount.0).into(),
+            near_shortfall: buy_hop.near_amount.0.saturating_sub(sell_hop.near_amount.0).into(),
+            total_slippage: ratio(sell_loss + buy_loss, sell_spot_value + buy_spot_value).into(),
+            hops: vec![sell_hop, buy_hop],
+        }
+    }
+
+    /// Most nfts a buy of any nfts from the pool can get for `budget_near`, royalty included.
+    pub fn get_max_buyable(&self, pool_id: u64, budget_near: U128) -> MaxBuyable {
+        let pair = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let none = MaxBuyable { num_nfts: 0, near_amount: U128(0), royalty: U128(0), token_ids: vec![] };
+        if pair.pool_type == PoolType::Token || pair.mt_token_id.is_some() {
+            return none;
+        }
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pool_id);
+        // (near amount, protocol fee, royalty) of buying num_items, if within the budget
+        let cost = |num_items: u64| {
+            let quote = pair.quote_buy(num_items, protocol_fee_multiplier).ok()?;
+            let royalty = self.royalty_amount(&pair.nft_token, quote.near_amount);
+            (quote.near_amount + royalty <= budget_near.0).then(|| (quote.near_amount, quote.protocol_fee, royalty))
+        };
+        // buy inputs grow with the number of items, so search for the bound
+        let (mut low, mut high) = (0u64, pair.num_items());
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if cost(mid).is_some() {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        // fewer items may be needed for enough of them to be above their min price
+        while low > 0 {
+            let (near_amount, protocol_fee, royalty) = cost(low).unwrap();
+            let token_ids = pair.preview_any_token_ids(low, (near_amount - protocol_fee) / low as u128);
+            if token_ids.len() as u64 == low {
+                return MaxBuyable { num_nfts: low, near_amount: near_amount.into(), royalty: royalty.into(), token_ids };
+            }
+            low -= 1;
+        }
+        none
+    }
+
+    /// Marginal buy and sell prices of the pool for up to `depth` items, capped at MAX_LADDER_DEPTH.
+    pub fn get_price_ladder(&self, pool_id: u64, depth: u64) -> PriceLadder {
+        let pair = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let protocol_fee_multiplier = self.pool_protocol_fee_multiplier(pool_id);
+        let depth = depth.min(MAX_LADDER_DEPTH);
+        PriceLadder {
+            buy_prices: marginal_prices(depth, |num_items| {
+                pair.quote_buy(num_items, protocol_fee_multiplier)
+                    .ok()
+                    .map(|quote| quote.near_amount)
+            }),
+            sell_prices: marginal_prices(depth, |num_items| {
+                pair.quote_sell(num_items, protocol_fee_multiplier)
+                    .ok()
+                    .map(|quote| quote.near_amount)
+            }),
+        }
+    }
+
+    pub fn get_pool_health(&self, pool_id: u64) -> PoolHealth {
+        let pair = self.pools.get(pool_id as usize).expect("pool id invalid");
+        let buy_depth = if pair.pool_type == PoolType::Token { 0 } else { pair.num_items() };
+        let floor_price = if pair.pool_type == PoolType::NFT {
+            0
+        } else {
+            let sell_info = pair.get_sell_info(1, self.pool_protocol_fee_multiplier(pool_id));
+            checked_as_u128(sell_info.output_value).unwrap_or(0)
+        };
+
+        let (near_balance, nft_value) = self.pool_tvl(pair);
+        let tvl = U256::from(near_balance) + U256::from(nft_value);
+        let utilization = if tvl.is_zero() { U256::zero() } else { U256::from(pair.total_volume) * U256::from(WAD) / tvl };
+        let fee_apr = match pair.recent_trades.first() {
+            Some(oldest) if !tvl.is_zero() => {
+                let window = (env::block_timestamp_ms() / 1000).saturating_sub(oldest.timestamp_sec).max(1);
+                let fees = pair.recent_trades.iter().map(|t| U256::from(t.pool_fee)).fold(U256::zero(), |a, b| a + b);
+                fees * U256::from(WAD) * U256::from(SECONDS_PER_YEAR) / (tvl * U256::from(window))
+            }
+            _ => U256::zero(),
+        };
+
+        PoolHealth {
+            sell_depth: self.pool_sell_depth(pair),
+            buy_depth,
+            floor_price: floor_price.into(),
+            spot_price: pair.spot_price.into(),
+            created_at: pair.created_at,
+            trade_count: pair.trade_seq,
+            total_volume: pair.total_volume.into(),
+            utilization: checked_as_u128(utilization).unwrap_or(u128::MAX).into(),
+            fee_apr: checked_as_u128(fee_apr).unwrap_or(u128::MAX).into(),
+        }
+    }
+
+    /// Sums the TVL of the pools in the given range, all pools by default.
+    pub fn get_tvl(&self, from_index: Option<u64>, limit: Option<u64>) -> TvlInfo {
+        let from = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(u64::MAX);
+        require!(limit != 0, "Cannot provide limit of 0.");
+
+        let (near_balance, nft_value) = self.pools
+            .iter()
+            .skip(from as usize)
+            .take(limit as usize)
+            .map(|p| self.pool_tvl(p))
+            .fold((0u128, 0u128), |(near, nft), (pool_near, pool_nft)| (near.saturating_add(pool_near), nft.saturating_add(pool_nft)));
+        TvlInfo { near_balance: near_balance.into(), nft_value: nft_value.into(), tvl: near_balance.saturating_add(nft_value).into() }
+    }
+
+    /// Near needed by `create_pair` besides the pool liquidity, excluding the storage of the
+    /// token ids added to the pool.
+    pub fn estimate_create_pair_cost(&self, asset_recipient: Option<AccountId>) -> CreatePairCost {
+        let registration_storage = match asset_recipient {
+            Some(account_id) if self.account_deposits.get(&account_id).is_none() => self.storage_per_account_creation,
+            _ => 0,
+        };
+        CreatePairCost {
+            pair_storage_cost: (self.storage_per_pair_creation as u128 * env::storage_byte_cost()).into(),
+            asset_recipient_registration_cost: (registration_storage as u128 * env::storage_byte_cost()).into(),
+        }
+    }
+
+    /// Checks pool creation parameters the way `create_pair` does, reporting every failure.
+    pub fn validate_pool_params(
+        &self,
+        curve_type: u8,
+        pool_type: u8,
+        spot_price: U128,
+        delta: U128,
+        fee: U128,
+        asset_recipient: Option<AccountId>,
+    ) -> PoolParamsValidation {
+        let mut errors = Vec::<PoolParamError>::new();
+        if curve_type > BondingCurve::ExponentialCurve as u8 {
+            errors.push(PoolParamError::new("curve_type", "unknown bonding curve"));
+        }
+        if pool_type > PoolType::Trade as u8 {
+            errors.push(PoolParamError::new("pool_type", "unknown pool type"));
+        }
+        if errors.is_empty() {
+            errors = pair::validate_pool_params(&Curve::new(curve_type.into()), pool_type.into(), spot_price.into(), delta.0, fee.into());
+        }
+        if pool_type == PoolType::Trade as u8 && asset_recipient.is_some() {
+            errors.push(PoolParamError::new("asset_recipient", "must be none for trade pools"));
+        }
+        PoolParamsValidation { valid: errors.is_empty(), errors }
     }
 
     pub fn get_metadata(&self) -> MetaData {
-        MetaData { governance_id: self.governance_id.clone(), protocol_fee_receiver_id: self.protocol_fee_receiver_id.clone(), protocol_fee_credit: self.protocol_fee_credit.into(), pools_acount: self.pools.len() as u64, protocol_fee_multiplier: self.protocol_fee_multiplier.into(), storage_per_account_creation: self.storage_per_account_creation, storage_per_nft_deposit: self.storage_per_nft_deposit, storage_per_pair_creation: self.storage_per_pair_creation }
+        MetaData { governance_id: self.governance_id.clone(), protocol_fee_receiver_id: self.protocol_fee_receiver_id.clone(), protocol_fee_credit: self.protocol_fee_credit.into(), pools_acount: self.pools.len() as u64, protocol_fee_multiplier: self.protocol_fee_multiplier.into(), storage_per_account_creation: self.storage_per_account_creation, storage_per_nft_deposit: self.storage_per_nft_deposit, storage_per_pair_creation: self.storage_per_pair_creation, early_unlock_penalty: self.early_unlock_penalty.map(U128), only_verified_collections: self.only_verified_collections, pool_type_protocol_fees: self.pool_type_protocol_fees.iter().map(|f| f.map(U128)).collect() }
+    }
+
+    /// Time from which governance can release the pool lock, none unless a release is queued.
+    pub fn get_force_release_at(&self, pool_id: u64) -> Option<u64> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.force_release_at
     }
 
     pub fn get_nft_asset_id(&self, pool_id: u64) -> AssetId {
@@ -0,0 +1,40 @@
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::near_bindgen;
+
+/// Reserves added to the real balances of a trade pool when pricing its trades, concentrating
+/// its liquidity around the current price. Trades are still bounded by the real balances.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VirtualReserves {
+    pub near: U128,
+    // at least one, so that buying every real nft has a finite price
+    pub nfts: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Switches the trade pool to constant product pricing over its real balances plus
+    /// `reserves`, or back to its curve if none. The spot price is set to the reserve price.
+    pub fn set_virtual_reserves(&mut self, pool_id: u64, reserves: Option<VirtualReserves>) {
+        let pool = &mut self.pools[pool_id as usize];
+        pool.assert_owner();
+        pool.assert_nft_pool();
+        require!(pool.pool_type == PoolType::Trade, "only trade pools can have virtual reserves");
+        require!(pool.oracle.is_none(), "oracle pegged pools cannot have virtual reserves");
+        if let Some(reserves) = &reserves {
+            require!(reserves.nfts > 0, "virtual nft reserve must be positive");
+            let near_reserve = pool.near_balance + reserves.near.0;
+            let nft_reserve = pool.token_ids_in_pools.len() + reserves.nfts;
+            require!(near_reserve > 0, "near reserve must be positive");
+            pool.spot_price = near_reserve / nft_reserve as u128;
+            pool.breaker_state.last_price = pool.spot_price.into();
+        }
+        pool.virtual_reserves = reserves;
+    }
+
+    pub fn get_virtual_reserves(&self, pool_id: u64) -> Option<VirtualReserves> {
+        let pool = self.pools.get(pool_id as usize).expect("pool id invalid");
+        pool.virtual_reserves.clone()
+    }
+}